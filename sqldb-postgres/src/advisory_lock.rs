@@ -0,0 +1,106 @@
+//! Advisory locks that are held across rpc calls.
+//!
+//! A session-level advisory lock belongs to the connection that took it, so each lock
+//! taken with `advisory_lock` or `try_advisory_lock` keeps its connection out of the pool
+//! until the actor releases it with `advisory_unlock`, or the actor's link is deleted.
+//! Locks are held per (actor, key): all instances of an actor share the actor's locks,
+//! and an instance waits for a lock that another instance holds.
+//!
+use crate::log_level::debug;
+use crate::{error::DbError, in_flight::InFlightConnection};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// (actor_id, key)
+type LockKey = (String, i64);
+
+/// statements of AdvisoryLock, TryAdvisoryLock, and AdvisoryUnlock
+pub(crate) const LOCK: &str = "SELECT pg_advisory_lock($1)";
+pub(crate) const TRY_LOCK: &str = "SELECT pg_try_advisory_lock($1)";
+pub(crate) const UNLOCK: &str = "SELECT pg_advisory_unlock($1)";
+
+/// Advisory locks held by actors
+#[derive(Clone, Default)]
+pub(crate) struct AdvisoryLocks {
+    inner: Arc<Mutex<HashMap<LockKey, InFlightConnection<'static>>>>,
+}
+
+impl AdvisoryLocks {
+    /// Take the lock on the connection, and keep the connection until the lock is released.
+    /// With `wait`, waits for the lock, for up to `timeout`, otherwise returns false
+    /// if another session holds it.
+    pub(crate) async fn lock(
+        &self,
+        actor_id: &str,
+        key: i64,
+        conn: InFlightConnection<'static>,
+        wait: bool,
+        timeout: Option<Duration>,
+    ) -> Result<bool, DbError> {
+        let locked = if wait {
+            conn.timeout(timeout, conn.execute(LOCK, &[&key])).await??;
+            true
+        } else {
+            conn.query_one(TRY_LOCK, &[&key]).await?.get::<_, bool>(0)
+        };
+        if locked {
+            // the server does not grant the lock to two sessions, so no other
+            // instance of the actor holds it
+            self.inner
+                .lock()
+                .unwrap()
+                .insert((actor_id.to_string(), key), conn);
+        }
+        Ok(locked)
+    }
+
+    /// Release the lock, and return its connection to the pool.
+    /// Returns NotFound if the actor does not hold the lock.
+    pub(crate) async fn unlock(&self, actor_id: &str, key: i64) -> Result<(), DbError> {
+        // removed before it is released, so that the next holder can insert it
+        let conn = self
+            .inner
+            .lock()
+            .unwrap()
+            .remove(&(actor_id.to_string(), key))
+            .ok_or_else(|| {
+                DbError::NotFound(format!("advisory lock {} is not held by the actor", key))
+            })?;
+        conn.execute(UNLOCK, &[&key]).await?;
+        Ok(())
+    }
+
+    /// Release all locks of the actor
+    pub(crate) async fn remove_actor(&self, actor_id: &str) {
+        let locks = {
+            let mut locks = self.inner.lock().unwrap();
+            let keys = locks
+                .keys()
+                .filter(|(actor, _)| actor == actor_id)
+                .cloned()
+                .collect::<Vec<_>>();
+            keys.into_iter()
+                .filter_map(|key| locks.remove(&key).map(|conn| (key, conn)))
+                .collect::<Vec<_>>()
+        };
+        release(locks).await;
+    }
+
+    /// Release all locks
+    pub(crate) async fn clear(&self) {
+        let locks = self.inner.lock().unwrap().drain().collect::<Vec<_>>();
+        release(locks).await;
+    }
+}
+
+/// Release the locks on their connections, which are returned to the pool
+async fn release(locks: Vec<(LockKey, InFlightConnection<'static>)>) {
+    for ((actor_id, key), conn) in locks {
+        if let Err(e) = conn.batch_execute("SELECT pg_advisory_unlock_all()").await {
+            debug!("releasing advisory lock {} of {}: {}", key, actor_id, e);
+        }
+    }
+}
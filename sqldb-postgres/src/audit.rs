@@ -0,0 +1,453 @@
+//! Audit log of the statements run by actors.
+//!
+//! With `audit_log`, each statement an actor runs on the link is recorded, with the time,
+//! operation, number of rows, and outcome, in a JSON Lines file (`file`), or in a table of
+//! the database (`table`). Statements do not wait for their records: the records are queued,
+//! and a task of the link writes them in batches. A batch that cannot be written is retried,
+//! and the failure is logged at ERROR. While the sink cannot be written, or the queue is full,
+//! statements still run, and records that do not fit in the queue are dropped
+//! (`audit_log_failure` 'open', the default), or the link's statements are rejected
+//! ('closed'), so that no statement runs without its record.
+//!
+//! When the link is deleted, the provider waits for the queued records to be written,
+//! for up to the link's shutdown_grace_period.
+//!
+use crate::log_level::{error, info};
+use crate::{copy::table_name, error::DbError, ext::SqlDbExtError, Pool};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{io::AsyncWriteExt, sync::mpsc, task::JoinHandle};
+use wasmcloud_interface_sqldb::SqlDbError;
+
+/// max number of records waiting to be written
+const QUEUE_CAPACITY: usize = 10_000;
+/// max number of records written at once
+const BATCH_SIZE: usize = 500;
+/// delay before a batch that could not be written is written again
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// table of the records, if audit_log_table is not set
+pub(crate) const DEFAULT_TABLE: &str = "sqldb_audit_log";
+
+/// Where the statements of a link are recorded
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum AuditSink {
+    /// statements are not recorded
+    #[default]
+    Off,
+    /// appended to the file audit_log_file, one JSON object per line
+    File,
+    /// inserted in the table audit_log_table
+    Table,
+}
+
+/// What happens to statements when their records cannot be written
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum AuditFailure {
+    /// statements run, and their records are dropped if the queue is full
+    #[default]
+    Open,
+    /// statements are rejected until the records can be written
+    Closed,
+}
+
+/// The sink of the audit log, and the connection pool of a table
+pub(crate) enum Target {
+    File(PathBuf),
+    Table { name: String, pool: Pool },
+}
+
+/// Error of a result, as it is recorded
+pub(crate) trait AuditError {
+    fn code(&self) -> &str;
+    fn message(&self) -> &str;
+}
+
+impl AuditError for SqlDbError {
+    fn code(&self) -> &str {
+        &self.code
+    }
+    fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl AuditError for SqlDbExtError {
+    fn code(&self) -> &str {
+        &self.code
+    }
+    fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// A statement run by an actor
+#[derive(Debug)]
+pub(crate) struct Record {
+    timestamp: DateTime<Utc>,
+    actor_id: String,
+    operation: &'static str,
+    statement: String,
+    /// rows affected or returned, if the operation reports them
+    rows: Option<u64>,
+    /// code and message of the error, if the statement failed
+    error: Option<(String, String)>,
+}
+
+impl Record {
+    pub(crate) fn new<E: AuditError>(
+        actor_id: &str,
+        operation: &'static str,
+        statement: &str,
+        rows: Option<u64>,
+        error: Option<&E>,
+    ) -> Self {
+        Record {
+            timestamp: Utc::now(),
+            actor_id: actor_id.to_string(),
+            operation,
+            statement: statement.to_string(),
+            rows: rows.filter(|_| error.is_none()),
+            error: error.map(|e| (e.code().to_string(), e.message().to_string())),
+        }
+    }
+
+    /// "ok", or the error code
+    fn outcome(&self) -> &str {
+        self.error.as_ref().map_or("ok", |(code, _)| code.as_str())
+    }
+
+    /// the record as a line of JSON
+    fn json_line(&self) -> String {
+        let mut line = serde_json::json!({
+            "timestamp": self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "actor_id": self.actor_id,
+            "operation": self.operation,
+            "statement": self.statement,
+            "rows": self.rows,
+            "outcome": self.outcome(),
+            "error": self.error.as_ref().map(|(_, message)| message),
+        })
+        .to_string();
+        line.push('\n');
+        line
+    }
+}
+
+/// state shared by the link's audit log and its task
+#[derive(Default)]
+struct State {
+    /// false while the last batch could not be written
+    healthy: AtomicBool,
+    /// records dropped since the last report
+    dropped: AtomicU64,
+    /// true after the link's audit log is dropped: the task writes the queued records,
+    /// without retrying a batch that fails, and ends
+    closing: AtomicBool,
+}
+
+/// Audit log of a link
+pub(crate) struct AuditLog {
+    actor_id: String,
+    sender: mpsc::Sender<Record>,
+    failure: AuditFailure,
+    state: Arc<State>,
+    task: JoinHandle<()>,
+    _closing: Closing,
+}
+
+/// sets closing when the audit log is dropped
+struct Closing(Arc<State>);
+
+impl Drop for Closing {
+    fn drop(&mut self) {
+        self.0.closing.store(true, Ordering::Relaxed);
+    }
+}
+
+impl AuditLog {
+    /// Open the file, or create the table if it does not exist, and start the task
+    /// that writes the records
+    pub(crate) async fn start(
+        target: Target,
+        failure: AuditFailure,
+        actor_id: &str,
+    ) -> Result<Self, DbError> {
+        let writer = Writer::open(target).await?;
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let state = Arc::new(State {
+            healthy: AtomicBool::new(true),
+            ..Default::default()
+        });
+        let task = tokio::spawn(run(receiver, writer, state.clone(), actor_id.to_string()));
+        Ok(AuditLog {
+            actor_id: actor_id.to_string(),
+            sender,
+            failure,
+            state: state.clone(),
+            task,
+            _closing: Closing(state),
+        })
+    }
+
+    /// Returns an error if statements are rejected: with audit_log_failure 'closed',
+    /// while the records cannot be written, or the queue is full
+    pub(crate) fn check(&self) -> Result<(), DbError> {
+        if self.failure == AuditFailure::Closed
+            && (!self.state.healthy.load(Ordering::Relaxed) || self.sender.capacity() == 0)
+        {
+            return Err(DbError::Provider(
+                "audit log records cannot be written: statements are rejected until they can be"
+                    .into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Queue the record, or drop it if the queue is full
+    pub(crate) fn record(&self, record: Record) {
+        if self.sender.try_send(record).is_err()
+            && self.state.dropped.fetch_add(1, Ordering::Relaxed) == 0
+        {
+            error!(
+                "{} audit log queue is full: records are dropped",
+                self.actor_id
+            );
+        }
+    }
+
+    /// Write the queued records, waiting for up to `timeout`
+    pub(crate) async fn close(self, timeout: Duration) {
+        let AuditLog {
+            actor_id,
+            sender,
+            task,
+            _closing,
+            ..
+        } = self;
+        drop((sender, _closing));
+        if tokio::time::timeout(timeout, task).await.is_err() {
+            error!(
+                "{} audit log: records not written after {}s",
+                actor_id,
+                timeout.as_secs()
+            );
+        }
+    }
+}
+
+/// Write the records as they are queued, until the audit log is dropped
+async fn run(
+    mut receiver: mpsc::Receiver<Record>,
+    mut writer: Writer,
+    state: Arc<State>,
+    actor_id: String,
+) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while let Some(record) = receiver.recv().await {
+        batch.push(record);
+        while batch.len() < BATCH_SIZE {
+            match receiver.try_recv() {
+                Ok(record) => batch.push(record),
+                Err(_) => break,
+            }
+        }
+        while let Err(e) = writer.write(&batch).await {
+            state.healthy.store(false, Ordering::Relaxed);
+            if state.closing.load(Ordering::Relaxed) {
+                error!(
+                    "{} audit log: {} records dropped: writing to {}: {}",
+                    actor_id,
+                    batch.len(),
+                    writer,
+                    e
+                );
+                break;
+            }
+            error!(
+                "{} audit log: writing {} records to {}, retrying in {}s: {}",
+                actor_id,
+                batch.len(),
+                writer,
+                RETRY_DELAY.as_secs(),
+                e
+            );
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+        batch.clear();
+        if !state.healthy.swap(true, Ordering::Relaxed) {
+            info!("{} audit log: writing to {} again", actor_id, writer);
+        }
+        let dropped = state.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            error!(
+                "{} audit log: {} records were dropped, because the queue was full",
+                actor_id, dropped
+            );
+        }
+    }
+}
+
+/// The open sink
+enum Writer {
+    File {
+        path: PathBuf,
+        file: tokio::fs::File,
+    },
+    Table {
+        name: String,
+        insert: String,
+        pool: Pool,
+    },
+}
+
+impl std::fmt::Display for Writer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Writer::File { path, .. } => write!(f, "file {}", path.display()),
+            Writer::Table { name, .. } => write!(f, "table {}", name),
+        }
+    }
+}
+
+impl Writer {
+    async fn open(target: Target) -> Result<Self, DbError> {
+        match target {
+            Target::File(path) => {
+                let file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .await
+                    .map_err(|e| {
+                        DbError::Config(format!("opening audit_log_file {}: {}", path.display(), e))
+                    })?;
+                Ok(Writer::File { path, file })
+            }
+            Target::Table { name, pool } => {
+                let table = table_name(&name).map_err(DbError::Config)?;
+                let conn = pool
+                    .get()
+                    .await
+                    .map_err(|e| DbError::Io(format!("connection for audit log: {}", e)))?;
+                conn.batch_execute(&format!(
+                    "CREATE TABLE IF NOT EXISTS {} (id bigserial PRIMARY KEY, \
+                     logged_at timestamptz NOT NULL, actor_id text NOT NULL, \
+                     operation text NOT NULL, statement text NOT NULL, rows bigint, \
+                     outcome text NOT NULL, error text)",
+                    table
+                ))
+                .await?;
+                drop(conn);
+                let insert = format!(
+                    "INSERT INTO {} (logged_at, actor_id, operation, statement, rows, outcome, \
+                     error) SELECT * FROM unnest($1::timestamptz[], $2::text[], $3::text[], \
+                     $4::text[], $5::bigint[], $6::text[], $7::text[])",
+                    table
+                );
+                Ok(Writer::Table { name, insert, pool })
+            }
+        }
+    }
+
+    /// Write the records. A batch is written completely, or not at all
+    async fn write(&mut self, records: &[Record]) -> Result<(), String> {
+        match self {
+            Writer::File { file, .. } => {
+                let lines = records.iter().map(Record::json_line).collect::<String>();
+                file.write_all(lines.as_bytes())
+                    .await
+                    .and(file.flush().await)
+                    .map_err(|e| e.to_string())
+            }
+            Writer::Table { insert, pool, .. } => {
+                let conn = pool.get().await.map_err(|e| e.to_string())?;
+                let timestamps = records.iter().map(|r| r.timestamp).collect::<Vec<_>>();
+                let actors = records
+                    .iter()
+                    .map(|r| r.actor_id.as_str())
+                    .collect::<Vec<_>>();
+                let operations = records.iter().map(|r| r.operation).collect::<Vec<_>>();
+                let statements = records
+                    .iter()
+                    .map(|r| r.statement.as_str())
+                    .collect::<Vec<_>>();
+                let rows = records
+                    .iter()
+                    .map(|r| r.rows.map(|n| n as i64))
+                    .collect::<Vec<_>>();
+                let outcomes = records.iter().map(Record::outcome).collect::<Vec<_>>();
+                let errors = records
+                    .iter()
+                    .map(|r| r.error.as_ref().map(|(_, message)| message.as_str()))
+                    .collect::<Vec<_>>();
+                conn.execute(
+                    insert.as_str(),
+                    &[
+                        &timestamps,
+                        &actors,
+                        &operations,
+                        &statements,
+                        &rows,
+                        &outcomes,
+                        &errors,
+                    ],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|e| DbError::from(e).to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let record = Record::new::<SqlDbError>(
+            "actor",
+            "SqlDb.Execute",
+            "insert into t values (1)",
+            Some(1),
+            None,
+        );
+        let value: serde_json::Value =
+            serde_json::from_str(&record.json_line()).expect("json line");
+        assert_eq!(value["actor_id"], "actor");
+        assert_eq!(value["operation"], "SqlDb.Execute");
+        assert_eq!(value["statement"], "insert into t values (1)");
+        assert_eq!(value["rows"], 1);
+        assert_eq!(value["outcome"], "ok");
+        assert!(value["error"].is_null());
+        assert!(value["timestamp"]
+            .as_str()
+            .map_or(false, |t| t.ends_with('Z')));
+
+        let error = SqlDbError::new("permissionDenied", "read-only link".to_string());
+        let record = Record::new(
+            "actor",
+            "SqlDb.Execute",
+            "delete from t",
+            Some(0),
+            Some(&error),
+        );
+        let line = record.json_line();
+        assert!(line.ends_with('\n') && !line[..line.len() - 1].contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).expect("json line");
+        assert_eq!(value["outcome"], "permissionDenied");
+        assert_eq!(value["error"], "read-only link");
+        assert!(value["rows"].is_null());
+    }
+}
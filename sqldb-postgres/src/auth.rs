@@ -0,0 +1,364 @@
+//! Credentials for database connections.
+//!
+//! Token-based authentication methods generate a new token for each
+//! physical connection, so expired tokens are never reused.
+//!
+use bb8_postgres::tokio_postgres::config::{Config, Host};
+
+/// default postgres port
+const DEFAULT_PORT: u16 = 5432;
+
+/// Source of the password used when establishing a connection
+#[derive(Clone, Debug)]
+pub(crate) enum Credentials {
+    /// use the password (if any) from the connection settings
+    Password,
+    /// generate an AWS RDS IAM authentication token
+    RdsIam(rds::RdsIam),
+    /// Azure AD access token
+    #[cfg(feature = "azure")]
+    AzureAd(azure::AzureAd),
+}
+
+impl Credentials {
+    /// Sets the password in the connection config for a new connection
+    pub(crate) async fn apply(&self, config: &mut Config) -> Result<(), String> {
+        match self {
+            Credentials::Password => {}
+            Credentials::RdsIam(rds) => {
+                let (host, port) = tcp_host(config)?;
+                let user = config
+                    .get_user()
+                    .ok_or_else(|| "rds-iam authentication requires a user name".to_string())?
+                    .to_string();
+                let token = rds.auth_token(&host, port, &user)?;
+                config.password(token);
+            }
+            #[cfg(feature = "azure")]
+            Credentials::AzureAd(azure) => {
+                config.password(azure.access_token().await?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the first host name and port from the connection config
+fn tcp_host(config: &Config) -> Result<(String, u16), String> {
+    let host = match config.get_hosts().first() {
+        Some(Host::Tcp(host)) => host.clone(),
+        _ => return Err("token authentication requires a tcp host name".into()),
+    };
+    let port = config.get_ports().first().copied().unwrap_or(DEFAULT_PORT);
+    Ok((host, port))
+}
+
+/// AWS RDS IAM authentication
+///
+/// The auth token is a SigV4-presigned url for the `rds-db:connect` action.
+/// AWS credentials are read from the environment variables `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY`, and `AWS_SESSION_TOKEN` (optional), or if those are
+/// not set, from the shared credentials file (`~/.aws/credentials`,
+/// or `AWS_SHARED_CREDENTIALS_FILE`), using the profile `aws_profile`,
+/// `AWS_PROFILE`, or "default".
+pub(crate) mod rds {
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write;
+
+    /// tokens are valid for 15 minutes
+    const TOKEN_EXPIRES_SEC: u32 = 900;
+    const SERVICE: &str = "rds-db";
+
+    #[derive(Clone, Debug)]
+    pub(crate) struct RdsIam {
+        /// AWS region of the database
+        region: String,
+        /// profile in the shared credentials file
+        profile: Option<String>,
+    }
+
+    /// AWS access keys
+    struct AwsCredentials {
+        access_key_id: String,
+        secret_access_key: String,
+        session_token: Option<String>,
+    }
+
+    impl RdsIam {
+        /// Creates the token generator. If region is not specified,
+        /// the environment variables `AWS_REGION` or `AWS_DEFAULT_REGION` are used.
+        pub(crate) fn new(region: Option<String>, profile: Option<String>) -> Result<Self, String> {
+            let region = region
+                .or_else(|| std::env::var("AWS_REGION").ok())
+                .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+                .ok_or_else(|| {
+                    "rds-iam authentication requires 'aws_region' or AWS_REGION".to_string()
+                })?;
+            Ok(Self { region, profile })
+        }
+
+        /// Generates an auth token, which is used as the connection password
+        pub(crate) fn auth_token(
+            &self,
+            host: &str,
+            port: u16,
+            user: &str,
+        ) -> Result<String, String> {
+            let creds = AwsCredentials::load(self.profile.as_deref())?;
+            Ok(presign(
+                &creds,
+                &self.region,
+                host,
+                port,
+                user,
+                chrono::Utc::now(),
+            ))
+        }
+    }
+
+    impl AwsCredentials {
+        /// Loads credentials from the environment or the shared credentials file
+        fn load(profile: Option<&str>) -> Result<Self, String> {
+            if let (Ok(access_key_id), Ok(secret_access_key)) = (
+                std::env::var("AWS_ACCESS_KEY_ID"),
+                std::env::var("AWS_SECRET_ACCESS_KEY"),
+            ) {
+                return Ok(AwsCredentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+                });
+            }
+            let path = match std::env::var("AWS_SHARED_CREDENTIALS_FILE") {
+                Ok(path) => std::path::PathBuf::from(path),
+                Err(_) => std::path::PathBuf::from(std::env::var("HOME").unwrap_or_default())
+                    .join(".aws")
+                    .join("credentials"),
+            };
+            let profile = profile
+                .map(String::from)
+                .or_else(|| std::env::var("AWS_PROFILE").ok())
+                .unwrap_or_else(|| "default".to_string());
+            let text = std::fs::read_to_string(&path).map_err(|e| {
+                format!(
+                    "no AWS credentials in environment, and reading {}: {}",
+                    path.display(),
+                    e
+                )
+            })?;
+            Self::from_ini(&text, &profile).ok_or_else(|| {
+                format!(
+                    "no AWS credentials for profile '{}' in {}",
+                    profile,
+                    path.display()
+                )
+            })
+        }
+
+        /// Parses credentials for the profile from the shared credentials file
+        fn from_ini(text: &str, profile: &str) -> Option<Self> {
+            let mut in_profile = false;
+            let (mut access_key_id, mut secret_access_key, mut session_token) = (None, None, None);
+            for line in text.lines().map(str::trim) {
+                if line.starts_with('[') && line.ends_with(']') {
+                    in_profile = line[1..line.len() - 1].trim() == profile;
+                } else if let (true, Some((key, value))) = (in_profile, line.split_once('=')) {
+                    let value = Some(value.trim().to_string());
+                    match key.trim() {
+                        "aws_access_key_id" => access_key_id = value,
+                        "aws_secret_access_key" => secret_access_key = value,
+                        "aws_session_token" => session_token = value,
+                        _ => {}
+                    }
+                }
+            }
+            Some(AwsCredentials {
+                access_key_id: access_key_id?,
+                secret_access_key: secret_access_key?,
+                session_token,
+            })
+        }
+    }
+
+    /// Creates a SigV4-presigned url for connecting to the database, without the url scheme
+    fn presign(
+        creds: &AwsCredentials,
+        region: &str,
+        host: &str,
+        port: u16,
+        user: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> String {
+        let date = now.format("%Y%m%d").to_string();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let scope = format!("{}/{}/{}/aws4_request", date, region, SERVICE);
+        let endpoint = format!("{}:{}", host, port);
+
+        // query parameters, sorted by name
+        let mut params = vec![
+            ("Action", "connect".to_string()),
+            ("DBUser", user.to_string()),
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256".to_string()),
+            (
+                "X-Amz-Credential",
+                format!("{}/{}", creds.access_key_id, scope),
+            ),
+            ("X-Amz-Date", timestamp.clone()),
+            ("X-Amz-Expires", TOKEN_EXPIRES_SEC.to_string()),
+        ];
+        if let Some(token) = &creds.session_token {
+            params.push(("X-Amz-Security-Token", token.clone()));
+        }
+        params.push(("X-Amz-SignedHeaders", "host".to_string()));
+        let query = params
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, uri_encode(v)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let canonical_request = format!(
+            "GET\n/\n{}\nhost:{}\n\nhost\n{}",
+            query,
+            endpoint,
+            hex(&Sha256::digest(b""))
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = hmac_sha256(format!("AWS4{}", creds.secret_access_key).as_bytes(), &date);
+        for part in [region, SERVICE, "aws4_request"] {
+            key = hmac_sha256(&key, part);
+        }
+        let signature = hex(&hmac_sha256(&key, &string_to_sign));
+        format!("{}/?{}&X-Amz-Signature={}", endpoint, query, signature)
+    }
+
+    fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().fold(String::new(), |mut s, b| {
+            let _ = write!(s, "{:02x}", b);
+            s
+        })
+    }
+
+    /// Percent-encodes everything except unreserved characters (RFC 3986)
+    fn uri_encode(s: &str) -> String {
+        s.bytes().fold(String::new(), |mut out, b| {
+            match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    out.push(b as char)
+                }
+                _ => {
+                    let _ = write!(out, "%{:02X}", b);
+                }
+            }
+            out
+        })
+    }
+}
+
+/// Azure AD (Entra ID) authentication for Azure Database for PostgreSQL
+///
+/// If a tenant id is configured, and the environment variables `AZURE_CLIENT_ID`
+/// and `AZURE_CLIENT_SECRET` are set, the token is requested for that service principal.
+/// Otherwise, credentials are tried in this order: the environment variables
+/// `AZURE_TENANT_ID`, `AZURE_CLIENT_ID`, and `AZURE_CLIENT_SECRET`; managed identity;
+/// and the Azure CLI.
+#[cfg(feature = "azure")]
+pub(crate) mod azure {
+    use azure_core::auth::{TokenCredential, TokenResponse};
+    use azure_identity::token_credentials::{
+        ClientSecretCredential, DefaultAzureCredential, TokenCredentialOptions,
+    };
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    /// audience of access tokens for Azure Database for PostgreSQL
+    pub(crate) const DEFAULT_AUDIENCE: &str = "https://ossrdbms-aad.database.windows.net";
+    /// a cached token is renewed when it is this close to expiring
+    const RENEW_BEFORE_EXPIRY_SEC: i64 = 300;
+    /// max time to wait for a token. (Without this, a request for a managed
+    /// identity token could wait a long time on hosts that are not in Azure)
+    const TOKEN_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+    #[derive(Clone)]
+    pub(crate) struct AzureAd {
+        credential: Arc<dyn TokenCredential>,
+        audience: String,
+        /// most recent token and its expiration time, shared by all connections of the pool
+        token: Arc<Mutex<Option<TokenResponse>>>,
+    }
+
+    impl std::fmt::Debug for AzureAd {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("AzureAd")
+                .field("audience", &self.audience)
+                .finish()
+        }
+    }
+
+    impl AzureAd {
+        pub(crate) fn new(tenant_id: Option<String>, audience: Option<String>) -> Self {
+            let credential: Arc<dyn TokenCredential> = match (
+                tenant_id,
+                std::env::var("AZURE_CLIENT_ID"),
+                std::env::var("AZURE_CLIENT_SECRET"),
+            ) {
+                (Some(tenant_id), Ok(client_id), Ok(client_secret)) => {
+                    Arc::new(ClientSecretCredential::new(
+                        tenant_id,
+                        client_id,
+                        client_secret,
+                        TokenCredentialOptions::default(),
+                    ))
+                }
+                _ => Arc::new(DefaultAzureCredential::default()),
+            };
+            Self {
+                credential,
+                audience: audience.unwrap_or_else(|| DEFAULT_AUDIENCE.to_string()),
+                token: Arc::new(Mutex::new(None)),
+            }
+        }
+
+        /// Returns an access token, which is used as the connection password.
+        /// Tokens are cached, and renewed shortly before they expire.
+        pub(crate) async fn access_token(&self) -> Result<String, String> {
+            let mut cached = self.token.lock().await;
+            if let Some(response) = cached.as_ref() {
+                if response.expires_on - Duration::seconds(RENEW_BEFORE_EXPIRY_SEC) > Utc::now() {
+                    return Ok(response.token.secret().to_string());
+                }
+            }
+            let response = tokio::time::timeout(
+                TOKEN_REQUEST_TIMEOUT,
+                self.credential.get_token(&self.audience),
+            )
+            .await
+            .map_err(|_| "timed out requesting azure-ad access token".to_string())?
+            .map_err(|e| {
+                // the error's message is generic, so include the underlying causes
+                let mut msg = format!("getting azure-ad access token: {}", e);
+                let mut source = std::error::Error::source(&e);
+                while let Some(cause) = source {
+                    msg.push_str(&format!(": {}", cause));
+                    source = cause.source();
+                }
+                msg
+            })?;
+            let token = response.token.secret().to_string();
+            *cached = Some(response);
+            Ok(token)
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Calls of stored procedures.
+//!
+//! `CALL proc($1, ...)` returns one row with the values of the procedure's OUT and INOUT
+//! parameters, if it has any; they are returned as a map of parameter name to value.
+//! A procedure returns result sets as cursors, in refcursor parameters. A cursor is closed
+//! at the end of its transaction, so the call runs in a transaction, in which the rows of
+//! each cursor are fetched before it is committed. A procedure called in a transaction
+//! block cannot COMMIT or ROLLBACK itself.
+//!
+use crate::{
+    copy::quote_identifier,
+    error::DbError,
+    ext,
+    results::{self, ReadError, ResultOptions},
+    types,
+};
+use tokio_postgres::{
+    types::{ToSql, Type},
+    Client,
+};
+
+/// the CALL statement of a procedure, with a placeholder for each of its arguments
+pub(crate) fn call_statement(procedure: &str, num_params: usize) -> Result<String, String> {
+    let name = procedure
+        .split('.')
+        .map(quote_identifier)
+        .collect::<Option<Vec<_>>>()
+        .filter(|parts| parts.len() <= 2)
+        .ok_or_else(|| format!("invalid procedure name '{}'", procedure))?
+        .join(".");
+    let placeholders = (1..=num_params)
+        .map(|i| format!("${}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok(format!("CALL {}({})", name, placeholders))
+}
+
+/// Encoded outputs of a call
+#[derive(Default)]
+pub(crate) struct Called {
+    pub(crate) outputs: Vec<u8>,
+    pub(crate) result_sets: Vec<ext::ResultSet>,
+}
+
+/// Run the CALL statement in a transaction, and fetch the rows of the cursors it returns.
+/// Each result set is limited by max_result_rows and max_result_bytes. The transaction is
+/// rolled back if the call, or a fetch, fails
+pub(crate) async fn run(
+    client: &mut Client,
+    statement: &str,
+    params: &[&(dyn ToSql + Sync)],
+    options: ResultOptions,
+) -> Result<Called, ReadError> {
+    let tx = client.transaction().await.map_err(ReadError::Db)?;
+    let rows = tx.query(statement, params).await.map_err(ReadError::Db)?;
+    let row = match rows.first() {
+        Some(row) => row,
+        None => {
+            tx.commit().await.map_err(ReadError::Db)?;
+            return Ok(Called::default());
+        }
+    };
+    let mut called = Called {
+        outputs: results::encode_map(row, options).map_err(ReadError::Result)?,
+        ..Default::default()
+    };
+    for (i, column) in row.columns().iter().enumerate() {
+        if *column.type_() != Type::REFCURSOR {
+            continue;
+        }
+        // a NULL (or empty) refcursor is a parameter the procedure did not open a cursor for
+        let cursor = match types::text_value(row, i) {
+            Ok(cursor) => match cursor.and_then(quote_identifier) {
+                Some(cursor) => cursor,
+                None => continue,
+            },
+            Err(e) => return Err(ReadError::Result(DbError::Encoding(e.to_string()))),
+        };
+        let stream = tx
+            .query_raw(
+                format!("FETCH ALL FROM {}", cursor).as_str(),
+                std::iter::empty::<&(dyn ToSql + Sync)>(),
+            )
+            .await
+            .map_err(ReadError::Db)?;
+        futures::pin_mut!(stream);
+        // a result set is returned whole, or the call fails
+        let mut enc = results::RowEncoder::new(ResultOptions {
+            partial_on_error: false,
+            ..options
+        });
+        results::read_rows(stream, &mut enc, None).await?;
+        let fetched = enc.finish();
+        if let Some(e) = fetched.error {
+            return Err(ReadError::Result(e));
+        }
+        called.result_sets.push(ext::ResultSet {
+            name: column.name().to_string(),
+            num_rows: fetched.num_rows,
+            columns: fetched.columns,
+            rows: fetched.rows,
+        });
+    }
+    tx.commit().await.map_err(ReadError::Db)?;
+    Ok(called)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statement() {
+        assert_eq!(
+            call_statement("public.transfer", 3).unwrap(),
+            r#"CALL "public"."transfer"($1, $2, $3)"#
+        );
+        assert_eq!(call_statement("Refresh", 0).unwrap(), r#"CALL "Refresh"()"#);
+        assert!(call_statement("a.b.c", 0).is_err());
+        assert!(call_statement("", 0).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+//! Schema introspection.
+//!
+//! `list_tables`, `list_columns`, and `list_indexes` query `pg_catalog` on a connection
+//! from the link's pool, so they see the schemas of the link's `search_path`. Names are
+//! exact, as stored in the catalog: they are compared as text, not parsed as sql
+//! identifiers, so they do not need to be quoted. Without a schema, a table is the first
+//! one with the name in the search path, as in a query.
+//!
+//! `server_info` reads the server's version and the database's extensions.
+//!
+use crate::{
+    error::DbError,
+    ext::{ColumnInfo, ExtensionInfo, IndexInfo, ServerInfo, TableInfo},
+    PooledConnection,
+};
+
+/// tables, views, materialized views, and foreign tables of a schema, or of the search path
+const TABLES: &str = "SELECT n.nspname::text, c.relname::text, \
+    CASE c.relkind WHEN 'r' THEN 'table' WHEN 'p' THEN 'partitioned table' \
+    WHEN 'v' THEN 'view' WHEN 'm' THEN 'materialized view' ELSE 'foreign table' END \
+    FROM pg_catalog.pg_class c JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+    WHERE c.relkind IN ('r', 'p', 'v', 'm', 'f') \
+    AND (n.nspname = $1 OR ($1 IS NULL AND n.nspname = ANY (pg_catalog.current_schemas(false)))) \
+    ORDER BY n.nspname, c.relname";
+
+/// oid of a table in a schema, or the first in the search path
+const RELATION: &str = "SELECT c.oid FROM pg_catalog.pg_class c \
+    JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+    WHERE c.relname = $2 AND c.relkind IN ('r', 'p', 'v', 'm', 'f') \
+    AND (n.nspname = $1 OR ($1 IS NULL AND n.nspname = ANY (pg_catalog.current_schemas(false)))) \
+    ORDER BY pg_catalog.array_position(pg_catalog.current_schemas(false), n.nspname) LIMIT 1";
+
+/// columns of a table, in order
+const COLUMNS: &str = "SELECT a.attname::text, pg_catalog.format_type(a.atttypid, a.atttypmod), \
+    NOT a.attnotnull, pg_catalog.pg_get_expr(d.adbin, d.adrelid), a.attnum::int4, a.atttypid \
+    FROM pg_catalog.pg_attribute a \
+    LEFT JOIN pg_catalog.pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum \
+    WHERE a.attrelid = $1 AND a.attnum > 0 AND NOT a.attisdropped \
+    ORDER BY a.attnum";
+
+/// indexes of a table, with their key columns in order
+const INDEXES: &str = "SELECT i.relname::text, \
+    ARRAY(SELECT a.attname::text FROM pg_catalog.unnest(x.indkey::int2[]) WITH ORDINALITY k(attnum, n) \
+    JOIN pg_catalog.pg_attribute a ON a.attrelid = x.indrelid AND a.attnum = k.attnum ORDER BY k.n), \
+    x.indisunique, x.indisprimary, pg_catalog.pg_get_indexdef(x.indexrelid) \
+    FROM pg_catalog.pg_index x JOIN pg_catalog.pg_class i ON i.oid = x.indexrelid \
+    WHERE x.indrelid = $1 \
+    ORDER BY i.relname";
+
+/// version of the server, and the extensions of the database
+const SERVER_INFO: &str = "SELECT pg_catalog.current_setting('server_version'), \
+    pg_catalog.current_setting('server_version_num')::int4, \
+    ARRAY(SELECT e.extname::text FROM pg_catalog.pg_extension e ORDER BY e.extname), \
+    ARRAY(SELECT e.extversion FROM pg_catalog.pg_extension e ORDER BY e.extname)";
+
+/// Version of the server, and the extensions installed in the database
+pub(crate) async fn server_info(conn: &PooledConnection<'_>) -> Result<ServerInfo, DbError> {
+    let row = conn.query_one(SERVER_INFO, &[]).await?;
+    let version_num = row.get::<_, i32>(1) as u32;
+    let names: Vec<String> = row.get(2);
+    let versions: Vec<String> = row.get(3);
+    Ok(ServerInfo {
+        version: row.get(0),
+        version_num,
+        major_version: version_num / 10000,
+        extensions: names
+            .into_iter()
+            .zip(versions)
+            .map(|(name, version)| ExtensionInfo { name, version })
+            .collect(),
+        error: None,
+    })
+}
+
+/// Tables of the schema, or of the schemas in the search path
+pub(crate) async fn tables(
+    conn: &PooledConnection<'_>,
+    schema: Option<&str>,
+) -> Result<Vec<TableInfo>, DbError> {
+    Ok(conn
+        .query(TABLES, &[&schema])
+        .await?
+        .iter()
+        .map(|row| TableInfo {
+            schema: row.get(0),
+            name: row.get(1),
+            kind: row.get(2),
+        })
+        .collect())
+}
+
+/// Columns of the table
+pub(crate) async fn columns(
+    conn: &PooledConnection<'_>,
+    schema: Option<&str>,
+    table: &str,
+) -> Result<Vec<ColumnInfo>, DbError> {
+    let oid = relation(conn, schema, table).await?;
+    Ok(conn
+        .query(COLUMNS, &[&oid])
+        .await?
+        .iter()
+        .map(|row| ColumnInfo {
+            name: row.get(0),
+            data_type: row.get(1),
+            nullable: row.get(2),
+            default: row.get(3),
+            ordinal: row.get::<_, i32>(4) as u32,
+            oid: row.get(5),
+        })
+        .collect())
+}
+
+/// Indexes of the table
+pub(crate) async fn indexes(
+    conn: &PooledConnection<'_>,
+    schema: Option<&str>,
+    table: &str,
+) -> Result<Vec<IndexInfo>, DbError> {
+    let oid = relation(conn, schema, table).await?;
+    Ok(conn
+        .query(INDEXES, &[&oid])
+        .await?
+        .iter()
+        .map(|row| IndexInfo {
+            name: row.get(0),
+            columns: row.get(1),
+            unique: row.get(2),
+            primary: row.get(3),
+            definition: row.get(4),
+        })
+        .collect())
+}
+
+/// oid of the table. Returns NotFound if there is no such table
+async fn relation(
+    conn: &PooledConnection<'_>,
+    schema: Option<&str>,
+    table: &str,
+) -> Result<u32, DbError> {
+    match conn.query_opt(RELATION, &[&schema, &table]).await? {
+        Some(row) => Ok(row.get(0)),
+        None => Err(DbError::NotFound(match schema {
+            Some(schema) => format!("table '{}' not found in schema '{}'", table, schema),
+            None => format!("table '{}' not found in the search path", table),
+        })),
+    }
+}
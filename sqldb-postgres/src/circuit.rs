@@ -0,0 +1,178 @@
+//! Circuit breaker of a link's pool, with circuit_breaker_failures.
+//!
+//! When the database is down, each statement waits for the pool's connection timeout before
+//! it fails. After `circuit_breaker_failures` consecutive failures to connect, within
+//! `circuit_breaker_window`, the circuit opens: for `circuit_breaker_cooldown`, statements
+//! fail with `CircuitOpen` without taking a connection. The first statement after the cooldown
+//! is a trial, while the others still fail: if it connects, the circuit closes, otherwise it
+//! opens for another cooldown. A trial that ends without connecting or failing to connect,
+//! for example because it timed out waiting for a permit, is replaced by another one after a
+//! cooldown. Waits for a connection of an exhausted pool are not failures.
+//!
+use crate::error::DbError;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Circuit breaker of a pool
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    /// consecutive failures that open the circuit
+    failures: u32,
+    /// time within which the failures open the circuit, from the first one
+    window: Duration,
+    /// time the circuit stays open, before a trial
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// connections are taken from the pool; `failures` have failed since `since`
+    Closed { failures: u32, since: Instant },
+    /// statements fail until the instant
+    Open { until: Instant },
+    /// a trial has been taking a connection since the instant, and the other statements fail
+    HalfOpen { since: Instant },
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(failures: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            failures,
+            window,
+            cooldown,
+            state: Mutex::new(State::Closed {
+                failures: 0,
+                since: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns CircuitOpen if the circuit is open, or if another statement is the trial
+    pub(crate) fn check(&self) -> Result<(), DbError> {
+        self.check_at(Instant::now())
+    }
+
+    fn check_at(&self, now: Instant) -> Result<(), DbError> {
+        let mut state = self.state.lock().unwrap();
+        let retry_at = match *state {
+            State::Closed { .. } => return Ok(()),
+            State::Open { until } => until,
+            State::HalfOpen { since } => since + self.cooldown,
+        };
+        if now >= retry_at {
+            *state = State::HalfOpen { since: now };
+            return Ok(());
+        }
+        let remaining = retry_at - now;
+        Err(DbError::CircuitOpen(format!(
+            "circuit breaker open after {} consecutive failures to connect to the database: \
+             retrying in {}ms",
+            self.failures,
+            remaining.as_millis()
+        )))
+    }
+
+    /// record a connection taken from the pool, which closes the circuit
+    pub(crate) fn success(&self) {
+        *self.state.lock().unwrap() = State::Closed {
+            failures: 0,
+            since: Instant::now(),
+        };
+    }
+
+    /// record a failure to connect to the database
+    pub(crate) fn failure(&self) {
+        self.failure_at(Instant::now())
+    }
+
+    fn failure_at(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        *state = match *state {
+            State::Closed { failures, since } => {
+                let (failures, since) =
+                    if failures == 0 || now.saturating_duration_since(since) > self.window {
+                        (1, now)
+                    } else {
+                        (failures + 1, since)
+                    };
+                if failures >= self.failures {
+                    State::Open {
+                        until: now + self.cooldown,
+                    }
+                } else {
+                    State::Closed { failures, since }
+                }
+            }
+            State::HalfOpen { .. } => State::Open {
+                until: now + self.cooldown,
+            },
+            // a statement that started before the circuit opened
+            open @ State::Open { .. } => open,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_and_closes() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(10), Duration::from_secs(5));
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        breaker.failure_at(start);
+        breaker.failure_at(secs(1));
+        assert!(breaker.check_at(secs(1)).is_ok());
+        breaker.failure_at(secs(2));
+        assert!(matches!(
+            breaker.check_at(secs(3)),
+            Err(DbError::CircuitOpen(_))
+        ));
+        // after the cooldown, one trial
+        assert!(breaker.check_at(secs(7)).is_ok());
+        assert!(breaker.check_at(secs(7)).is_err());
+        // the trial fails: open for another cooldown
+        breaker.failure_at(secs(8));
+        assert!(breaker.check_at(secs(12)).is_err());
+        assert!(breaker.check_at(secs(13)).is_ok());
+        breaker.success();
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn window() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(10), Duration::from_secs(5));
+        let start = Instant::now();
+        breaker.failure_at(start);
+        // the failures are not within the window
+        breaker.failure_at(start + Duration::from_secs(11));
+        assert!(breaker.check_at(start + Duration::from_secs(11)).is_ok());
+        breaker.failure_at(start + Duration::from_secs(12));
+        assert!(breaker.check_at(start + Duration::from_secs(12)).is_err());
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(10), Duration::from_secs(5));
+        breaker.failure();
+        breaker.success();
+        breaker.failure();
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn abandoned_trial() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(10), Duration::from_secs(5));
+        let start = Instant::now();
+        breaker.failure_at(start);
+        assert!(breaker.check_at(start + Duration::from_secs(5)).is_ok());
+        // the trial did not report: another one after a cooldown
+        assert!(breaker.check_at(start + Duration::from_secs(9)).is_err());
+        assert!(breaker.check_at(start + Duration::from_secs(10)).is_ok());
+    }
+}
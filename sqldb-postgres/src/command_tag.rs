@@ -0,0 +1,210 @@
+//! Command tags of statements, for ExecuteReturning.
+//!
+//! The server ends each statement with a command tag that says what ran, such as `INSERT 0 1`,
+//! `CREATE TABLE`, or `SET`. tokio-postgres only returns the number of rows of the tag, so the
+//! tag is read from the first keywords of the statement, after comments and parentheses, with
+//! the tokens of the read-only check. It is the server's tag, without the row count, for the
+//! common statements; for a statement that is not recognized, it is the first keyword.
+//!
+use crate::read_only::{tokens, Token};
+
+/// words before the object type of CREATE, ALTER, and DROP, which are not in its tag
+const MODIFIERS: [&str; 12] = [
+    "or",
+    "replace",
+    "unique",
+    "temp",
+    "temporary",
+    "unlogged",
+    "global",
+    "local",
+    "recursive",
+    "trusted",
+    "procedural",
+    "constraint",
+];
+
+/// Returns the command tag of the statement, such as `CREATE TABLE`, or an empty string if
+/// it has no keyword
+pub(crate) fn command_tag(statement: &str) -> String {
+    let tokens = tokens(statement);
+    let start = tokens
+        .iter()
+        .position(|t| *t != Token::Punct('('))
+        .unwrap_or(tokens.len());
+    let tokens = &tokens[start..];
+    let word = |i: usize| match tokens.get(i) {
+        Some(Token::Word(word)) => Some(word.as_str()),
+        _ => None,
+    };
+    let first = match word(0) {
+        Some(first) => first,
+        None => return String::new(),
+    };
+    match (first, word(1)) {
+        ("select" | "values" | "table", _) => "SELECT".into(),
+        ("with", _) => main_command(tokens).into(),
+        ("create" | "alter" | "drop", _) => object_command(first, tokens),
+        ("start", _) => "START TRANSACTION".into(),
+        ("end", _) => "COMMIT".into(),
+        ("abort", _) => "ROLLBACK".into(),
+        ("commit" | "rollback", Some("prepared")) | ("prepare", Some("transaction")) => {
+            format!("{} {}", first, word(1).unwrap_or_default()).to_uppercase()
+        }
+        ("set", Some("constraints")) => "SET CONSTRAINTS".into(),
+        ("declare", _) => "DECLARE CURSOR".into(),
+        ("close", _) => "CLOSE CURSOR".into(),
+        ("truncate", _) => "TRUNCATE TABLE".into(),
+        ("lock", _) => "LOCK TABLE".into(),
+        ("analyse", _) => "ANALYZE".into(),
+        ("refresh", _) => "REFRESH MATERIALIZED VIEW".into(),
+        ("security", _) => "SECURITY LABEL".into(),
+        ("import", _) => "IMPORT FOREIGN SCHEMA".into(),
+        ("discard", Some("temporary")) => "DISCARD TEMP".into(),
+        ("discard" | "deallocate", Some(what @ ("all" | "plans" | "sequences" | "temp"))) => {
+            format!("{} {}", first, what).to_uppercase()
+        }
+        _ => first.to_uppercase(),
+    }
+}
+
+/// The command of a WITH statement: the first SELECT, INSERT, UPDATE, DELETE, or MERGE
+/// outside of parentheses
+fn main_command(tokens: &[Token]) -> &'static str {
+    let mut depth = 0usize;
+    for token in tokens {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') => depth = depth.saturating_sub(1),
+            Token::Word(word) if depth == 0 => match word.as_str() {
+                "insert" => return "INSERT",
+                "update" => return "UPDATE",
+                "delete" => return "DELETE",
+                "merge" => return "MERGE",
+                "select" | "values" | "table" => return "SELECT",
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    "SELECT"
+}
+
+/// The tag of CREATE, ALTER, or DROP: the verb and the object type, such as `CREATE INDEX`
+fn object_command(verb: &str, tokens: &[Token]) -> String {
+    let words = tokens
+        .iter()
+        .skip(1)
+        .map(|t| match t {
+            Token::Word(word) => word.as_str(),
+            _ => "",
+        })
+        .skip_while(|w| MODIFIERS.contains(w))
+        .collect::<Vec<_>>();
+    let len = match words.as_slice() {
+        ["foreign", "data", ..] | ["text", "search", ..] => 3,
+        ["materialized" | "foreign" | "event" | "access" | "large" | "default", ..]
+        | ["operator", "class" | "family", ..]
+        | ["user", "mapping", ..] => 2,
+        [first, ..] if !first.is_empty() => 1,
+        _ => 0,
+    };
+    let object = match &words[..len.min(words.len())] {
+        // CREATE USER and CREATE GROUP create roles
+        ["user" | "group"] => "role".to_string(),
+        object => object.join(" "),
+    };
+    // CREATE TABLE AS and CREATE MATERIALIZED VIEW run a query
+    if verb == "create" && matches!(object.as_str(), "table" | "materialized view") {
+        let mut depth = 0usize;
+        for token in tokens {
+            match token {
+                Token::Punct('(') => depth += 1,
+                Token::Punct(')') => depth = depth.saturating_sub(1),
+                Token::Word(word) if depth == 0 && word == "as" => return "SELECT".into(),
+                _ => {}
+            }
+        }
+    }
+    if object.is_empty() {
+        verb.to_uppercase()
+    } else {
+        format!("{} {}", verb, object).to_uppercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags() {
+        for (statement, tag) in [
+            ("select 1", "SELECT"),
+            ("(select 1) union (select 2)", "SELECT"),
+            ("insert into t values (1)", "INSERT"),
+            ("delete from t where a = 1", "DELETE"),
+            (
+                "merge into t using u on t.a = u.a when matched then delete",
+                "MERGE",
+            ),
+            (
+                "with a as (select 1), b as (delete from t returning *) update u set x = 1",
+                "UPDATE",
+            ),
+            (
+                "with a as (insert into t values (1) returning *) select * from a",
+                "SELECT",
+            ),
+            ("SET search_path = public", "SET"),
+            ("set local lock_timeout = '1s'", "SET"),
+            ("SET CONSTRAINTS ALL DEFERRED", "SET CONSTRAINTS"),
+            ("reset all", "RESET"),
+            ("DO $$ begin perform 1; end $$", "DO"),
+            (
+                "create table t (a int generated always as (1) stored)",
+                "CREATE TABLE",
+            ),
+            ("create temp table if not exists t (a int)", "CREATE TABLE"),
+            ("create table t as select 1", "SELECT"),
+            ("create materialized view v as select 1", "SELECT"),
+            (
+                "create unique index concurrently i on t (a)",
+                "CREATE INDEX",
+            ),
+            (
+                "create or replace function f() returns int as $$ select 1 $$ language sql",
+                "CREATE FUNCTION",
+            ),
+            ("create user u", "CREATE ROLE"),
+            (
+                "create foreign data wrapper w",
+                "CREATE FOREIGN DATA WRAPPER",
+            ),
+            (
+                "create text search configuration c (copy = english)",
+                "CREATE TEXT SEARCH CONFIGURATION",
+            ),
+            ("alter table t add column b int", "ALTER TABLE"),
+            (
+                "alter default privileges grant select on tables to u",
+                "ALTER DEFAULT PRIVILEGES",
+            ),
+            (
+                "drop materialized view if exists v",
+                "DROP MATERIALIZED VIEW",
+            ),
+            ("-- comment\n/* block */ truncate t", "TRUNCATE TABLE"),
+            ("start transaction", "START TRANSACTION"),
+            ("end", "COMMIT"),
+            ("commit prepared 'x'", "COMMIT PREPARED"),
+            ("rollback to savepoint s", "ROLLBACK"),
+            ("discard temporary", "DISCARD TEMP"),
+            ("declare c cursor for select 1", "DECLARE CURSOR"),
+            ("vacuum t", "VACUUM"),
+            (";", ""),
+        ] {
+            assert_eq!(command_tag(statement), tag, "{}", statement);
+        }
+    }
+}
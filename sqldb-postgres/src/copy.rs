@@ -0,0 +1,227 @@
+//! Bulk loading and export with COPY.
+//!
+//! The actor's data is sent to the server as the input of `COPY table FROM STDIN`.
+//! The table and column names are quoted identifiers, so they are case-sensitive
+//! and cannot inject sql. COPY is a single statement: if any row is rejected,
+//! no rows are copied.
+//!
+//! The output of `COPY (query) TO STDOUT` is returned to the actor in chunks,
+//! which are read from the server as the actor asks for them, so a large export
+//! is not held in memory.
+//!
+use crate::ext::{CopyInRequest, CopyOutRequest};
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, TryStreamExt};
+use std::pin::Pin;
+use tokio_postgres::{Client, CopyOutStream};
+
+/// size of the chunks of copy_out, if the request does not set it
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Returns the COPY statement for the request
+pub(crate) fn copy_in_statement(req: &CopyInRequest) -> Result<String, String> {
+    let table = table_name(&req.table)?;
+    let columns = if req.columns.is_empty() {
+        String::new()
+    } else {
+        let columns = req
+            .columns
+            .iter()
+            .map(|c| quote_identifier(c).ok_or_else(|| format!("invalid column name '{}'", c)))
+            .collect::<Result<Vec<_>, _>>()?;
+        format!(" ({})", columns.join(", "))
+    };
+    let options = copy_options(&req.format, req.delimiter.as_deref(), req.header)?;
+    Ok(format!(
+        "COPY {}{} FROM STDIN WITH ({})",
+        table, columns, options
+    ))
+}
+
+/// Returns the COPY statement for the request
+pub(crate) fn copy_out_statement(req: &CopyOutRequest) -> Result<String, String> {
+    let query = req.query.trim().trim_end_matches(';').trim_end();
+    if query.is_empty() {
+        return Err("query must not be empty".into());
+    }
+    let options = copy_options(&req.format, req.delimiter.as_deref(), req.header)?;
+    Ok(format!("COPY ({}) TO STDOUT WITH ({})", query, options))
+}
+
+/// options of a COPY statement
+fn copy_options(format: &str, delimiter: Option<&str>, header: bool) -> Result<String, String> {
+    let mut options = Vec::new();
+    let fmt = format.trim().to_ascii_lowercase();
+    match fmt.as_str() {
+        "csv" | "text" | "binary" => options.push(format!("FORMAT {}", fmt)),
+        _ => {
+            return Err(format!(
+                "invalid format '{}'. Expected 'csv', 'text', or 'binary'",
+                format
+            ))
+        }
+    }
+    if let Some(delimiter) = delimiter {
+        if fmt == "binary" {
+            return Err("delimiter is not allowed with the binary format".into());
+        }
+        let mut chars = delimiter.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c != '\0' && c != '\n' && c != '\r' => {
+                options.push(format!("DELIMITER '{}'", delimiter.replace('\'', "''")))
+            }
+            _ => {
+                return Err(format!(
+                    "invalid delimiter '{}'. Expected a single character",
+                    delimiter
+                ))
+            }
+        }
+    }
+    if header {
+        if fmt != "csv" {
+            return Err("header is only allowed with the csv format".into());
+        }
+        options.push("HEADER true".into());
+    }
+    Ok(options.join(", "))
+}
+
+/// Copy the data into the table, and return the number of rows copied.
+/// If the copy fails, it is aborted, and no rows are copied.
+pub(crate) async fn copy_in(
+    client: &Client,
+    statement: &str,
+    data: &[u8],
+) -> Result<u64, tokio_postgres::Error> {
+    let sink = client.copy_in::<_, Bytes>(statement).await?;
+    futures::pin_mut!(sink);
+    // if the sink is dropped before it is finished, the copy is aborted
+    sink.send(Bytes::copy_from_slice(data)).await?;
+    sink.finish().await
+}
+
+/// Output of a COPY ... TO STDOUT statement, read in chunks
+pub(crate) struct CopyOut {
+    stream: Pin<Box<CopyOutStream>>,
+    /// data read from the stream that has not been returned
+    buf: BytesMut,
+    chunk_size: usize,
+}
+
+impl CopyOut {
+    pub(crate) fn new(stream: CopyOutStream, chunk_size: usize) -> Self {
+        Self {
+            stream: Box::pin(stream),
+            buf: BytesMut::new(),
+            chunk_size,
+        }
+    }
+
+    /// Returns the next chunk of chunk_size bytes, or the rest of the output,
+    /// and true if it is the end of the output
+    pub(crate) async fn next_chunk(&mut self) -> Result<(Vec<u8>, bool), tokio_postgres::Error> {
+        while self.buf.len() < self.chunk_size {
+            match self.stream.as_mut().try_next().await? {
+                Some(data) => self.buf.extend_from_slice(&data),
+                None => return Ok((self.buf.split().to_vec(), true)),
+            }
+        }
+        Ok((self.buf.split_to(self.chunk_size).to_vec(), false))
+    }
+}
+
+/// quote a table name, which may be qualified with its schema ("schema.table")
+pub(crate) fn table_name(name: &str) -> Result<String, String> {
+    let parts = name
+        .split('.')
+        .map(quote_identifier)
+        .collect::<Option<Vec<_>>>()
+        .filter(|parts| parts.len() <= 2)
+        .ok_or_else(|| format!("invalid table name '{}'", name))?;
+    Ok(parts.join("."))
+}
+
+/// quote an identifier, or return None if it is empty or contains a nul character
+pub(crate) fn quote_identifier(name: &str) -> Option<String> {
+    if name.is_empty() || name.contains('\0') {
+        None
+    } else {
+        Some(format!("\"{}\"", name.replace('"', "\"\"")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statement() {
+        let req = CopyInRequest {
+            table: "public.items".into(),
+            columns: vec!["id".into(), "Name".into()],
+            format: "CSV".into(),
+            delimiter: Some("|".into()),
+            header: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            copy_in_statement(&req).unwrap(),
+            r#"COPY "public"."items" ("id", "Name") FROM STDIN WITH (FORMAT csv, DELIMITER '|', HEADER true)"#
+        );
+
+        let req = CopyInRequest {
+            table: r#"items"; drop table items; --"#.into(),
+            format: "binary".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            copy_in_statement(&req).unwrap(),
+            r#"COPY "items""; drop table items; --" FROM STDIN WITH (FORMAT binary)"#
+        );
+
+        for req in [
+            CopyInRequest {
+                table: "a.b.c".into(),
+                format: "csv".into(),
+                ..Default::default()
+            },
+            CopyInRequest {
+                table: "items".into(),
+                format: "json".into(),
+                ..Default::default()
+            },
+            CopyInRequest {
+                table: "items".into(),
+                format: "binary".into(),
+                delimiter: Some(",".into()),
+                ..Default::default()
+            },
+            CopyInRequest {
+                table: "items".into(),
+                format: "csv".into(),
+                delimiter: Some(",,".into()),
+                ..Default::default()
+            },
+            CopyInRequest {
+                table: "items".into(),
+                format: "text".into(),
+                header: true,
+                ..Default::default()
+            },
+        ] {
+            assert!(copy_in_statement(&req).is_err(), "{:?}", req);
+        }
+
+        let req = CopyOutRequest {
+            query: "select * from items;".into(),
+            format: "csv".into(),
+            header: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            copy_out_statement(&req).unwrap(),
+            "COPY (select * from items) TO STDOUT WITH (FORMAT csv, HEADER true)"
+        );
+    }
+}
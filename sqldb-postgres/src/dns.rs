@@ -0,0 +1,159 @@
+//! Addresses of the database hosts, for dns_refresh.
+//!
+//! tokio-postgres resolves a host name each time the pool opens a connection, so new
+//! connections go to the host's current address. A connection in the pool stays connected
+//! to the address it was opened with, and after a failover that moves the name to another
+//! address, it is only dropped once it fails. With `dns_refresh`, the names are resolved
+//! again, at most once per `dns_refresh` seconds, when a connection is taken from the pool,
+//! and if their addresses have changed, the connections opened before are dropped, so that
+//! the pool reconnects to the new address.
+//!
+use crate::log_level::{debug, info};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio_postgres::config::Host;
+
+/// Addresses that the host names of a pool resolve to
+pub(crate) struct DnsRefresh {
+    /// host names and ports, without ip addresses and unix sockets
+    names: Vec<(String, u16)>,
+    /// min time between resolutions
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// sorted addresses of the last resolution, if there was one
+    addrs: Option<Vec<SocketAddr>>,
+    /// number of times the addresses have changed
+    generation: u64,
+    /// time of the last resolution
+    resolved_at: Option<Instant>,
+}
+
+impl DnsRefresh {
+    /// Resolves the host names of the config every `interval`. Returns None if the config
+    /// only has ip addresses and unix sockets
+    pub(crate) fn new(config: &tokio_postgres::Config, interval: Duration) -> Option<Self> {
+        let ports = config.get_ports();
+        let names = config
+            .get_hosts()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, host)| match host {
+                Host::Tcp(name) if name.parse::<IpAddr>().is_err() => {
+                    let port = match ports {
+                        [port] => *port,
+                        ports => ports.get(i).copied().unwrap_or(5432),
+                    };
+                    Some((name.clone(), port))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return None;
+        }
+        Some(Self {
+            names,
+            interval,
+            state: Default::default(),
+        })
+    }
+
+    /// The generation of the addresses: it is incremented each time they change. The names are
+    /// resolved again if they were last resolved more than `interval` ago. If they cannot
+    /// be resolved, the previous addresses are kept
+    pub(crate) async fn generation(&self) -> u64 {
+        {
+            let mut state = self.state.lock().unwrap();
+            match state.resolved_at {
+                Some(at) if at.elapsed() < self.interval => return state.generation,
+                // other checks use the current addresses while they are resolved
+                _ => state.resolved_at = Some(Instant::now()),
+            }
+        }
+        let mut addrs = Vec::new();
+        for (name, port) in self.names.iter() {
+            match tokio::net::lookup_host((name.as_str(), *port)).await {
+                Ok(resolved) => addrs.extend(resolved),
+                Err(e) => {
+                    debug!("dns_refresh: resolving '{}': {}", name, e);
+                    return self.state.lock().unwrap().generation;
+                }
+            }
+        }
+        self.update(addrs)
+    }
+
+    /// Records the resolved addresses, and returns the generation
+    fn update(&self, mut addrs: Vec<SocketAddr>) -> u64 {
+        addrs.sort_unstable();
+        addrs.dedup();
+        let mut state = self.state.lock().unwrap();
+        match &state.addrs {
+            Some(previous) if *previous != addrs => {
+                info!(
+                    "dns_refresh: the addresses of the database hosts changed to [{}]; \
+                     reconnecting",
+                    addrs
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                state.generation += 1;
+            }
+            _ => {}
+        }
+        state.addrs = Some(addrs);
+        state.generation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn names() {
+        let config = tokio_postgres::Config::from_str(
+            "postgresql://user@db.example.com:5433,10.0.0.2:5434,standby:5435/db",
+        )
+        .unwrap();
+        let dns = DnsRefresh::new(&config, Duration::ZERO).expect("names");
+        assert_eq!(
+            dns.names,
+            vec![
+                ("db.example.com".to_string(), 5433),
+                ("standby".to_string(), 5435)
+            ]
+        );
+        let config = tokio_postgres::Config::from_str("postgresql://user@10.0.0.2/db").unwrap();
+        assert!(DnsRefresh::new(&config, Duration::ZERO).is_none());
+    }
+
+    #[tokio::test]
+    async fn generation() {
+        let config = tokio_postgres::Config::from_str("postgresql://user@localhost/db").unwrap();
+        let dns = DnsRefresh::new(&config, Duration::from_secs(60)).expect("names");
+        assert_eq!(dns.generation().await, 0);
+        let a = addr("10.0.0.1:5432");
+        let b = addr("10.0.0.2:5432");
+        assert_eq!(dns.update(vec![a, b]), 1);
+        // the same addresses, in another order
+        assert_eq!(dns.update(vec![b, a]), 1);
+        assert_eq!(dns.update(vec![b]), 2);
+        // not resolved again within the interval
+        assert_eq!(dns.generation().await, 2);
+    }
+}
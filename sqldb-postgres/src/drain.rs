@@ -0,0 +1,92 @@
+//! Draining of the provider's statements at shutdown.
+//!
+//! Each `Execute`, `Fetch`, `ExecuteParams`, and `FetchParams` call is counted while it runs.
+//! At shutdown, the provider stops accepting calls, and waits for the running calls to
+//! complete, for up to the links' `shutdown_grace_period`, before it closes the pools.
+//!
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// Counter of the provider's running calls
+#[derive(Clone, Default)]
+pub(crate) struct Drain {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// true after shutdown has started
+    closed: AtomicBool,
+    running: AtomicUsize,
+    /// notified when the last running call completes
+    idle: Notify,
+}
+
+/// A running call, which is counted until it is dropped
+pub(crate) struct Call {
+    inner: Arc<Inner>,
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        if self.inner.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inner.idle.notify_one();
+        }
+    }
+}
+
+impl Drain {
+    /// Start a call. Returns None if the provider is shutting down
+    pub(crate) fn start(&self) -> Option<Call> {
+        self.inner.running.fetch_add(1, Ordering::AcqRel);
+        let call = Call {
+            inner: self.inner.clone(),
+        };
+        if self.inner.closed.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(call)
+    }
+
+    /// Stop accepting calls, and wait up to `grace` for the running calls to complete.
+    /// Returns the number of calls still running
+    pub(crate) async fn close(&self, grace: Duration) -> usize {
+        self.inner.closed.store(true, Ordering::Release);
+        let _ = tokio::time::timeout(grace, async {
+            while self.inner.running.load(Ordering::Acquire) > 0 {
+                self.inner.idle.notified().await;
+            }
+        })
+        .await;
+        self.inner.running.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn close() {
+        let drain = Drain::default();
+        let call = drain.start().expect("call");
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(call);
+        });
+        assert_eq!(drain.close(Duration::from_secs(5)).await, 0);
+        release.await.unwrap();
+        assert!(drain.start().is_none());
+
+        // a call that does not complete within the grace period is abandoned
+        let drain = Drain::default();
+        let _call = drain.start().expect("call");
+        assert_eq!(drain.close(Duration::from_millis(50)).await, 1);
+    }
+}
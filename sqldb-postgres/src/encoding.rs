@@ -0,0 +1,205 @@
+//! Encoding of result rows as JSON or MessagePack.
+//!
+//! Rows are always encoded as CBOR first, then converted, so that the conversion
+//! of each column type is the same for all encodings. In JSON, byte strings are
+//! base64 strings, and floats that are NaN or infinite are null.
+//!
+use minicbor::{data::Type as CborType, Decoder};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Encoding of the rows of query results
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ResultEncoding {
+    /// CBOR, as specified by wasmcloud:sqldb
+    #[default]
+    Cbor,
+    /// JSON text
+    Json,
+    /// MessagePack
+    Msgpack,
+}
+
+impl ResultEncoding {
+    /// name of the encoding, as reported to actors
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ResultEncoding::Cbor => "cbor",
+            ResultEncoding::Json => "json",
+            ResultEncoding::Msgpack => "msgpack",
+        }
+    }
+
+    /// null, in the encoding
+    pub(crate) fn null(&self) -> Vec<u8> {
+        match self {
+            ResultEncoding::Cbor => vec![0xf6],
+            ResultEncoding::Json => b"null".to_vec(),
+            ResultEncoding::Msgpack => vec![0xc0],
+        }
+    }
+}
+
+/// Convert a cbor data item to a json value
+pub(crate) fn cbor_to_json(cbor: &[u8]) -> Result<Value, String> {
+    json_value(&mut Decoder::new(cbor)).map_err(|e| e.to_string())
+}
+
+fn json_value(d: &mut Decoder<'_>) -> Result<Value, minicbor::decode::Error> {
+    Ok(match d.datatype()? {
+        CborType::Bool => Value::Bool(d.bool()?),
+        CborType::Null | CborType::Undefined => {
+            d.skip()?;
+            Value::Null
+        }
+        CborType::U8 | CborType::U16 | CborType::U32 | CborType::U64 => Value::from(d.u64()?),
+        CborType::I8 | CborType::I16 | CborType::I32 | CborType::I64 => Value::from(d.i64()?),
+        CborType::F16 | CborType::F32 | CborType::F64 => {
+            let f = match d.datatype()? {
+                CborType::F16 => d.f16()? as f64,
+                CborType::F32 => d.f32()? as f64,
+                _ => d.f64()?,
+            };
+            // NaN and infinities are not json numbers
+            serde_json::Number::from_f64(f)
+                .map(Value::Number)
+                .unwrap_or(Value::Null)
+        }
+        CborType::Bytes => Value::String(base64::encode(d.bytes()?)),
+        CborType::String => Value::String(d.str()?.to_string()),
+        CborType::Array => {
+            let len = d.array()?.unwrap_or_default();
+            let mut values = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                values.push(json_value(d)?);
+            }
+            Value::Array(values)
+        }
+        CborType::Map => {
+            let len = d.map()?.unwrap_or_default();
+            let mut map = serde_json::Map::new();
+            for _ in 0..len {
+                let key = d.str()?.to_string();
+                map.insert(key, json_value(d)?);
+            }
+            Value::Object(map)
+        }
+        t => {
+            return Err(minicbor::decode::Error::Message(unsupported(t)));
+        }
+    })
+}
+
+/// Convert a cbor data item to msgpack, appended to `out`
+pub(crate) fn cbor_to_msgpack(cbor: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+    msgpack_value(&mut Decoder::new(cbor), out).map_err(|e| e.to_string())
+}
+
+fn msgpack_value(d: &mut Decoder<'_>, out: &mut Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    use rmp::encode;
+    match d.datatype()? {
+        CborType::Bool => encode::write_bool(out, d.bool()?)?,
+        CborType::Null | CborType::Undefined => {
+            d.skip()?;
+            encode::write_nil(out)?
+        }
+        CborType::U8 | CborType::U16 | CborType::U32 | CborType::U64 => {
+            encode::write_uint(out, d.u64()?)?;
+        }
+        CborType::I8 | CborType::I16 | CborType::I32 | CborType::I64 => {
+            encode::write_sint(out, d.i64()?)?;
+        }
+        CborType::F16 => encode::write_f32(out, d.f16()?)?,
+        CborType::F32 => encode::write_f32(out, d.f32()?)?,
+        CborType::F64 => encode::write_f64(out, d.f64()?)?,
+        CborType::Bytes => encode::write_bin(out, d.bytes()?)?,
+        CborType::String => encode::write_str(out, d.str()?)?,
+        CborType::Array => {
+            let len = d.array()?.unwrap_or_default();
+            encode::write_array_len(out, len as u32)?;
+            for _ in 0..len {
+                msgpack_value(d, out)?;
+            }
+        }
+        CborType::Map => {
+            let len = d.map()?.unwrap_or_default();
+            encode::write_map_len(out, len as u32)?;
+            for _ in 0..len * 2 {
+                msgpack_value(d, out)?;
+            }
+        }
+        t => return Err(unsupported(t).into()),
+    }
+    Ok(())
+}
+
+fn unsupported(t: CborType) -> &'static str {
+    match t {
+        CborType::Tag => "cbor tags are not supported",
+        CborType::Simple => "cbor simple values are not supported",
+        _ => "indefinite-length cbor items are not supported",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cbor() -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut enc = minicbor::Encoder::new(&mut buf);
+        enc.array(7)
+            .unwrap()
+            .i32(-5)
+            .unwrap()
+            .str("a")
+            .unwrap()
+            .bytes(&[0, 1, 255])
+            .unwrap()
+            .null()
+            .unwrap()
+            .f64(f64::NAN)
+            .unwrap()
+            .map(1)
+            .unwrap()
+            .str("k")
+            .unwrap()
+            .bool(true)
+            .unwrap()
+            .f32(1.5)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            cbor_to_json(&cbor()).unwrap().to_string(),
+            r#"[-5,"a","AAH/",null,null,{"k":true},1.5]"#
+        );
+    }
+
+    #[test]
+    fn msgpack() {
+        let mut out = Vec::new();
+        cbor_to_msgpack(&cbor(), &mut out).unwrap();
+        type Row = (
+            i32,
+            String,
+            serde_bytes::ByteBuf,
+            Option<u8>,
+            f64,
+            serde_json::Value,
+            f32,
+        );
+        let row: Row = rmp_serde::from_slice(&out).unwrap();
+        assert_eq!(row.0, -5);
+        assert_eq!(row.1, "a");
+        assert_eq!(row.2.as_ref(), &[0, 1, 255]);
+        assert_eq!(row.3, None);
+        assert!(row.4.is_nan());
+        assert_eq!(row.5, serde_json::json!({"k": true}));
+        assert_eq!(row.6, 1.5);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::ext::SqlDbExtError;
+use crate::log_level::warn;
+use serde::Deserialize;
+use wasmbus_rpc::RpcError;
 use wasmcloud_interface_sqldb::SqlDbError;
 
 /// Errors reported by this provider
@@ -6,20 +10,246 @@ use wasmcloud_interface_sqldb::SqlDbError;
 pub(crate) enum DbError {
     /// Database error
     Db(String),
+    /// Error reported by the server, with its SQLSTATE code
+    Sql(SqlError),
     /// Error communicating with the database
     Io(String),
+    /// The connection to the database was lost, for example because the server restarted.
+    /// The statement can be retried on a new connection, but a statement that was
+    /// running when the connection was lost may or may not have completed.
+    ConnectionReset(String),
     /// Configuration error
     Config(String),
     /// Error encoding results
     Encoding(String),
     /// No rows returned when a result was expected
     NotFound(String),
+    /// More than one row returned when a single row was expected
+    TooManyRows(String),
+    /// Statement did not complete within its timeout
+    Timeout(String),
+    /// Query result is larger than the link's max_result_rows or max_result_bytes
+    LimitExceeded(String),
+    /// A row could not be encoded, and the rows before it are returned, with partial_on_error
+    PartialResult(String),
+    /// The actor ran more statements than the link's max_queries_per_second
+    RateLimited(String),
+    /// The actor's max_concurrent_queries statements were running for longer than
+    /// the concurrency timeout
+    ConcurrencyLimit(String),
+    /// The link's circuit breaker is open, after consecutive failures to connect to the
+    /// database, and the statement was not sent
+    CircuitOpen(String),
+    /// The statement is not allowed on the link, for example a write on a read-only link
+    PermissionDenied(String),
     /// Error encountered in capability provider
     Provider(String),
     /// Error that could not be categorized as one of the above
     Other(String),
 }
 
+/// Error reported by the server
+pub(crate) struct SqlError {
+    /// five-character SQLSTATE code, such as "23505"
+    pub(crate) code: String,
+    pub(crate) category: ErrorCategory,
+    /// the server's message, with its detail and hint
+    pub(crate) message: String,
+}
+
+/// Category of a SQLSTATE code, mostly from its class (the first two characters)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ErrorCategory {
+    /// class 23: unique, foreign key, not null, or check constraint violation
+    IntegrityViolation,
+    /// class 42, except 42501: syntax error, or undefined table, column, or function
+    Syntax,
+    /// 42501 insufficient privilege, and class 28: invalid authorization
+    Permission,
+    /// class 40: serialization failure or deadlock; the transaction can be retried
+    Serialization,
+    /// class 08, and 57P01, 57P02, 57P03: connection lost, or server shutting down
+    Connection,
+    /// class 22: invalid value, such as a division by zero or a value out of range
+    Data,
+    /// class 25: the statement is not allowed in the current transaction state
+    TransactionState,
+    /// 57014: the statement was canceled, for example by statement_timeout
+    Canceled,
+    /// class 53 and 54: insufficient resources, such as disk space, or a program limit
+    Resources,
+    /// class P0: raised by a PL/pgSQL function, for example with RAISE EXCEPTION
+    Raised,
+    /// class 0A: feature not supported
+    Unsupported,
+    /// class XX: internal error
+    Internal,
+    /// any other code
+    Other,
+}
+
+impl ErrorCategory {
+    pub(crate) fn from_sqlstate(code: &str) -> Self {
+        match code {
+            "42501" => return ErrorCategory::Permission,
+            "57014" => return ErrorCategory::Canceled,
+            "57P01" | "57P02" | "57P03" => return ErrorCategory::Connection,
+            _ => {}
+        }
+        match code.get(..2).unwrap_or_default() {
+            "23" => ErrorCategory::IntegrityViolation,
+            "42" => ErrorCategory::Syntax,
+            "28" => ErrorCategory::Permission,
+            "40" => ErrorCategory::Serialization,
+            "08" => ErrorCategory::Connection,
+            "22" => ErrorCategory::Data,
+            "25" => ErrorCategory::TransactionState,
+            "53" | "54" => ErrorCategory::Resources,
+            "P0" => ErrorCategory::Raised,
+            "0A" => ErrorCategory::Unsupported,
+            "XX" => ErrorCategory::Internal,
+            _ => ErrorCategory::Other,
+        }
+    }
+
+    /// generic message of the category, returned to actors with error_detail `sanitized`
+    pub(crate) fn description(&self) -> &'static str {
+        match self {
+            ErrorCategory::IntegrityViolation => "integrity constraint violation",
+            ErrorCategory::Syntax => "syntax error or undefined object",
+            ErrorCategory::Permission => "insufficient privilege",
+            ErrorCategory::Serialization => "serialization failure or deadlock",
+            ErrorCategory::Connection => "connection exception",
+            ErrorCategory::Data => "invalid data",
+            ErrorCategory::TransactionState => "invalid transaction state",
+            ErrorCategory::Canceled => "statement canceled",
+            ErrorCategory::Resources => "insufficient resources",
+            ErrorCategory::Raised => "error raised by a function",
+            ErrorCategory::Unsupported => "feature not supported",
+            ErrorCategory::Internal => "internal error",
+            ErrorCategory::Other => "database error",
+        }
+    }
+
+    /// name of the category, as reported to actors
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::IntegrityViolation => "integrityViolation",
+            ErrorCategory::Syntax => "syntax",
+            ErrorCategory::Permission => "permission",
+            ErrorCategory::Serialization => "serialization",
+            ErrorCategory::Connection => "connection",
+            ErrorCategory::Data => "data",
+            ErrorCategory::TransactionState => "transactionState",
+            ErrorCategory::Canceled => "canceled",
+            ErrorCategory::Resources => "resources",
+            ErrorCategory::Raised => "raised",
+            ErrorCategory::Unsupported => "unsupported",
+            ErrorCategory::Internal => "internal",
+            ErrorCategory::Other => "other",
+        }
+    }
+}
+
+/// The message starts with the code and category, so that actors can find them
+/// without parsing the server's message: "SQLSTATE 23505 (integrityViolation): ERROR: ..."
+impl std::fmt::Display for SqlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SQLSTATE {} ({}): {}",
+            self.code,
+            self.category.as_str(),
+            self.message
+        )
+    }
+}
+
+/// How much of the errors of the database is returned to actors
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ErrorDetail {
+    /// the server's message, with its detail and hint
+    #[default]
+    Full,
+    /// the SQLSTATE code and category, and a generic message of the category, without the
+    /// server's message, which may contain values and the names of tables and columns
+    Sanitized,
+    /// the SQLSTATE code
+    CodeOnly,
+}
+
+tokio::task_local! {
+    /// error_detail of the link of the operation that is running. Errors converted outside
+    /// of an operation have full detail
+    pub(crate) static ERROR_DETAIL: ErrorDetail;
+}
+
+impl DbError {
+    /// The error as it is returned to actors, with the error_detail of the operation. Unless
+    /// it is `full`, the messages of the database are replaced, and the error is logged with
+    /// them; the provider's own errors are unchanged
+    fn with_detail(self) -> DbError {
+        let detail = ERROR_DETAIL.try_with(|d| *d).unwrap_or_default();
+        let redacted = match (&self, detail) {
+            (_, ErrorDetail::Full) => return self,
+            (DbError::Sql(e), ErrorDetail::Sanitized) => DbError::Sql(SqlError {
+                code: e.code.clone(),
+                category: e.category,
+                message: e.category.description().to_string(),
+            }),
+            (DbError::Sql(e), ErrorDetail::CodeOnly) => DbError::Db(format!("SQLSTATE {}", e.code)),
+            (DbError::Db(_), _) => DbError::Db("database error".into()),
+            _ => return self,
+        };
+        warn!("error returned as '{}': {}", redacted, self);
+        redacted
+    }
+
+    /// Returns true for errors after which the operation may succeed if it is run again:
+    /// a lost or failed connection, a serialization failure (40001), or a deadlock (40P01).
+    /// Errors in the statement, such as constraint violations and syntax errors, are not
+    /// retryable.
+    pub(crate) fn retryable(&self) -> bool {
+        match self {
+            DbError::Io(_)
+            | DbError::ConnectionReset(_)
+            | DbError::RateLimited(_)
+            | DbError::ConcurrencyLimit(_)
+            | DbError::CircuitOpen(_) => true,
+            DbError::Sql(e) => {
+                e.category == ErrorCategory::Connection
+                    || matches!(e.code.as_str(), "40001" | "40P01")
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::Sql(e) => e.fmt(f),
+            DbError::Db(s)
+            | DbError::Io(s)
+            | DbError::ConnectionReset(s)
+            | DbError::Config(s)
+            | DbError::Encoding(s)
+            | DbError::NotFound(s)
+            | DbError::TooManyRows(s)
+            | DbError::Timeout(s)
+            | DbError::LimitExceeded(s)
+            | DbError::PartialResult(s)
+            | DbError::RateLimited(s)
+            | DbError::ConcurrencyLimit(s)
+            | DbError::CircuitOpen(s)
+            | DbError::PermissionDenied(s)
+            | DbError::Provider(s)
+            | DbError::Other(s) => f.write_str(s),
+        }
+    }
+}
+
 /// convert cbor encoding errors to DbError
 impl<W: std::io::Write> From<minicbor::encode::Error<W>> for DbError {
     fn from(e: minicbor::encode::Error<W>) -> DbError
@@ -47,15 +277,92 @@ impl From<std::io::Error> for DbError {
 /// convert tokio-postgres errors to DbError
 impl From<tokio_postgres::Error> for DbError {
     fn from(e: tokio_postgres::Error) -> DbError {
-        DbError::Db(e.to_string())
+        DbError::from(&e)
+    }
+}
+
+impl From<&tokio_postgres::Error> for DbError {
+    fn from(e: &tokio_postgres::Error) -> DbError {
+        if is_connection_lost(e) {
+            return DbError::ConnectionReset(format!("connection to the database was lost: {}", e));
+        }
+        match e.as_db_error() {
+            Some(db) => DbError::from(db),
+            None => DbError::Db(e.to_string()),
+        }
+    }
+}
+
+/// Returns true for errors that mean the connection was lost, rather than that a
+/// statement failed: the connection is closed, an io error, a connection exception (class 08),
+/// or the server is shutting down or starting up (57P01, 57P02, 57P03)
+pub(crate) fn is_connection_lost(e: &tokio_postgres::Error) -> bool {
+    if e.is_closed() {
+        return true;
+    }
+    match e.code() {
+        Some(code) => {
+            let code = code.code();
+            code.starts_with("08") || matches!(code, "57P01" | "57P02" | "57P03")
+        }
+        None => std::error::Error::source(e).map_or(false, |source| source.is::<std::io::Error>()),
+    }
+}
+
+/// Statements of Execute and Fetch that are run again, once, on a new connection, if the
+/// connection was lost while they ran
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ConnectionRetry {
+    /// queries of Fetch
+    #[default]
+    Reads,
+    /// also the statements of Execute, which may then run twice, if the connection was
+    /// lost after the statement completed
+    All,
+    /// none
+    Off,
+}
+
+impl ConnectionRetry {
+    /// true if a statement of Execute (`write`), or of Fetch, is retried
+    pub(crate) fn retries(self, write: bool) -> bool {
+        match self {
+            ConnectionRetry::Reads => !write,
+            ConnectionRetry::All => true,
+            ConnectionRetry::Off => false,
+        }
+    }
+}
+
+/// Returns true for errors that mean a prepared statement is no longer valid:
+/// the result type of a cached plan changed because a table was altered (0A000),
+/// or the statement was deallocated, for example with DISCARD ALL (26000)
+pub(crate) fn is_stale_statement(e: &tokio_postgres::Error) -> bool {
+    use tokio_postgres::error::SqlState;
+    match e.code() {
+        Some(code) if *code == SqlState::INVALID_SQL_STATEMENT_NAME => true,
+        Some(code) if *code == SqlState::FEATURE_NOT_SUPPORTED => e
+            .as_db_error()
+            .map(|db| {
+                db.message()
+                    .starts_with("cached plan must not change result type")
+            })
+            .unwrap_or(false),
+        _ => false,
     }
 }
 
 /// convert tokio-postgres db error messages to DbError
-impl From<tokio_postgres::error::DbError> for DbError {
-    fn from(e: tokio_postgres::error::DbError) -> DbError {
-        // format: SEVERITY: message \n detail \n hint
-        DbError::Db(format!("{}", e))
+impl From<&tokio_postgres::error::DbError> for DbError {
+    fn from(e: &tokio_postgres::error::DbError) -> DbError {
+        let code = e.code().code();
+        DbError::Sql(SqlError {
+            code: code.to_string(),
+            category: ErrorCategory::from_sqlstate(code),
+            // format: SEVERITY: message \n detail \n hint
+            message: format!("{}", e),
+        })
     }
 }
 
@@ -69,14 +376,170 @@ impl<E: std::error::Error + 'static> From<bb8_postgres::bb8::RunError<E>> for Db
 /// convert DbError to the sqldb interface-defined error for client return
 impl From<DbError> for SqlDbError {
     fn from(e: DbError) -> SqlDbError {
-        match e {
+        match e.with_detail() {
             DbError::Db(s) => SqlDbError::new("db", s),
+            e @ DbError::Sql(_) => SqlDbError::new("db", e.to_string()),
             DbError::Io(s) => SqlDbError::new("io", s),
+            DbError::ConnectionReset(s) => SqlDbError::new("connectionReset", s),
             DbError::Config(s) => SqlDbError::new("config", s),
             DbError::Encoding(s) => SqlDbError::new("encoding", s),
             DbError::NotFound(s) => SqlDbError::new("notFound", s),
+            DbError::TooManyRows(s) => SqlDbError::new("tooManyRows", s),
+            DbError::Timeout(s) => SqlDbError::new("timeout", s),
+            DbError::LimitExceeded(s) => SqlDbError::new("limitExceeded", s),
+            DbError::PartialResult(s) => SqlDbError::new("partialResult", s),
+            DbError::RateLimited(s) => SqlDbError::new("rateLimited", s),
+            DbError::ConcurrencyLimit(s) => SqlDbError::new("concurrencyLimit", s),
+            DbError::CircuitOpen(s) => SqlDbError::new("circuitOpen", s),
+            DbError::PermissionDenied(s) => SqlDbError::new("permissionDenied", s),
             DbError::Provider(s) => SqlDbError::new("provider", s),
             DbError::Other(s) => SqlDbError::new("other", s),
         }
     }
 }
+
+/// convert DbError to the error of SqlDbExt results, which says whether it is retryable
+impl From<DbError> for SqlDbExtError {
+    fn from(e: DbError) -> SqlDbExtError {
+        let retryable = e.retryable();
+        let SqlDbError { code, message } = SqlDbError::from(e);
+        SqlDbExtError {
+            code,
+            message,
+            retryable,
+        }
+    }
+}
+
+/// convert DbError to RpcError, for errors encountered while setting up a link
+impl From<DbError> for RpcError {
+    fn from(e: DbError) -> RpcError {
+        let e = SqlDbError::from(e);
+        RpcError::ProviderInit(format!("{}: {}", e.code, e.message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqlstate_category() {
+        for (code, category) in [
+            ("23505", ErrorCategory::IntegrityViolation),
+            ("23503", ErrorCategory::IntegrityViolation),
+            ("42601", ErrorCategory::Syntax),
+            ("42P01", ErrorCategory::Syntax),
+            ("42501", ErrorCategory::Permission),
+            ("28P01", ErrorCategory::Permission),
+            ("40001", ErrorCategory::Serialization),
+            ("40P01", ErrorCategory::Serialization),
+            ("08006", ErrorCategory::Connection),
+            ("57P01", ErrorCategory::Connection),
+            ("22012", ErrorCategory::Data),
+            ("25006", ErrorCategory::TransactionState),
+            ("57014", ErrorCategory::Canceled),
+            ("53100", ErrorCategory::Resources),
+            ("P0001", ErrorCategory::Raised),
+            ("0A000", ErrorCategory::Unsupported),
+            ("XX000", ErrorCategory::Internal),
+            ("55P03", ErrorCategory::Other),
+            ("", ErrorCategory::Other),
+        ] {
+            assert_eq!(ErrorCategory::from_sqlstate(code), category, "{}", code);
+        }
+
+        let e = SqlDbError::from(DbError::Sql(SqlError {
+            code: "23505".into(),
+            category: ErrorCategory::IntegrityViolation,
+            message: "ERROR: duplicate key value violates unique constraint \"t_pkey\"".into(),
+        }));
+        assert_eq!(e.code, "db");
+        assert_eq!(
+            e.message,
+            "SQLSTATE 23505 (integrityViolation): ERROR: duplicate key value violates unique constraint \"t_pkey\""
+        );
+    }
+
+    #[test]
+    fn error_detail() {
+        let sql = || {
+            DbError::Sql(SqlError {
+                code: "23505".into(),
+                category: ErrorCategory::IntegrityViolation,
+                message: "ERROR: duplicate key value violates unique constraint \"t_pkey\"\n\
+                          DETAIL: Key (id)=(1) already exists."
+                    .into(),
+            })
+        };
+        let message =
+            |detail, e: DbError| ERROR_DETAIL.sync_scope(detail, || SqlDbError::from(e).message);
+        assert!(message(ErrorDetail::Full, sql()).contains("Key (id)=(1)"));
+        assert_eq!(
+            message(ErrorDetail::Sanitized, sql()),
+            "SQLSTATE 23505 (integrityViolation): integrity constraint violation"
+        );
+        assert_eq!(message(ErrorDetail::CodeOnly, sql()), "SQLSTATE 23505");
+        assert_eq!(
+            message(
+                ErrorDetail::CodeOnly,
+                DbError::Db("invalid value 'secret'".into())
+            ),
+            "database error"
+        );
+        // the provider's own errors are unchanged
+        assert_eq!(
+            message(ErrorDetail::CodeOnly, DbError::Timeout("timed out".into())),
+            "timed out"
+        );
+        // sanitized errors are still retryable
+        let e = ERROR_DETAIL.sync_scope(ErrorDetail::CodeOnly, || {
+            SqlDbExtError::from(DbError::Sql(SqlError {
+                code: "40001".into(),
+                category: ErrorCategory::Serialization,
+                message: "ERROR: could not serialize access".into(),
+            }))
+        });
+        assert!(e.retryable);
+    }
+
+    #[test]
+    fn connection_retry() {
+        assert!(ConnectionRetry::default().retries(false));
+        assert!(!ConnectionRetry::default().retries(true));
+        assert!(ConnectionRetry::All.retries(true));
+        assert!(!ConnectionRetry::Off.retries(false));
+    }
+
+    #[test]
+    fn retryable() {
+        let sql = |code: &str| {
+            DbError::Sql(SqlError {
+                code: code.into(),
+                category: ErrorCategory::from_sqlstate(code),
+                message: "ERROR".into(),
+            })
+        };
+        for code in ["40001", "40P01", "57P01", "08006", "08003"] {
+            assert!(sql(code).retryable(), "{}", code);
+        }
+        for code in [
+            "23505", "23503", "42601", "42P01", "22012", "25P02", "57014",
+        ] {
+            assert!(!sql(code).retryable(), "{}", code);
+        }
+        assert!(DbError::ConnectionReset("connection to the database was lost".into()).retryable());
+        assert!(DbError::Io("connection pool: timed out".into()).retryable());
+        assert!(DbError::RateLimited("too many statements".into()).retryable());
+        assert!(DbError::ConcurrencyLimit("timed out".into()).retryable());
+        assert!(DbError::CircuitOpen("circuit breaker open".into()).retryable());
+        assert!(!DbError::LimitExceeded("too many rows".into()).retryable());
+        assert!(!DbError::Db("error".into()).retryable());
+
+        let e = SqlDbExtError::from(sql("40001"));
+        assert_eq!(e.code, "db");
+        assert!(e.retryable);
+        assert!(e.message.starts_with("SQLSTATE 40001 (serialization): "));
+        assert!(!SqlDbExtError::from(sql("23505")).retryable);
+    }
+}
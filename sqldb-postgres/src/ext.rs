@@ -0,0 +1,2765 @@
+//! Extensions to the wasmcloud:sqldb interface.
+//!
+//! The `SqlDbExt` service is implemented by this provider in addition to `SqlDb`,
+//! on the same contract id. It follows the layout of the code generated for
+//! `wasmcloud-interface-sqldb`, so that actors can send these messages with
+//! `SqlDbExtSender` the same way they use `SqlDbSender`.
+//!
+//! The `SqlDbListener` service is implemented by actors that receive
+//! notifications from the channels in the link's `listen_channels`, and
+//! `SqlDbHealthListener` by actors that receive the changes of the health of their
+//! pool, with `health_event_interval`.
+//!
+//! This file does not depend on the rest of the provider, so that it can be
+//! copied into (or included by) clients.
+//!
+#![allow(dead_code, unused_imports, clippy::ptr_arg, clippy::needless_lifetimes)]
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use wasmbus_rpc::{
+    deserialize, serialize, Context, Message, MessageDispatch, RpcError, RpcResult, Transport,
+};
+use wasmcloud_interface_sqldb::{ExecuteResult, FetchResult, Query};
+
+/// Metadata about a column in the result set. The same as `Column` of
+/// wasmcloud:sqldb, with the type oid, so it can be decoded as either.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Column {
+    /// column ordinal
+    pub ordinal: u32,
+    /// Column name in the result
+    #[serde(default)]
+    pub name: String,
+    /// column data type as reported by the database
+    #[serde(rename = "dbType")]
+    #[serde(default)]
+    pub db_type: String,
+    /// oid of the column's data type. Unlike the name, it identifies
+    /// a user-defined type even if types in other schemas have the same name.
+    #[serde(default)]
+    pub oid: u32,
+}
+
+/// List of columns in the result set
+pub type Columns = Vec<Column>;
+
+/// Error information in the results of SqlDbExt operations. The same as `SqlDbError`
+/// of wasmcloud:sqldb, with `retryable`, so it can be decoded as either.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SqlDbExtError {
+    /// Type of error, as in SqlDbError
+    #[serde(default)]
+    pub code: String,
+    /// error message
+    #[serde(default)]
+    pub message: String,
+    /// true if the operation may succeed if it is run again, for example after a
+    /// serialization failure, a deadlock, or a lost connection
+    #[serde(default)]
+    pub retryable: bool,
+}
+
+/// A list of statements to run in a single transaction
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TransactionRequest {
+    /// statements, run in order
+    pub queries: Vec<Query>,
+    /// optional isolation level: "read uncommitted", "read committed",
+    /// "repeatable read", or "serializable". Default: the server's default_transaction_isolation
+    #[serde(rename = "isolationLevel")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isolation_level: Option<String>,
+    /// if true, the transaction is read-only
+    #[serde(rename = "readOnly")]
+    #[serde(default)]
+    pub read_only: bool,
+    /// if true, the transaction is deferrable. This has an effect only for
+    /// read-only, serializable transactions.
+    #[serde(default)]
+    pub deferrable: bool,
+}
+
+/// Result of a Transaction operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TransactionResult {
+    /// results of the statements that were run, in order.
+    /// If a statement failed, its result (with the error) is the last one.
+    #[serde(default)]
+    pub results: Vec<ExecuteResult>,
+    /// true if the transaction was committed
+    #[serde(default)]
+    pub committed: bool,
+    /// number of times the transaction was retried after a serialization failure or deadlock
+    #[serde(default)]
+    pub retries: u32,
+    /// optional error information.
+    /// If error is set, the transaction was rolled back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Options for a transaction opened with BeginTransaction
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BeginTransactionRequest {
+    /// optional isolation level: "read uncommitted", "read committed",
+    /// "repeatable read", or "serializable". Default: the server's default_transaction_isolation
+    #[serde(rename = "isolationLevel")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isolation_level: Option<String>,
+    /// if true, the transaction is read-only
+    #[serde(rename = "readOnly")]
+    #[serde(default)]
+    pub read_only: bool,
+    /// if true, the transaction is deferrable. This has an effect only for
+    /// read-only, serializable transactions.
+    #[serde(default)]
+    pub deferrable: bool,
+}
+
+/// Result of a BeginTransaction operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BeginTransactionResult {
+    /// token identifying the transaction in ExecuteIn, FetchIn, Commit, and Rollback
+    #[serde(default)]
+    pub token: TransactionToken,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Opaque token of a transaction opened with BeginTransaction
+pub type TransactionToken = String;
+
+/// A statement to run in an open transaction
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SessionQuery {
+    /// token returned by BeginTransaction
+    pub token: TransactionToken,
+    /// statement or query
+    pub query: Query,
+}
+
+/// Result of a Commit or Rollback operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EndTransactionResult {
+    /// true if the transaction was committed
+    #[serde(default)]
+    pub committed: bool,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// An open transaction to prepare, with PrepareTransaction, for two-phase commit
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PrepareTransactionRequest {
+    /// token returned by BeginTransaction
+    pub token: TransactionToken,
+    /// global id of the prepared transaction, used to commit or roll it back.
+    /// It must be unique among the prepared transactions of the server
+    pub gid: String,
+}
+
+/// Result of a PrepareTransaction operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PrepareTransactionResult {
+    /// true if the transaction was prepared. Otherwise it was rolled back
+    #[serde(default)]
+    pub prepared: bool,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// A prepared transaction, to commit with CommitPrepared or roll back with RollbackPrepared
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PreparedTransactionRequest {
+    /// global id the transaction was prepared with
+    pub gid: String,
+}
+
+/// A statement or query with parameters
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ParamQuery {
+    /// statement or query, with placeholders $1, $2, ...
+    pub query: Query,
+    /// cbor-encoded array of parameter values, one for each placeholder.
+    /// Values may be null, bool, integer, float, text, or bytes (for bytea)
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub params: Vec<u8>,
+    /// max time, in milliseconds, to wait for the statement to complete.
+    /// When it expires, the statement is cancelled. 0 is no timeout
+    #[serde(rename = "timeoutMs")]
+    #[serde(default)]
+    pub timeout_ms: u32,
+}
+
+/// Independent statements to run, in order, on one connection
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BatchRequest {
+    /// statements, run in order. Each statement is committed when it completes.
+    pub queries: Vec<Query>,
+    /// if true, statements after the first one that fails are not run
+    #[serde(rename = "stopOnError")]
+    #[serde(default)]
+    pub stop_on_error: bool,
+    /// if true, the statements are sent in a pipeline, without waiting for the result of
+    /// each statement before sending the next. They must not depend on each other's results.
+    /// It cannot be used with stop_on_error
+    #[serde(default)]
+    pub pipeline: bool,
+}
+
+/// Results of the statements of an ExecuteBatch operation, in the order of the statements
+pub type ExecuteResults = Vec<ExecuteResult>;
+
+/// Result of an ExecuteReturning operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExecuteReturningResult {
+    /// the number of rows affected by the statement
+    #[serde(rename = "rowsAffected")]
+    pub rows_affected: u64,
+    /// command tag of the statement, such as "INSERT", "CREATE TABLE", "SET", or "DO",
+    /// without the row count. For commands that do not affect rows, such as DDL and SET,
+    /// rows_affected is 0, and the tag says what ran
+    #[serde(rename = "commandTag")]
+    #[serde(default)]
+    pub command_tag: String,
+    /// number of rows returned, for example by a RETURNING clause
+    #[serde(rename = "numRows")]
+    pub num_rows: u64,
+    /// description of columns returned
+    pub columns: Columns,
+    /// returned rows, encoded as in FetchResult
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub rows: Vec<u8>,
+    /// encoding of rows: "cbor", or the link's result_encoding
+    #[serde(default)]
+    pub encoding: String,
+    /// time, in milliseconds, from when the statement was sent to the database until its
+    /// last row was read. It does not include the wait for a connection
+    #[serde(rename = "durationMs")]
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// time, in milliseconds, waiting for a connection from the pool
+    #[serde(rename = "acquireMs")]
+    #[serde(default)]
+    pub acquire_ms: u64,
+    /// notices sent by the server while the statement ran, such as those of RAISE NOTICE,
+    /// as "SEVERITY: message". They are also returned with an error
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notices: Vec<String>,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Result of a FetchCursor operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CursorResult {
+    /// token identifying the cursor in FetchNext
+    #[serde(default)]
+    pub token: TransactionToken,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Request for the next rows of a cursor
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FetchNextRequest {
+    /// token returned by FetchCursor
+    pub token: TransactionToken,
+    /// max number of rows to return
+    #[serde(rename = "batchSize")]
+    pub batch_size: u32,
+}
+
+/// Rows read from a cursor
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FetchNextResult {
+    /// number of rows returned
+    #[serde(rename = "numRows")]
+    pub num_rows: u64,
+    /// description of columns returned
+    pub columns: Columns,
+    /// result rows, encoded as in FetchResult
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub rows: Vec<u8>,
+    /// encoding of rows: "cbor", or the link's result_encoding
+    #[serde(default)]
+    pub encoding: String,
+    /// true if there are no more rows. The cursor has been closed.
+    #[serde(default)]
+    pub done: bool,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored,
+    /// and the cursor has been closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Request for a page of the results of a select query
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PageRequest {
+    /// select query, with placeholders $1, $2, ... for params.
+    /// It should have an ORDER BY clause, so that pages are in a consistent order.
+    pub query: Query,
+    /// cbor-encoded array of parameter values, as in ParamQuery
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub params: Vec<u8>,
+    /// number of rows to skip
+    #[serde(default)]
+    pub offset: u64,
+    /// max number of rows to return
+    pub limit: u32,
+}
+
+/// A page of the results of a select query
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PageResult {
+    /// number of rows returned
+    #[serde(rename = "numRows")]
+    pub num_rows: u64,
+    /// description of columns returned
+    pub columns: Columns,
+    /// result rows, encoded as in FetchResult
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub rows: Vec<u8>,
+    /// encoding of rows: "cbor", or the link's result_encoding
+    #[serde(default)]
+    pub encoding: String,
+    /// true if there are more rows after this page
+    #[serde(default)]
+    pub more: bool,
+    /// total number of rows of the query. Exact if `more` is false;
+    /// otherwise the query planner's estimate, if available
+    #[serde(rename = "totalRows")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_rows: Option<u64>,
+    /// time, in milliseconds, from when the page's query was sent to the database until its
+    /// rows were read. It does not include the wait for a connection, or the estimate of
+    /// total_rows
+    #[serde(rename = "durationMs")]
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// time, in milliseconds, waiting for a connection from the pool
+    #[serde(rename = "acquireMs")]
+    #[serde(default)]
+    pub acquire_ms: u64,
+    /// notices sent by the server while the page's query ran, as in ExecuteReturningResult
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notices: Vec<String>,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Data to copy into a table with COPY ... FROM STDIN
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CopyInRequest {
+    /// name of the table, optionally qualified with its schema ("schema.table").
+    /// The name is quoted, so it is case-sensitive.
+    pub table: String,
+    /// columns of the data, in order. If empty, the data has all columns of the table
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// data format: "csv", "text", or "binary", as in COPY
+    pub format: String,
+    /// field delimiter, a single character, for the csv and text formats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    /// for the csv format: the first line is a header, which is ignored
+    #[serde(default)]
+    pub header: bool,
+    /// data to copy
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub data: Vec<u8>,
+}
+
+/// Request to export the results of a query with COPY ... TO STDOUT
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CopyOutRequest {
+    /// select query. COPY does not accept parameters
+    pub query: Query,
+    /// data format: "csv", "text", or "binary", as in COPY
+    pub format: String,
+    /// field delimiter, a single character, for the csv and text formats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    /// for the csv format: the first line is a header with the column names
+    #[serde(default)]
+    pub header: bool,
+    /// size, in bytes, of each chunk of output. If 0, the default is 1 MiB
+    #[serde(rename = "chunkSize")]
+    #[serde(default)]
+    pub chunk_size: u32,
+}
+
+/// A chunk of the output of CopyOut
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CopyOutResult {
+    /// token for reading the next chunk with CopyOutNext. Empty when done is true
+    #[serde(default)]
+    pub token: String,
+    /// chunk of output, of chunkSize bytes, or less in the last chunk
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub data: Vec<u8>,
+    /// true if this is the last chunk
+    #[serde(default)]
+    pub done: bool,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored,
+    /// and the export has been closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Request to read the results of a select query in chunks
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FetchStreamRequest {
+    /// select query, with placeholders $1, $2, ... for params
+    pub query: Query,
+    /// cbor-encoded array of parameter values, as in ParamQuery
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub params: Vec<u8>,
+    /// max size, in bytes, of each chunk of rows. If 0, the default is 1 MiB
+    #[serde(rename = "chunkSize")]
+    #[serde(default)]
+    pub chunk_size: u32,
+}
+
+/// A chunk of the rows of FetchStream
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FetchStreamResult {
+    /// token for reading the next chunk with FetchStreamNext. Empty when done is true
+    #[serde(default)]
+    pub token: String,
+    /// description of columns returned. Only in the first chunk
+    #[serde(default)]
+    pub columns: Columns,
+    /// whole encoded rows. The chunks of a stream together are one array of rows:
+    /// in cbor, an indefinite-length array
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub data: Vec<u8>,
+    /// encoding of rows: "cbor", or the link's result_encoding
+    #[serde(default)]
+    pub encoding: String,
+    /// true if this is the last chunk
+    #[serde(default)]
+    pub done: bool,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored,
+    /// and the stream has been closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Statistics of the connection pool of a link
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PoolStats {
+    /// number of connections in the pool, idle or in use
+    #[serde(default)]
+    pub size: u32,
+    /// number of idle connections
+    #[serde(default)]
+    pub idle: u32,
+    /// number of connections in use
+    #[serde(rename = "inUse")]
+    #[serde(default)]
+    pub in_use: u32,
+    /// max number of connections, the link's pool.max_connections
+    #[serde(rename = "maxSize")]
+    #[serde(default)]
+    pub max_size: u32,
+    /// number of callers waiting for a connection from the pool
+    #[serde(default)]
+    pub waiting: u32,
+    /// number of connections taken from the pool since the link was created
+    #[serde(default)]
+    pub acquired: u64,
+    /// number of waits for a connection that failed, because of a timeout or connection error
+    #[serde(rename = "acquireFailed")]
+    #[serde(default)]
+    pub acquire_failed: u64,
+    /// total time, in milliseconds, of the waits for a connection
+    #[serde(rename = "waitMsTotal")]
+    #[serde(default)]
+    pub wait_ms_total: u64,
+    /// histogram of the waits for a connection, with cumulative counts
+    #[serde(rename = "waitBuckets")]
+    #[serde(default)]
+    pub wait_buckets: Vec<WaitBucket>,
+}
+
+/// A bucket of the histogram of the waits for a connection
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WaitBucket {
+    /// upper bound of the bucket, in milliseconds
+    #[serde(rename = "leMs")]
+    #[serde(default)]
+    pub le_ms: u64,
+    /// number of waits of at most le_ms
+    #[serde(default)]
+    pub count: u64,
+}
+
+/// A session-level advisory lock
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AdvisoryLockRequest {
+    /// key of the lock, as in pg_advisory_lock(bigint)
+    #[serde(default)]
+    pub key: i64,
+    /// max time, in milliseconds, that AdvisoryLock waits for the lock.
+    /// 0 is no timeout. Not used by TryAdvisoryLock and AdvisoryUnlock
+    #[serde(rename = "timeoutMs")]
+    #[serde(default)]
+    pub timeout_ms: u32,
+}
+
+/// Result of an AdvisoryLock, TryAdvisoryLock, or AdvisoryUnlock operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AdvisoryLockResult {
+    /// true if the actor holds the lock after the operation
+    #[serde(default)]
+    pub locked: bool,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// A notification to send with pg_notify
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NotifyRequest {
+    /// name of the channel. Names are case-sensitive, as in listen_channels
+    #[serde(default)]
+    pub channel: String,
+    /// payload of the notification
+    #[serde(default)]
+    pub payload: String,
+    /// token of an open transaction to send the notification in, if any.
+    /// The notification is delivered when the transaction commits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<TransactionToken>,
+}
+
+/// Result of a Notify operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NotifyResult {
+    /// true if the notification was queued. In a transaction, it is only delivered
+    /// if the transaction commits
+    #[serde(default)]
+    pub queued: bool,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Tables to list with ListTables
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ListTablesRequest {
+    /// schema of the tables. Default: the schemas in the search path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+}
+
+/// A table, view, materialized view, or foreign table
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TableInfo {
+    /// schema of the table
+    #[serde(default)]
+    pub schema: String,
+    /// name of the table
+    #[serde(default)]
+    pub name: String,
+    /// "table", "partitioned table", "view", "materialized view", or "foreign table"
+    #[serde(default)]
+    pub kind: String,
+}
+
+/// Result of a ListTables operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ListTablesResult {
+    /// tables, in order of schema and name
+    #[serde(default)]
+    pub tables: Vec<TableInfo>,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Table of a ListColumns or ListIndexes operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TableRequest {
+    /// schema of the table. Default: the first schema in the search path with the table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// name of the table, as stored in the catalog (not quoted)
+    #[serde(default)]
+    pub table: String,
+}
+
+/// A column of a table
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ColumnInfo {
+    /// column name
+    #[serde(default)]
+    pub name: String,
+    /// data type, with its modifiers, for example "character varying(20)"
+    #[serde(rename = "dataType")]
+    #[serde(default)]
+    pub data_type: String,
+    /// true if the column may be null
+    #[serde(default)]
+    pub nullable: bool,
+    /// expression of the default value, if the column has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// position of the column in the table, starting at 1
+    #[serde(default)]
+    pub ordinal: u32,
+    /// oid of the data type
+    #[serde(default)]
+    pub oid: u32,
+}
+
+/// Result of a ListColumns operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ListColumnsResult {
+    /// columns, in order
+    #[serde(default)]
+    pub columns: Vec<ColumnInfo>,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// An index of a table
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IndexInfo {
+    /// index name
+    #[serde(default)]
+    pub name: String,
+    /// key columns, in order. Expressions are not included
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// true if the index is unique
+    #[serde(default)]
+    pub unique: bool,
+    /// true if the index is the primary key
+    #[serde(default)]
+    pub primary: bool,
+    /// CREATE INDEX statement of the index
+    #[serde(default)]
+    pub definition: String,
+}
+
+/// Result of a ListIndexes operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ListIndexesResult {
+    /// indexes, in order of name
+    #[serde(default)]
+    pub indexes: Vec<IndexInfo>,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Result of a CancelQueries operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CancelQueriesResult {
+    /// backend process ids of the connections whose cancel request was sent to the server,
+    /// including those that had no running statement
+    #[serde(default)]
+    pub pids: Vec<i32>,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// A procedure to call with Call
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CallRequest {
+    /// name of the procedure, optionally qualified with its schema ("schema.procedure")
+    #[serde(default)]
+    pub procedure: String,
+    /// cbor-encoded array of the procedure's arguments, as in ParamQuery, including a value
+    /// (usually null) for each OUT parameter
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub params: Vec<u8>,
+}
+
+/// Rows of a cursor returned by a procedure, in a refcursor OUT or INOUT parameter
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ResultSet {
+    /// name of the parameter
+    #[serde(default)]
+    pub name: String,
+    /// number of rows returned
+    #[serde(rename = "numRows")]
+    pub num_rows: u64,
+    /// description of columns returned
+    pub columns: Columns,
+    /// rows of the cursor, encoded as in FetchResult
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub rows: Vec<u8>,
+}
+
+/// Result of a Call operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CallResult {
+    /// values of the OUT and INOUT parameters, encoded as a map of parameter name to value.
+    /// Empty if the procedure has none
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub outputs: Vec<u8>,
+    /// rows of the cursors returned in refcursor parameters, in the order of the parameters
+    #[serde(rename = "resultSets")]
+    #[serde(default)]
+    pub result_sets: Vec<ResultSet>,
+    /// encoding of outputs and of the rows of result sets: "cbor", or the link's result_encoding
+    #[serde(default)]
+    pub encoding: String,
+    /// notices sent by the server while the procedure ran, as in ExecuteReturningResult
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notices: Vec<String>,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Result of a ServerInfo operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ServerInfo {
+    /// the server's version, as in SHOW server_version, for example "15.4"
+    #[serde(default)]
+    pub version: String,
+    /// the version as a number, as in SHOW server_version_num, for example 150004
+    #[serde(rename = "versionNum")]
+    #[serde(default)]
+    pub version_num: u32,
+    /// the major version, for example 15
+    #[serde(rename = "majorVersion")]
+    #[serde(default)]
+    pub major_version: u32,
+    /// extensions installed in the database, in order of name
+    #[serde(default)]
+    pub extensions: Vec<ExtensionInfo>,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// An extension installed in the database
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExtensionInfo {
+    /// name of the extension, for example "pgcrypto"
+    #[serde(default)]
+    pub name: String,
+    /// installed version of the extension
+    #[serde(default)]
+    pub version: String,
+}
+
+/// A query whose result is cached, with FetchCached
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CachedQuery {
+    /// select query. Only queries whose result does not depend on when they run should be cached
+    pub query: Query,
+    /// time, in milliseconds, to serve the cached result before the query is run again
+    #[serde(rename = "ttlMs")]
+    #[serde(default)]
+    pub ttl_ms: u32,
+}
+
+/// Cached results to drop with InvalidateCache
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InvalidateCacheRequest {
+    /// query whose cached result is dropped. Default: all cached results of the actor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+}
+
+/// Result of an InvalidateCache operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InvalidateCacheResult {
+    /// number of cached results that were dropped
+    #[serde(default)]
+    pub invalidated: u32,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Result of a LoCreate operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LoCreateResult {
+    /// oid of the new, empty, large object
+    #[serde(default)]
+    pub oid: u32,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Data to write to a large object with LoWrite
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LoWriteRequest {
+    /// oid of the large object
+    pub oid: u32,
+    /// offset, in bytes, to write the data at. A large object is written in parts by
+    /// writing each at its offset
+    #[serde(default)]
+    pub offset: u64,
+    /// data to write
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub data: Vec<u8>,
+}
+
+/// Part of a large object to read with LoRead
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LoReadRequest {
+    /// oid of the large object
+    pub oid: u32,
+    /// offset, in bytes, of the first byte to read
+    #[serde(default)]
+    pub offset: u64,
+    /// max number of bytes to read
+    pub len: u32,
+}
+
+/// Result of a LoRead operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LoReadResult {
+    /// data read. It is shorter than the requested length at the end of the large object
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub data: Vec<u8>,
+    /// true if the end of the large object was reached
+    #[serde(default)]
+    pub eof: bool,
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Large object to delete with LoUnlink
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LoUnlinkRequest {
+    /// oid of the large object
+    pub oid: u32,
+}
+
+/// Result of a LoWrite or LoUnlink operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LoResult {
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Result of a FetchOne or FetchOptional operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FetchRowResult {
+    /// true if the query returned a row
+    pub found: bool,
+    /// description of columns returned. Empty if no row was found
+    pub columns: Columns,
+    /// the row, encoded alone as an array, or as a map with row_format `map`, not in an
+    /// array of rows. If FetchOptional found no row, it is null, in the encoding
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub row: Vec<u8>,
+    /// encoding of row: "cbor", or the link's result_encoding
+    #[serde(default)]
+    pub encoding: String,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Result of a FetchScalar operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FetchScalarResult {
+    /// the value of the first column of the first row, encoded alone, without the row or
+    /// the columns. If the query returned no rows, it is null, in the encoding
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub value: Vec<u8>,
+    /// encoding of value: "cbor", or the link's result_encoding
+    #[serde(default)]
+    pub encoding: String,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Result of a ClearStatementCache operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ClearStatementCacheResult {
+    /// optional error information
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// Rows to insert into a table with InsertMany
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InsertManyRequest {
+    /// name of the table, optionally qualified with its schema ("schema.table").
+    /// The name is quoted, so it is case-sensitive.
+    pub table: String,
+    /// columns of the rows, in order. The names are quoted, so they are case-sensitive
+    pub columns: Vec<String>,
+    /// cbor-encoded array of rows, each an array with a value for each column, encoded as
+    /// the params of ExecuteParams
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub rows: Vec<u8>,
+}
+
+/// Rows to insert into a table with Upsert, or to update if they conflict with existing rows
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UpsertRequest {
+    /// name of the table, optionally qualified with its schema ("schema.table").
+    /// The name is quoted, so it is case-sensitive.
+    pub table: String,
+    /// columns of the rows, in order. The names are quoted, so they are case-sensitive
+    pub columns: Vec<String>,
+    /// columns of the unique index or constraint whose conflicts are updated. If empty, rows
+    /// that conflict with any unique index or constraint are skipped
+    #[serde(rename = "conflictColumns")]
+    #[serde(default)]
+    pub conflict_columns: Vec<String>,
+    /// columns set to the values of the row, for a row that conflicts with an existing row.
+    /// Each must be one of the columns. If empty, the rows that conflict are skipped
+    #[serde(rename = "updateColumns")]
+    #[serde(default)]
+    pub update_columns: Vec<String>,
+    /// cbor-encoded array of rows, each an array with a value for each column, encoded as
+    /// the params of ExecuteParams
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub rows: Vec<u8>,
+}
+
+/// Result of an Upsert operation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UpsertResult {
+    /// number of rows inserted
+    #[serde(default)]
+    pub inserted: u64,
+    /// number of existing rows updated. The rows that were skipped are neither inserted nor
+    /// updated
+    #[serde(default)]
+    pub updated: u64,
+    /// optional error information.
+    /// If error is included in the result, other values should be ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SqlDbExtError>,
+}
+
+/// SqlDbExt - extended operations of the sqldb-postgres provider
+/// wasmbus.contractId: wasmcloud:sqldb
+/// wasmbus.providerReceive
+#[async_trait]
+pub trait SqlDbExt {
+    /// returns the capability contract id for this interface
+    fn contract_id() -> &'static str {
+        "wasmcloud:sqldb"
+    }
+    /// Run statements in a single transaction, on one connection.
+    /// If any statement fails, the transaction is rolled back.
+    async fn transaction(
+        &self,
+        ctx: &Context,
+        arg: &TransactionRequest,
+    ) -> RpcResult<TransactionResult>;
+    /// Begin a transaction that remains open across calls, on a connection
+    /// that is held until commit, rollback, or the transaction idle timeout.
+    async fn begin_transaction(
+        &self,
+        ctx: &Context,
+        arg: &BeginTransactionRequest,
+    ) -> RpcResult<BeginTransactionResult>;
+    /// Execute an sql statement in an open transaction
+    async fn execute_in(&self, ctx: &Context, arg: &SessionQuery) -> RpcResult<ExecuteResult>;
+    /// Perform select query in an open transaction, returning all result rows
+    async fn fetch_in(&self, ctx: &Context, arg: &SessionQuery) -> RpcResult<FetchResult>;
+    /// Commit an open transaction
+    async fn commit(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<EndTransactionResult>;
+    /// Roll back an open transaction
+    async fn rollback(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<EndTransactionResult>;
+    /// Prepare an open transaction for two-phase commit, with PREPARE TRANSACTION.
+    /// The transaction's connection is returned to the pool, and the prepared transaction
+    /// remains on the server until it is committed or rolled back
+    async fn prepare_transaction(
+        &self,
+        ctx: &Context,
+        arg: &PrepareTransactionRequest,
+    ) -> RpcResult<PrepareTransactionResult>;
+    /// Commit a prepared transaction, with COMMIT PREPARED
+    async fn commit_prepared(
+        &self,
+        ctx: &Context,
+        arg: &PreparedTransactionRequest,
+    ) -> RpcResult<EndTransactionResult>;
+    /// Roll back a prepared transaction, with ROLLBACK PREPARED
+    async fn rollback_prepared(
+        &self,
+        ctx: &Context,
+        arg: &PreparedTransactionRequest,
+    ) -> RpcResult<EndTransactionResult>;
+    /// Execute an sql statement with parameters
+    async fn execute_params(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<ExecuteResult>;
+    /// Perform select query with parameters, returning all result rows
+    async fn fetch_params(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchResult>;
+    /// Execute statements in order on one connection, returning a result for each statement
+    async fn execute_batch(&self, ctx: &Context, arg: &BatchRequest) -> RpcResult<ExecuteResults>;
+    /// Execute an sql statement, with optional parameters, returning the number of rows
+    /// affected and the rows it returns, such as generated keys from `INSERT ... RETURNING`
+    async fn execute_returning(
+        &self,
+        ctx: &Context,
+        arg: &ParamQuery,
+    ) -> RpcResult<ExecuteReturningResult>;
+    /// Open a cursor for a select query, with optional parameters, on a connection
+    /// that is held until the cursor is read to the end, it is closed with Rollback,
+    /// or the transaction idle timeout.
+    async fn fetch_cursor(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<CursorResult>;
+    /// Read the next rows of a cursor
+    async fn fetch_next(&self, ctx: &Context, arg: &FetchNextRequest)
+        -> RpcResult<FetchNextResult>;
+    /// Perform select query, returning the rows from offset to offset + limit
+    async fn fetch_page(&self, ctx: &Context, arg: &PageRequest) -> RpcResult<PageResult>;
+    /// Copy data into a table, returning the number of rows copied
+    async fn copy_in(&self, ctx: &Context, arg: &CopyInRequest) -> RpcResult<ExecuteResult>;
+    /// Export the results of a query with COPY, returning the first chunk of output
+    /// and a token for reading the next chunks. The connection is held until
+    /// the output is read to the end, the export is closed with Rollback,
+    /// or the transaction idle timeout.
+    async fn copy_out(&self, ctx: &Context, arg: &CopyOutRequest) -> RpcResult<CopyOutResult>;
+    /// Read the next chunk of output of CopyOut
+    async fn copy_out_next(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<CopyOutResult>;
+    /// Perform select query, with optional parameters, returning the first chunk of
+    /// encoded rows and a token for reading the next chunks. The connection is held until
+    /// the rows are read to the end, the stream is closed with Rollback,
+    /// or the transaction idle timeout.
+    async fn fetch_stream(
+        &self,
+        ctx: &Context,
+        arg: &FetchStreamRequest,
+    ) -> RpcResult<FetchStreamResult>;
+    /// Read the next chunk of rows of FetchStream
+    async fn fetch_stream_next(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<FetchStreamResult>;
+    /// Returns the statistics of the connection pool of the actor's link
+    async fn pool_stats(&self, ctx: &Context) -> RpcResult<PoolStats>;
+    /// Take an advisory lock, waiting until it is available
+    async fn advisory_lock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult>;
+    /// Take an advisory lock if it is available, without waiting
+    async fn try_advisory_lock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult>;
+    /// Release an advisory lock taken by the actor
+    async fn advisory_unlock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult>;
+    /// Send a notification on a channel, optionally in an open transaction
+    async fn notify(&self, ctx: &Context, arg: &NotifyRequest) -> RpcResult<NotifyResult>;
+    /// List the tables of a schema, or of the search path
+    async fn list_tables(
+        &self,
+        ctx: &Context,
+        arg: &ListTablesRequest,
+    ) -> RpcResult<ListTablesResult>;
+    /// List the columns of a table
+    async fn list_columns(&self, ctx: &Context, arg: &TableRequest)
+        -> RpcResult<ListColumnsResult>;
+    /// List the indexes of a table
+    async fn list_indexes(&self, ctx: &Context, arg: &TableRequest)
+        -> RpcResult<ListIndexesResult>;
+    /// Cancel the statements running on the connections the actor is using
+    async fn cancel_queries(&self, ctx: &Context) -> RpcResult<CancelQueriesResult>;
+    /// Fetch, or return the cached result of the query, until its ttl expires
+    async fn fetch_cached(&self, ctx: &Context, arg: &CachedQuery) -> RpcResult<FetchResult>;
+    /// Drop cached results of FetchCached
+    async fn invalidate_cache(
+        &self,
+        ctx: &Context,
+        arg: &InvalidateCacheRequest,
+    ) -> RpcResult<InvalidateCacheResult>;
+    /// Call a procedure, and return its OUT and INOUT parameters, and the rows of
+    /// the cursors it returns
+    async fn call(&self, ctx: &Context, arg: &CallRequest) -> RpcResult<CallResult>;
+    /// Returns the version of the database server, and the extensions installed in the
+    /// database. They are read once for each link
+    async fn server_info(&self, ctx: &Context) -> RpcResult<ServerInfo>;
+    /// Create an empty large object, returning its oid
+    async fn lo_create(&self, ctx: &Context) -> RpcResult<LoCreateResult>;
+    /// Write data to a large object, at an offset
+    async fn lo_write(&self, ctx: &Context, arg: &LoWriteRequest) -> RpcResult<LoResult>;
+    /// Read up to len bytes of a large object, from an offset
+    async fn lo_read(&self, ctx: &Context, arg: &LoReadRequest) -> RpcResult<LoReadResult>;
+    /// Delete a large object
+    async fn lo_unlink(&self, ctx: &Context, arg: &LoUnlinkRequest) -> RpcResult<LoResult>;
+    /// Perform a select query, with optional parameters, that returns exactly one row.
+    /// No row is a notFound error, and more than one a tooManyRows error
+    async fn fetch_one(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchRowResult>;
+    /// Perform a select query, with optional parameters, that returns at most one row.
+    /// If there is none, the row is null
+    async fn fetch_optional(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchRowResult>;
+    /// Perform a select query, with optional parameters, returning the value of the first
+    /// column of its first row, such as the count of `select count(*)`, or null if it has none
+    async fn fetch_scalar(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchScalarResult>;
+    /// Close the prepared statements cached on the connections of the actor's pools. The
+    /// cache of each connection is cleared when it is next taken from the pool
+    async fn clear_statement_cache(&self, ctx: &Context) -> RpcResult<ClearStatementCacheResult>;
+    /// Insert rows of values into a table, with multi-row INSERT statements in one
+    /// transaction, returning the number of rows inserted
+    async fn insert_many(&self, ctx: &Context, arg: &InsertManyRequest)
+        -> RpcResult<ExecuteResult>;
+    /// Insert rows of values into a table, updating the rows that conflict with existing rows,
+    /// with INSERT ... ON CONFLICT statements in one transaction, returning the number of rows
+    /// inserted and updated
+    async fn upsert(&self, ctx: &Context, arg: &UpsertRequest) -> RpcResult<UpsertResult>;
+}
+
+/// SqlDbExtReceiver receives messages defined in the SqlDbExt service trait
+#[doc(hidden)]
+#[async_trait]
+pub trait SqlDbExtReceiver: MessageDispatch + SqlDbExt {
+    async fn dispatch(&self, ctx: &Context, message: &Message<'_>) -> RpcResult<Message<'_>> {
+        match message.method {
+            "Transaction" => {
+                let value: TransactionRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::transaction(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.Transaction",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "BeginTransaction" => {
+                let value: BeginTransactionRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::begin_transaction(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.BeginTransaction",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ExecuteIn" => {
+                let value: SessionQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::execute_in(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ExecuteIn",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchIn" => {
+                let value: SessionQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_in(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchIn",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "Commit" => {
+                let value: TransactionToken = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::commit(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.Commit",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "Rollback" => {
+                let value: TransactionToken = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::rollback(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.Rollback",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "PrepareTransaction" => {
+                let value: PrepareTransactionRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::prepare_transaction(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.PrepareTransaction",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "CommitPrepared" => {
+                let value: PreparedTransactionRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::commit_prepared(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.CommitPrepared",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "RollbackPrepared" => {
+                let value: PreparedTransactionRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::rollback_prepared(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.RollbackPrepared",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ExecuteParams" => {
+                let value: ParamQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::execute_params(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ExecuteParams",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchParams" => {
+                let value: ParamQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_params(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchParams",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ExecuteBatch" => {
+                let value: BatchRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::execute_batch(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ExecuteBatch",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ExecuteReturning" => {
+                let value: ParamQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::execute_returning(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ExecuteReturning",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchCursor" => {
+                let value: ParamQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_cursor(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchCursor",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchNext" => {
+                let value: FetchNextRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_next(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchNext",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchPage" => {
+                let value: PageRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_page(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchPage",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "CopyIn" => {
+                let value: CopyInRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::copy_in(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.CopyIn",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "CopyOut" => {
+                let value: CopyOutRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::copy_out(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.CopyOut",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "CopyOutNext" => {
+                let value: TransactionToken = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::copy_out_next(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.CopyOutNext",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchStream" => {
+                let value: FetchStreamRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_stream(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchStream",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchStreamNext" => {
+                let value: TransactionToken = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_stream_next(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchStreamNext",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "PoolStats" => {
+                let resp = SqlDbExt::pool_stats(self, ctx).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.PoolStats",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "AdvisoryLock" => {
+                let value: AdvisoryLockRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::advisory_lock(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.AdvisoryLock",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "TryAdvisoryLock" => {
+                let value: AdvisoryLockRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::try_advisory_lock(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.TryAdvisoryLock",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "AdvisoryUnlock" => {
+                let value: AdvisoryLockRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::advisory_unlock(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.AdvisoryUnlock",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "Notify" => {
+                let value: NotifyRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::notify(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.Notify",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ListTables" => {
+                let value: ListTablesRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::list_tables(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ListTables",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ListColumns" => {
+                let value: TableRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::list_columns(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ListColumns",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ListIndexes" => {
+                let value: TableRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::list_indexes(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ListIndexes",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "CancelQueries" => {
+                let resp = SqlDbExt::cancel_queries(self, ctx).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.CancelQueries",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchCached" => {
+                let value: CachedQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_cached(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchCached",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "InvalidateCache" => {
+                let value: InvalidateCacheRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::invalidate_cache(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.InvalidateCache",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "Call" => {
+                let value: CallRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::call(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.Call",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ServerInfo" => {
+                let resp = SqlDbExt::server_info(self, ctx).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ServerInfo",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "LoCreate" => {
+                let resp = SqlDbExt::lo_create(self, ctx).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.LoCreate",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "LoWrite" => {
+                let value: LoWriteRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::lo_write(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.LoWrite",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "LoRead" => {
+                let value: LoReadRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::lo_read(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.LoRead",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "LoUnlink" => {
+                let value: LoUnlinkRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::lo_unlink(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.LoUnlink",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchOne" => {
+                let value: ParamQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_one(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchOne",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchOptional" => {
+                let value: ParamQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_optional(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchOptional",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "FetchScalar" => {
+                let value: ParamQuery = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::fetch_scalar(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.FetchScalar",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "ClearStatementCache" => {
+                let resp = SqlDbExt::clear_statement_cache(self, ctx).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.ClearStatementCache",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "InsertMany" => {
+                let value: InsertManyRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::insert_many(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.InsertMany",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            "Upsert" => {
+                let value: UpsertRequest = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let resp = SqlDbExt::upsert(self, ctx, &value).await?;
+                let buf = serialize(&resp)?;
+                Ok(Message {
+                    method: "SqlDbExt.Upsert",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            _ => Err(RpcError::MethodNotHandled(format!(
+                "SqlDbExt::{}",
+                message.method
+            ))),
+        }
+    }
+}
+
+/// SqlDbExtSender sends messages to a SqlDbExt service
+#[derive(Debug)]
+pub struct SqlDbExtSender<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> SqlDbExtSender<T> {
+    /// Constructs a SqlDbExtSender with the specified transport
+    pub fn via(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn set_timeout(&self, interval: std::time::Duration) {
+        self.transport.set_timeout(interval);
+    }
+}
+
+#[async_trait]
+impl<T: Transport + std::marker::Sync + std::marker::Send> SqlDbExt for SqlDbExtSender<T> {
+    #[allow(unused)]
+    /// Run statements in a single transaction, on one connection.
+    async fn transaction(
+        &self,
+        ctx: &Context,
+        arg: &TransactionRequest,
+    ) -> RpcResult<TransactionResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.Transaction",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "Transaction", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Begin a transaction that remains open across calls, on a connection
+    async fn begin_transaction(
+        &self,
+        ctx: &Context,
+        arg: &BeginTransactionRequest,
+    ) -> RpcResult<BeginTransactionResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.BeginTransaction",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "BeginTransaction", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Execute an sql statement in an open transaction
+    async fn execute_in(&self, ctx: &Context, arg: &SessionQuery) -> RpcResult<ExecuteResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ExecuteIn",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "ExecuteIn", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Perform select query in an open transaction, returning all result rows
+    async fn fetch_in(&self, ctx: &Context, arg: &SessionQuery) -> RpcResult<FetchResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchIn",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchIn", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Commit an open transaction
+    async fn commit(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<EndTransactionResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.Commit",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "Commit", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Roll back an open transaction
+    async fn rollback(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<EndTransactionResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.Rollback",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "Rollback", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Prepare an open transaction for two-phase commit, with PREPARE TRANSACTION
+    async fn prepare_transaction(
+        &self,
+        ctx: &Context,
+        arg: &PrepareTransactionRequest,
+    ) -> RpcResult<PrepareTransactionResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.PrepareTransaction",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "PrepareTransaction", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Commit a prepared transaction, with COMMIT PREPARED
+    async fn commit_prepared(
+        &self,
+        ctx: &Context,
+        arg: &PreparedTransactionRequest,
+    ) -> RpcResult<EndTransactionResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.CommitPrepared",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "CommitPrepared", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Roll back a prepared transaction, with ROLLBACK PREPARED
+    async fn rollback_prepared(
+        &self,
+        ctx: &Context,
+        arg: &PreparedTransactionRequest,
+    ) -> RpcResult<EndTransactionResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.RollbackPrepared",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "RollbackPrepared", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Execute an sql statement with parameters
+    async fn execute_params(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<ExecuteResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ExecuteParams",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "ExecuteParams", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Perform select query with parameters, returning all result rows
+    async fn fetch_params(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchParams",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchParams", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Execute statements in order on one connection, returning a result for each statement
+    async fn execute_batch(&self, ctx: &Context, arg: &BatchRequest) -> RpcResult<ExecuteResults> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ExecuteBatch",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "ExecuteBatch", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Execute an sql statement, with optional parameters, returning the number of rows
+    /// affected and the rows it returns
+    async fn execute_returning(
+        &self,
+        ctx: &Context,
+        arg: &ParamQuery,
+    ) -> RpcResult<ExecuteReturningResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ExecuteReturning",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "ExecuteReturning", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Open a cursor for a select query, with optional parameters
+    async fn fetch_cursor(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<CursorResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchCursor",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchCursor", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Read the next rows of a cursor
+    async fn fetch_next(
+        &self,
+        ctx: &Context,
+        arg: &FetchNextRequest,
+    ) -> RpcResult<FetchNextResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchNext",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchNext", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Perform select query, returning the rows from offset to offset + limit
+    async fn fetch_page(&self, ctx: &Context, arg: &PageRequest) -> RpcResult<PageResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchPage",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchPage", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Copy data into a table, returning the number of rows copied
+    async fn copy_in(&self, ctx: &Context, arg: &CopyInRequest) -> RpcResult<ExecuteResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.CopyIn",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "CopyIn", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Export the results of a query with COPY, returning the first chunk of output
+    async fn copy_out(&self, ctx: &Context, arg: &CopyOutRequest) -> RpcResult<CopyOutResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.CopyOut",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "CopyOut", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Read the next chunk of output of CopyOut
+    async fn copy_out_next(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<CopyOutResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.CopyOutNext",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "CopyOutNext", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Perform select query, returning the first chunk of encoded rows
+    async fn fetch_stream(
+        &self,
+        ctx: &Context,
+        arg: &FetchStreamRequest,
+    ) -> RpcResult<FetchStreamResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchStream",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchStream", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Read the next chunk of rows of FetchStream
+    async fn fetch_stream_next(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<FetchStreamResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchStreamNext",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchStreamNext", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Returns the statistics of the connection pool of the actor's link
+    async fn pool_stats(&self, ctx: &Context) -> RpcResult<PoolStats> {
+        let buf = *b"";
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.PoolStats",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "PoolStats", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Take an advisory lock, waiting until it is available
+    async fn advisory_lock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.AdvisoryLock",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "AdvisoryLock", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Take an advisory lock if it is available, without waiting
+    async fn try_advisory_lock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.TryAdvisoryLock",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "TryAdvisoryLock", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Release an advisory lock taken by the actor
+    async fn advisory_unlock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.AdvisoryUnlock",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "AdvisoryUnlock", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Send a notification on a channel, optionally in an open transaction
+    async fn notify(&self, ctx: &Context, arg: &NotifyRequest) -> RpcResult<NotifyResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.Notify",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "Notify", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// List the tables of a schema, or of the search path
+    async fn list_tables(
+        &self,
+        ctx: &Context,
+        arg: &ListTablesRequest,
+    ) -> RpcResult<ListTablesResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ListTables",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "ListTables", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// List the columns of a table
+    async fn list_columns(
+        &self,
+        ctx: &Context,
+        arg: &TableRequest,
+    ) -> RpcResult<ListColumnsResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ListColumns",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "ListColumns", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// List the indexes of a table
+    async fn list_indexes(
+        &self,
+        ctx: &Context,
+        arg: &TableRequest,
+    ) -> RpcResult<ListIndexesResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ListIndexes",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "ListIndexes", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Cancel the statements running on the connections the actor is using
+    async fn cancel_queries(&self, ctx: &Context) -> RpcResult<CancelQueriesResult> {
+        let buf = *b"";
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.CancelQueries",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "CancelQueries", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Fetch, or return the cached result of the query, until its ttl expires
+    async fn fetch_cached(&self, ctx: &Context, arg: &CachedQuery) -> RpcResult<FetchResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchCached",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchCached", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Drop cached results of FetchCached
+    async fn invalidate_cache(
+        &self,
+        ctx: &Context,
+        arg: &InvalidateCacheRequest,
+    ) -> RpcResult<InvalidateCacheResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.InvalidateCache",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "InvalidateCache", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Call a procedure, and return its OUT and INOUT parameters, and the rows of
+    /// the cursors it returns
+    async fn call(&self, ctx: &Context, arg: &CallRequest) -> RpcResult<CallResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.Call",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "Call", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Returns the version of the database server, and the extensions installed in the
+    /// database. They are read once for each link
+    async fn server_info(&self, ctx: &Context) -> RpcResult<ServerInfo> {
+        let buf = *b"";
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ServerInfo",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "ServerInfo", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Create an empty large object, returning its oid
+    async fn lo_create(&self, ctx: &Context) -> RpcResult<LoCreateResult> {
+        let buf = *b"";
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.LoCreate",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "LoCreate", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Write data to a large object, at an offset
+    async fn lo_write(&self, ctx: &Context, arg: &LoWriteRequest) -> RpcResult<LoResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.LoWrite",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "LoWrite", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Read up to len bytes of a large object, from an offset
+    async fn lo_read(&self, ctx: &Context, arg: &LoReadRequest) -> RpcResult<LoReadResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.LoRead",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "LoRead", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Delete a large object
+    async fn lo_unlink(&self, ctx: &Context, arg: &LoUnlinkRequest) -> RpcResult<LoResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.LoUnlink",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "LoUnlink", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Perform a select query that returns exactly one row
+    async fn fetch_one(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchRowResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchOne",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchOne", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Perform a select query that returns at most one row
+    async fn fetch_optional(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchRowResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchOptional",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchOptional", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Perform a select query, returning the value of the first column of its first row
+    async fn fetch_scalar(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchScalarResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.FetchScalar",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "FetchScalar", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Close the prepared statements cached on the connections of the actor's pools
+    async fn clear_statement_cache(&self, ctx: &Context) -> RpcResult<ClearStatementCacheResult> {
+        let buf = *b"";
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.ClearStatementCache",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp).map_err(|e| {
+            RpcError::Deser(format!("response to {}: {}", "ClearStatementCache", e))
+        })?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Insert rows of values into a table, with multi-row INSERT statements in one
+    /// transaction, returning the number of rows inserted
+    async fn insert_many(
+        &self,
+        ctx: &Context,
+        arg: &InsertManyRequest,
+    ) -> RpcResult<ExecuteResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.InsertMany",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "InsertMany", e)))?;
+        Ok(value)
+    }
+    #[allow(unused)]
+    /// Insert rows of values into a table, updating the rows that conflict with existing rows,
+    /// with INSERT ... ON CONFLICT statements in one transaction, returning the number of rows
+    /// inserted and updated
+    async fn upsert(&self, ctx: &Context, arg: &UpsertRequest) -> RpcResult<UpsertResult> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbExt.Upsert",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        let value = deserialize(&resp)
+            .map_err(|e| RpcError::Deser(format!("response to {}: {}", "Upsert", e)))?;
+        Ok(value)
+    }
+}
+
+/// A notification sent with NOTIFY (or pg_notify) on a channel the link listens on
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Notification {
+    /// name of the channel
+    pub channel: String,
+    /// payload of the notification. Empty if it has none
+    #[serde(default)]
+    pub payload: String,
+    /// process id of the server process that sent the notification
+    #[serde(rename = "processId")]
+    #[serde(default)]
+    pub process_id: i32,
+}
+
+/// SqlDbListener - notifications delivered to actors that are linked
+/// with listen_channels
+/// wasmbus.contractId: wasmcloud:sqldb
+/// wasmbus.actorReceive
+#[async_trait]
+pub trait SqlDbListener {
+    /// returns the capability contract id for this interface
+    fn contract_id() -> &'static str {
+        "wasmcloud:sqldb"
+    }
+    /// Handle a notification
+    async fn handle_notification(&self, ctx: &Context, arg: &Notification) -> RpcResult<()>;
+}
+
+/// SqlDbListenerReceiver receives messages defined in the SqlDbListener service trait
+#[doc(hidden)]
+#[async_trait]
+pub trait SqlDbListenerReceiver: MessageDispatch + SqlDbListener {
+    async fn dispatch(&self, ctx: &Context, message: &Message<'_>) -> RpcResult<Message<'_>> {
+        match message.method {
+            "HandleNotification" => {
+                let value: Notification = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let _resp = SqlDbListener::handle_notification(self, ctx, &value).await?;
+                let buf = Vec::new();
+                Ok(Message {
+                    method: "SqlDbListener.HandleNotification",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            _ => Err(RpcError::MethodNotHandled(format!(
+                "SqlDbListener::{}",
+                message.method
+            ))),
+        }
+    }
+}
+
+/// SqlDbListenerSender sends messages to a SqlDbListener service
+/// client for sending SqlDbListener messages
+#[derive(Debug)]
+pub struct SqlDbListenerSender<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> SqlDbListenerSender<T> {
+    /// Constructs a SqlDbListenerSender with the specified transport
+    pub fn via(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn set_timeout(&self, interval: std::time::Duration) {
+        self.transport.set_timeout(interval);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'send> SqlDbListenerSender<wasmbus_rpc::provider::ProviderTransport<'send>> {
+    /// Constructs a Sender using an actor's LinkDefinition,
+    /// Uses the provider's HostBridge for rpc
+    pub fn for_actor(ld: &'send wasmbus_rpc::core::LinkDefinition) -> Self {
+        Self {
+            transport: wasmbus_rpc::provider::ProviderTransport::new(ld, None),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Transport + std::marker::Sync + std::marker::Send> SqlDbListener
+    for SqlDbListenerSender<T>
+{
+    #[allow(unused)]
+    /// Handle a notification
+    async fn handle_notification(&self, ctx: &Context, arg: &Notification) -> RpcResult<()> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbListener.HandleNotification",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// A change of the health of the connection pool of an actor's link
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PoolHealthEvent {
+    /// status of the pool: "healthy", "degraded", or "down"
+    pub status: String,
+    /// status of the pool before the change
+    pub previous: String,
+    /// why the status changed: the error of the probe, the exhausted pool, or, when the pool
+    /// is healthy again, that the probe succeeded
+    pub reason: String,
+    /// time of the probe that found the change, in RFC 3339 format, in UTC
+    pub timestamp: String,
+}
+
+/// SqlDbHealthListener - changes of the health of the pool, delivered to actors that are
+/// linked with health_event_interval
+/// wasmbus.contractId: wasmcloud:sqldb
+/// wasmbus.actorReceive
+#[async_trait]
+pub trait SqlDbHealthListener {
+    /// returns the capability contract id for this interface
+    fn contract_id() -> &'static str {
+        "wasmcloud:sqldb"
+    }
+    /// Handle a change of the health of the pool
+    async fn handle_pool_health(&self, ctx: &Context, arg: &PoolHealthEvent) -> RpcResult<()>;
+}
+
+/// SqlDbHealthListenerReceiver receives messages defined in the SqlDbHealthListener service trait
+#[doc(hidden)]
+#[async_trait]
+pub trait SqlDbHealthListenerReceiver: MessageDispatch + SqlDbHealthListener {
+    async fn dispatch(&self, ctx: &Context, message: &Message<'_>) -> RpcResult<Message<'_>> {
+        match message.method {
+            "HandlePoolHealth" => {
+                let value: PoolHealthEvent = deserialize(message.arg.as_ref())
+                    .map_err(|e| RpcError::Deser(format!("message '{}': {}", message.method, e)))?;
+                let _resp = SqlDbHealthListener::handle_pool_health(self, ctx, &value).await?;
+                let buf = Vec::new();
+                Ok(Message {
+                    method: "SqlDbHealthListener.HandlePoolHealth",
+                    arg: Cow::Owned(buf),
+                })
+            }
+            _ => Err(RpcError::MethodNotHandled(format!(
+                "SqlDbHealthListener::{}",
+                message.method
+            ))),
+        }
+    }
+}
+
+/// SqlDbHealthListenerSender sends messages to a SqlDbHealthListener service
+/// client for sending SqlDbHealthListener messages
+#[derive(Debug)]
+pub struct SqlDbHealthListenerSender<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> SqlDbHealthListenerSender<T> {
+    /// Constructs a SqlDbHealthListenerSender with the specified transport
+    pub fn via(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn set_timeout(&self, interval: std::time::Duration) {
+        self.transport.set_timeout(interval);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'send> SqlDbHealthListenerSender<wasmbus_rpc::provider::ProviderTransport<'send>> {
+    /// Constructs a Sender using an actor's LinkDefinition,
+    /// Uses the provider's HostBridge for rpc
+    pub fn for_actor(ld: &'send wasmbus_rpc::core::LinkDefinition) -> Self {
+        Self {
+            transport: wasmbus_rpc::provider::ProviderTransport::new(ld, None),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Transport + std::marker::Sync + std::marker::Send> SqlDbHealthListener
+    for SqlDbHealthListenerSender<T>
+{
+    #[allow(unused)]
+    /// Handle a change of the health of the pool
+    async fn handle_pool_health(&self, ctx: &Context, arg: &PoolHealthEvent) -> RpcResult<()> {
+        let buf = serialize(arg)?;
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "SqlDbHealthListener.HandlePoolHealth",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+//! Health of the database of each link.
+//!
+//! For a health check, each link's pool is probed with `SELECT 1` on one of its
+//! connections, within `PROBE_TIMEOUT`. A link whose pool has no idle connection,
+//! because all its connections are in use, is degraded: the database is reachable,
+//! but statements wait for a connection. The provider is unhealthy if the database
+//! of any link is down.
+//!
+//! For a link with `health_event_interval`, a task probes the pool at that interval, and
+//! sends the actor a `SqlDbHealthListener.HandlePoolHealth` event when the status differs
+//! from the status of the previous probe. The pool is assumed to be healthy when the link
+//! is created, so a link whose database is down gets an event after the first probe.
+//!
+use crate::ext::{PoolHealthEvent, SqlDbHealthListener, SqlDbHealthListenerSender};
+use crate::log_level::{debug, warn};
+use crate::Pool;
+use bb8_postgres::bb8::RunError;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+use wasmbus_rpc::{
+    core::{HealthCheckResponse, LinkDefinition},
+    Context,
+};
+
+/// max time to get a connection and run the probe, for each link
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// number of times an event is sent to the actor, if the rpc fails
+const DELIVERY_ATTEMPTS: u32 = 3;
+
+/// Health of a link's database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Status {
+    Healthy,
+    /// the database is reachable, but the pool has no connection available
+    Degraded(String),
+    /// the database is not reachable
+    Down(String),
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Healthy => "healthy",
+            Status::Degraded(_) => "degraded",
+            Status::Down(_) => "down",
+        }
+    }
+
+    fn message(&self) -> Option<&str> {
+        match self {
+            Status::Healthy => None,
+            Status::Degraded(m) | Status::Down(m) => Some(m),
+        }
+    }
+}
+
+/// Probe the database of a link
+pub(crate) async fn probe(pool: &Pool) -> Status {
+    let start = Instant::now();
+    let conn = match tokio::time::timeout(PROBE_TIMEOUT, pool.get()).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(RunError::User(e))) => return Status::Down(e.to_string()),
+        // timed out waiting for a connection
+        Ok(Err(RunError::TimedOut)) | Err(_) => {
+            let state = pool.state();
+            return if state.connections > 0 && state.idle_connections == 0 {
+                Status::Degraded(format!(
+                    "connection pool exhausted: all {} connections are in use",
+                    state.connections
+                ))
+            } else {
+                Status::Down(format!(
+                    "timed out after {}s connecting to the database",
+                    PROBE_TIMEOUT.as_secs()
+                ))
+            };
+        }
+    };
+    let remaining = PROBE_TIMEOUT.saturating_sub(start.elapsed());
+    match tokio::time::timeout(remaining, conn.simple_query("SELECT 1")).await {
+        Ok(Ok(_)) => Status::Healthy,
+        Ok(Err(e)) => Status::Down(e.to_string()),
+        Err(_) => Status::Down(format!(
+            "timed out after {}s running SELECT 1",
+            PROBE_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+/// Returns the event of the change from the previous status, or None if the status is the
+/// same. A new message with the same status, such as another connection error, is not a change
+pub(crate) fn transition(
+    previous: &Status,
+    status: &Status,
+    at: DateTime<Utc>,
+) -> Option<PoolHealthEvent> {
+    if previous.name() == status.name() {
+        return None;
+    }
+    Some(PoolHealthEvent {
+        status: status.name().to_string(),
+        previous: previous.name().to_string(),
+        reason: status.message().unwrap_or("SELECT 1 succeeded").to_string(),
+        timestamp: at.to_rfc3339_opts(SecondsFormat::Millis, true),
+    })
+}
+
+/// Sends the actor the changes of the health of its pool. The watcher stops when it is dropped.
+pub(crate) struct HealthWatcher {
+    task: JoinHandle<()>,
+}
+
+impl Drop for HealthWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl HealthWatcher {
+    /// Start probing the pool at the interval
+    pub(crate) fn start(ld: LinkDefinition, pool: Pool, interval: Duration) -> Self {
+        let task = tokio::spawn(watch(ld, pool, interval));
+        Self { task }
+    }
+}
+
+/// Probe the pool, and send the actor an event for each change
+async fn watch(ld: LinkDefinition, pool: Pool, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    // a probe that waits for its timeout does not cause a burst of probes
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut previous = Status::Healthy;
+    loop {
+        ticks.tick().await;
+        let status = probe(&pool).await;
+        if let Some(event) = transition(&previous, &status, Utc::now()) {
+            debug!(
+                "{} pool health: {} -> {}: {}",
+                &ld.actor_id, &event.previous, &event.status, &event.reason
+            );
+            deliver(&ld, &event).await;
+        }
+        previous = status;
+    }
+}
+
+/// Send the event to the actor. If the rpc fails, it is sent again,
+/// so the actor may receive an event more than once.
+async fn deliver(ld: &LinkDefinition, event: &PoolHealthEvent) {
+    let sender = SqlDbHealthListenerSender::for_actor(ld);
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        match sender.handle_pool_health(&Context::default(), event).await {
+            Ok(()) => return,
+            Err(e) => warn!(
+                "{} delivering pool health event '{}' (attempt {} of {}): {}",
+                &ld.actor_id, &event.status, attempt, DELIVERY_ATTEMPTS, e
+            ),
+        }
+    }
+}
+
+/// Health check response for the status of each link, by actor id.
+/// The message is a json object with the overall status, and the status of each link.
+pub(crate) fn response(links: &[(String, Status)]) -> HealthCheckResponse {
+    let status = if links.iter().any(|(_, s)| matches!(s, Status::Down(_))) {
+        "unhealthy"
+    } else if links.iter().any(|(_, s)| matches!(s, Status::Degraded(_))) {
+        "degraded"
+    } else {
+        "healthy"
+    };
+    let actors = links
+        .iter()
+        .map(|(actor_id, s)| {
+            let mut detail = json!({ "status": s.name() });
+            if let Some(message) = s.message() {
+                detail["message"] = json!(message);
+            }
+            (actor_id.clone(), detail)
+        })
+        .collect::<serde_json::Map<_, _>>();
+    HealthCheckResponse {
+        healthy: status != "unhealthy",
+        message: Some(json!({ "status": status, "actors": actors }).to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_status() {
+        let resp = response(&[]);
+        assert!(resp.healthy);
+        assert_eq!(resp.message.unwrap(), r#"{"actors":{},"status":"healthy"}"#);
+
+        let links = [
+            ("a".to_string(), Status::Healthy),
+            ("b".to_string(), Status::Degraded("exhausted".into())),
+        ];
+        let resp = response(&links);
+        assert!(resp.healthy);
+        let message: serde_json::Value = serde_json::from_str(&resp.message.unwrap()).unwrap();
+        assert_eq!(message["status"], "degraded");
+        assert_eq!(message["actors"]["a"], json!({ "status": "healthy" }));
+        assert_eq!(
+            message["actors"]["b"],
+            json!({ "status": "degraded", "message": "exhausted" })
+        );
+
+        let links = [
+            ("a".to_string(), Status::Down("refused".into())),
+            ("b".to_string(), Status::Degraded("exhausted".into())),
+        ];
+        let resp = response(&links);
+        assert!(!resp.healthy);
+        let message: serde_json::Value = serde_json::from_str(&resp.message.unwrap()).unwrap();
+        assert_eq!(message["status"], "unhealthy");
+    }
+
+    #[test]
+    fn transitions() {
+        let at = DateTime::parse_from_rfc3339("2024-05-01T12:00:00.250Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let down = Status::Down("connection refused".into());
+        assert_eq!(transition(&Status::Healthy, &Status::Healthy, at), None);
+        assert_eq!(
+            transition(&down, &Status::Down("timed out".into()), at),
+            None
+        );
+        assert_eq!(
+            transition(&Status::Healthy, &down, at),
+            Some(PoolHealthEvent {
+                status: "down".into(),
+                previous: "healthy".into(),
+                reason: "connection refused".into(),
+                timestamp: "2024-05-01T12:00:00.250Z".into(),
+            })
+        );
+        let event = transition(&Status::Degraded("exhausted".into()), &down, at).unwrap();
+        assert_eq!(
+            (event.previous.as_str(), event.status.as_str()),
+            ("degraded", "down")
+        );
+        let event = transition(&down, &Status::Healthy, at).unwrap();
+        assert_eq!(event.status, "healthy");
+        assert_eq!(event.reason, "SELECT 1 succeeded");
+    }
+}
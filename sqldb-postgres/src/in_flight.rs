@@ -0,0 +1,117 @@
+//! Connections in use for each link.
+//!
+//! A connection taken from a link's pool is registered, with the handle that cancels its
+//! running statement, and its backend process id, until it is returned to the pool. When the link is deleted,
+//! the statements still running are cancelled, so that they do not keep running
+//! on the server (and holding locks) after the actor is gone.
+//!
+//! A connection registered with a permit of the link's `max_concurrent_queries`
+//! holds the permit until it is returned to the pool.
+//!
+use crate::log_level::{debug, info};
+use crate::{manager::Canceller, PooledConnection};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OwnedSemaphorePermit;
+
+/// Connections of a link that are in use
+#[derive(Clone, Default)]
+pub(crate) struct InFlight {
+    inner: Arc<Mutex<Registry>>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    connections: HashMap<u64, Canceller>,
+}
+
+impl InFlight {
+    /// Register a connection until it is dropped
+    pub(crate) fn register<'a>(
+        &self,
+        conn: PooledConnection<'a>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> InFlightConnection<'a> {
+        let mut registry = self.inner.lock().unwrap();
+        registry.next_id += 1;
+        let id = registry.next_id;
+        registry.connections.insert(id, conn.canceller());
+        InFlightConnection {
+            conn,
+            in_flight: self.clone(),
+            id,
+            _permit: permit,
+        }
+    }
+
+    /// Cancel the statements running on all registered connections.
+    /// Returns the backend process ids of the connections whose cancel request was sent to the
+    /// server, which cancels the statement running on each of them, if there is one
+    pub(crate) async fn cancel_all(&self) -> Vec<i32> {
+        let cancellers = self
+            .inner
+            .lock()
+            .unwrap()
+            .connections
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        if cancellers.is_empty() {
+            return Vec::new();
+        }
+        info!(
+            "cancelling statements on {} connections, backend pids [{}]",
+            cancellers.len(),
+            cancellers
+                .iter()
+                .map(|c| c.pid.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let mut pids = Vec::with_capacity(cancellers.len());
+        for canceller in cancellers {
+            match canceller.cancel().await {
+                Ok(()) => pids.push(canceller.pid),
+                Err(e) => debug!("cancelling statement of backend {}: {}", canceller.pid, e),
+            }
+        }
+        pids
+    }
+}
+
+/// A connection from a link's pool that is registered while it is in use
+pub(crate) struct InFlightConnection<'a> {
+    conn: PooledConnection<'a>,
+    in_flight: InFlight,
+    id: u64,
+    /// permit of the link's concurrency limit, if it has one
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for InFlightConnection<'_> {
+    fn drop(&mut self) {
+        self.in_flight
+            .inner
+            .lock()
+            .unwrap()
+            .connections
+            .remove(&self.id);
+    }
+}
+
+impl<'a> std::ops::Deref for InFlightConnection<'a> {
+    type Target = PooledConnection<'a>;
+
+    fn deref(&self) -> &PooledConnection<'a> {
+        &self.conn
+    }
+}
+
+impl<'a> std::ops::DerefMut for InFlightConnection<'a> {
+    fn deref_mut(&mut self) -> &mut PooledConnection<'a> {
+        &mut self.conn
+    }
+}
@@ -0,0 +1,256 @@
+//! Bulk inserts of rows of values, with InsertMany and Upsert.
+//!
+//! The rows are inserted with multi-row `INSERT INTO table (columns) VALUES ($1, $2), ($3, $4)`
+//! statements, each with as many rows as fit in the 32767 parameters tokio-postgres sends with a
+//! statement. The values are parameters, which are converted to the types of their columns as
+//! the params of ExecuteParams, so they are never interpreted as sql, and the table and column
+//! names are quoted identifiers, as in CopyIn. The statements run in one transaction: if any
+//! row is rejected, no rows are inserted.
+//!
+//! An upsert adds `ON CONFLICT (conflict columns) DO UPDATE SET column = EXCLUDED.column`, or
+//! `DO NOTHING` without update columns, and `RETURNING (xmax = 0)`, which tells the inserted rows
+//! from the updated ones: the new version of a row that is inserted has no deleting transaction,
+//! while the update of a row sets the xmax of its new version to the updating transaction, for
+//! the row lock taken on the conflict. Rows skipped with `DO NOTHING` are not returned.
+//!
+use crate::{
+    copy::{quote_identifier, table_name},
+    params::Param,
+};
+use tokio_postgres::{types::ToSql, Client, Error};
+
+/// max number of parameters of a statement. The server accepts up to 65535, but tokio-postgres
+/// encodes their count as a signed int16
+const MAX_PARAMS: usize = i16::MAX as usize;
+
+/// The INSERT statements of a table's columns
+#[derive(Debug)]
+pub(crate) struct Insert {
+    /// `INSERT INTO table (columns) VALUES `
+    prefix: String,
+    /// the conflict clause of an upsert, after the values
+    suffix: String,
+    columns: usize,
+}
+
+/// Rows inserted, and rows updated by an upsert
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Counts {
+    pub(crate) inserted: u64,
+    pub(crate) updated: u64,
+}
+
+impl Insert {
+    /// Returns an error if the table or a column name is invalid, or there are no columns
+    pub(crate) fn new(table: &str, columns: &[String]) -> Result<Self, String> {
+        let table = table_name(table)?;
+        if columns.is_empty() {
+            return Err("columns must not be empty".into());
+        }
+        if columns.len() > MAX_PARAMS {
+            return Err(format!("too many columns: at most {}", MAX_PARAMS));
+        }
+        Ok(Self {
+            prefix: format!(
+                "INSERT INTO {} ({}) VALUES ",
+                table,
+                quote_columns(columns)?
+            ),
+            suffix: String::new(),
+            columns: columns.len(),
+        })
+    }
+
+    /// The INSERT statements of an upsert: on a conflict on the conflict columns, the update
+    /// columns, which must be columns of the rows, are set to the row's values, or, without
+    /// update columns, the row is skipped. Returns an error if a name is invalid
+    pub(crate) fn upsert(
+        table: &str,
+        columns: &[String],
+        conflict_columns: &[String],
+        update_columns: &[String],
+    ) -> Result<Self, String> {
+        let mut insert = Self::new(table, columns)?;
+        let target = if conflict_columns.is_empty() {
+            if !update_columns.is_empty() {
+                return Err("updateColumns require conflictColumns".into());
+            }
+            String::new()
+        } else {
+            format!(" ({})", quote_columns(conflict_columns)?)
+        };
+        let action = if update_columns.is_empty() {
+            "DO NOTHING".to_string()
+        } else {
+            let set = update_columns
+                .iter()
+                .map(|c| {
+                    if !columns.contains(c) {
+                        return Err(format!("update column '{}' is not one of the columns", c));
+                    }
+                    let name = quote_identifier(c)
+                        .ok_or_else(|| format!("invalid column name '{}'", c))?;
+                    Ok(format!("{} = EXCLUDED.{}", name, name))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            format!("DO UPDATE SET {}", set.join(", "))
+        };
+        insert.suffix = format!(" ON CONFLICT{} {} RETURNING (xmax = 0)", target, action);
+        Ok(insert)
+    }
+
+    /// number of columns, and of the values of each row
+    pub(crate) fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// max number of rows of a statement
+    fn rows_per_statement(&self) -> usize {
+        MAX_PARAMS / self.columns
+    }
+
+    /// the statement that inserts `rows` rows
+    pub(crate) fn statement(&self, rows: usize) -> String {
+        let mut statement = self.prefix.clone();
+        for row in 0..rows {
+            if row > 0 {
+                statement.push_str(", ");
+            }
+            statement.push('(');
+            for column in 0..self.columns {
+                if column > 0 {
+                    statement.push_str(", ");
+                }
+                statement.push_str(&format!("${}", row * self.columns + column + 1));
+            }
+            statement.push(')');
+        }
+        statement.push_str(&self.suffix);
+        statement
+    }
+
+    /// the statements, as they are written in logs and the audit log: without the values
+    pub(crate) fn summary(&self, rows: usize) -> String {
+        format!("{}...{} ({} rows)", self.prefix, self.suffix, rows)
+    }
+
+    /// true for an upsert, whose statements return a row for each row inserted or updated
+    fn returns_rows(&self) -> bool {
+        !self.suffix.is_empty()
+    }
+}
+
+/// quote the names of columns, and join them with commas
+fn quote_columns(columns: &[String]) -> Result<String, String> {
+    let names = columns
+        .iter()
+        .map(|c| quote_identifier(c).ok_or_else(|| format!("invalid column name '{}'", c)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(names.join(", "))
+}
+
+/// Insert the rows, in one transaction. Returns the number of rows inserted, and updated
+pub(crate) async fn run(
+    client: &mut Client,
+    insert: &Insert,
+    rows: &[Vec<Param>],
+) -> Result<Counts, Error> {
+    let per_statement = insert.rows_per_statement();
+    let tx = client.transaction().await?;
+    // the statement of the full batches is prepared once
+    let mut full = None;
+    let mut counts = Counts::default();
+    for batch in rows.chunks(per_statement) {
+        if batch.len() == per_statement && full.is_none() {
+            full = Some(tx.prepare(&insert.statement(per_statement)).await?);
+        }
+        let last;
+        let statement = match &full {
+            Some(statement) if batch.len() == per_statement => statement,
+            _ => {
+                last = tx.prepare(&insert.statement(batch.len())).await?;
+                &last
+            }
+        };
+        let values = batch
+            .iter()
+            .flatten()
+            .map(|p| p as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        if !insert.returns_rows() {
+            counts.inserted += tx.execute(statement, &values).await?;
+            continue;
+        }
+        for row in tx.query(statement, &values).await? {
+            if row.try_get(0)? {
+                counts.inserted += 1;
+            } else {
+                counts.updated += 1;
+            }
+        }
+    }
+    tx.commit().await?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements() {
+        let insert = Insert::new("public.items", &["id".into(), "Name".into()]).unwrap();
+        assert_eq!(
+            insert.statement(2),
+            r#"INSERT INTO "public"."items" ("id", "Name") VALUES ($1, $2), ($3, $4)"#
+        );
+        assert_eq!(insert.rows_per_statement(), 16383);
+        assert_eq!(
+            insert.summary(3),
+            r#"INSERT INTO "public"."items" ("id", "Name") VALUES ... (3 rows)"#
+        );
+        let insert = Insert::new("items", &["a".into(), "b".into(), "c".into()]).unwrap();
+        assert_eq!(insert.rows_per_statement(), 10922);
+        assert!(insert.statement(1).ends_with("VALUES ($1, $2, $3)"));
+
+        assert!(Insert::new("items", &[]).is_err());
+        assert!(Insert::new("a.b.c", &["a".into()]).is_err());
+        assert!(Insert::new("items", &["".into()]).is_err());
+    }
+
+    #[test]
+    fn upserts() {
+        let columns = ["id".to_string(), "name".into(), "Price".into()];
+        let insert = Insert::upsert(
+            "items",
+            &columns,
+            &["id".into()],
+            &["name".into(), "Price".into()],
+        )
+        .unwrap();
+        assert_eq!(
+            insert.statement(1),
+            r#"INSERT INTO "items" ("id", "name", "Price") VALUES ($1, $2, $3) ON CONFLICT ("id") DO UPDATE SET "name" = EXCLUDED."name", "Price" = EXCLUDED."Price" RETURNING (xmax = 0)"#
+        );
+        assert!(insert.returns_rows());
+        assert_eq!(
+            insert.summary(2),
+            r#"INSERT INTO "items" ("id", "name", "Price") VALUES ... ON CONFLICT ("id") DO UPDATE SET "name" = EXCLUDED."name", "Price" = EXCLUDED."Price" RETURNING (xmax = 0) (2 rows)"#
+        );
+
+        let insert = Insert::upsert("items", &columns, &["id".into()], &[]).unwrap();
+        assert!(insert
+            .statement(1)
+            .ends_with(r#"ON CONFLICT ("id") DO NOTHING RETURNING (xmax = 0)"#));
+        let insert = Insert::upsert("items", &columns, &[], &[]).unwrap();
+        assert!(insert
+            .statement(1)
+            .ends_with(" ON CONFLICT DO NOTHING RETURNING (xmax = 0)"));
+        assert!(!Insert::new("items", &columns).unwrap().returns_rows());
+
+        // update columns must be columns of the rows, and need a conflict target
+        assert!(Insert::upsert("items", &columns, &["id".into()], &["other".into()]).is_err());
+        assert!(Insert::upsert("items", &columns, &[], &["name".into()]).is_err());
+        assert!(Insert::upsert("items", &columns, &["".into()], &[]).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Delivery of LISTEN/NOTIFY notifications to actors.
+//!
+//! For a link with `listen_channels`, the provider keeps a connection, outside the pool,
+//! that LISTENs on the channels. Each notification is sent to the actor with
+//! `SqlDbListener.HandleNotification`, one at a time, in the order they are received.
+//! If the connection is closed, it is opened again, and the channels are LISTENed to again.
+//! Postgres does not keep notifications for a listener that is not connected,
+//! so notifications sent while the listener is reconnecting are not delivered.
+//!
+use crate::log_level::{debug, info, warn};
+use crate::{
+    copy::quote_identifier,
+    ext::{Notification, SqlDbListener, SqlDbListenerSender},
+    manager::PgConnectionManager,
+};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use wasmbus_rpc::{core::LinkDefinition, Context, RpcError};
+
+/// delay before the first attempt to reconnect, doubled for each failed attempt
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// upper limit of the delay between attempts to reconnect
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// number of times a notification is sent to the actor, if the rpc fails
+const DELIVERY_ATTEMPTS: u32 = 3;
+
+/// max length, in bytes, of a channel name. Postgres truncates longer identifiers
+/// in LISTEN, and rejects them in pg_notify
+const MAX_CHANNEL_LENGTH: usize = 63;
+
+/// true if the channel name is a legal identifier: not empty, at most 63 bytes,
+/// and without a nul character
+pub(crate) fn valid_channel(channel: &str) -> bool {
+    !channel.is_empty() && channel.len() <= MAX_CHANNEL_LENGTH && !channel.contains('\0')
+}
+
+/// Listener of a link. The listener stops when it is dropped.
+pub(crate) struct Listener {
+    task: JoinHandle<()>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Listener {
+    /// Start listening on the channels. Returns an error if a channel name is invalid.
+    pub(crate) fn start(
+        ld: LinkDefinition,
+        manager: PgConnectionManager,
+        channels: &[String],
+    ) -> Result<Self, RpcError> {
+        let listen = channels
+            .iter()
+            .map(|channel| {
+                quote_identifier(channel)
+                    .filter(|_| valid_channel(channel))
+                    .map(|name| format!("LISTEN {}", name))
+                    .ok_or_else(|| {
+                        RpcError::ProviderInit(format!(
+                            "invalid channel in listen_channels: '{}'",
+                            channel
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .join(";");
+        let task = tokio::spawn(run(ld, manager, listen));
+        Ok(Self { task })
+    }
+}
+
+/// Listen, and deliver notifications, reconnecting when the connection is closed
+async fn run(ld: LinkDefinition, manager: PgConnectionManager, listen: String) {
+    let mut delay = MIN_RECONNECT_DELAY;
+    loop {
+        match manager.connect_listener().await {
+            Ok((client, mut notifications)) => match client.batch_execute(&listen).await {
+                Ok(()) => {
+                    info!("{} listening: {}", &ld.actor_id, &listen);
+                    delay = MIN_RECONNECT_DELAY;
+                    while let Some(n) = notifications.recv().await {
+                        deliver(&ld, n).await;
+                    }
+                    warn!("{} listener connection closed", &ld.actor_id);
+                }
+                Err(e) => warn!("{} listener: {}: {}", &ld.actor_id, &listen, e),
+            },
+            Err(e) => warn!("{} listener: connecting: {}", &ld.actor_id, e),
+        }
+        debug!(
+            "{} listener: reconnecting in {}ms",
+            &ld.actor_id,
+            delay.as_millis()
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Send the notification to the actor. If the rpc fails, it is sent again,
+/// so the actor may receive a notification more than once.
+async fn deliver(ld: &LinkDefinition, n: tokio_postgres::Notification) {
+    let notification = Notification {
+        channel: n.channel().to_string(),
+        payload: n.payload().to_string(),
+        process_id: n.process_id(),
+    };
+    let sender = SqlDbListenerSender::for_actor(ld);
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        match sender
+            .handle_notification(&Context::default(), &notification)
+            .await
+        {
+            Ok(()) => return,
+            Err(e) => warn!(
+                "{} delivering notification on '{}' (attempt {} of {}): {}",
+                &ld.actor_id, &notification.channel, attempt, DELIVERY_ATTEMPTS, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_names() {
+        assert!(valid_channel("orders"));
+        assert!(valid_channel("Order Events"));
+        assert!(valid_channel(&"c".repeat(63)));
+        assert!(!valid_channel(&"c".repeat(64)));
+        assert!(!valid_channel(""));
+        assert!(!valid_channel("a\0b"));
+    }
+}
@@ -0,0 +1,88 @@
+//! Large objects, with the server-side large-object functions.
+//!
+//! A large object is read and written through a descriptor from `lo_open`, which is only
+//! valid in the transaction that opened it, so each read and write runs in its own
+//! transaction on a connection from the pool. The data is sent to, and read from, the
+//! server in chunks of `CHUNK_SIZE` bytes, so the server does not hold a whole part in
+//! one value. The actor reads and writes a large object in parts, at offsets, so that large
+//! files are not held in memory at once.
+//!
+use tokio_postgres::{Client, Error, Transaction};
+
+/// size of the chunks sent to, and read from, the server
+pub(crate) const CHUNK_SIZE: usize = 256 * 1024;
+
+/// mode of lo_open, to write
+const INV_WRITE: i32 = 0x20000;
+/// mode of lo_open, to read
+const INV_READ: i32 = 0x40000;
+/// whence of lo_lseek64, to seek from the start
+const SEEK_SET: i32 = 0;
+
+/// Create an empty large object, and return its oid
+pub(crate) async fn create(client: &Client) -> Result<u32, Error> {
+    let row = client.query_one("select lo_create(0)", &[]).await?;
+    row.try_get(0)
+}
+
+/// Write the data to the large object, at the offset
+pub(crate) async fn write(
+    client: &mut Client,
+    oid: u32,
+    offset: i64,
+    data: &[u8],
+) -> Result<(), Error> {
+    let tx = client.transaction().await?;
+    let fd = open(&tx, oid, INV_WRITE, offset).await?;
+    let lowrite = tx.prepare("select lowrite($1, $2)").await?;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        tx.execute(&lowrite, &[&fd, &chunk]).await?;
+    }
+    tx.commit().await
+}
+
+/// Read up to `len` bytes of the large object, from the offset. Returns the data, and
+/// true if the end of the large object was reached
+pub(crate) async fn read(
+    client: &mut Client,
+    oid: u32,
+    offset: i64,
+    len: usize,
+) -> Result<(Vec<u8>, bool), Error> {
+    let tx = client.transaction().await?;
+    let fd = open(&tx, oid, INV_READ, offset).await?;
+    let loread = tx.prepare("select loread($1, $2)").await?;
+    let mut data = Vec::with_capacity(len.min(CHUNK_SIZE));
+    let mut eof = false;
+    while data.len() < len {
+        let want = (len - data.len()).min(CHUNK_SIZE);
+        let row = tx.query_one(&loread, &[&fd, &(want as i32)]).await?;
+        let chunk: &[u8] = row.try_get(0)?;
+        data.extend_from_slice(chunk);
+        if chunk.len() < want {
+            eof = true;
+            break;
+        }
+    }
+    tx.commit().await?;
+    Ok((data, eof))
+}
+
+/// Delete the large object
+pub(crate) async fn unlink(client: &Client, oid: u32) -> Result<(), Error> {
+    client.execute("select lo_unlink($1)", &[&oid]).await?;
+    Ok(())
+}
+
+/// Open the large object in the transaction, and seek to the offset. Returns its descriptor
+async fn open(tx: &Transaction<'_>, oid: u32, mode: i32, offset: i64) -> Result<i32, Error> {
+    let fd: i32 = tx
+        .query_one("select lo_open($1, $2)", &[&oid, &mode])
+        .await?
+        .try_get(0)?;
+    if offset > 0 {
+        tx.execute("select lo_lseek64($1, $2, $3)", &[&fd, &offset, &SEEK_SET])
+            .await?;
+    }
+    Ok(fd)
+}
@@ -0,0 +1,131 @@
+//! Log level of each link, with log_level.
+//!
+//! The provider's logger writes the lines up to one level, which is set from `RUST_LOG`.
+//! A link's `log_level` overrides it for the lines that are logged while one of the actor's
+//! calls is handled: the macros of this module, which the provider logs with instead of those
+//! of `log`, check the level of the call's link before logging. The logger drops the lines
+//! above its level, so it is raised to the most verbose level of `RUST_LOG` and the links,
+//! and the lines that are not logged for an actor are checked against the level of `RUST_LOG`.
+//! The lines of the libraries the provider uses only have the logger's level, so with a link
+//! at `debug` or `trace`, their lines at that level are also logged.
+//!
+use log::{Level, LevelFilter};
+use serde::Deserialize;
+use std::{str::FromStr, sync::OnceLock};
+
+tokio::task_local! {
+    /// log level of the link of the actor whose call is handled, if it has one
+    pub(crate) static LOG_LEVEL: Option<LevelFilter>;
+}
+
+/// Level of the lines logged for a link
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub(crate) fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// The level of RUST_LOG, which the provider's logger starts with: info if it is not set
+pub(crate) fn global() -> LevelFilter {
+    static GLOBAL: OnceLock<LevelFilter> = OnceLock::new();
+    *GLOBAL.get_or_init(|| {
+        std::env::var("RUST_LOG")
+            .ok()
+            .and_then(|level| LevelFilter::from_str(&level).ok())
+            .unwrap_or(LevelFilter::Info)
+    })
+}
+
+/// Set the level of the logger to the most verbose of RUST_LOG and the links' levels
+pub(crate) fn update<I: IntoIterator<Item = LevelFilter>>(links: I) {
+    let max = links.into_iter().fold(global(), Ord::max);
+    if log::max_level() != max {
+        log::set_max_level(max);
+    }
+}
+
+/// Returns true if lines at the level are logged for the actor of the current call, or,
+/// outside of a call, at the level of RUST_LOG
+pub(crate) fn enabled(level: Level) -> bool {
+    let max = LOG_LEVEL
+        .try_with(|level| *level)
+        .ok()
+        .flatten()
+        .unwrap_or_else(global);
+    level <= max
+}
+
+macro_rules! log_error {
+    ($($arg:tt)+) => {
+        if $crate::log_level::enabled(log::Level::Error) {
+            log::error!($($arg)+)
+        }
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        if $crate::log_level::enabled(log::Level::Warn) {
+            log::warn!($($arg)+)
+        }
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)+) => {
+        if $crate::log_level::enabled(log::Level::Info) {
+            log::info!($($arg)+)
+        }
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        if $crate::log_level::enabled(log::Level::Debug) {
+            log::debug!($($arg)+)
+        }
+    };
+}
+
+// `warn` is also a built-in attribute, which `use warn` would be ambiguous with
+pub(crate) use {log_debug as debug, log_error as error, log_info as info, log_warn as warn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enabled_levels() {
+        LOG_LEVEL.sync_scope(Some(LevelFilter::Debug), || {
+            assert!(enabled(Level::Debug));
+            assert!(!enabled(Level::Trace));
+        });
+        LOG_LEVEL.sync_scope(Some(LevelFilter::Error), || {
+            assert!(enabled(Level::Error));
+            assert!(!enabled(Level::Warn));
+        });
+        // a link without log_level has the level of RUST_LOG
+        LOG_LEVEL.sync_scope(None, || {
+            assert_eq!(enabled(Level::Trace), global() >= LevelFilter::Trace);
+        });
+        assert_eq!(enabled(Level::Info), global() >= LevelFilter::Info);
+    }
+}
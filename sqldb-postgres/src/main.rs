@@ -4,18 +4,78 @@
 //! 'wasmcloud:sqldb' capability.
 //!
 
-use bb8_postgres::tokio_postgres::NoTls;
 #[allow(unused_imports)]
-use log::{debug, error, info, trace};
-use std::{collections::HashMap, convert::Infallible, sync::Arc};
-use tokio::sync::RwLock;
-use wasmbus_rpc::provider::prelude::*;
-use wasmcloud_interface_sqldb::{Column, ExecuteResult, FetchResult, Query, SqlDb, SqlDbReceiver};
+use log_level::{debug, error, info, warn};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio_postgres::types::ToSql;
+use wasmbus_rpc::{
+    core::{HealthCheckRequest, HealthCheckResponse},
+    provider::prelude::*,
+};
+use wasmcloud_interface_sqldb::{
+    ExecuteResult, FetchResult, Query, SqlDb, SqlDbError, SqlDbReceiver,
+};
 
+mod advisory_lock;
+mod audit;
+mod auth;
+mod call;
+mod catalog;
+mod circuit;
+mod command_tag;
 mod config;
+mod copy;
+mod dns;
+mod drain;
+mod encoding;
 mod error;
+mod health;
+mod in_flight;
+mod insert;
+mod listen;
+mod lo;
+mod log_level;
+mod metrics;
+mod migrate;
+mod otel;
 use error::DbError;
+mod ext;
+use ext::{
+    AdvisoryLockRequest, AdvisoryLockResult, BatchRequest, BeginTransactionRequest,
+    BeginTransactionResult, CachedQuery, CallRequest, CallResult, CancelQueriesResult,
+    ClearStatementCacheResult, CopyInRequest, CopyOutRequest, CopyOutResult, CursorResult,
+    EndTransactionResult, ExecuteResults, ExecuteReturningResult, FetchNextRequest,
+    FetchNextResult, FetchRowResult, FetchScalarResult, FetchStreamRequest, FetchStreamResult,
+    InsertManyRequest, InvalidateCacheRequest, InvalidateCacheResult, ListColumnsResult,
+    ListIndexesResult, ListTablesRequest, ListTablesResult, LoCreateResult, LoReadRequest,
+    LoReadResult, LoResult, LoUnlinkRequest, LoWriteRequest, NotifyRequest, NotifyResult,
+    PageRequest, PageResult, ParamQuery, PoolStats, PrepareTransactionRequest,
+    PrepareTransactionResult, PreparedTransactionRequest, ServerInfo, SessionQuery, SqlDbExt,
+    SqlDbExtReceiver, TableRequest, TransactionRequest, TransactionResult, TransactionToken,
+    UpsertRequest, UpsertResult,
+};
 
+mod manager;
+mod page;
+mod params;
+mod placeholders;
+mod pool;
+mod prepared;
+mod query_cache;
+mod quota;
+mod rate_limit;
+mod read_only;
+mod results;
+mod session;
+mod statement_cache;
+mod tls;
 mod types;
 
 // main (via provider_main) initializes the threaded tokio executor,
@@ -23,56 +83,602 @@ mod types;
 // and returns only when it receives a shutdown message
 //
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    provider_main(SqlDbProvider::default())?;
+    let connection_quota = quota::ConnectionQuota::from_env()?;
+    provider_main(SqlDbProvider {
+        connection_quota,
+        ..Default::default()
+    })?;
 
     eprintln!("sqldb provider exiting");
     Ok(())
 }
 
-//pub(crate) type NTLS = native_tls::TlsConnector;
-pub(crate) type PgConnection = bb8_postgres::PostgresConnectionManager<NoTls>;
-pub(crate) type Pool = bb8_postgres::bb8::Pool<PgConnection>;
+pub(crate) type Pool = bb8_postgres::bb8::Pool<manager::PgConnectionManager>;
+pub(crate) type PooledConnection<'a> =
+    bb8_postgres::bb8::PooledConnection<'a, manager::PgConnectionManager>;
+
+/// upper limit of the delay between transaction retries
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Database resources for an actor link
+#[derive(Clone)]
+struct Link {
+    /// values of the link definition, to report the changes when the link is updated
+    values: HashMap<String, String>,
+    pool: Pool,
+    /// how connections are taken from the pool
+    checkout: pool::Checkout,
+    /// pool of the read replica, and how connections are taken from it, for the queries of
+    /// Fetch, FetchParams, and FetchPage, if read_uri is set and read_consistency is replica
+    read_pool: Option<(Pool, pool::Checkout)>,
+    /// max idle time of a transaction opened with begin_transaction
+    transaction_idle_timeout: Duration,
+    /// max time to wait at shutdown for running statements to complete
+    shutdown_grace_period: Duration,
+    /// max number of times a transaction is retried after a serialization failure or deadlock
+    max_retries: u32,
+    /// delay before the first retry, doubled for each subsequent retry
+    retry_backoff: Duration,
+    /// max size, and encoding, of query results
+    result_options: results::ResultOptions,
+    /// connections in use, whose statements are cancelled when the link is deleted
+    in_flight: in_flight::InFlight,
+    /// waits for connections from the pool
+    metrics: metrics::PoolMetrics,
+    /// how statements are recorded in their spans
+    trace_statement: otel::TraceStatement,
+    /// statements of Execute and Fetch that are retried after the connection was lost
+    connection_retry: error::ConnectionRetry,
+    /// how much of the errors of the database is returned to the actor
+    error_detail: error::ErrorDetail,
+    /// placeholders of the queries of operations with params
+    placeholder_style: placeholders::PlaceholderStyle,
+    /// level of the lines logged for the actor's calls, if it is not that of RUST_LOG
+    log_level: Option<log::LevelFilter>,
+    /// statements that take longer are logged, if it is set
+    slow_query: Option<Duration>,
+    /// max length of a statement in the slow query log
+    slow_query_max_length: usize,
+    /// limits the statements of Execute and Fetch, if max_queries_per_second is set
+    rate_limiter: Option<Arc<rate_limit::RateLimiter>>,
+    /// permits for the connections in use, if max_concurrent_queries is set
+    concurrency: Option<Arc<Semaphore>>,
+    /// max time to wait for a permit
+    concurrency_timeout: Duration,
+    /// permits of the connections of all links, if SQLDB_TOTAL_MAX_CONNECTIONS is set, with
+    /// the link's exhausted behavior
+    connection_quota: quota::ConnectionQuota,
+    /// true if the link only runs queries
+    read_only: bool,
+    /// false if statements are left out of the logs of failed and slow statements
+    log_statements: bool,
+    /// comment prepended to the statements of Execute and Fetch, if query_tags is on
+    query_tag: Option<String>,
+    /// records the actor's statements, if audit_log is set
+    audit_log: Option<Arc<audit::AuditLog>>,
+    /// delivers notifications on the link's listen_channels, if there are any,
+    /// until the link is dropped
+    _listener: Option<Arc<listen::Listener>>,
+    /// sends the actor the changes of its pool's health, if health_event_interval is set,
+    /// until the link is dropped
+    _health_watcher: Option<Arc<health::HealthWatcher>>,
+    /// version of the server and extensions of the database, once they have been read
+    server_info: Arc<tokio::sync::OnceCell<ServerInfo>>,
+    /// when ClearStatementCache was last called: the statement cache of a connection that
+    /// has not been cleared since is cleared when it is taken from the pool
+    statements_cleared: Arc<std::sync::Mutex<Option<Instant>>>,
+    /// the link's use of shared pools, with shared_pool, which are removed with their last link
+    _shared_pools: Vec<Arc<pool::SharedPoolLease>>,
+}
+
+impl Link {
+    /// the query of an operation with `num_params` params, with the link's placeholder_style
+    fn param_query<'a>(&self, query: &'a str, num_params: usize) -> RpcResult<Cow<'a, str>> {
+        self.placeholder_style
+            .query(query, num_params)
+            .map_err(RpcError::InvalidParameter)
+    }
+
+    /// true if a statement of Execute (`write`), or of Fetch, that failed with the error is
+    /// run again on a new connection: the link retries it, and the connection was lost
+    fn retries_on(&self, write: bool, e: &tokio_postgres::Error) -> bool {
+        self.connection_retry.retries(write) && error::is_connection_lost(e)
+    }
+
+    /// get a connection from the pool, after a permit of the concurrency limit
+    async fn connection(&self) -> Result<in_flight::InFlightConnection<'_>, DbError> {
+        self.connection_from(&self.pool, &self.checkout).await
+    }
+
+    /// get a connection for a query: from the read replica's pool, if the link has one,
+    /// otherwise from the pool. Returns the connection, and the name of its pool
+    async fn read_connection(
+        &self,
+    ) -> Result<(in_flight::InFlightConnection<'_>, &'static str), DbError> {
+        match &self.read_pool {
+            Some((pool, checkout)) => Ok((self.connection_from(pool, checkout).await?, "replica")),
+            None => Ok((self.connection().await?, "primary")),
+        }
+    }
+
+    /// get a connection from one of the link's pools, after a permit of the concurrency limit
+    async fn connection_from<'a>(
+        &'a self,
+        pool: &'a Pool,
+        checkout: &pool::Checkout,
+    ) -> Result<in_flight::InFlightConnection<'a>, DbError> {
+        checkout.check_circuit()?;
+        let permit = self.concurrency_permit().await?;
+        checkout.check(pool)?;
+        self.connection_quota.check(pool.state().idle_connections)?;
+        let conn = self
+            .metrics
+            .acquire(pool.get())
+            .await
+            .map_err(|e| checkout.error(pool, e))?;
+        checkout.connected();
+        self.clear_statements(&conn);
+        Ok(self.in_flight.register(conn, permit))
+    }
+
+    /// get a connection from the pool that is not tied to the lifetime of the link,
+    /// for a transaction that remains open across calls
+    async fn owned_connection(&self) -> Result<in_flight::InFlightConnection<'static>, DbError> {
+        self.checkout.check_circuit()?;
+        let permit = self.concurrency_permit().await?;
+        self.checkout.check(&self.pool)?;
+        self.connection_quota
+            .check(self.pool.state().idle_connections)?;
+        let conn = self
+            .metrics
+            .acquire(self.pool.get_owned())
+            .await
+            .map_err(|e| self.checkout.error(&self.pool, e))?;
+        self.checkout.connected();
+        self.clear_statements(&conn);
+        Ok(self.in_flight.register(conn, permit))
+    }
+
+    /// clear the statement cache of a connection taken from the pool, if it has not been
+    /// cleared since ClearStatementCache was called
+    fn clear_statements(&self, conn: &manager::PgConnection) {
+        if let Some(at) = *self.statements_cleared.lock().unwrap() {
+            conn.statements.clear_before(at);
+        }
+    }
+
+    /// Wait for a permit of max_concurrent_queries, if it is set. Returns ConcurrencyLimit
+    /// if none is released within the concurrency timeout
+    async fn concurrency_permit(&self) -> Result<Option<OwnedSemaphorePermit>, DbError> {
+        let semaphore = match &self.concurrency {
+            Some(semaphore) => semaphore.clone(),
+            None => return Ok(None),
+        };
+        let limit = || {
+            DbError::ConcurrencyLimit(format!(
+                "timed out after {}ms waiting for one of the link's max_concurrent_queries \
+                 statements to finish",
+                self.concurrency_timeout.as_millis()
+            ))
+        };
+        match tokio::time::timeout(self.concurrency_timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            // the semaphore is never closed
+            Ok(Err(_)) | Err(_) => Err(limit()),
+        }
+    }
+
+    /// delay before retrying a transaction, after `retries` previous retries
+    fn retry_delay(&self, retries: u32) -> Duration {
+        self.retry_backoff
+            .saturating_mul(2u32.saturating_pow(retries))
+            .min(MAX_RETRY_DELAY)
+    }
+
+    /// Returns RateLimited if the actor has used up the statements of its rate limit
+    fn check_rate_limit(&self) -> Result<(), DbError> {
+        match &self.rate_limiter {
+            Some(limiter) if !limiter.try_acquire() => Err(DbError::RateLimited(format!(
+                "rate limit exceeded: more than max_queries_per_second ({}) statements",
+                limiter.max_per_second()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// the statement as it is sent to the database: after the link's query tag, if it has one
+    fn tagged<'a>(&self, statement: &'a str) -> Cow<'a, str> {
+        match &self.query_tag {
+            Some(tag) => Cow::Owned(format!("{}{}", tag, statement)),
+            None => Cow::Borrowed(statement),
+        }
+    }
+
+    /// the statement, as it is written in logs
+    fn logged<'a>(&self, statement: &'a str) -> &'a str {
+        logged(statement, self.log_statements)
+    }
+
+    /// Returns an error if the statement may not be run on the link: PermissionDenied if the
+    /// link is read-only and the statement is not a query, an error while the audit log
+    /// rejects statements, or RateLimited. Each statement the actor runs is checked, and
+    /// takes a token of the rate limit
+    fn check_statement(&self, statement: &str) -> Result<(), DbError> {
+        if self.read_only {
+            read_only::check(statement)
+                .map_err(|e| DbError::PermissionDenied(format!("read-only link: {}", e)))?;
+        }
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.check()?;
+        }
+        self.check_rate_limit()
+    }
+
+    /// check an operation that writes without a statement of the actor, such as LoWrite
+    fn check_write(&self, operation: &str) -> Result<(), DbError> {
+        if self.read_only {
+            return Err(DbError::PermissionDenied(format!(
+                "read-only link: {} is not allowed",
+                operation
+            )));
+        }
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.check()?;
+        }
+        self.check_rate_limit()
+    }
+
+    /// record a statement of the actor, and its outcome, if the audit log is enabled
+    fn audit<E: audit::AuditError>(
+        &self,
+        actor_id: &str,
+        operation: &'static str,
+        statement: &str,
+        rows: Option<u64>,
+        error: Option<&E>,
+    ) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(audit::Record::new(
+                actor_id, operation, statement, rows, error,
+            ));
+        }
+    }
+
+    /// the result of a statement of an ExecuteBatch, which is logged if it failed, and recorded
+    /// in the audit log
+    fn batch_result(
+        &self,
+        actor_id: &str,
+        query: &str,
+        res: Result<u64, tokio_postgres::Error>,
+    ) -> ExecuteResult {
+        let result = match res {
+            Ok(res) => ExecuteResult {
+                rows_affected: res,
+                ..Default::default()
+            },
+            Err(db_err) => {
+                error!(
+                    "{} batch query:'{}' error:{}",
+                    actor_id,
+                    self.logged(query),
+                    &db_err.to_string()
+                );
+                ExecuteResult {
+                    error: Some(DbError::from(db_err).into()),
+                    ..Default::default()
+                }
+            }
+        };
+        self.audit(
+            actor_id,
+            "SqlDbExt.ExecuteBatch",
+            query,
+            Some(result.rows_affected),
+            result.error.as_ref(),
+        );
+        result
+    }
+
+    /// log the statement at WARN if it took longer than the slow query threshold
+    fn log_slow_query(&self, actor_id: &str, query: &str, elapsed: Duration, pid: Option<i32>) {
+        match self.slow_query {
+            Some(threshold) if elapsed > threshold => {
+                warn!(
+                    "{} slow query ({}ms, backend pid {}): '{}'",
+                    actor_id,
+                    elapsed.as_millis(),
+                    pid.map_or_else(|| "none".to_string(), |pid| pid.to_string()),
+                    truncate(self.logged(query), self.slow_query_max_length)
+                )
+            }
+            _ => {}
+        }
+    }
+}
 
 /// sqldb capability provider implementation
-#[derive(Default, Clone, Provider)]
-#[services(SqlDb)]
+#[derive(Default, Clone)]
 struct SqlDbProvider {
-    actors: Arc<RwLock<HashMap<String, Pool>>>,
+    actors: Arc<RwLock<HashMap<String, Link>>>,
+    /// transactions opened with begin_transaction
+    sessions: session::Sessions,
+    /// running Execute and Fetch calls, which are completed before shutdown
+    drain: drain::Drain,
+    /// advisory locks taken with advisory_lock and try_advisory_lock
+    advisory_locks: advisory_lock::AdvisoryLocks,
+    /// actors that prepared the transactions of prepare_transaction
+    prepared: prepared::PreparedTransactions,
+    /// results of fetch_cached
+    query_cache: query_cache::QueryCache,
+    /// pools of the links with shared_pool
+    shared_pools: pool::SharedPools,
+    /// limit of the connections in use by all links
+    connection_quota: quota::ConnectionQuota,
 }
 
 /// use default implementations of provider message handlers
 impl ProviderDispatch for SqlDbProvider {}
 
+impl SqlDbReceiver for SqlDbProvider {}
+impl SqlDbExtReceiver for SqlDbProvider {}
+
+/// Dispatches messages to the SqlDb and SqlDbExt receivers, as `#[services(SqlDb, SqlDbExt)]`
+/// would, with the error_detail of the actor's link, which errors are converted with,
+/// and its log_level, which the lines logged for the call are checked against
+#[async_trait]
+impl MessageDispatch for SqlDbProvider {
+    async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Message<'_>> {
+        let (detail, log_level) = match &ctx.actor {
+            Some(actor_id) => self
+                .actors
+                .read()
+                .await
+                .get(actor_id)
+                .map(|link| (link.error_detail, link.log_level))
+                .unwrap_or_default(),
+            None => Default::default(),
+        };
+        let dispatch = error::ERROR_DETAIL.scope(detail, async {
+            let (trait_name, trait_method) = message
+                .method
+                .rsplit_once('.')
+                .unwrap_or(("_", message.method));
+            let message = Message {
+                method: trait_method,
+                arg: message.arg,
+            };
+            match trait_name {
+                "SqlDb" => SqlDbReceiver::dispatch(self, ctx, &message).await,
+                "SqlDbExt" => SqlDbExtReceiver::dispatch(self, ctx, &message).await,
+                _ => Err(RpcError::MethodNotHandled(format!(
+                    "{}.{} - unknown method",
+                    trait_name, message.method
+                ))),
+            }
+        });
+        log_level::LOG_LEVEL.scope(log_level, dispatch).await
+    }
+}
+
 /// Handle connection pools for each link
 #[async_trait]
 impl ProviderHandler for SqlDbProvider {
     /// Provider should perform any operations needed for a new link,
     /// including setting up per-actor resources, and checking authorization.
     /// If the link is allowed, return true, otherwise return false to deny the link.
+    ///
+    /// If the actor is already linked, the link is updated: the new pool replaces the old one
+    /// after the running statements complete, and open transactions keep the connections of
+    /// the old pool, which is closed when they end.
     async fn put_link(&self, ld: &LinkDefinition) -> RpcResult<bool> {
+        otel::init();
         let config = config::load_config(ld)?;
-        let pool = config::create_pool(config).await?;
+        let mut checkout = config.pool_checkout();
+        let max_connections = config.pool_max_connections();
+        let trace_statement = config.trace_statement();
+        let (slow_query, slow_query_max_length) = config.slow_query();
+        let rate_limiter = config
+            .max_queries_per_second()
+            .map(|n| Arc::new(rate_limit::RateLimiter::new(n)));
+        let (max_concurrent, concurrency_timeout) = config.concurrency();
+        let concurrency = max_concurrent.map(|n| Arc::new(Semaphore::new(n)));
+        let transaction_idle_timeout = config.transaction_idle_timeout();
+        let shutdown_grace_period = config.shutdown_grace_period();
+        let read_only = config.read_only();
+        let log_statements = config.log_statements();
+        let query_tag = config.query_tag();
+        let connection_quota = self
+            .connection_quota
+            .with_wait(checkout.behavior, checkout.timeout);
+        let (max_retries, retry_backoff) = config.retry();
+        let result_options = config.result_options();
+        let channels = config.listen_channels();
+        let listener = if channels.is_empty() {
+            None
+        } else {
+            let manager = config::create_manager(&config, &self.connection_quota).await?;
+            Some(Arc::new(listen::Listener::start(
+                ld.clone(),
+                manager,
+                &channels,
+            )?))
+        };
+        let mut shared_pools = Vec::new();
+        let (pool, lease) = self.pool(&config, &mut checkout).await?;
+        shared_pools.extend(lease.map(Arc::new));
+        let audit_log = config::create_audit_log(&config, &self.connection_quota)
+            .await?
+            .map(Arc::new);
+        let read_pool = match config.read_replica() {
+            Some(replica) => {
+                let mut checkout = replica.pool_checkout();
+                let (pool, lease) = self.pool(&replica, &mut checkout).await?;
+                shared_pools.extend(lease.map(Arc::new));
+                Some((pool, checkout))
+            }
+            None => None,
+        };
+        let health_watcher = config.health_event_interval().map(|interval| {
+            Arc::new(health::HealthWatcher::start(
+                ld.clone(),
+                pool.clone(),
+                interval,
+            ))
+        });
+        if let Some(dir) = config.migrations()? {
+            let applied = migrate::run(&pool, dir, &ld.actor_id)
+                .await
+                .map_err(|e| RpcError::ProviderInit(format!("migrations: {}", e)))?;
+            info!(
+                "{} applied {} migrations from {}",
+                &ld.actor_id, applied, dir
+            );
+        }
+        // waits for the running statements, which hold the read lock
         let mut update_map = self.actors.write().await;
-        update_map.insert(ld.actor_id.to_string(), pool);
+        let old = update_map.insert(
+            ld.actor_id.to_string(),
+            Link {
+                values: ld.values.clone(),
+                pool,
+                checkout,
+                read_pool,
+                transaction_idle_timeout,
+                shutdown_grace_period,
+                max_retries,
+                retry_backoff,
+                result_options,
+                in_flight: Default::default(),
+                metrics: metrics::PoolMetrics::new(max_connections),
+                trace_statement,
+                connection_retry: config.retry_on_connection_error(),
+                error_detail: config.error_detail(),
+                placeholder_style: config.placeholder_style(),
+                log_level: config.log_level(),
+                slow_query,
+                slow_query_max_length,
+                rate_limiter,
+                concurrency,
+                concurrency_timeout,
+                connection_quota,
+                read_only,
+                log_statements,
+                query_tag,
+                audit_log,
+                _listener: listener,
+                _health_watcher: health_watcher,
+                server_info: Default::default(),
+                statements_cleared: Default::default(),
+                _shared_pools: shared_pools,
+            },
+        );
+        log_level::update(update_map.values().filter_map(|link| link.log_level));
+        drop(update_map);
+        if let Some(old) = old {
+            // the new link may use another database
+            self.query_cache.invalidate(&ld.actor_id, None);
+            // the values are not logged, as they may contain credentials
+            info!(
+                "{} link updated, changed values: [{}]",
+                &ld.actor_id,
+                config::changed_values(&old.values, &ld.values).join(", ")
+            );
+        }
         Ok(true)
     }
 
     /// Handle notification that a link is dropped - close the connection
     async fn delete_link(&self, actor_id: &str) {
+        // cancel running statements first: until they complete, they hold their connections
+        // and the transactions of the actor's sessions
+        let in_flight = match self.actors.read().await.get(actor_id) {
+            Some(link) => link.in_flight.clone(),
+            None => Default::default(),
+        };
+        in_flight.cancel_all().await;
+        self.sessions.remove_actor(actor_id).await;
+        self.query_cache.invalidate(actor_id, None);
         let mut aw = self.actors.write().await;
-        if let Some(conn) = aw.remove(actor_id) {
+        let link = aw.remove(actor_id);
+        log_level::update(aw.values().filter_map(|link| link.log_level));
+        drop(aw);
+        if let Some(link) = link {
+            if let Some(Ok(audit_log)) = link.audit_log.map(Arc::try_unwrap) {
+                audit_log.close(link.shutdown_grace_period).await;
+            }
             // close all connections for this actor-link's pool
-            drop(conn);
         }
+        // after the actor's calls have completed, so that a lock that was being taken
+        // is also released
+        self.advisory_locks.remove_actor(actor_id).await;
+    }
+
+    /// Report the health of the database of each link
+    async fn health_request(&self, _arg: &HealthCheckRequest) -> RpcResult<HealthCheckResponse> {
+        let pools = self
+            .actors
+            .read()
+            .await
+            .iter()
+            .map(|(actor_id, link)| (actor_id.clone(), link.pool.clone()))
+            .collect::<Vec<_>>();
+        let statuses =
+            futures::future::join_all(pools.iter().map(|(_, pool)| health::probe(pool))).await;
+        let links = pools
+            .into_iter()
+            .map(|(actor_id, _)| actor_id)
+            .zip(statuses)
+            .collect::<Vec<_>>();
+        for (actor_id, status) in links.iter() {
+            if *status != health::Status::Healthy {
+                warn!("{} health check: {:?}", actor_id, status);
+            }
+        }
+        Ok(health::response(&links))
     }
 
-    /// Handle shutdown request by closing all connections
+    /// Handle shutdown request by closing all connections, after the running statements
+    /// complete, or the longest shutdown_grace_period of the links elapses
     async fn shutdown(&self) -> Result<(), Infallible> {
-        let mut aw = self.actors.write().await;
-        // close all connections
-        for (_, conn) in aw.drain() {
-            drop(conn);
+        let grace = self
+            .actors
+            .read()
+            .await
+            .values()
+            .map(|link| link.shutdown_grace_period)
+            .max()
+            .unwrap_or_default();
+        let abandoned = self.drain.close(grace).await;
+        if abandoned > 0 {
+            warn!(
+                "shutdown: {} running queries abandoned after the grace period of {}s",
+                abandoned,
+                grace.as_secs()
+            );
         }
+        let in_flight = self
+            .actors
+            .read()
+            .await
+            .values()
+            .map(|link| link.in_flight.clone())
+            .collect::<Vec<_>>();
+        for in_flight in in_flight {
+            in_flight.cancel_all().await;
+        }
+        self.sessions.clear().await;
+        self.advisory_locks.clear().await;
+        self.query_cache.clear();
+        let links = self.actors.write().await.drain().collect::<Vec<_>>();
+        // close all connections, after the audit records are written
+        for (_, link) in links {
+            if let Some(Ok(audit_log)) = link.audit_log.map(Arc::try_unwrap) {
+                audit_log.close(link.shutdown_grace_period).await;
+            }
+        }
+        // export the remaining spans. Shutting down the exporter blocks until it is done
+        let _ = tokio::task::spawn_blocking(otel::shutdown).await;
         Ok(())
     }
 }
@@ -91,107 +697,2702 @@ fn actor_id(ctx: &Context) -> Result<&String, RpcError> {
 #[async_trait]
 impl SqlDb for SqlDbProvider {
     async fn execute(&self, ctx: &Context, query: &Query) -> RpcResult<ExecuteResult> {
+        self.execute_query(ctx, "SqlDb.Execute", query, &[], None)
+            .await
+    }
+
+    /// perform select query on database, returning all result rows
+    async fn fetch(&self, ctx: &Context, query: &Query) -> RpcResult<FetchResult> {
+        self.fetch_query(ctx, "SqlDb.Fetch", query, &[], None).await
+    }
+}
+
+impl SqlDbProvider {
+    /// the pool of a link: with shared_pool, the pool of the links with the same settings,
+    /// if there is one, and the lease of the shared pool. The checkout reports the errors
+    /// of the pool's connection attempts
+    async fn pool(
+        &self,
+        config: &config::Config,
+        checkout: &mut pool::Checkout,
+    ) -> Result<(Pool, Option<pool::SharedPoolLease>), RpcError> {
+        let key = match config.shared_pool_key() {
+            Some(key) => key,
+            None => {
+                return Ok((
+                    config::create_pool(config, &checkout.errors, &self.connection_quota).await?,
+                    None,
+                ))
+            }
+        };
+        if let Some((pool, errors, lease)) = self.shared_pools.get(&key) {
+            checkout.errors = errors;
+            return Ok((pool, Some(lease)));
+        }
+        let pool = config::create_pool(config, &checkout.errors, &self.connection_quota).await?;
+        let (pool, errors, lease) = self.shared_pools.insert(key, pool, checkout.errors.clone());
+        checkout.errors = errors;
+        Ok((pool, Some(lease)))
+    }
+
+    /// the query of an operation with params, with the placeholder_style of the actor's link.
+    /// If the actor is not linked, the query is unchanged, and the operation fails
+    async fn param_query<'a>(
+        &self,
+        ctx: &Context,
+        query: &'a str,
+        num_params: usize,
+    ) -> RpcResult<Cow<'a, str>> {
+        match self.actors.read().await.get(actor_id(ctx)?) {
+            Some(link) => link.param_query(query, num_params),
+            None => Ok(Cow::Borrowed(query)),
+        }
+    }
+
+    /// execute a statement on a connection from the actor's pool, in the span of the operation
+    async fn execute_query(
+        &self,
+        ctx: &Context,
+        operation: &'static str,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+        timeout: Option<Duration>,
+    ) -> RpcResult<ExecuteResult> {
         let actor_id = actor_id(ctx)?;
+        let _call = match self.drain.start() {
+            Some(call) => call,
+            None => {
+                return Ok(ExecuteResult {
+                    error: Some(DbError::Provider("provider is shutting down".into()).into()),
+                    ..Default::default()
+                })
+            }
+        };
         let rd = self.actors.read().await;
-        let pool = rd
+        let link = rd
             .get(actor_id)
             .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
-        let conn = match pool.get().await {
-            Ok(conn) => conn,
-            Err(e) => {
-                return Ok(ExecuteResult {
-                    error: Some(DbError::Io(format!("connection pool: {}", e)).into()),
+        let span = otel::QuerySpan::start(ctx, actor_id, operation, query, link.trace_statement);
+        let start = Instant::now();
+        // backend of the statement's connection, for the slow query log
+        let mut pid = None;
+        let result = async {
+            if let Err(e) = link.check_statement(query) {
+                return ExecuteResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                };
+            }
+            let mut retried = false;
+            loop {
+                let conn = match link.connection().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        return ExecuteResult {
+                            error: Some(e.into()),
+                            ..Default::default()
+                        }
+                    }
+                };
+                pid = Some(conn.pid);
+                let res = match conn
+                    .timeout(timeout, conn.execute_cached(&link.tagged(query), params))
+                    .await
+                {
+                    Ok(res) => res,
+                    Err(e) => {
+                        error!("{} query:'{}' error:{}", actor_id, link.logged(query), &e);
+                        return ExecuteResult {
+                            error: Some(e.into()),
+                            ..Default::default()
+                        };
+                    }
+                };
+                break match res {
+                    Ok(res) => ExecuteResult {
+                        rows_affected: res,
+                        ..Default::default()
+                    },
+                    Err(db_err) if !retried && link.retries_on(true, &db_err) => {
+                        warn!(
+                            "{} query:'{}' connection lost, retrying on a new connection: {}",
+                            actor_id,
+                            link.logged(query),
+                            &db_err
+                        );
+                        retried = true;
+                        continue;
+                    }
+                    Err(db_err) => {
+                        error!(
+                            "{} query:'{}' error:{}",
+                            actor_id,
+                            link.logged(query),
+                            &db_err.to_string()
+                        );
+                        ExecuteResult {
+                            error: Some(DbError::from(db_err).into()),
+                            ..Default::default()
+                        }
+                    }
+                };
+            }
+        }
+        .await;
+        link.log_slow_query(actor_id, query, start.elapsed(), pid);
+        span.end(result.rows_affected, result.error.as_ref());
+        // statements that were not sent to the database are not recorded
+        if pid.is_some() {
+            link.audit(
+                actor_id,
+                operation,
+                query,
+                Some(result.rows_affected),
+                result.error.as_ref(),
+            );
+        }
+        Ok(result)
+    }
+
+    /// perform a select query on a connection from the actor's pool, in the span of the operation
+    async fn fetch_query(
+        &self,
+        ctx: &Context,
+        operation: &'static str,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+        timeout: Option<Duration>,
+    ) -> RpcResult<FetchResult> {
+        let actor_id = actor_id(ctx)?;
+        let _call = match self.drain.start() {
+            Some(call) => call,
+            None => {
+                return Ok(FetchResult {
+                    error: Some(DbError::Provider("provider is shutting down".into()).into()),
                     ..Default::default()
                 })
             }
         };
-        match conn.execute(query.as_str(), &[]).await {
-            Ok(res) => Ok(ExecuteResult {
-                rows_affected: res,
-                ..Default::default()
-            }),
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let span = otel::QuerySpan::start(ctx, actor_id, operation, query, link.trace_statement);
+        let start = Instant::now();
+        // backend of the statement's connection, for the slow query log
+        let mut pid = None;
+        let result = async {
+            if let Err(e) = link.check_statement(query) {
+                return FetchResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                };
+            }
+            let mut retried = false;
+            loop {
+                let conn = match link.read_connection().await {
+                    Ok((conn, served_by)) => {
+                        debug!("{} query served by the {} pool", actor_id, served_by);
+                        conn
+                    }
+                    Err(e) => {
+                        return FetchResult {
+                            error: Some(e.into()),
+                            ..Default::default()
+                        };
+                    }
+                };
+                pid = Some(conn.pid);
+
+                let res = conn
+                    .timeout(timeout, async {
+                        let stream = conn.query_cached(&link.tagged(query), params).await?;
+                        futures::pin_mut!(stream);
+                        results::fetch_all(stream, link.result_options).await
+                    })
+                    .await;
+                let res = match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        error!("{} query:'{}' error:{}", actor_id, link.logged(query), &e);
+                        return FetchResult {
+                            error: Some(e.into()),
+                            ..Default::default()
+                        };
+                    }
+                };
+                break match res {
+                    Ok(result) => result.into(),
+                    Err(db_err) if !retried && link.retries_on(false, &db_err) => {
+                        warn!(
+                            "{} query:'{}' connection lost, retrying on a new connection: {}",
+                            actor_id,
+                            link.logged(query),
+                            &db_err
+                        );
+                        retried = true;
+                        continue;
+                    }
+                    Err(db_err) => {
+                        error!(
+                            "{} query:'{}' error:{}",
+                            actor_id,
+                            link.logged(query),
+                            &db_err.to_string()
+                        );
+                        FetchResult {
+                            error: Some(DbError::from(db_err).into()),
+                            ..Default::default()
+                        }
+                    }
+                };
+            }
+        }
+        .await;
+        link.log_slow_query(actor_id, query, start.elapsed(), pid);
+        span.end(result.num_rows, result.error.as_ref());
+        if pid.is_some() {
+            link.audit(
+                actor_id,
+                operation,
+                query,
+                Some(result.num_rows),
+                result.error.as_ref(),
+            );
+        }
+        Ok(result)
+    }
+}
+
+impl SqlDbProvider {
+    /// record a statement of the actor in the audit log of its link, if it has one. For
+    /// the statements of open transactions, which do not hold the link while they run
+    async fn audit<E: audit::AuditError>(
+        &self,
+        actor_id: &str,
+        operation: &'static str,
+        statement: &str,
+        rows: Option<u64>,
+        error: Option<&E>,
+    ) {
+        if let Some(link) = self.actors.read().await.get(actor_id) {
+            link.audit(actor_id, operation, statement, rows, error);
+        }
+    }
+
+    /// read the next chunk of output of a copy_out session. The session is closed
+    /// after the last chunk, or if the COPY fails
+    async fn copy_out_chunk(&self, actor_id: &str, token: &str) -> CopyOutResult {
+        let session = match self.sessions.get(actor_id, token).await {
+            Some(session) if session.kind() == session::SessionKind::CopyOut => session,
+            _ => {
+                return CopyOutResult {
+                    error: Some(copy_not_found().into()),
+                    ..Default::default()
+                }
+            }
+        };
+        let res = match session.lock().await {
+            Some(mut state) => match state.output.as_mut() {
+                Some(session::Output::Copy(copy)) => {
+                    let res = copy.next_chunk().await;
+                    session.touch();
+                    res
+                }
+                _ => Ok((Vec::new(), true)),
+            },
+            None => {
+                return CopyOutResult {
+                    error: Some(copy_not_found().into()),
+                    ..Default::default()
+                }
+            }
+        };
+        let result = match res {
+            Ok((data, done)) => CopyOutResult {
+                token: if done {
+                    String::new()
+                } else {
+                    token.to_string()
+                },
+                data,
+                done,
+                error: None,
+            },
             Err(db_err) => {
-                error!(
-                    "{} query:'{}' error:{}",
-                    actor_id,
-                    query,
-                    &db_err.to_string()
-                );
-                Ok(ExecuteResult {
+                error!("{} copy error:{}", actor_id, &db_err.to_string());
+                CopyOutResult {
                     error: Some(DbError::from(db_err).into()),
                     ..Default::default()
-                })
+                }
+            }
+        };
+        if result.done || result.error.is_some() {
+            if let Some(session) = self.sessions.remove(actor_id, token).await {
+                let end = if result.error.is_some() {
+                    "ROLLBACK"
+                } else {
+                    "COMMIT"
+                };
+                if let Err(e) = session.end(end).await {
+                    error!("{} closing copy error:{}", actor_id, e);
+                }
             }
         }
+        result
     }
 
-    /// perform select query on database, returning all result rows
-    async fn fetch(&self, ctx: &Context, query: &Query) -> RpcResult<FetchResult> {
+    /// read the next chunk of rows of a fetch_stream session. The session is closed
+    /// after the last chunk, or if the query fails
+    async fn stream_chunk(&self, actor_id: &str, token: &str) -> FetchStreamResult {
+        let session = match self.sessions.get(actor_id, token).await {
+            Some(session) if session.kind() == session::SessionKind::Stream => session,
+            _ => {
+                return FetchStreamResult {
+                    error: Some(stream_not_found().into()),
+                    ..Default::default()
+                }
+            }
+        };
+        let (res, columns, encoding) = match session.lock().await {
+            Some(mut state) => match state.output.as_mut() {
+                Some(session::Output::Rows(rows)) => {
+                    let res = rows.next_chunk().await;
+                    session.touch();
+                    (res, rows.take_columns(), rows.encoding())
+                }
+                _ => (Ok((Vec::new(), true)), Vec::new(), Default::default()),
+            },
+            None => {
+                return FetchStreamResult {
+                    error: Some(stream_not_found().into()),
+                    ..Default::default()
+                }
+            }
+        };
+        let result = match res {
+            Ok((data, done)) => FetchStreamResult {
+                token: if done {
+                    String::new()
+                } else {
+                    token.to_string()
+                },
+                columns,
+                data,
+                encoding: encoding.as_str().to_string(),
+                done,
+                error: None,
+            },
+            Err(e) => {
+                let e = match e {
+                    results::ReadError::Db(db_err) => DbError::from(db_err),
+                    results::ReadError::Result(e) => e,
+                };
+                error!("{} stream error:{}", actor_id, &e);
+                FetchStreamResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                }
+            }
+        };
+        if result.done || result.error.is_some() {
+            if let Some(session) = self.sessions.remove(actor_id, token).await {
+                let end = if result.error.is_some() {
+                    "ROLLBACK"
+                } else {
+                    "COMMIT"
+                };
+                if let Err(e) = session.end(end).await {
+                    error!("{} closing stream error:{}", actor_id, e);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// SqlDbExt - extended operations of the sqldb-postgres provider
+/// wasmbus.contractId: wasmcloud:sqldb
+/// wasmbus.providerReceive
+#[async_trait]
+impl SqlDbExt for SqlDbProvider {
+    /// run statements in a transaction, on a single connection
+    async fn transaction(
+        &self,
+        ctx: &Context,
+        arg: &TransactionRequest,
+    ) -> RpcResult<TransactionResult> {
         let actor_id = actor_id(ctx)?;
+        if arg.queries.is_empty() {
+            return Err(RpcError::InvalidParameter(
+                "transaction must contain at least one query".into(),
+            ));
+        }
+        let mode = session::TransactionMode::new(
+            arg.isolation_level.as_deref(),
+            arg.read_only,
+            arg.deferrable,
+        )
+        .map_err(RpcError::InvalidParameter)?;
         let rd = self.actors.read().await;
-        let pool = rd
+        let link = rd
             .get(actor_id)
             .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
-        let conn = match pool.get().await {
+        if let Some(e) = arg
+            .queries
+            .iter()
+            .find_map(|query| link.check_statement(query).err())
+        {
+            return Ok(TransactionResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let mut conn = match link.connection().await {
             Ok(conn) => conn,
             Err(e) => {
-                return Ok(FetchResult {
-                    error: Some(DbError::Io(format!("connection pool: {}", e)).into()),
+                return Ok(TransactionResult {
+                    error: Some(e.into()),
                     ..Default::default()
-                });
+                })
             }
         };
-
-        match conn.query(query.as_str(), &[]).await {
-            Ok(rows) => {
-                if rows.is_empty() {
-                    Ok(FetchResult::default())
-                } else {
-                    let cols = rows
-                        .get(0)
-                        .unwrap()
-                        .columns()
-                        .iter()
-                        .enumerate()
-                        .map(|(i, c)| Column {
-                            name: c.name().to_string(),
-                            ordinal: i as u32,
-                            db_type: c.type_().name().to_string(),
-                        })
-                        .collect::<Vec<Column>>();
-                    match encode_result_set(&rows) {
-                        Ok(buf) => Ok(FetchResult {
-                            columns: cols,
-                            num_rows: rows.len() as u64,
-                            error: None,
-                            rows: buf,
-                        }),
-                        Err(e) => Ok(FetchResult {
-                            error: Some(e.into()),
-                            ..Default::default()
-                        }),
+        // retryable errors are retried after a rollback, on the same connection if it is still open
+        let mut retries = 0;
+        let result = loop {
+            match run_transaction(
+                &mut conn,
+                &mode,
+                &arg.queries,
+                actor_id,
+                link.log_statements,
+            )
+            .await
+            {
+                Ok(results) => {
+                    break TransactionResult {
+                        results,
+                        committed: true,
+                        error: None,
+                        retries,
+                    }
+                }
+                Err((results, db_err)) => {
+                    let err = DbError::from(&db_err);
+                    // if the connection was lost during the commit, the transaction may have
+                    // been committed, so it is not run again
+                    let commit_lost = results.len() == arg.queries.len()
+                        && results.last().map_or(false, |r| r.error.is_none())
+                        && error::is_connection_lost(&db_err);
+                    if err.retryable() && !commit_lost && retries < link.max_retries {
+                        let delay = link.retry_delay(retries);
+                        warn!(
+                            "{} transaction failed, retrying in {}ms: {}",
+                            actor_id,
+                            delay.as_millis(),
+                            &err
+                        );
+                        retries += 1;
+                        tokio::time::sleep(delay).await;
+                        if conn.client.is_closed() {
+                            conn = match link.connection().await {
+                                Ok(conn) => conn,
+                                Err(e) => {
+                                    break TransactionResult {
+                                        results,
+                                        committed: false,
+                                        error: Some(e.into()),
+                                        retries,
+                                    }
+                                }
+                            };
+                        }
+                        continue;
                     }
+                    break TransactionResult {
+                        results,
+                        committed: false,
+                        error: Some(err.into()),
+                        retries,
+                    };
                 }
             }
+        };
+        for (query, r) in arg.queries.iter().zip(result.results.iter()) {
+            link.audit(
+                actor_id,
+                "SqlDbExt.Transaction",
+                query,
+                Some(r.rows_affected),
+                r.error.as_ref(),
+            );
+        }
+        let end = if result.committed {
+            "COMMIT"
+        } else {
+            "ROLLBACK"
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.Transaction",
+            end,
+            None,
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// begin a transaction that remains open until commit or rollback
+    async fn begin_transaction(
+        &self,
+        ctx: &Context,
+        arg: &BeginTransactionRequest,
+    ) -> RpcResult<BeginTransactionResult> {
+        let actor_id = actor_id(ctx)?;
+        let mode = session::TransactionMode::new(
+            arg.isolation_level.as_deref(),
+            arg.read_only,
+            arg.deferrable,
+        )
+        .map_err(RpcError::InvalidParameter)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let conn = match link.owned_connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Ok(BeginTransactionResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let result = match self
+            .sessions
+            .begin(actor_id, conn, &mode, link.transaction_idle_timeout)
+            .await
+        {
+            Ok(token) => BeginTransactionResult { token, error: None },
+            Err(e) => BeginTransactionResult {
+                error: Some(e.into()),
+                ..Default::default()
+            },
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.BeginTransaction",
+            "BEGIN",
+            None,
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// execute a statement in an open transaction
+    async fn execute_in(&self, ctx: &Context, arg: &SessionQuery) -> RpcResult<ExecuteResult> {
+        let actor_id = actor_id(ctx)?;
+        if let Some(Err(e)) = self
+            .actors
+            .read()
+            .await
+            .get(actor_id)
+            .map(|link| link.check_statement(&arg.query))
+        {
+            return Ok(ExecuteResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let session = match self.sessions.get(actor_id, &arg.token).await {
+            Some(session) => session,
+            None => {
+                return Ok(ExecuteResult {
+                    error: Some(transaction_not_found().into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let mut state = match session.lock().await {
+            Some(state) => state,
+            None => {
+                return Ok(ExecuteResult {
+                    error: Some(transaction_not_found().into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let log_statements = self
+            .actors
+            .read()
+            .await
+            .get(actor_id)
+            .map_or(true, |link| link.log_statements);
+        let conn = &state.conn;
+        let res = conn
+            .statements
+            .execute(&conn.client, arg.query.as_str(), &[])
+            .await;
+        session.touch();
+        let result = match res {
+            Ok(res) => ExecuteResult {
+                rows_affected: res,
+                ..Default::default()
+            },
             Err(db_err) => {
                 error!(
                     "{} query:'{}' error:{}",
                     actor_id,
-                    query,
+                    logged(&arg.query, log_statements),
                     &db_err.to_string()
                 );
-                Ok(FetchResult {
+                state.aborted = true;
+                ExecuteResult {
                     error: Some(DbError::from(db_err).into()),
                     ..Default::default()
-                })
+                }
             }
-        }
+        };
+        self.audit(
+            actor_id,
+            "SqlDbExt.ExecuteIn",
+            &arg.query,
+            Some(result.rows_affected),
+            result.error.as_ref(),
+        )
+        .await;
+        Ok(result)
+    }
+
+    /// perform select query in an open transaction, returning all result rows
+    async fn fetch_in(&self, ctx: &Context, arg: &SessionQuery) -> RpcResult<FetchResult> {
+        let actor_id = actor_id(ctx)?;
+        if let Some(Err(e)) = self
+            .actors
+            .read()
+            .await
+            .get(actor_id)
+            .map(|link| link.check_statement(&arg.query))
+        {
+            return Ok(FetchResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let session = match self.sessions.get(actor_id, &arg.token).await {
+            Some(session) => session,
+            None => {
+                return Ok(FetchResult {
+                    error: Some(transaction_not_found().into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let mut state = match session.lock().await {
+            Some(state) => state,
+            None => {
+                return Ok(FetchResult {
+                    error: Some(transaction_not_found().into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let (options, log_statements) = match self.actors.read().await.get(actor_id) {
+            Some(link) => (link.result_options, link.log_statements),
+            None => (Default::default(), true),
+        };
+        let conn = &state.conn;
+        let res = match conn
+            .statements
+            .query_raw(&conn.client, arg.query.as_str(), &[])
+            .await
+        {
+            Ok(stream) => {
+                futures::pin_mut!(stream);
+                results::fetch_all(stream, options).await
+            }
+            Err(db_err) => Err(db_err),
+        };
+        session.touch();
+        let result = match res {
+            Ok(result) => FetchResult::from(result),
+            Err(db_err) => {
+                error!(
+                    "{} query:'{}' error:{}",
+                    actor_id,
+                    logged(&arg.query, log_statements),
+                    &db_err.to_string()
+                );
+                state.aborted = true;
+                FetchResult {
+                    error: Some(DbError::from(db_err).into()),
+                    ..Default::default()
+                }
+            }
+        };
+        self.audit(
+            actor_id,
+            "SqlDbExt.FetchIn",
+            &arg.query,
+            Some(result.num_rows),
+            result.error.as_ref(),
+        )
+        .await;
+        Ok(result)
+    }
+
+    /// commit an open transaction. If a statement in the transaction failed,
+    /// the transaction is rolled back, and an error is returned.
+    async fn commit(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<EndTransactionResult> {
+        let actor_id = actor_id(ctx)?;
+        let session = match self.sessions.remove(actor_id, arg).await {
+            Some(session) => session,
+            None => {
+                return Ok(EndTransactionResult {
+                    error: Some(transaction_not_found().into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let aborted = match session.lock().await {
+            Some(state) => state.aborted,
+            None => false,
+        };
+        let (end, result) = if aborted {
+            if let Err(e) = session.end("ROLLBACK").await {
+                error!(
+                    "{} transaction rollback error:{}",
+                    actor_id,
+                    SqlDbError::from(e).message
+                );
+            }
+            let error =
+                DbError::Db("transaction was rolled back because a statement in it failed".into());
+            (
+                "ROLLBACK",
+                EndTransactionResult {
+                    committed: false,
+                    error: Some(error.into()),
+                },
+            )
+        } else {
+            let result = match session.end("COMMIT").await {
+                Ok(()) => EndTransactionResult {
+                    committed: true,
+                    error: None,
+                },
+                Err(e) => EndTransactionResult {
+                    committed: false,
+                    error: Some(e.into()),
+                },
+            };
+            ("COMMIT", result)
+        };
+        self.audit(
+            actor_id,
+            "SqlDbExt.Commit",
+            end,
+            None,
+            result.error.as_ref(),
+        )
+        .await;
+        Ok(result)
+    }
+
+    /// roll back an open transaction
+    async fn rollback(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<EndTransactionResult> {
+        let actor_id = actor_id(ctx)?;
+        let error = match self.sessions.remove(actor_id, arg).await {
+            Some(session) => {
+                let error = session
+                    .end("ROLLBACK")
+                    .await
+                    .err()
+                    .map(ext::SqlDbExtError::from);
+                self.audit(
+                    actor_id,
+                    "SqlDbExt.Rollback",
+                    "ROLLBACK",
+                    None,
+                    error.as_ref(),
+                )
+                .await;
+                error
+            }
+            None => Some(transaction_not_found().into()),
+        };
+        Ok(EndTransactionResult {
+            committed: false,
+            error,
+        })
+    }
+
+    /// prepare an open transaction for two-phase commit, and return its connection to the pool
+    async fn prepare_transaction(
+        &self,
+        ctx: &Context,
+        arg: &PrepareTransactionRequest,
+    ) -> RpcResult<PrepareTransactionResult> {
+        let actor_id = actor_id(ctx)?;
+        let statement = prepared::statement("PREPARE TRANSACTION", &arg.gid)
+            .map_err(RpcError::InvalidParameter)?;
+        let session = match self.sessions.remove(actor_id, &arg.token).await {
+            Some(session) => session,
+            None => {
+                return Ok(PrepareTransactionResult {
+                    error: Some(transaction_not_found().into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let aborted = match session.lock().await {
+            Some(state) => state.aborted,
+            None => false,
+        };
+        let (end, result) = if aborted {
+            if let Err(e) = session.end("ROLLBACK").await {
+                error!(
+                    "{} transaction rollback error:{}",
+                    actor_id,
+                    SqlDbError::from(e).message
+                );
+            }
+            let error =
+                DbError::Db("transaction was rolled back because a statement in it failed".into());
+            (
+                "ROLLBACK",
+                PrepareTransactionResult {
+                    prepared: false,
+                    error: Some(error.into()),
+                },
+            )
+        } else {
+            // if PREPARE fails, the server rolls the transaction back
+            let result = match session.end(&statement).await {
+                Ok(()) => {
+                    self.prepared.insert(actor_id, &arg.gid);
+                    PrepareTransactionResult {
+                        prepared: true,
+                        error: None,
+                    }
+                }
+                Err(e) => PrepareTransactionResult {
+                    prepared: false,
+                    error: Some(e.into()),
+                },
+            };
+            (statement.as_str(), result)
+        };
+        self.audit(
+            actor_id,
+            "SqlDbExt.PrepareTransaction",
+            end,
+            None,
+            result.error.as_ref(),
+        )
+        .await;
+        Ok(result)
+    }
+
+    /// commit a prepared transaction
+    async fn commit_prepared(
+        &self,
+        ctx: &Context,
+        arg: &PreparedTransactionRequest,
+    ) -> RpcResult<EndTransactionResult> {
+        self.end_prepared(ctx, &arg.gid, true).await
+    }
+
+    /// roll back a prepared transaction
+    async fn rollback_prepared(
+        &self,
+        ctx: &Context,
+        arg: &PreparedTransactionRequest,
+    ) -> RpcResult<EndTransactionResult> {
+        self.end_prepared(ctx, &arg.gid, false).await
+    }
+
+    /// execute a statement with parameters
+    async fn execute_params(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<ExecuteResult> {
+        let params = params::decode_params(&arg.params).map_err(RpcError::InvalidParameter)?;
+        let query = self.param_query(ctx, &arg.query, params.len()).await?;
+        self.execute_query(
+            ctx,
+            "SqlDbExt.ExecuteParams",
+            &query,
+            &params::as_sql(&params),
+            timeout(arg.timeout_ms),
+        )
+        .await
+    }
+
+    /// perform select query with parameters, returning all result rows
+    async fn fetch_params(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchResult> {
+        let params = params::decode_params(&arg.params).map_err(RpcError::InvalidParameter)?;
+        let query = self.param_query(ctx, &arg.query, params.len()).await?;
+        self.fetch_query(
+            ctx,
+            "SqlDbExt.FetchParams",
+            &query,
+            &params::as_sql(&params),
+            timeout(arg.timeout_ms),
+        )
+        .await
+    }
+
+    /// execute independent statements in order, on one connection
+    async fn execute_batch(&self, ctx: &Context, arg: &BatchRequest) -> RpcResult<ExecuteResults> {
+        let actor_id = actor_id(ctx)?;
+        if arg.queries.is_empty() {
+            return Err(RpcError::InvalidParameter(
+                "batch must contain at least one query".into(),
+            ));
+        }
+        if arg.pipeline && arg.stop_on_error {
+            return Err(RpcError::InvalidParameter(
+                "stopOnError cannot be used with pipeline: the statements are sent \
+                 before the first one completes"
+                    .into(),
+            ));
+        }
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let conn = match link.connection().await {
+            Ok(conn) => conn,
+            // reported as the result of the first statement, which could not be run
+            Err(e) => {
+                return Ok(vec![ExecuteResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                }])
+            }
+        };
+        if arg.pipeline {
+            // statements that may not be run are not sent
+            let checked = arg
+                .queries
+                .iter()
+                .map(|query| link.check_statement(query))
+                .collect::<Vec<_>>();
+            let sent = arg
+                .queries
+                .iter()
+                .zip(checked.iter())
+                .filter(|(_, checked)| checked.is_ok())
+                .map(|(query, _)| query.as_str())
+                .collect::<Vec<_>>();
+            let mut sent_results = conn.execute_pipelined(&sent).await.into_iter();
+            let results = arg
+                .queries
+                .iter()
+                .zip(checked)
+                .map(|(query, checked)| match checked {
+                    Err(e) => ExecuteResult {
+                        error: Some(e.into()),
+                        ..Default::default()
+                    },
+                    Ok(()) => {
+                        let res = sent_results
+                            .next()
+                            .expect("a result for each statement sent");
+                        link.batch_result(actor_id, query, res)
+                    }
+                })
+                .collect();
+            return Ok(results);
+        }
+        let mut results = Vec::with_capacity(arg.queries.len());
+        for query in arg.queries.iter() {
+            if let Err(e) = link.check_statement(query) {
+                results.push(ExecuteResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                });
+                if arg.stop_on_error {
+                    break;
+                }
+                continue;
+            }
+            let result = link.batch_result(actor_id, query, conn.execute_cached(query, &[]).await);
+            let failed = result.error.is_some();
+            results.push(result);
+            if failed && arg.stop_on_error {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// execute a statement, returning the rows it returns and the number of rows affected
+    async fn execute_returning(
+        &self,
+        ctx: &Context,
+        arg: &ParamQuery,
+    ) -> RpcResult<ExecuteReturningResult> {
+        let actor_id = actor_id(ctx)?;
+        let params = params::decode_params(&arg.params).map_err(RpcError::InvalidParameter)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let query = link.param_query(&arg.query, params.len())?;
+        if let Err(e) = link.check_statement(&query) {
+            return Ok(ExecuteReturningResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let acquire = Instant::now();
+        let conn = match link.connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Ok(ExecuteReturningResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let acquire_ms = acquire.elapsed().as_millis() as u64;
+        let params = params::as_sql(&params);
+        // notices of earlier statements on the connection
+        conn.take_notices();
+        let start = Instant::now();
+        let res = conn
+            .timeout(timeout(arg.timeout_ms), async {
+                let stream = conn
+                    .query_cached(&query, &params)
+                    .await
+                    .map_err(results::ReadError::Db)?;
+                futures::pin_mut!(stream);
+                let mut enc = results::RowEncoder::new(link.result_options);
+                results::read_rows(stream.as_mut(), &mut enc, None).await?;
+                // rows_affected is known after the last row
+                Ok((enc, stream.rows_affected().unwrap_or_default()))
+            })
+            .await
+            .unwrap_or_else(|e| Err(results::ReadError::Result(e)));
+        let duration_ms = start.elapsed().as_millis() as u64;
+        let notices = conn.take_notices();
+        let result = match res {
+            Ok((enc, rows_affected)) => {
+                let fetched = enc.finish();
+                ExecuteReturningResult {
+                    rows_affected,
+                    command_tag: command_tag::command_tag(&query),
+                    num_rows: fetched.num_rows,
+                    columns: fetched.columns,
+                    rows: fetched.rows,
+                    encoding: fetched.encoding.as_str().to_string(),
+                    duration_ms,
+                    acquire_ms,
+                    notices,
+                    error: fetched.error.map(Into::into),
+                }
+            }
+            Err(results::ReadError::Db(db_err)) => {
+                error!(
+                    "{} query:'{}' error:{}",
+                    actor_id,
+                    link.logged(&query),
+                    &db_err.to_string()
+                );
+                ExecuteReturningResult {
+                    notices,
+                    error: Some(DbError::from(db_err).into()),
+                    ..Default::default()
+                }
+            }
+            Err(results::ReadError::Result(e)) => ExecuteReturningResult {
+                notices,
+                error: Some(e.into()),
+                ..Default::default()
+            },
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.ExecuteReturning",
+            &query,
+            Some(result.rows_affected),
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// open a cursor for a query, in a transaction on a connection held for the cursor
+    async fn fetch_cursor(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<CursorResult> {
+        let actor_id = actor_id(ctx)?;
+        let params = params::decode_params(&arg.params).map_err(RpcError::InvalidParameter)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let query = link.param_query(&arg.query, params.len())?;
+        if let Err(e) = link.check_statement(&query) {
+            return Ok(CursorResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let conn = match link.owned_connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Ok(CursorResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let result = match self
+            .sessions
+            .declare_cursor(
+                actor_id,
+                conn,
+                &query,
+                &params::as_sql(&params),
+                link.transaction_idle_timeout,
+            )
+            .await
+        {
+            Ok(token) => CursorResult { token, error: None },
+            Err(e) => {
+                error!(
+                    "{} cursor query:'{}' error:{}",
+                    actor_id,
+                    link.logged(&query),
+                    &e
+                );
+                CursorResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                }
+            }
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.FetchCursor",
+            &query,
+            None,
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// read the next rows of a cursor. The cursor is closed when it has been read to the end
+    async fn fetch_next(
+        &self,
+        ctx: &Context,
+        arg: &FetchNextRequest,
+    ) -> RpcResult<FetchNextResult> {
+        let actor_id = actor_id(ctx)?;
+        if arg.batch_size == 0 {
+            return Err(RpcError::InvalidParameter(
+                "batch_size must be at least 1".into(),
+            ));
+        }
+        let session = match self.sessions.get(actor_id, &arg.token).await {
+            Some(session) if session.kind() == session::SessionKind::Cursor => session,
+            _ => {
+                return Ok(FetchNextResult {
+                    error: Some(cursor_not_found().into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let options = match self.actors.read().await.get(actor_id) {
+            Some(link) => link.result_options,
+            None => Default::default(),
+        };
+        let res = match session.lock().await {
+            Some(state) => {
+                let fetch = format!(
+                    "FETCH FORWARD {} FROM {}",
+                    arg.batch_size,
+                    session::CURSOR_NAME
+                );
+                let res = match state
+                    .conn
+                    .query_raw(fetch.as_str(), std::iter::empty::<&(dyn ToSql + Sync)>())
+                    .await
+                {
+                    Ok(stream) => {
+                        futures::pin_mut!(stream);
+                        results::fetch_all(stream, options).await
+                    }
+                    Err(db_err) => Err(db_err),
+                };
+                session.touch();
+                res
+            }
+            None => {
+                return Ok(FetchNextResult {
+                    error: Some(cursor_not_found().into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let result = match res {
+            Ok(fetched) => FetchNextResult {
+                done: fetched.num_rows < arg.batch_size as u64,
+                num_rows: fetched.num_rows,
+                columns: fetched.columns,
+                rows: fetched.rows,
+                encoding: fetched.encoding.as_str().to_string(),
+                error: fetched.error.map(Into::into),
+            },
+            Err(db_err) => {
+                error!("{} cursor fetch error:{}", actor_id, &db_err.to_string());
+                FetchNextResult {
+                    error: Some(DbError::from(db_err).into()),
+                    ..Default::default()
+                }
+            }
+        };
+        if result.done || result.error.is_some() {
+            if let Some(session) = self.sessions.remove(actor_id, &arg.token).await {
+                let end = if result.error.is_some() {
+                    "ROLLBACK"
+                } else {
+                    "COMMIT"
+                };
+                if let Err(e) = session.end(end).await {
+                    error!("{} closing cursor error:{}", actor_id, e);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// perform select query, returning one page of the results
+    async fn fetch_page(&self, ctx: &Context, arg: &PageRequest) -> RpcResult<PageResult> {
+        let actor_id = actor_id(ctx)?;
+        if arg.limit == 0 {
+            return Err(RpcError::InvalidParameter(
+                "limit must be at least 1".into(),
+            ));
+        }
+        let offset = i64::try_from(arg.offset)
+            .map_err(|_| RpcError::InvalidParameter("offset is too large".into()))?;
+        let mut params = params::decode_params(&arg.params).map_err(RpcError::InvalidParameter)?;
+        let num_params = params.len();
+        // one extra row shows whether there is another page
+        params.push(params::Param::Int(arg.limit as i64 + 1));
+        params.push(params::Param::Int(offset));
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let arg_query = link.param_query(&arg.query, num_params)?;
+        let query = page::page_query(&arg_query, num_params);
+        if let Err(e) = link.check_statement(&arg_query) {
+            return Ok(PageResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let acquire = Instant::now();
+        let conn = match link.read_connection().await {
+            Ok((conn, served_by)) => {
+                debug!("{} page query served by the {} pool", actor_id, served_by);
+                conn
+            }
+            Err(e) => {
+                return Ok(PageResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let acquire_ms = acquire.elapsed().as_millis() as u64;
+        // notices of earlier statements on the connection
+        conn.take_notices();
+        let start = Instant::now();
+        let mut enc = results::RowEncoder::new(link.result_options);
+        let res = match conn.query_cached(&query, &params::as_sql(&params)).await {
+            Ok(stream) => {
+                futures::pin_mut!(stream);
+                results::read_rows(stream, &mut enc, Some(arg.limit as u64)).await
+            }
+            Err(db_err) => Err(results::ReadError::Db(db_err)),
+        };
+        let duration_ms = start.elapsed().as_millis() as u64;
+        let notices = conn.take_notices();
+        let more = match res {
+            Ok(more) => more,
+            Err(results::ReadError::Db(db_err)) => {
+                error!(
+                    "{} query:'{}' error:{}",
+                    actor_id,
+                    link.logged(&query),
+                    &db_err.to_string()
+                );
+                let result = PageResult {
+                    notices,
+                    error: Some(DbError::from(db_err).into()),
+                    ..Default::default()
+                };
+                link.audit(
+                    actor_id,
+                    "SqlDbExt.FetchPage",
+                    &query,
+                    None,
+                    result.error.as_ref(),
+                );
+                return Ok(result);
+            }
+            Err(results::ReadError::Result(e)) => {
+                let result = PageResult {
+                    notices,
+                    error: Some(e.into()),
+                    ..Default::default()
+                };
+                link.audit(
+                    actor_id,
+                    "SqlDbExt.FetchPage",
+                    &query,
+                    None,
+                    result.error.as_ref(),
+                );
+                return Ok(result);
+            }
+        };
+        let num_rows = enc.num_rows();
+        let total_rows = if !more && (num_rows > 0 || arg.offset == 0) {
+            Some(arg.offset + num_rows)
+        } else {
+            // the actor's parameters, without limit and offset
+            let params = params::as_sql(&params[..params.len() - 2]);
+            match conn
+                .query(page::explain_query(&arg_query).as_str(), &params)
+                .await
+            {
+                Ok(plan) => plan
+                    .first()
+                    .and_then(|row| row.try_get::<_, &str>(0).ok())
+                    .and_then(page::plan_rows),
+                Err(e) => {
+                    debug!("{} estimating rows of query: {}", actor_id, e);
+                    None
+                }
+            }
+        };
+        let fetched = enc.finish();
+        let result = PageResult {
+            num_rows: fetched.num_rows,
+            columns: fetched.columns,
+            rows: fetched.rows,
+            encoding: fetched.encoding.as_str().to_string(),
+            more,
+            total_rows,
+            duration_ms,
+            acquire_ms,
+            notices,
+            error: fetched.error.map(Into::into),
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.FetchPage",
+            &query,
+            Some(result.num_rows),
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// copy data into a table
+    async fn copy_in(&self, ctx: &Context, arg: &CopyInRequest) -> RpcResult<ExecuteResult> {
+        let actor_id = actor_id(ctx)?;
+        let statement = copy::copy_in_statement(arg).map_err(RpcError::InvalidParameter)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        if let Err(e) = link.check_statement(&statement) {
+            return Ok(ExecuteResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let conn = match link.connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Ok(ExecuteResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let result = match copy::copy_in(&conn, &statement, &arg.data).await {
+            Ok(rows) => ExecuteResult {
+                rows_affected: rows,
+                ..Default::default()
+            },
+            Err(db_err) => {
+                error!(
+                    "{} copy:'{}' error:{}",
+                    actor_id,
+                    link.logged(&statement),
+                    &db_err.to_string()
+                );
+                ExecuteResult {
+                    error: Some(DbError::from(db_err).into()),
+                    ..Default::default()
+                }
+            }
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.CopyIn",
+            &statement,
+            Some(result.rows_affected),
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// start exporting the results of a query with COPY, and return its first chunk
+    async fn copy_out(&self, ctx: &Context, arg: &CopyOutRequest) -> RpcResult<CopyOutResult> {
+        let actor_id = actor_id(ctx)?;
+        let statement = copy::copy_out_statement(arg).map_err(RpcError::InvalidParameter)?;
+        let chunk_size = match arg.chunk_size {
+            0 => copy::DEFAULT_CHUNK_SIZE,
+            n => n as usize,
+        };
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        if let Err(e) = link.check_statement(&arg.query) {
+            return Ok(CopyOutResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let conn = match link.owned_connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Ok(CopyOutResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let token = match self
+            .sessions
+            .copy_out(
+                actor_id,
+                conn,
+                &statement,
+                chunk_size,
+                link.transaction_idle_timeout,
+            )
+            .await
+        {
+            Ok(token) => token,
+            Err(e) => {
+                error!(
+                    "{} copy:'{}' error:{}",
+                    actor_id,
+                    link.logged(&statement),
+                    &e
+                );
+                let result = CopyOutResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                };
+                link.audit(
+                    actor_id,
+                    "SqlDbExt.CopyOut",
+                    &statement,
+                    None,
+                    result.error.as_ref(),
+                );
+                return Ok(result);
+            }
+        };
+        link.audit::<ext::SqlDbExtError>(actor_id, "SqlDbExt.CopyOut", &statement, None, None);
+        Ok(self.copy_out_chunk(actor_id, &token).await)
+    }
+
+    /// read the next chunk of a COPY export. The export is closed after its last chunk
+    async fn copy_out_next(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<CopyOutResult> {
+        let actor_id = actor_id(ctx)?;
+        Ok(self.copy_out_chunk(actor_id, arg).await)
+    }
+
+    /// start a select query, and return the first chunk of its rows
+    async fn fetch_stream(
+        &self,
+        ctx: &Context,
+        arg: &FetchStreamRequest,
+    ) -> RpcResult<FetchStreamResult> {
+        let actor_id = actor_id(ctx)?;
+        let params = params::decode_params(&arg.params).map_err(RpcError::InvalidParameter)?;
+        let chunk_size = match arg.chunk_size {
+            0 => copy::DEFAULT_CHUNK_SIZE,
+            n => n as usize,
+        };
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let query = link.param_query(&arg.query, params.len())?;
+        if let Err(e) = link.check_statement(&query) {
+            return Ok(FetchStreamResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let conn = match link.owned_connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Ok(FetchStreamResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let token = match self
+            .sessions
+            .stream_rows(
+                actor_id,
+                conn,
+                &query,
+                &params::as_sql(&params),
+                link.result_options,
+                chunk_size,
+                link.transaction_idle_timeout,
+            )
+            .await
+        {
+            Ok(token) => token,
+            Err(e) => {
+                error!(
+                    "{} stream query:'{}' error:{}",
+                    actor_id,
+                    link.logged(&query),
+                    &e
+                );
+                let result = FetchStreamResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                };
+                link.audit(
+                    actor_id,
+                    "SqlDbExt.FetchStream",
+                    &query,
+                    None,
+                    result.error.as_ref(),
+                );
+                return Ok(result);
+            }
+        };
+        link.audit::<ext::SqlDbExtError>(actor_id, "SqlDbExt.FetchStream", &query, None, None);
+        Ok(self.stream_chunk(actor_id, &token).await)
+    }
+
+    /// read the next chunk of rows of a stream. The stream is closed after its last chunk
+    async fn fetch_stream_next(
+        &self,
+        ctx: &Context,
+        arg: &TransactionToken,
+    ) -> RpcResult<FetchStreamResult> {
+        let actor_id = actor_id(ctx)?;
+        Ok(self.stream_chunk(actor_id, arg).await)
+    }
+
+    /// statistics of the connection pool of the actor's link
+    async fn pool_stats(&self, ctx: &Context) -> RpcResult<PoolStats> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        Ok(link.metrics.stats(&link.pool))
+    }
+
+    /// version of the database server, and extensions of the database, which are read
+    /// on the first call for the link
+    async fn server_info(&self, ctx: &Context) -> RpcResult<ServerInfo> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let res = link
+            .server_info
+            .get_or_try_init(|| async {
+                let conn = link.connection().await?;
+                catalog::server_info(&conn).await
+            })
+            .await;
+        match res {
+            Ok(info) => Ok(info.clone()),
+            Err(e) => Ok(ServerInfo {
+                error: Some(e.into()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// take an advisory lock on a connection that is held until the lock is released
+    async fn advisory_lock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult> {
+        self.take_advisory_lock(ctx, arg, true).await
+    }
+
+    /// take an advisory lock, if it is available, on a connection that is held
+    /// until the lock is released
+    async fn try_advisory_lock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult> {
+        self.take_advisory_lock(ctx, arg, false).await
+    }
+
+    /// release an advisory lock, and return its connection to the pool
+    async fn advisory_unlock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+    ) -> RpcResult<AdvisoryLockResult> {
+        let actor_id = actor_id(ctx)?;
+        // not refused if the audit log is down, so that the lock's connection is returned
+        let result = match self.advisory_locks.unlock(actor_id, arg.key).await {
+            Ok(()) => AdvisoryLockResult::default(),
+            Err(e) => AdvisoryLockResult {
+                error: Some(e.into()),
+                ..Default::default()
+            },
+        };
+        self.audit(
+            actor_id,
+            "SqlDbExt.AdvisoryUnlock",
+            advisory_lock::UNLOCK,
+            None,
+            result.error.as_ref(),
+        )
+        .await;
+        Ok(result)
+    }
+
+    /// send a notification with pg_notify, on a connection from the pool,
+    /// or in an open transaction
+    async fn notify(&self, ctx: &Context, arg: &NotifyRequest) -> RpcResult<NotifyResult> {
+        let actor_id = actor_id(ctx)?;
+        if !listen::valid_channel(&arg.channel) {
+            return Err(RpcError::InvalidParameter(format!(
+                "invalid channel name '{}'",
+                arg.channel
+            )));
+        }
+        const NOTIFY: &str = "SELECT pg_notify($1, $2)";
+        if let Some(Err(e)) = self
+            .actors
+            .read()
+            .await
+            .get(actor_id)
+            .map(|link| link.check_statement(NOTIFY))
+        {
+            return Ok(NotifyResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let params: [&(dyn ToSql + Sync); 2] = [&arg.channel, &arg.payload];
+        let res = match &arg.token {
+            Some(token) => {
+                let session = match self.sessions.get(actor_id, token).await {
+                    Some(session) => session,
+                    None => {
+                        return Ok(NotifyResult {
+                            error: Some(transaction_not_found().into()),
+                            ..Default::default()
+                        })
+                    }
+                };
+                let mut state = match session.lock().await {
+                    Some(state) => state,
+                    None => {
+                        return Ok(NotifyResult {
+                            error: Some(transaction_not_found().into()),
+                            ..Default::default()
+                        })
+                    }
+                };
+                let conn = &state.conn;
+                let res = conn.statements.execute(&conn.client, NOTIFY, &params).await;
+                session.touch();
+                if res.is_err() {
+                    state.aborted = true;
+                }
+                res.map_err(DbError::from)
+            }
+            None => {
+                let rd = self.actors.read().await;
+                let link = rd.get(actor_id).ok_or_else(|| {
+                    RpcError::InvalidParameter(format!("actor not linked:{}", actor_id))
+                })?;
+                let res = match link.connection().await {
+                    Ok(conn) => conn
+                        .execute_cached(NOTIFY, &params)
+                        .await
+                        .map_err(DbError::from),
+                    Err(e) => Err(e),
+                };
+                drop(rd);
+                res
+            }
+        };
+        let result = match res {
+            Ok(_) => NotifyResult {
+                queued: true,
+                error: None,
+            },
+            Err(e) => {
+                error!("{} notify on '{}' error:{}", actor_id, &arg.channel, &e);
+                NotifyResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                }
+            }
+        };
+        self.audit(
+            actor_id,
+            "SqlDbExt.Notify",
+            NOTIFY,
+            None,
+            result.error.as_ref(),
+        )
+        .await;
+        Ok(result)
+    }
+
+    /// list the tables of a schema, or of the link's search path
+    async fn list_tables(
+        &self,
+        ctx: &Context,
+        arg: &ListTablesRequest,
+    ) -> RpcResult<ListTablesResult> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let res = match link.connection().await {
+            Ok(conn) => catalog::tables(&conn, arg.schema.as_deref()).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(tables) => Ok(ListTablesResult {
+                tables,
+                error: None,
+            }),
+            Err(e) => Ok(ListTablesResult {
+                error: Some(e.into()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// list the columns of a table
+    async fn list_columns(
+        &self,
+        ctx: &Context,
+        arg: &TableRequest,
+    ) -> RpcResult<ListColumnsResult> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let res = match link.connection().await {
+            Ok(conn) => catalog::columns(&conn, arg.schema.as_deref(), &arg.table).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(columns) => Ok(ListColumnsResult {
+                columns,
+                error: None,
+            }),
+            Err(e) => Ok(ListColumnsResult {
+                error: Some(e.into()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// list the indexes of a table
+    async fn list_indexes(
+        &self,
+        ctx: &Context,
+        arg: &TableRequest,
+    ) -> RpcResult<ListIndexesResult> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let res = match link.connection().await {
+            Ok(conn) => catalog::indexes(&conn, arg.schema.as_deref(), &arg.table).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(indexes) => Ok(ListIndexesResult {
+                indexes,
+                error: None,
+            }),
+            Err(e) => Ok(ListIndexesResult {
+                error: Some(e.into()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// cancel the statements running on the connections of the actor's link
+    async fn cancel_queries(&self, ctx: &Context) -> RpcResult<CancelQueriesResult> {
+        let actor_id = actor_id(ctx)?;
+        let in_flight = match self.actors.read().await.get(actor_id) {
+            Some(link) => link.in_flight.clone(),
+            None => {
+                return Err(RpcError::InvalidParameter(format!(
+                    "actor not linked:{}",
+                    actor_id
+                )))
+            }
+        };
+        let pids = in_flight.cancel_all().await;
+        Ok(CancelQueriesResult { pids, error: None })
+    }
+
+    /// return the cached result of the query, or run the query and cache its result
+    async fn fetch_cached(&self, ctx: &Context, arg: &CachedQuery) -> RpcResult<FetchResult> {
+        let actor_id = actor_id(ctx)?;
+        if let Some(result) = self.query_cache.get(actor_id, &arg.query) {
+            debug!("{} query served from the cache", actor_id);
+            return Ok(result);
+        }
+        let result = self
+            .fetch_query(ctx, "SqlDbExt.FetchCached", &arg.query, &[], None)
+            .await?;
+        // errors are not cached, so the query is run again on the next call
+        if result.error.is_none() && arg.ttl_ms > 0 {
+            self.query_cache.insert(
+                actor_id,
+                &arg.query,
+                result.clone(),
+                Duration::from_millis(arg.ttl_ms as u64),
+            );
+        }
+        Ok(result)
+    }
+
+    async fn invalidate_cache(
+        &self,
+        ctx: &Context,
+        arg: &InvalidateCacheRequest,
+    ) -> RpcResult<InvalidateCacheResult> {
+        let actor_id = actor_id(ctx)?;
+        let invalidated = self.query_cache.invalidate(actor_id, arg.query.as_deref());
+        Ok(InvalidateCacheResult {
+            invalidated: invalidated as u32,
+            error: None,
+        })
+    }
+
+    /// call a procedure in a transaction, and fetch the cursors it returns
+    async fn call(&self, ctx: &Context, arg: &CallRequest) -> RpcResult<CallResult> {
+        let actor_id = actor_id(ctx)?;
+        let params = params::decode_params(&arg.params).map_err(RpcError::InvalidParameter)?;
+        let statement = call::call_statement(&arg.procedure, params.len())
+            .map_err(RpcError::InvalidParameter)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        if let Err(e) = link.check_statement(&statement) {
+            return Ok(CallResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let mut conn = match link.connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Ok(CallResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        // notices of earlier statements on the connection
+        conn.take_notices();
+        let start = Instant::now();
+        let res = call::run(
+            &mut conn.client,
+            &statement,
+            &params::as_sql(&params),
+            link.result_options,
+        )
+        .await;
+        link.log_slow_query(actor_id, &statement, start.elapsed(), Some(conn.pid));
+        let notices = conn.take_notices();
+        let encoding = link.result_options.result_encoding.as_str().to_string();
+        let result = match res {
+            Ok(called) => CallResult {
+                outputs: called.outputs,
+                result_sets: called.result_sets,
+                encoding,
+                notices,
+                error: None,
+            },
+            Err(results::ReadError::Db(db_err)) => {
+                error!(
+                    "{} call:'{}' error:{}",
+                    actor_id,
+                    link.logged(&statement),
+                    &db_err.to_string()
+                );
+                CallResult {
+                    notices,
+                    error: Some(DbError::from(db_err).into()),
+                    ..Default::default()
+                }
+            }
+            Err(results::ReadError::Result(e)) => CallResult {
+                notices,
+                error: Some(e.into()),
+                ..Default::default()
+            },
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.Call",
+            &statement,
+            None,
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// create an empty large object
+    async fn lo_create(&self, ctx: &Context) -> RpcResult<LoCreateResult> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let statement = "select lo_create(0)";
+        if let Err(e) = link.check_write("LoCreate") {
+            return Ok(LoCreateResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let res = match link.connection().await {
+            Ok(conn) => lo::create(&conn).await.map_err(|db_err| {
+                error!("{} lo_create error:{}", actor_id, &db_err.to_string());
+                DbError::from(db_err)
+            }),
+            Err(e) => Err(e),
+        };
+        let result = match res {
+            Ok(oid) => LoCreateResult { oid, error: None },
+            Err(e) => LoCreateResult {
+                error: Some(e.into()),
+                ..Default::default()
+            },
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.LoCreate",
+            statement,
+            None,
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// write data to a large object, in chunks, in a transaction
+    async fn lo_write(&self, ctx: &Context, arg: &LoWriteRequest) -> RpcResult<LoResult> {
+        let actor_id = actor_id(ctx)?;
+        let offset = lo_offset(arg.offset)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let statement = format!(
+            "lowrite: oid {}, offset {}, {} bytes",
+            arg.oid,
+            arg.offset,
+            arg.data.len()
+        );
+        if let Err(e) = link.check_write("LoWrite") {
+            return Ok(LoResult {
+                error: Some(e.into()),
+            });
+        }
+        let res = match link.connection().await {
+            Ok(mut conn) => lo::write(&mut conn, arg.oid, offset, &arg.data)
+                .await
+                .map_err(|db_err| {
+                    error!("{} lo_write error:{}", actor_id, &db_err.to_string());
+                    DbError::from(db_err)
+                }),
+            Err(e) => Err(e),
+        };
+        let result = LoResult {
+            error: res.err().map(Into::into),
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.LoWrite",
+            &statement,
+            None,
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// read part of a large object, in chunks, in a transaction
+    async fn lo_read(&self, ctx: &Context, arg: &LoReadRequest) -> RpcResult<LoReadResult> {
+        let actor_id = actor_id(ctx)?;
+        let offset = lo_offset(arg.offset)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        if let Some(max) = link.result_options.limits.max_bytes {
+            if arg.len as u64 > max {
+                return Ok(LoReadResult {
+                    error: Some(
+                        DbError::LimitExceeded(format!(
+                            "len {} is larger than max_result_bytes ({})",
+                            arg.len, max
+                        ))
+                        .into(),
+                    ),
+                    ..Default::default()
+                });
+            }
+        }
+        let res = match link.connection().await {
+            Ok(mut conn) => lo::read(&mut conn, arg.oid, offset, arg.len as usize)
+                .await
+                .map_err(|db_err| {
+                    error!("{} lo_read error:{}", actor_id, &db_err.to_string());
+                    DbError::from(db_err)
+                }),
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok((data, eof)) => Ok(LoReadResult {
+                data,
+                eof,
+                error: None,
+            }),
+            Err(e) => Ok(LoReadResult {
+                error: Some(e.into()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// delete a large object
+    async fn lo_unlink(&self, ctx: &Context, arg: &LoUnlinkRequest) -> RpcResult<LoResult> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let statement = format!("lo_unlink: oid {}", arg.oid);
+        if let Err(e) = link.check_write("LoUnlink") {
+            return Ok(LoResult {
+                error: Some(e.into()),
+            });
+        }
+        let res = match link.connection().await {
+            Ok(conn) => lo::unlink(&conn, arg.oid).await.map_err(|db_err| {
+                error!("{} lo_unlink error:{}", actor_id, &db_err.to_string());
+                DbError::from(db_err)
+            }),
+            Err(e) => Err(e),
+        };
+        let result = LoResult {
+            error: res.err().map(Into::into),
+        };
+        link.audit(
+            actor_id,
+            "SqlDbExt.LoUnlink",
+            &statement,
+            None,
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+
+    /// perform a select query that returns exactly one row
+    async fn fetch_one(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchRowResult> {
+        self.fetch_row(ctx, "SqlDbExt.FetchOne", arg, false).await
+    }
+
+    /// perform a select query that returns at most one row
+    async fn fetch_optional(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchRowResult> {
+        self.fetch_row(ctx, "SqlDbExt.FetchOptional", arg, true)
+            .await
+    }
+
+    /// perform a select query, returning the value of the first column of its first row
+    async fn fetch_scalar(&self, ctx: &Context, arg: &ParamQuery) -> RpcResult<FetchScalarResult> {
+        let result = match self
+            .fetch_single_row(ctx, "SqlDbExt.FetchScalar", arg, results::RowShape::Scalar)
+            .await?
+        {
+            Ok(results::SingleRow {
+                value, encoding, ..
+            }) => FetchScalarResult {
+                value: value.unwrap_or_else(|| encoding.null()),
+                encoding: encoding.as_str().to_string(),
+                error: None,
+            },
+            Err(e) => FetchScalarResult {
+                error: Some(e),
+                ..Default::default()
+            },
+        };
+        Ok(result)
+    }
+
+    /// close the prepared statements cached on the connections of the actor's pools
+    async fn clear_statement_cache(&self, ctx: &Context) -> RpcResult<ClearStatementCacheResult> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        *link.statements_cleared.lock().unwrap() = Some(Instant::now());
+        debug!("{} statement cache cleared", actor_id);
+        Ok(ClearStatementCacheResult { error: None })
+    }
+
+    /// insert rows of values into a table, with multi-row INSERT statements in a transaction
+    async fn insert_many(
+        &self,
+        ctx: &Context,
+        arg: &InsertManyRequest,
+    ) -> RpcResult<ExecuteResult> {
+        let insert =
+            insert::Insert::new(&arg.table, &arg.columns).map_err(RpcError::InvalidParameter)?;
+        let result = match self
+            .insert_rows(ctx, "SqlDbExt.InsertMany", &insert, &arg.rows)
+            .await?
+        {
+            Ok(counts) => ExecuteResult {
+                rows_affected: counts.inserted,
+                ..Default::default()
+            },
+            Err(e) => ExecuteResult {
+                error: Some(SqlDbError::new(e.code, e.message)),
+                ..Default::default()
+            },
+        };
+        Ok(result)
+    }
+
+    /// insert rows of values into a table, updating the rows that conflict,
+    /// with INSERT ... ON CONFLICT statements in a transaction
+    async fn upsert(&self, ctx: &Context, arg: &UpsertRequest) -> RpcResult<UpsertResult> {
+        let insert = insert::Insert::upsert(
+            &arg.table,
+            &arg.columns,
+            &arg.conflict_columns,
+            &arg.update_columns,
+        )
+        .map_err(RpcError::InvalidParameter)?;
+        let result = match self
+            .insert_rows(ctx, "SqlDbExt.Upsert", &insert, &arg.rows)
+            .await?
+        {
+            Ok(counts) => UpsertResult {
+                inserted: counts.inserted,
+                updated: counts.updated,
+                error: None,
+            },
+            Err(e) => UpsertResult {
+                error: Some(e),
+                ..Default::default()
+            },
+        };
+        Ok(result)
+    }
+}
+
+impl SqlDbProvider {
+    /// insert the cbor-encoded rows of InsertMany or Upsert, in a transaction on a connection
+    /// from the actor's pool, and return the number of rows inserted and updated
+    async fn insert_rows(
+        &self,
+        ctx: &Context,
+        operation: &'static str,
+        insert: &insert::Insert,
+        rows: &[u8],
+    ) -> RpcResult<Result<insert::Counts, ext::SqlDbExtError>> {
+        let actor_id = actor_id(ctx)?;
+        let rows =
+            params::decode_rows(rows, insert.columns()).map_err(RpcError::InvalidParameter)?;
+        let statement = insert.summary(rows.len());
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        if let Err(e) = link.check_statement(&insert.statement(1)) {
+            return Ok(Err(e.into()));
+        }
+        if rows.is_empty() {
+            return Ok(Ok(insert::Counts::default()));
+        }
+        let mut conn = match link.connection().await {
+            Ok(conn) => conn,
+            Err(e) => return Ok(Err(e.into())),
+        };
+        let start = Instant::now();
+        let res = insert::run(&mut conn.client, insert, &rows).await;
+        link.log_slow_query(actor_id, &statement, start.elapsed(), Some(conn.pid));
+        let result = res.map_err(|db_err| {
+            error!(
+                "{} insert:'{}' error:{}",
+                actor_id,
+                link.logged(&statement),
+                &db_err.to_string()
+            );
+            ext::SqlDbExtError::from(DbError::from(db_err))
+        });
+        link.audit(
+            actor_id,
+            operation,
+            &statement,
+            result.as_ref().ok().map(|c| c.inserted + c.updated),
+            result.as_ref().err(),
+        );
+        Ok(result)
+    }
+
+    /// perform a select query, with parameters, for its single row, on a connection from the
+    /// actor's pool. With `optional`, no row is a null row; otherwise it is a NotFound error
+    async fn fetch_row(
+        &self,
+        ctx: &Context,
+        operation: &'static str,
+        arg: &ParamQuery,
+        optional: bool,
+    ) -> RpcResult<FetchRowResult> {
+        let shape = results::RowShape::Row { optional };
+        let result = match self.fetch_single_row(ctx, operation, arg, shape).await? {
+            Ok(results::SingleRow {
+                value: Some(row),
+                columns,
+                encoding,
+            }) => FetchRowResult {
+                found: true,
+                columns,
+                row,
+                encoding: encoding.as_str().to_string(),
+                error: None,
+            },
+            Ok(results::SingleRow { encoding, .. }) => FetchRowResult {
+                row: encoding.null(),
+                encoding: encoding.as_str().to_string(),
+                ..Default::default()
+            },
+            Err(e) => FetchRowResult {
+                error: Some(e),
+                ..Default::default()
+            },
+        };
+        Ok(result)
+    }
+
+    /// read the single row of a select query, with parameters, on a connection from the
+    /// actor's pool, in the shape of the result of the operation: FetchOne, FetchOptional,
+    /// or FetchScalar. As in fetch_query, the read is retried on a new connection if its
+    /// connection is lost, and logged if it is slow
+    async fn fetch_single_row(
+        &self,
+        ctx: &Context,
+        operation: &'static str,
+        arg: &ParamQuery,
+        shape: results::RowShape,
+    ) -> RpcResult<Result<results::SingleRow, ext::SqlDbExtError>> {
+        let actor_id = actor_id(ctx)?;
+        let params = params::decode_params(&arg.params).map_err(RpcError::InvalidParameter)?;
+        let _call = match self.drain.start() {
+            Some(call) => call,
+            None => {
+                return Ok(Err(
+                    DbError::Provider("provider is shutting down".into()).into()
+                ))
+            }
+        };
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let query = link.param_query(&arg.query, params.len())?;
+        let params = params::as_sql(&params);
+        let span = otel::QuerySpan::start(ctx, actor_id, operation, &query, link.trace_statement);
+        let start = Instant::now();
+        // backend of the statement's connection, for the slow query log
+        let mut pid = None;
+        let result: Result<results::SingleRow, DbError> = async {
+            link.check_statement(&query)?;
+            let mut retried = false;
+            loop {
+                let (conn, served_by) = link.read_connection().await?;
+                debug!("{} query served by the {} pool", actor_id, served_by);
+                pid = Some(conn.pid);
+
+                let res = conn
+                    .timeout(timeout(arg.timeout_ms), async {
+                        let sql = link.tagged(&query);
+                        if let results::RowShape::Scalar = shape {
+                            // the columns are known before the query is run, also if it has
+                            // no rows
+                            let statement = conn
+                                .prepare_cached(&sql)
+                                .await
+                                .map_err(results::ReadError::Db)?;
+                            if statement.columns().is_empty() {
+                                return Err(results::ReadError::Result(DbError::Other(
+                                    "query returns no columns".into(),
+                                )));
+                            }
+                        }
+                        let stream = conn
+                            .query_cached(&sql, &params)
+                            .await
+                            .map_err(results::ReadError::Db)?;
+                        futures::pin_mut!(stream);
+                        match shape {
+                            results::RowShape::Row { .. } => {
+                                results::fetch_single(stream, link.result_options).await
+                            }
+                            results::RowShape::Scalar => {
+                                results::fetch_scalar(stream, link.result_options)
+                                    .await
+                                    .map(|value| value.map(|value| (value, Default::default())))
+                            }
+                        }
+                    })
+                    .await;
+                let res = match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        error!("{} query:'{}' error:{}", actor_id, link.logged(&query), &e);
+                        return Err(e);
+                    }
+                };
+                break match res {
+                    Ok(Some((row, columns))) => Ok(results::SingleRow {
+                        value: Some(row),
+                        columns,
+                        encoding: link.result_options.result_encoding,
+                    }),
+                    Ok(None) if shape.optional() => Ok(results::SingleRow {
+                        encoding: link.result_options.result_encoding,
+                        ..Default::default()
+                    }),
+                    Ok(None) => Err(DbError::NotFound("query returned no rows".into())),
+                    Err(results::ReadError::Db(db_err))
+                        if !retried && link.retries_on(false, &db_err) =>
+                    {
+                        warn!(
+                            "{} query:'{}' connection lost, retrying on a new connection: {}",
+                            actor_id,
+                            link.logged(&query),
+                            &db_err
+                        );
+                        retried = true;
+                        continue;
+                    }
+                    Err(results::ReadError::Db(db_err)) => {
+                        error!(
+                            "{} query:'{}' error:{}",
+                            actor_id,
+                            link.logged(&query),
+                            &db_err.to_string()
+                        );
+                        Err(db_err.into())
+                    }
+                    Err(results::ReadError::Result(e)) => Err(e),
+                };
+            }
+        }
+        .await;
+        let result = result.map_err(ext::SqlDbExtError::from);
+        let found = matches!(result, Ok(results::SingleRow { value: Some(_), .. }));
+        link.log_slow_query(actor_id, &query, start.elapsed(), pid);
+        span.end(
+            found as u64,
+            result
+                .as_ref()
+                .err()
+                .map(|e| SqlDbError::new(&e.code, e.message.clone()))
+                .as_ref(),
+        );
+        if pid.is_some() {
+            link.audit(
+                actor_id,
+                operation,
+                &query,
+                shape.audited_rows(found),
+                result.as_ref().err(),
+            );
+        }
+        Ok(result)
+    }
+
+    /// commit, or roll back, a prepared transaction, on a connection from the actor's pool
+    async fn end_prepared(
+        &self,
+        ctx: &Context,
+        gid: &str,
+        commit: bool,
+    ) -> RpcResult<EndTransactionResult> {
+        let actor_id = actor_id(ctx)?;
+        let (command, operation) = if commit {
+            ("COMMIT PREPARED", "SqlDbExt.CommitPrepared")
+        } else {
+            ("ROLLBACK PREPARED", "SqlDbExt.RollbackPrepared")
+        };
+        let statement = prepared::statement(command, gid).map_err(RpcError::InvalidParameter)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        if let Err(e) = self.prepared.check(actor_id, gid) {
+            return Ok(EndTransactionResult {
+                committed: false,
+                error: Some(e.into()),
+            });
+        }
+        let res = match link.connection().await {
+            Ok(conn) => conn.batch_execute(&statement).await.map_err(|db_err| {
+                error!(
+                    "{} {} error:{}",
+                    actor_id,
+                    link.logged(&statement),
+                    &db_err.to_string()
+                );
+                DbError::from(db_err)
+            }),
+            Err(e) => Err(e),
+        };
+        let result = match res {
+            Ok(()) => {
+                self.prepared.remove(gid);
+                EndTransactionResult {
+                    committed: commit,
+                    error: None,
+                }
+            }
+            Err(e) => EndTransactionResult {
+                committed: false,
+                error: Some(e.into()),
+            },
+        };
+        link.audit(actor_id, operation, &statement, None, result.error.as_ref());
+        Ok(result)
+    }
+
+    /// take an advisory lock on a connection from the actor's pool, waiting for it if `wait`
+    async fn take_advisory_lock(
+        &self,
+        ctx: &Context,
+        arg: &AdvisoryLockRequest,
+        wait: bool,
+    ) -> RpcResult<AdvisoryLockResult> {
+        let actor_id = actor_id(ctx)?;
+        let rd = self.actors.read().await;
+        let link = rd
+            .get(actor_id)
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
+        let (operation, statement) = match wait {
+            true => ("SqlDbExt.AdvisoryLock", advisory_lock::LOCK),
+            false => ("SqlDbExt.TryAdvisoryLock", advisory_lock::TRY_LOCK),
+        };
+        if let Err(e) = link.check_statement(statement) {
+            return Ok(AdvisoryLockResult {
+                error: Some(e.into()),
+                ..Default::default()
+            });
+        }
+        let conn = match link.owned_connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Ok(AdvisoryLockResult {
+                    error: Some(e.into()),
+                    ..Default::default()
+                })
+            }
+        };
+        let result = match self
+            .advisory_locks
+            .lock(actor_id, arg.key, conn, wait, timeout(arg.timeout_ms))
+            .await
+        {
+            Ok(locked) => AdvisoryLockResult {
+                locked,
+                error: None,
+            },
+            Err(e) => AdvisoryLockResult {
+                error: Some(e.into()),
+                ..Default::default()
+            },
+        };
+        link.audit(
+            actor_id,
+            operation,
+            statement,
+            Some(result.locked as u64),
+            result.error.as_ref(),
+        );
+        Ok(result)
+    }
+}
+
+/// Run the statements in a transaction. If a statement fails, the transaction is rolled back,
+/// and the error is returned, with the results of the statements so far.
+/// The result of the failed statement contains its error.
+async fn run_transaction(
+    conn: &mut PooledConnection<'_>,
+    mode: &session::TransactionMode,
+    queries: &[Query],
+    actor_id: &str,
+    log_statements: bool,
+) -> Result<Vec<ExecuteResult>, (Vec<ExecuteResult>, tokio_postgres::Error)> {
+    let manager::PgConnection {
+        client, statements, ..
+    } = &mut **conn;
+    let tx = mode
+        .builder(client)
+        .start()
+        .await
+        .map_err(|db_err| (Vec::new(), db_err))?;
+    let mut results = Vec::with_capacity(queries.len());
+    for query in queries.iter() {
+        match statements.execute(&tx, query.as_str(), &[]).await {
+            Ok(res) => results.push(ExecuteResult {
+                rows_affected: res,
+                ..Default::default()
+            }),
+            Err(db_err) => {
+                error!(
+                    "{} transaction query:'{}' error:{}",
+                    actor_id,
+                    logged(query, log_statements),
+                    &db_err.to_string()
+                );
+                results.push(ExecuteResult {
+                    error: Some(DbError::from(&db_err).into()),
+                    ..Default::default()
+                });
+                // roll back explicitly, so the connection is clean when it returns to the pool
+                if let Err(e) = tx.rollback().await {
+                    error!("{} transaction rollback error:{}", actor_id, e);
+                }
+                return Err((results, db_err));
+            }
+        }
+    }
+    match tx.commit().await {
+        Ok(()) => Ok(results),
+        Err(db_err) => {
+            error!(
+                "{} transaction commit error:{}",
+                actor_id,
+                &db_err.to_string()
+            );
+            Err((results, db_err))
+        }
     }
 }
 
-fn encode_result_set(rows: &[tokio_postgres::Row]) -> Result<Vec<u8>, DbError> {
-    let mut buf = Vec::with_capacity(rows.len() * 2);
-    let mut enc = minicbor::Encoder::new(&mut buf);
-    types::encode_rows(&mut enc, rows).map_err(|e| DbError::Encoding(e.to_string()))?;
-    Ok(buf)
+/// the statement, as it is written in logs: redacted if the link's log_statements is off
+fn logged(statement: &str, log_statements: bool) -> &str {
+    if log_statements {
+        statement
+    } else {
+        "<redacted>"
+    }
+}
+
+/// the first max_len characters of the statement, followed by "..." if it is longer
+fn truncate(query: &str, max_len: usize) -> std::borrow::Cow<'_, str> {
+    match query.char_indices().nth(max_len) {
+        Some((end, _)) => format!("{}...", &query[..end]).into(),
+        None => query.into(),
+    }
+}
+
+/// timeout of a statement, from its timeout_ms argument. 0 is no timeout
+fn timeout(timeout_ms: u32) -> Option<Duration> {
+    (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms as u64))
+}
+
+/// offset of a large object, which lo_lseek64 takes as a bigint
+fn lo_offset(offset: u64) -> RpcResult<i64> {
+    i64::try_from(offset)
+        .map_err(|_| RpcError::InvalidParameter(format!("invalid offset {}", offset)))
+}
+
+/// error for a transaction token that is unknown, or whose transaction has ended
+fn transaction_not_found() -> DbError {
+    DbError::NotFound(
+        "transaction not found. It may have been rolled back after the transaction idle timeout"
+            .into(),
+    )
+}
+
+/// error for a copy_out token that is unknown, or whose export has been closed
+fn copy_not_found() -> DbError {
+    DbError::NotFound(
+        "copy not found. It is closed after its last chunk is read, \
+         or after the transaction idle timeout"
+            .into(),
+    )
+}
+
+/// error for a cursor token that is unknown, or whose cursor has been closed
+fn cursor_not_found() -> DbError {
+    DbError::NotFound(
+        "cursor not found. It is closed after its last rows are read, \
+         or after the transaction idle timeout"
+            .into(),
+    )
+}
+
+/// error for a fetch_stream token that is unknown, or whose stream has been closed
+fn stream_not_found() -> DbError {
+    DbError::NotFound(
+        "stream not found. It is closed after its last chunk is read, \
+         or after the transaction idle timeout"
+            .into(),
+    )
 }
@@ -0,0 +1,650 @@
+//! Connection manager for the connection pool.
+//!
+//! This is similar to bb8_postgres::PostgresConnectionManager, but it sets up
+//! credentials for each new physical connection, so that authentication tokens
+//! that expire are regenerated when the pool opens a connection, and it
+//! initializes session settings on each new connection.
+//! Each connection has a cache of its prepared statements, and keeps the notices
+//! (such as those of RAISE NOTICE) that the server sends on it, until they are taken.
+//!
+//! With dns_refresh, a connection opened before the addresses of the database hosts changed
+//! is dropped when it is taken from the pool.
+//!
+//! After a connection attempt fails, for example while the server is restarting,
+//! the next attempts of the pool are made one at a time, with a delay that doubles
+//! after each failure, so that a pool recovers as soon as the server is available,
+//! without every waiting request connecting at once. With jitter, the delay is instead
+//! a random one between the initial delay and three times the previous delay (the
+//! "decorrelated jitter" of exponential backoff), so that the pools of many links and
+//! providers do not all reconnect at the same time.
+//!
+//! With SQLDB_TOTAL_MAX_CONNECTIONS, each connection takes a permit of the provider's
+//! connection quota before it is opened, and holds it until it is closed.
+//!
+use crate::log_level::{debug, warn};
+use crate::{
+    auth::Credentials,
+    dns::DnsRefresh,
+    error::{self, DbError},
+    pool::ConnectErrors,
+    quota::ConnectionQuota,
+    statement_cache::StatementCache,
+    tls::MakeTlsConnect,
+};
+use async_trait::async_trait;
+use bb8_postgres::{
+    bb8,
+    tokio_postgres::{
+        self, types::ToSql, AsyncMessage, CancelToken, Client, Notification, RowStream, Statement,
+    },
+};
+use futures::StreamExt;
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, Mutex, OwnedSemaphorePermit};
+
+/// max time to check that a connection taken from the pool is still usable,
+/// if the config does not have a connect timeout
+const DEFAULT_VALIDATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// max number of notices kept for a connection until they are taken. Later notices are dropped
+const MAX_NOTICES: usize = 100;
+
+/// Errors establishing a connection
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ConnectError {
+    #[error(transparent)]
+    Db(#[from] tokio_postgres::Error),
+    #[error("authentication: {0}")]
+    Auth(String),
+    #[error("server is read-only, but target_session_attrs is read-write")]
+    ReadOnly,
+    #[error("connection did not respond within {0}s")]
+    Unresponsive(u64),
+    #[error("the addresses of the database hosts have changed")]
+    AddressChanged,
+    #[error("{0}")]
+    Quota(String),
+}
+
+/// A database connection, and its prepared statements
+pub(crate) struct PgConnection {
+    pub(crate) client: Client,
+    pub(crate) statements: StatementCache,
+    /// process id of the connection's server backend, as in pg_stat_activity
+    pub(crate) pid: i32,
+    /// tls settings of the connection, for cancel requests
+    tls: MakeTlsConnect,
+    /// notices received on the connection
+    notices: Notices,
+    /// generation of the host addresses when the connection was opened, with dns_refresh
+    dns_generation: u64,
+    /// permit of the provider's connection quota, if it has one, until the connection is closed
+    _quota_permit: Option<OwnedSemaphorePermit>,
+}
+
+impl PgConnection {
+    /// Returns the notices received on this connection since they were last taken, and
+    /// clears them. The server sends the notices of a statement before its response, so
+    /// once a statement has completed, its notices have been received
+    pub(crate) fn take_notices(&self) -> Vec<String> {
+        std::mem::take(&mut *self.notices.0.lock().unwrap())
+    }
+
+    /// Returns a handle that cancels the statement running on this connection
+    pub(crate) fn canceller(&self) -> Canceller {
+        Canceller {
+            pid: self.pid,
+            token: self.client.cancel_token(),
+            tls: self.tls.clone(),
+        }
+    }
+
+    /// Runs a statement on this connection, with a timeout. If the statement does not complete
+    /// in time, the server is asked to cancel it, and a Timeout error is returned.
+    pub(crate) async fn timeout<F: Future>(
+        &self,
+        timeout: Option<Duration>,
+        statement: F,
+    ) -> Result<F::Output, DbError> {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return Ok(statement.await),
+        };
+        match tokio::time::timeout(timeout, statement).await {
+            Ok(res) => Ok(res),
+            Err(_) => {
+                if let Err(e) = self.canceller().cancel().await {
+                    warn!(
+                        "cancelling statement of backend {} after timeout: {}",
+                        self.pid, e
+                    );
+                }
+                Err(DbError::Timeout(format!(
+                    "statement timed out after {}ms",
+                    timeout.as_millis()
+                )))
+            }
+        }
+    }
+
+    /// Executes a statement, using the connection's prepared statement cache.
+    /// If the cached statement is no longer valid, it is prepared and executed again.
+    /// Must not be used in a transaction, where the failed statement aborts the transaction.
+    pub(crate) async fn execute_cached(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, tokio_postgres::Error> {
+        match self.statements.execute(&self.client, sql, params).await {
+            Err(e) if error::is_stale_statement(&e) => {
+                self.statements.execute(&self.client, sql, params).await
+            }
+            res => res,
+        }
+    }
+
+    /// Executes statements in a pipeline, using the connection's prepared statement cache:
+    /// each statement is sent without waiting for the result of the previous one, so the
+    /// statements take about one round trip to prepare, and one to execute, in all. They are
+    /// executed in order, each in its own implicit transaction. Returns their results, in order.
+    /// A statement whose cached statement is no longer valid is prepared and executed again,
+    /// after the others.
+    pub(crate) async fn execute_pipelined(
+        &self,
+        sqls: &[&str],
+    ) -> Vec<Result<u64, tokio_postgres::Error>> {
+        let prepared = futures::future::join_all(
+            sqls.iter()
+                .map(|sql| self.statements.prepare(&self.client, sql)),
+        )
+        .await;
+        // each execute is sent when it is first polled, in the order of the statements
+        let mut results = futures::future::join_all(
+            prepared
+                .into_iter()
+                .map(|statement| async move { self.client.execute(&statement?, &[]).await }),
+        )
+        .await;
+        for (sql, result) in sqls.iter().zip(results.iter_mut()) {
+            if matches!(result, Err(e) if error::is_stale_statement(e)) {
+                self.statements.remove(sql);
+                *result = self.statements.execute(&self.client, sql, &[]).await;
+            }
+        }
+        results
+    }
+
+    /// Runs a query, using the connection's prepared statement cache,
+    /// and returns the stream of its rows.
+    /// If the cached statement is no longer valid, it is prepared and run again.
+    /// Must not be used in a transaction, where the failed statement aborts the transaction.
+    pub(crate) async fn query_cached(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<RowStream, tokio_postgres::Error> {
+        match self.statements.query_raw(&self.client, sql, params).await {
+            Err(e) if error::is_stale_statement(&e) => {
+                self.statements.query_raw(&self.client, sql, params).await
+            }
+            res => res,
+        }
+    }
+
+    /// Returns the prepared statement for the sql, from the connection's prepared statement
+    /// cache, for example to read the statement's columns before the query is run
+    pub(crate) async fn prepare_cached(
+        &self,
+        sql: &str,
+    ) -> Result<Statement, tokio_postgres::Error> {
+        self.statements.prepare(&self.client, sql).await
+    }
+}
+
+/// Cancels the statement running on a connection, if there is one
+#[derive(Clone)]
+pub(crate) struct Canceller {
+    /// process id of the connection's server backend
+    pub(crate) pid: i32,
+    token: CancelToken,
+    tls: MakeTlsConnect,
+}
+
+impl Canceller {
+    /// Asks the server to cancel the statement. The cancel request is sent on a new connection,
+    /// and it succeeds even if no statement is running.
+    ///
+    /// The server closes the connection as soon as it has read the request, without the TLS
+    /// close of a TLS connection, so shutting the connection down after the request is written
+    /// may fail with `NotConnected` or `BrokenPipe`. Those errors only occur once the request
+    /// was sent, and are not errors of the cancel.
+    pub(crate) async fn cancel(&self) -> Result<(), tokio_postgres::Error> {
+        match self.token.cancel_query(self.tls.clone()).await {
+            Err(e) if closed_after_request(&e) => Ok(()),
+            res => res,
+        }
+    }
+}
+
+/// true if the error is the server closing the connection of a cancel request after reading it
+fn closed_after_request(e: &tokio_postgres::Error) -> bool {
+    std::error::Error::source(e)
+        .and_then(|source| source.downcast_ref::<std::io::Error>())
+        .map(|e| {
+            matches!(
+                e.kind(),
+                std::io::ErrorKind::NotConnected | std::io::ErrorKind::BrokenPipe
+            )
+        })
+        .unwrap_or(false)
+}
+
+impl std::ops::Deref for PgConnection {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl std::ops::DerefMut for PgConnection {
+    fn deref_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+}
+
+/// Creates database connections for the pool
+pub(crate) struct PgConnectionManager {
+    config: tokio_postgres::Config,
+    tls: MakeTlsConnect,
+    credentials: Credentials,
+    /// statements run when a connection is opened, to initialize session settings
+    session_init: Vec<String>,
+    /// if true, connections to a server that has become read-only
+    /// (for example, after failover) are dropped from the pool
+    read_write: bool,
+    /// max number of prepared statements cached on each connection
+    statement_cache_capacity: usize,
+    /// prepared statements not used for this long are closed, with statement_cache_ttl
+    statement_cache_ttl: Option<Duration>,
+    /// delay of connection attempts after a failure
+    backoff: Arc<Backoff>,
+    /// result of the last connection attempt of the pool
+    connect_errors: ConnectErrors,
+    /// addresses of the host names, if dns_refresh is set
+    dns_refresh: Option<Arc<DnsRefresh>>,
+    /// permits of the connections of all links, if SQLDB_TOTAL_MAX_CONNECTIONS is set
+    quota: ConnectionQuota,
+}
+
+impl PgConnectionManager {
+    pub(crate) fn new(
+        config: tokio_postgres::Config,
+        tls: MakeTlsConnect,
+        credentials: Credentials,
+        session_init: Vec<String>,
+        statement_cache_capacity: usize,
+        backoff: Backoff,
+    ) -> Self {
+        let read_write = config.get_target_session_attrs()
+            == tokio_postgres::config::TargetSessionAttrs::ReadWrite;
+        Self {
+            config,
+            tls,
+            credentials,
+            session_init,
+            read_write,
+            statement_cache_capacity,
+            statement_cache_ttl: None,
+            backoff: Arc::new(backoff),
+            connect_errors: Default::default(),
+            dns_refresh: None,
+            quota: Default::default(),
+        }
+    }
+
+    /// Take a permit of the quota for each connection
+    pub(crate) fn with_quota(mut self, quota: ConnectionQuota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Record the result of each connection attempt in `errors`
+    pub(crate) fn with_connect_errors(mut self, errors: ConnectErrors) -> Self {
+        self.connect_errors = errors;
+        self
+    }
+
+    /// Close the cached prepared statements that have not been used for the ttl
+    pub(crate) fn with_statement_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.statement_cache_ttl = ttl;
+        self
+    }
+
+    /// Resolve the host names again every `interval`, and drop the connections opened before
+    /// their addresses changed
+    pub(crate) fn with_dns_refresh(mut self, interval: Duration) -> Self {
+        self.dns_refresh = DnsRefresh::new(&self.config, interval).map(Arc::new);
+        self
+    }
+
+    /// the generation of the host addresses, with dns_refresh
+    async fn dns_generation(&self) -> u64 {
+        match &self.dns_refresh {
+            Some(dns) => dns.generation().await,
+            None => 0,
+        }
+    }
+
+    /// Take a permit of the connection quota, for a connection that is being opened
+    async fn quota_permit(&self) -> Result<Option<OwnedSemaphorePermit>, ConnectError> {
+        self.quota.acquire().await.map_err(|e| {
+            self.connect_errors.record_quota(&e);
+            ConnectError::Quota(e.to_string())
+        })
+    }
+
+    /// Opens a connection for the pool, which holds the permit
+    async fn open(
+        &self,
+        quota_permit: Option<OwnedSemaphorePermit>,
+    ) -> Result<PgConnection, ConnectError> {
+        let mut config = self.config.clone();
+        self.credentials
+            .apply(&mut config)
+            .await
+            .map_err(ConnectError::Auth)?;
+        let dns_generation = self.dns_generation().await;
+        let (client, mut connection) = config.connect(self.tls.clone()).await?;
+        let notices = Notices::default();
+        let received = notices.clone();
+        // drives the connection, as connection.await does, keeping its notices
+        tokio::spawn(async move {
+            let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(Ok(message)) = messages.next().await {
+                if let AsyncMessage::Notice(notice) = message {
+                    received.push(format!("{}: {}", notice.severity(), notice.message()));
+                }
+            }
+        });
+        // the client does not expose the backend's process id, so it is read with the
+        // session settings, in the same round trip
+        let mut init = self.session_init.clone();
+        init.push("SELECT pg_backend_pid()".to_string());
+        let pid = client
+            .simple_query(&init.join(";"))
+            .await?
+            .iter()
+            .find_map(|message| match message {
+                tokio_postgres::SimpleQueryMessage::Row(row) => row.get(0)?.parse().ok(),
+                _ => None,
+            })
+            .unwrap_or_default();
+        Ok(PgConnection {
+            client,
+            pid,
+            statements: StatementCache::new(self.statement_cache_capacity)
+                .with_ttl(self.statement_cache_ttl),
+            tls: self.tls.clone(),
+            notices,
+            dns_generation,
+            _quota_permit: quota_permit,
+        })
+    }
+}
+
+/// Notices received on a connection, shared with the task that drives it
+#[derive(Clone, Default)]
+struct Notices(Arc<std::sync::Mutex<Vec<String>>>);
+
+impl Notices {
+    fn push(&self, notice: String) {
+        let mut notices = self.0.lock().unwrap();
+        if notices.len() < MAX_NOTICES {
+            notices.push(notice);
+        } else {
+            debug!("notice dropped: {}", notice);
+        }
+    }
+}
+
+/// Delay between connection attempts after a failure, shared by the connections of a pool
+pub(crate) struct Backoff {
+    /// delay after the first failure
+    initial: Duration,
+    /// upper limit of the delay
+    max: Duration,
+    /// if true, delays are random, with decorrelated jitter
+    jitter: bool,
+    state: Mutex<BackoffState>,
+}
+
+#[derive(Default)]
+struct BackoffState {
+    /// delay before the next attempt, if the last attempt failed
+    delay: Option<Duration>,
+    /// time of the last failed attempt
+    failed_at: Option<Instant>,
+}
+
+impl Backoff {
+    pub(crate) fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max: max.max(initial),
+            jitter: false,
+            state: Default::default(),
+        }
+    }
+
+    /// Randomize the delays, with decorrelated jitter
+    pub(crate) fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Run a connection attempt. If the last attempt failed, the attempt waits for the delay,
+    /// and other attempts wait for it to complete.
+    async fn attempt<T, E, F>(&self, connect: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let mut state = self.state.lock().await;
+        if let (Some(delay), Some(failed_at)) = (state.delay, state.failed_at) {
+            tokio::time::sleep_until((failed_at + delay).into()).await;
+            let res = connect.await;
+            state.update(res.is_ok(), self);
+            return res;
+        }
+        drop(state);
+        let res = connect.await;
+        if res.is_err() {
+            self.state.lock().await.update(false, self);
+        }
+        res
+    }
+}
+
+impl BackoffState {
+    fn update(&mut self, connected: bool, backoff: &Backoff) {
+        if connected {
+            *self = Self::default();
+        } else {
+            self.delay = Some(next_delay(self.delay, backoff, &mut rand::thread_rng()));
+            self.failed_at = Some(Instant::now());
+        }
+    }
+}
+
+/// The delay after a failure, given the delay after the previous failure, if there was one
+fn next_delay(previous: Option<Duration>, backoff: &Backoff, rng: &mut impl rand::Rng) -> Duration {
+    match previous {
+        Some(delay) if backoff.jitter => {
+            let upper = (delay * 3).min(backoff.max);
+            if upper > backoff.initial {
+                rng.gen_range(backoff.initial..=upper)
+            } else {
+                backoff.initial
+            }
+        }
+        Some(delay) => (delay * 2).min(backoff.max),
+        None if backoff.jitter => {
+            // the first delay is also random, so that the pools do not retry in lockstep
+            rng.gen_range(backoff.initial..=(backoff.initial * 3).min(backoff.max))
+        }
+        None => backoff.initial,
+    }
+}
+
+impl PgConnectionManager {
+    /// Opens a connection that is not in the pool, for LISTEN. Notifications received on it
+    /// are sent to the returned channel, which is closed when the connection is closed.
+    pub(crate) async fn connect_listener(
+        &self,
+    ) -> Result<(Client, mpsc::UnboundedReceiver<Notification>), ConnectError> {
+        let quota_permit = self.quota_permit().await?;
+        let mut config = self.config.clone();
+        self.credentials
+            .apply(&mut config)
+            .await
+            .map_err(ConnectError::Auth)?;
+        let (client, mut connection) = config.connect(self.tls.clone()).await?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            // the permit is held until the connection is closed
+            let _quota_permit = quota_permit;
+            let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(AsyncMessage::Notification(n)) => {
+                        if sender.send(n).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("listener connection: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        if !self.session_init.is_empty() {
+            client.batch_execute(&self.session_init.join(";")).await?;
+        }
+        Ok((client, receiver))
+    }
+}
+
+#[async_trait]
+impl bb8::ManageConnection for PgConnectionManager {
+    type Connection = PgConnection;
+    type Error = ConnectError;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        // waiting for a permit is not an attempt, which would delay the next ones after it fails
+        let quota_permit = self.quota_permit().await?;
+        let res = self.backoff.attempt(self.open(quota_permit)).await;
+        self.connect_errors.record(&res);
+        res
+    }
+
+    /// Checks a connection when it is taken from the pool. A connection to a server
+    /// that has restarted, or that does not respond, is dropped from the pool, as is one
+    /// to an address that a host name no longer resolves to, with dns_refresh.
+    async fn is_valid(
+        &self,
+        conn: &mut bb8::PooledConnection<'_, Self>,
+    ) -> Result<(), Self::Error> {
+        if self.dns_refresh.is_some() && self.dns_generation().await != conn.dns_generation {
+            return Err(ConnectError::AddressChanged);
+        }
+        let timeout = self
+            .config
+            .get_connect_timeout()
+            .copied()
+            .unwrap_or(DEFAULT_VALIDATION_TIMEOUT);
+        let query = if self.read_write {
+            "SHOW transaction_read_only"
+        } else {
+            ""
+        };
+        let rows = tokio::time::timeout(timeout, conn.simple_query(query))
+            .await
+            .map_err(|_| ConnectError::Unresponsive(timeout.as_secs()))??;
+        match rows.first() {
+            Some(tokio_postgres::SimpleQueryMessage::Row(row)) if row.get(0) == Some("on") => {
+                Err(ConnectError::ReadOnly)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn backoff() {
+        let backoff = Backoff::new(Duration::from_millis(20), Duration::from_millis(30));
+        let start = Instant::now();
+        assert!(backoff.attempt(async { Err::<(), _>(()) }).await.is_err());
+        assert!(start.elapsed() < Duration::from_millis(20));
+
+        // after a failure, attempts wait for the delay, which doubles up to the max
+        let start = Instant::now();
+        assert!(backoff.attempt(async { Err::<(), _>(()) }).await.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let start = Instant::now();
+        assert!(backoff.attempt(async { Ok::<_, ()>(()) }).await.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        // a success resets the delay
+        let start = Instant::now();
+        assert!(backoff.attempt(async { Ok::<_, ()>(()) }).await.is_ok());
+        assert!(start.elapsed() < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn jitter() {
+        let initial = Duration::from_millis(100);
+        let max = Duration::from_millis(5000);
+        let backoff = Backoff::new(initial, max).with_jitter(true);
+        let mut rng = rand::thread_rng();
+        let mut delays = Vec::new();
+        let mut delay = None;
+        for _ in 0..1000 {
+            let next = next_delay(delay, &backoff, &mut rng);
+            assert!(next >= initial && next <= max, "{:?}", next);
+            assert!(
+                next <= delay.unwrap_or(initial) * 3,
+                "{:?} after {:?}",
+                next,
+                delay
+            );
+            delays.push(next);
+            delay = Some(next);
+        }
+        // the delays are not all the same
+        assert!(delays.iter().any(|d| *d != delays[0]));
+
+        // without jitter, the delay doubles up to the max
+        let backoff = Backoff::new(initial, Duration::from_millis(300));
+        let delays = std::iter::successors(Some(initial), |d| {
+            Some(next_delay(Some(*d), &backoff, &mut rng))
+        })
+        .take(4)
+        .map(|d| d.as_millis())
+        .collect::<Vec<_>>();
+        assert_eq!(delays, vec![100, 200, 300, 300]);
+    }
+}
@@ -0,0 +1,146 @@
+//! Statistics of each link's connection pool.
+//!
+//! The size of the pool, and the number of idle connections, are from the pool's state.
+//! The pool does not report how many callers are waiting for a connection, or how long
+//! they wait, so each link counts them as connections are taken from its pool.
+//! Wait times are counted in a histogram with the upper bounds of `WAIT_BUCKETS_MS`.
+//!
+use crate::{
+    ext::{PoolStats, WaitBucket},
+    Pool,
+};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// upper bounds, in milliseconds, of the buckets of the wait time histogram
+const WAIT_BUCKETS_MS: [u64; 10] = [1, 5, 10, 25, 50, 100, 250, 1000, 5000, 30000];
+
+/// Counters of the connections taken from a link's pool
+#[derive(Clone, Default)]
+pub(crate) struct PoolMetrics {
+    /// the pool's max_size, which the pool does not report
+    max_size: u32,
+    inner: Arc<Mutex<Counters>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// callers waiting for a connection
+    waiting: u32,
+    /// number of connections taken from the pool
+    acquired: u64,
+    /// number of waits that failed, because of a timeout or connection error
+    failed: u64,
+    /// number of waits, successful or not, of at most each bound in WAIT_BUCKETS_MS,
+    /// and more than the previous bound
+    buckets: [u64; WAIT_BUCKETS_MS.len()],
+    /// total time of the waits
+    wait_total: Duration,
+}
+
+impl PoolMetrics {
+    pub(crate) fn new(max_size: u32) -> Self {
+        Self {
+            max_size,
+            inner: Default::default(),
+        }
+    }
+
+    /// Count the wait for a connection from the pool
+    pub(crate) async fn acquire<T, E>(
+        &self,
+        get: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        self.inner.lock().unwrap().waiting += 1;
+        let mut guard = WaitGuard {
+            metrics: self,
+            start: Instant::now(),
+            ok: false,
+        };
+        let res = get.await;
+        guard.ok = res.is_ok();
+        res
+    }
+
+    /// Returns the statistics of the pool
+    pub(crate) fn stats(&self, pool: &Pool) -> PoolStats {
+        let state = pool.state();
+        let counters = self.inner.lock().unwrap();
+        let mut count = 0;
+        let wait_buckets = WAIT_BUCKETS_MS
+            .iter()
+            .zip(counters.buckets.iter())
+            .map(|(le_ms, n)| {
+                count += n;
+                WaitBucket {
+                    le_ms: *le_ms,
+                    count,
+                }
+            })
+            .collect();
+        PoolStats {
+            size: state.connections,
+            idle: state.idle_connections,
+            in_use: state.connections.saturating_sub(state.idle_connections),
+            max_size: self.max_size,
+            waiting: counters.waiting,
+            acquired: counters.acquired,
+            acquire_failed: counters.failed,
+            wait_ms_total: counters.wait_total.as_millis() as u64,
+            wait_buckets,
+        }
+    }
+}
+
+/// Counts the end of a wait, including a wait whose future is dropped
+struct WaitGuard<'a> {
+    metrics: &'a PoolMetrics,
+    start: Instant,
+    ok: bool,
+}
+
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        let wait = self.start.elapsed();
+        let mut counters = self.metrics.inner.lock().unwrap();
+        counters.waiting = counters.waiting.saturating_sub(1);
+        if self.ok {
+            counters.acquired += 1;
+        } else {
+            counters.failed += 1;
+        }
+        counters.wait_total += wait;
+        let ms = wait.as_millis() as u64;
+        if let Some(i) = WAIT_BUCKETS_MS.iter().position(|le| ms <= *le) {
+            counters.buckets[i] += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_counts() {
+        let metrics = PoolMetrics::default();
+        let _: Result<(), ()> = metrics.acquire(async { Ok(()) }).await;
+        let _: Result<(), ()> = metrics
+            .acquire(async {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                Err(())
+            })
+            .await;
+        let counters = metrics.inner.lock().unwrap();
+        assert_eq!(counters.waiting, 0);
+        assert_eq!(counters.acquired, 1);
+        assert_eq!(counters.failed, 1);
+        assert_eq!(counters.buckets[0], 1);
+        // 30ms is in the 50ms bucket
+        assert_eq!(counters.buckets[4], 1);
+        assert!(counters.wait_total >= Duration::from_millis(30));
+    }
+}
@@ -0,0 +1,192 @@
+//! Versioned sql migrations, applied when a link is created.
+//!
+//! With `migrate` on, the provider reads the files named `NNNN_name.sql` in `migrations_dir`,
+//! and applies those whose version (the number before the first `_`) is not yet recorded in
+//! the `schema_migrations` table, in order of version. Each migration runs in its own
+//! transaction, with the insert of its version, so a migration that fails is not recorded,
+//! and the migrations before it remain applied.
+//!
+//! Links of several actors, or several provider instances, may use the same database, so
+//! the run holds an advisory lock, and a run that waits for it sees the versions recorded
+//! by the one before.
+//!
+use crate::log_level::info;
+use crate::{error::DbError, Pool};
+use std::path::{Path, PathBuf};
+
+/// key of the advisory lock around a migration run
+const MIGRATION_LOCK: i64 = 0x7371_6c64_626d_6967; // "sqldbmig"
+
+/// creates the table that records the applied migrations
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (\
+    version bigint PRIMARY KEY, name text NOT NULL, \
+    applied_at timestamptz NOT NULL DEFAULT now())";
+
+/// A migration file
+#[derive(Debug, PartialEq, Eq)]
+struct Migration {
+    version: i64,
+    /// the part of the file name after the version
+    name: String,
+    path: PathBuf,
+}
+
+/// Apply the pending migrations in `dir`. Returns the number of migrations applied
+pub(crate) async fn run(pool: &Pool, dir: &str, actor_id: &str) -> Result<usize, DbError> {
+    let migrations = scan(Path::new(dir))?;
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| DbError::Io(format!("connection for migrations: {}", e)))?;
+    conn.execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    let res = apply(&conn, &migrations, actor_id).await;
+    // released on every path: the connection returns to the pool
+    let unlock = conn
+        .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK])
+        .await;
+    let applied = res?;
+    unlock?;
+    Ok(applied)
+}
+
+/// Apply the migrations that are not recorded, while holding the lock
+async fn apply(
+    conn: &crate::PooledConnection<'_>,
+    migrations: &[Migration],
+    actor_id: &str,
+) -> Result<usize, DbError> {
+    conn.batch_execute(CREATE_TABLE).await?;
+    let applied = conn
+        .query("SELECT version FROM schema_migrations", &[])
+        .await?
+        .iter()
+        .map(|row| row.get::<_, i64>(0))
+        .collect::<std::collections::HashSet<_>>();
+    let mut count = 0;
+    for migration in migrations.iter().filter(|m| !applied.contains(&m.version)) {
+        let sql = std::fs::read_to_string(&migration.path).map_err(|e| {
+            DbError::Config(format!(
+                "reading migration {}: {}",
+                migration.path.display(),
+                e
+            ))
+        })?;
+        conn.batch_execute("BEGIN").await?;
+        let res = async {
+            conn.batch_execute(&sql).await?;
+            conn.execute(
+                "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+            conn.batch_execute("COMMIT").await
+        }
+        .await;
+        if let Err(e) = res {
+            let _ = conn.batch_execute("ROLLBACK").await;
+            return Err(DbError::Db(format!(
+                "migration {} failed: {}",
+                migration.path.display(),
+                DbError::from(e)
+            )));
+        }
+        info!(
+            "{} applied migration {} {}",
+            actor_id, migration.version, migration.name
+        );
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The migration files in the directory, in order of version.
+/// Other files are ignored. Two files with the same version are an error.
+fn scan(dir: &Path) -> Result<Vec<Migration>, DbError> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| DbError::Config(format!("reading migrations_dir {}: {}", dir.display(), e)))?;
+    let mut migrations = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| DbError::Config(format!("reading migrations_dir: {}", e)))?
+            .path();
+        if let Some((version, name)) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_name)
+        {
+            migrations.push(Migration {
+                version,
+                name: name.to_string(),
+                path,
+            });
+        }
+    }
+    migrations.sort_by_key(|m| m.version);
+    if let Some(pair) = migrations.windows(2).find(|w| w[0].version == w[1].version) {
+        return Err(DbError::Config(format!(
+            "migrations {} and {} have the same version",
+            pair[0].path.display(),
+            pair[1].path.display()
+        )));
+    }
+    Ok(migrations)
+}
+
+/// version and name of a file named `NNNN_name.sql`
+fn parse_name(file_name: &str) -> Option<(i64, &str)> {
+    let (version, name) = file_name.strip_suffix(".sql")?.split_once('_')?;
+    if version.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) || name.is_empty() {
+        return None;
+    }
+    Some((version.parse().ok()?, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            parse_name("0001_create_users.sql"),
+            Some((1, "create_users"))
+        );
+        assert_eq!(
+            parse_name("20240101_add_index.sql"),
+            Some((20240101, "add_index"))
+        );
+        assert_eq!(parse_name("0002_a_b.sql"), Some((2, "a_b")));
+        assert_eq!(parse_name("0003_.sql"), None);
+        assert_eq!(parse_name("_name.sql"), None);
+        assert_eq!(parse_name("v4_name.sql"), None);
+        assert_eq!(parse_name("0005_name.txt"), None);
+        assert_eq!(parse_name("README.md"), None);
+    }
+
+    #[test]
+    fn scan_dir() {
+        let dir = std::env::temp_dir().join(format!("sqldb-migrations-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["0010_second.sql", "0002_first.sql", "notes.txt"] {
+            std::fs::write(dir.join(name), "select 1").unwrap();
+        }
+        let Ok(migrations) = scan(&dir) else {
+            panic!("scan {}", dir.display())
+        };
+        let versions = migrations
+            .into_iter()
+            .map(|m| (m.version, m.name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            versions,
+            vec![(2, "first".to_string()), (10, "second".to_string())]
+        );
+
+        std::fs::write(dir.join("10_again.sql"), "select 1").unwrap();
+        assert!(scan(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(scan(&dir).is_err());
+    }
+}
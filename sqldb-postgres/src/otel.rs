@@ -0,0 +1,227 @@
+//! OpenTelemetry spans for statements.
+//!
+//! With the `otel` feature, each `Execute`, `Fetch`, `ExecuteParams`, and `FetchParams` runs
+//! in a span, with the actor id, the statement, the number of rows, and the error, if the
+//! statement failed. Spans are exported with OTLP over http, configured with the standard
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` (default `http://localhost:4318`) and `OTEL_SERVICE_NAME`
+//! environment variables. If the rpc Context has a `span`, it is the W3C `traceparent`
+//! of the actor's span, and the statement's span is its child.
+//!
+//! The statement in the span is redacted by default: its literals are replaced with `?`,
+//! so that values in the sql text are not exported. The link's `trace_statement`
+//! may instead be `full`, or `none` to leave it out.
+//!
+//! Without the feature, spans are not created, and this module does nothing.
+//!
+use serde::Deserialize;
+use wasmbus_rpc::Context;
+use wasmcloud_interface_sqldb::SqlDbError;
+
+/// How the statement is recorded in its span
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum TraceStatement {
+    /// the statement, with its literals replaced with '?'
+    #[default]
+    Redacted,
+    /// the statement as the actor sent it
+    Full,
+    /// the statement is not recorded
+    None,
+}
+
+/// Span of a statement. The span ends when `end` is called, or when it is dropped.
+pub(crate) struct QuerySpan {
+    #[cfg(feature = "otel")]
+    span: opentelemetry::global::BoxedSpan,
+}
+
+#[cfg(not(feature = "otel"))]
+impl QuerySpan {
+    pub(crate) fn start(
+        _ctx: &Context,
+        _actor_id: &str,
+        _operation: &'static str,
+        _statement: &str,
+        _mode: TraceStatement,
+    ) -> Self {
+        QuerySpan {}
+    }
+
+    pub(crate) fn end(self, _rows: u64, _error: Option<&SqlDbError>) {}
+}
+
+#[cfg(feature = "otel")]
+impl QuerySpan {
+    /// Start the span of a statement, as a child of the actor's span, if the Context has one
+    pub(crate) fn start(
+        ctx: &Context,
+        actor_id: &str,
+        operation: &'static str,
+        statement: &str,
+        mode: TraceStatement,
+    ) -> Self {
+        use opentelemetry::{
+            trace::{SpanKind, Tracer},
+            KeyValue,
+        };
+        let mut attributes = vec![
+            KeyValue::new("db.system", "postgresql"),
+            KeyValue::new("db.operation", operation),
+            KeyValue::new("wasmcloud.actor_id", actor_id.to_string()),
+        ];
+        match mode {
+            TraceStatement::Redacted => {
+                attributes.push(KeyValue::new("db.statement", redact(statement)))
+            }
+            TraceStatement::Full => {
+                attributes.push(KeyValue::new("db.statement", statement.to_string()))
+            }
+            TraceStatement::None => {}
+        }
+        let tracer = opentelemetry::global::tracer("sqldb-postgres");
+        let builder = tracer
+            .span_builder(operation)
+            .with_kind(SpanKind::Client)
+            .with_attributes(attributes);
+        QuerySpan {
+            span: tracer.build_with_context(builder, &parent(ctx)),
+        }
+    }
+
+    /// End the span, with the number of rows affected or returned, or the error
+    pub(crate) fn end(mut self, rows: u64, error: Option<&SqlDbError>) {
+        use opentelemetry::{
+            trace::{Span, Status},
+            KeyValue,
+        };
+        match error {
+            Some(e) => {
+                self.span
+                    .set_attribute(KeyValue::new("db.error_code", e.code.clone()));
+                self.span.set_status(Status::error(e.message.clone()));
+            }
+            None => {
+                self.span
+                    .set_attribute(KeyValue::new("db.rows", rows as i64));
+            }
+        }
+        self.span.end();
+    }
+}
+
+/// Install the OTLP exporter, the first time it is called.
+/// Spans are not exported if the exporter cannot be created.
+#[cfg(feature = "otel")]
+pub(crate) fn init() {
+    use once_cell::sync::OnceCell;
+    static INIT: OnceCell<()> = OnceCell::new();
+    INIT.get_or_init(|| {
+        opentelemetry::global::set_text_map_propagator(
+            opentelemetry::sdk::propagation::TraceContextPropagator::new(),
+        );
+        if let Err(e) = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().http())
+            .install_batch(opentelemetry::runtime::Tokio)
+        {
+            log::error!("creating the OpenTelemetry exporter: {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "otel"))]
+pub(crate) fn init() {}
+
+/// Flush and stop the exporter
+#[cfg(feature = "otel")]
+pub(crate) fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(not(feature = "otel"))]
+pub(crate) fn shutdown() {}
+
+/// Context of the actor's span, from the traceparent in the rpc Context
+#[cfg(feature = "otel")]
+fn parent(ctx: &Context) -> opentelemetry::Context {
+    let mut carrier = std::collections::HashMap::new();
+    if let Some(traceparent) = &ctx.span {
+        carrier.insert("traceparent".to_string(), traceparent.clone());
+    }
+    opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(&carrier))
+}
+
+/// Replace the string and numeric literals of a statement with '?'.
+/// Quoted identifiers, and parameter placeholders such as $1, are not changed.
+#[cfg(any(feature = "otel", test))]
+fn redact(statement: &str) -> String {
+    let mut out = String::with_capacity(statement.len());
+    let mut chars = statement.chars().peekable();
+    // true if the previous character is part of an identifier or placeholder
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // a string literal, where '' is a quote
+                while let Some(c) = chars.next() {
+                    if c == '\'' {
+                        if chars.peek() == Some(&'\'') {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                out.push('?');
+                in_word = false;
+            }
+            '"' => {
+                out.push(c);
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                in_word = false;
+            }
+            '0'..='9' if !in_word => {
+                // digits, a decimal point, and an exponent, which may have a sign
+                let mut prev = c;
+                while let Some(&c) = chars.peek() {
+                    let exponent_sign = matches!(c, '+' | '-') && matches!(prev, 'e' | 'E');
+                    if !(c.is_ascii_alphanumeric() || c == '.' || exponent_sign) {
+                        break;
+                    }
+                    prev = c;
+                    chars.next();
+                }
+                out.push('?');
+            }
+            c => {
+                in_word = c.is_alphanumeric() || c == '_' || c == '$';
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_literals() {
+        assert_eq!(
+            redact("select * from t1 where name = 'O''Brien' and id > 42 and x = $1"),
+            "select * from t1 where name = ? and id > ? and x = $1"
+        );
+        assert_eq!(
+            redact(r#"insert into "Table 2" (a, b) values (1.5e-3, 'x')"#),
+            r#"insert into "Table 2" (a, b) values (?, ?)"#
+        );
+        assert_eq!(redact("select 1"), "select ?");
+    }
+}
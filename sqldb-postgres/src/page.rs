@@ -0,0 +1,62 @@
+//! Paginated queries.
+//!
+//! The actor's query is wrapped in a subquery with LIMIT and OFFSET placeholders
+//! after the actor's own parameters. A LIMIT in the actor's query is applied first,
+//! so pages are pages of its result.
+//!
+//! One more row than the page size is fetched, to find out whether there is another page.
+//!
+
+/// Returns the query for a page of the results of `query`, which has `num_params` parameters.
+/// The limit and offset are parameters `num_params + 1` and `num_params + 2`.
+pub(crate) fn page_query(query: &str, num_params: usize) -> String {
+    format!(
+        "SELECT * FROM ({}) AS page LIMIT ${} OFFSET ${}",
+        trim_query(query),
+        num_params + 1,
+        num_params + 2
+    )
+}
+
+/// Returns the statement that explains `query`, for an estimate of its number of rows
+pub(crate) fn explain_query(query: &str) -> String {
+    format!("EXPLAIN {}", trim_query(query))
+}
+
+/// Returns the number of rows estimated by the planner, from the first line
+/// of EXPLAIN output, for example "Seq Scan on t  (cost=0.00..35.50 rows=2550 width=4)"
+pub(crate) fn plan_rows(line: &str) -> Option<u64> {
+    let start = line.find(" rows=")? + " rows=".len();
+    let digits = line[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>();
+    digits.parse().ok()
+}
+
+/// remove a trailing semicolon, which is not allowed in a subquery
+fn trim_query(query: &str) -> &str {
+    query.trim().trim_end_matches(';').trim_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page() {
+        assert_eq!(
+            page_query("select * from t order by id;\n", 0),
+            "SELECT * FROM (select * from t order by id) AS page LIMIT $1 OFFSET $2"
+        );
+        assert_eq!(
+            page_query("select * from t where a = $1 and b = $2", 2),
+            "SELECT * FROM (select * from t where a = $1 and b = $2) AS page LIMIT $3 OFFSET $4"
+        );
+        assert_eq!(
+            plan_rows("Seq Scan on t  (cost=0.00..35.50 rows=2550 width=4)"),
+            Some(2550)
+        );
+        assert_eq!(plan_rows("Result"), None);
+    }
+}
@@ -0,0 +1,347 @@
+//! Query parameters sent by actors.
+//!
+//! Parameters are a CBOR array, with one item per placeholder (`$1`, `$2`, ...).
+//! Each item is decoded into a [`Param`], which is converted to the type that
+//! postgres inferred for its placeholder when the statement is executed,
+//! so that (for example) a CBOR integer can be used for an int2, int4, int8,
+//! or float column.
+//!
+use bytes::BytesMut;
+use minicbor::{data::Type as CborType, Decoder};
+use std::{convert::TryFrom, error::Error};
+use tokio_postgres::types::{to_sql_checked, IsNull, ToSql, Type};
+
+/// A query parameter
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Param {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Param {
+    fn kind(&self) -> &'static str {
+        match self {
+            Param::Null => "null",
+            Param::Bool(_) => "bool",
+            Param::Int(_) => "integer",
+            Param::Float(_) => "float",
+            Param::Text(_) => "text",
+            Param::Bytes(_) => "bytes",
+        }
+    }
+
+    fn mismatch(&self, ty: &Type) -> Box<dyn Error + Sync + Send> {
+        format!(
+            "cannot convert {} to {}. Cast the parameter in the query, for example $1::text::{}",
+            self.kind(),
+            ty,
+            ty
+        )
+        .into()
+    }
+}
+
+/// decode cbor-encoded parameters. An empty buffer is an empty list.
+pub(crate) fn decode_params(buf: &[u8]) -> Result<Vec<Param>, String> {
+    if buf.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut d = Decoder::new(buf);
+    let params = decode_array(&mut d, "params", |d, i| {
+        decode_param(d).map_err(|e| format!("param {}: {}", i + 1, e))
+    })?;
+    if d.position() != buf.len() {
+        return Err("unexpected data after params array".into());
+    }
+    Ok(params)
+}
+
+/// decode cbor-encoded rows of parameters, for InsertMany: an array with an array of
+/// `columns` values for each row. An empty buffer has no rows.
+pub(crate) fn decode_rows(buf: &[u8], columns: usize) -> Result<Vec<Vec<Param>>, String> {
+    if buf.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut d = Decoder::new(buf);
+    let rows = decode_array(&mut d, "rows", |d, i| {
+        let row = decode_array(d, &format!("row {}", i + 1), |d, j| {
+            decode_param(d).map_err(|e| format!("row {}, value {}: {}", i + 1, j + 1, e))
+        })?;
+        if row.len() != columns {
+            return Err(format!(
+                "row {}: expected {} values, one for each column, found {}",
+                i + 1,
+                columns,
+                row.len()
+            ));
+        }
+        Ok(row)
+    })?;
+    if d.position() != buf.len() {
+        return Err("unexpected data after rows array".into());
+    }
+    Ok(rows)
+}
+
+/// decode an array, of definite or indefinite length, with `item`, which is called with
+/// the index of each item
+fn decode_array<T>(
+    d: &mut Decoder,
+    name: &str,
+    mut item: impl FnMut(&mut Decoder, usize) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    let mut items = Vec::new();
+    match d
+        .array()
+        .map_err(|e| format!("{} must be an array: {}", name, e))?
+    {
+        Some(len) => {
+            for i in 0..len {
+                items.push(item(d, i as usize)?);
+            }
+        }
+        None => {
+            while d.datatype().map_err(|e| e.to_string())? != CborType::Break {
+                let i = items.len();
+                items.push(item(d, i)?);
+            }
+            d.skip().map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(items)
+}
+
+fn decode_param(d: &mut Decoder) -> Result<Param, String> {
+    let param = match d.datatype().map_err(|e| e.to_string())? {
+        CborType::Null | CborType::Undefined => {
+            d.skip().map_err(|e| e.to_string())?;
+            Param::Null
+        }
+        CborType::Bool => Param::Bool(d.bool().map_err(|e| e.to_string())?),
+        CborType::U8
+        | CborType::U16
+        | CborType::U32
+        | CborType::U64
+        | CborType::I8
+        | CborType::I16
+        | CborType::I32
+        | CborType::I64 => Param::Int(
+            d.i64()
+                .map_err(|_| "integer is out of range for int8".to_string())?,
+        ),
+        CborType::F16 => Param::Float(d.f16().map_err(|e| e.to_string())? as f64),
+        CborType::F32 => Param::Float(d.f32().map_err(|e| e.to_string())? as f64),
+        CborType::F64 => Param::Float(d.f64().map_err(|e| e.to_string())?),
+        CborType::String => Param::Text(d.str().map_err(|e| e.to_string())?.to_string()),
+        CborType::StringIndef => {
+            let mut s = String::new();
+            for chunk in d.str_iter().map_err(|e| e.to_string())? {
+                s.push_str(chunk.map_err(|e| e.to_string())?);
+            }
+            Param::Text(s)
+        }
+        CborType::Bytes => Param::Bytes(d.bytes().map_err(|e| e.to_string())?.to_vec()),
+        CborType::BytesIndef => {
+            let mut b = Vec::new();
+            for chunk in d.bytes_iter().map_err(|e| e.to_string())? {
+                b.extend_from_slice(chunk.map_err(|e| e.to_string())?);
+            }
+            Param::Bytes(b)
+        }
+        t => {
+            return Err(format!(
+                "unsupported type {}. Expected null, bool, integer, float, text, or bytes",
+                t
+            ))
+        }
+    };
+    Ok(param)
+}
+
+/// parameters as the argument of `Client::execute` and `Client::query`
+pub(crate) fn as_sql(params: &[Param]) -> Vec<&(dyn ToSql + Sync)> {
+    params.iter().map(|p| p as &(dyn ToSql + Sync)).collect()
+}
+
+impl ToSql for Param {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        match self {
+            Param::Null => Ok(IsNull::Yes),
+            Param::Bool(v) if *ty == Type::BOOL => v.to_sql(ty, out),
+            Param::Int(v) => match *ty {
+                Type::INT2 => i16::try_from(*v)
+                    .map_err(|_| out_of_range(*v, ty))?
+                    .to_sql(ty, out),
+                Type::INT4 => i32::try_from(*v)
+                    .map_err(|_| out_of_range(*v, ty))?
+                    .to_sql(ty, out),
+                Type::INT8 => v.to_sql(ty, out),
+                Type::OID => u32::try_from(*v)
+                    .map_err(|_| out_of_range(*v, ty))?
+                    .to_sql(ty, out),
+                Type::FLOAT4 => (*v as f32).to_sql(ty, out),
+                Type::FLOAT8 => (*v as f64).to_sql(ty, out),
+                _ => Err(self.mismatch(ty)),
+            },
+            Param::Float(v) => match *ty {
+                Type::FLOAT4 => (*v as f32).to_sql(ty, out),
+                Type::FLOAT8 => v.to_sql(ty, out),
+                _ => Err(self.mismatch(ty)),
+            },
+            Param::Text(v) if <&str as ToSql>::accepts(ty) => v.as_str().to_sql(ty, out),
+            // the name of a cursor, such as an INOUT refcursor parameter of a procedure,
+            // has the binary format of text
+            Param::Text(v) if *ty == Type::REFCURSOR => {
+                out.extend_from_slice(v.as_bytes());
+                Ok(IsNull::No)
+            }
+            Param::Bytes(v) if <&[u8] as ToSql>::accepts(ty) => v.as_slice().to_sql(ty, out),
+            _ => Err(self.mismatch(ty)),
+        }
+    }
+
+    /// the conversion depends on the value, so it is checked in to_sql
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn out_of_range(v: i64, ty: &Type) -> Box<dyn Error + Sync + Send> {
+    format!("integer {} is out of range for {}", v, ty).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let mut buf = Vec::new();
+        let mut enc = minicbor::Encoder::new(&mut buf);
+        enc.array(8).unwrap();
+        enc.i32(-5)
+            .unwrap()
+            .u64(u32::MAX as u64 + 1)
+            .unwrap()
+            .f64(1.5)
+            .unwrap()
+            .str("it's")
+            .unwrap()
+            .bytes(&[0, 1])
+            .unwrap()
+            .bool(true)
+            .unwrap()
+            .null()
+            .unwrap()
+            .f32(0.25)
+            .unwrap();
+        assert_eq!(
+            decode_params(&buf).unwrap(),
+            vec![
+                Param::Int(-5),
+                Param::Int(u32::MAX as i64 + 1),
+                Param::Float(1.5),
+                Param::Text("it's".into()),
+                Param::Bytes(vec![0, 1]),
+                Param::Bool(true),
+                Param::Null,
+                Param::Float(0.25),
+            ]
+        );
+
+        assert_eq!(decode_params(&[]).unwrap(), Vec::new());
+
+        // indefinite-length array
+        let mut buf = Vec::new();
+        let mut enc = minicbor::Encoder::new(&mut buf);
+        enc.begin_array().unwrap().i8(1).unwrap().end().unwrap();
+        assert_eq!(decode_params(&buf).unwrap(), vec![Param::Int(1)]);
+
+        // not an array
+        let mut buf = Vec::new();
+        minicbor::Encoder::new(&mut buf).str("1").unwrap();
+        assert!(decode_params(&buf).is_err());
+
+        // u64 larger than i64::MAX
+        let mut buf = Vec::new();
+        let mut enc = minicbor::Encoder::new(&mut buf);
+        enc.array(1).unwrap().u64(u64::MAX).unwrap();
+        assert!(decode_params(&buf).is_err());
+
+        // maps are not supported
+        let mut buf = Vec::new();
+        let mut enc = minicbor::Encoder::new(&mut buf);
+        enc.array(1).unwrap().map(0).unwrap();
+        assert!(decode_params(&buf).is_err());
+    }
+
+    #[test]
+    fn decode_rows_of_params() {
+        let mut buf = Vec::new();
+        let mut enc = minicbor::Encoder::new(&mut buf);
+        enc.array(2).unwrap();
+        enc.array(2).unwrap().i8(1).unwrap().str("a").unwrap();
+        enc.begin_array()
+            .unwrap()
+            .i8(2)
+            .unwrap()
+            .null()
+            .unwrap()
+            .end()
+            .unwrap();
+        assert_eq!(
+            decode_rows(&buf, 2).unwrap(),
+            vec![
+                vec![Param::Int(1), Param::Text("a".into())],
+                vec![Param::Int(2), Param::Null],
+            ]
+        );
+        assert_eq!(decode_rows(&[], 2).unwrap(), Vec::<Vec<Param>>::new());
+        assert_eq!(
+            decode_rows(&buf, 3).unwrap_err(),
+            "row 1: expected 3 values, one for each column, found 2"
+        );
+
+        // a row that is not an array
+        let mut buf = Vec::new();
+        let mut enc = minicbor::Encoder::new(&mut buf);
+        enc.array(1).unwrap().i8(1).unwrap();
+        assert!(decode_rows(&buf, 1)
+            .unwrap_err()
+            .starts_with("row 1 must be an array"));
+    }
+
+    #[test]
+    fn convert() {
+        let mut out = BytesMut::new();
+        assert!(Param::Int(1).to_sql(&Type::INT2, &mut out).is_ok());
+        assert!(Param::Int(70000).to_sql(&Type::INT2, &mut out).is_err());
+        assert!(Param::Int(-1).to_sql(&Type::OID, &mut out).is_err());
+        assert!(Param::Int(1).to_sql(&Type::FLOAT8, &mut out).is_ok());
+        assert!(Param::Float(1.0).to_sql(&Type::INT4, &mut out).is_err());
+        assert!(Param::Text("a".into())
+            .to_sql(&Type::VARCHAR, &mut out)
+            .is_ok());
+        assert!(Param::Text("a".into())
+            .to_sql(&Type::INT4, &mut out)
+            .is_err());
+        assert!(Param::Text("a".into())
+            .to_sql(&Type::REFCURSOR, &mut out)
+            .is_ok());
+        assert!(Param::Bytes(vec![1]).to_sql(&Type::BYTEA, &mut out).is_ok());
+        assert!(matches!(
+            Param::Null.to_sql(&Type::TIMESTAMP, &mut out),
+            Ok(IsNull::Yes)
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+//! `?` placeholders, with placeholder_style `question`.
+//!
+//! Postgres numbers its placeholders, `$1`, `$2`, ..., while other drivers use `?`. With
+//! placeholder_style `question`, the `?` of a query with params are replaced with `$1`,
+//! `$2`, ..., in order, before the query is sent. A `?` in a string literal, a quoted
+//! identifier, a dollar-quoted string, or a comment is not a placeholder, and `??` is a
+//! `?` that is not a placeholder, such as the jsonb operators `?`, `?|` and `?&`.
+//!
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// Placeholders of the queries of operations with params
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PlaceholderStyle {
+    /// `$1`, `$2`, ..., as the query is sent
+    #[default]
+    Dollar,
+    /// `?`, which are numbered in order
+    Question,
+}
+
+impl PlaceholderStyle {
+    /// The query to send, for `num_params` params. With `question`, returns an error if
+    /// the query does not have one placeholder for each param
+    pub(crate) fn query(self, query: &str, num_params: usize) -> Result<Cow<'_, str>, String> {
+        if self == PlaceholderStyle::Dollar {
+            return Ok(Cow::Borrowed(query));
+        }
+        let (query, count) = number_placeholders(query)?;
+        if count != num_params {
+            return Err(format!(
+                "query has {} '?' placeholders, but {} params",
+                count, num_params
+            ));
+        }
+        Ok(Cow::Owned(query))
+    }
+}
+
+/// Replace the `?` placeholders of the query with `$1`, `$2`, ..., and `??` with `?`.
+/// Returns the query and the number of placeholders
+fn number_placeholders(query: &str) -> Result<(String, usize), String> {
+    let mut out = String::with_capacity(query.len() + 8);
+    let mut chars = query.chars().peekable();
+    let mut count = 0;
+    // true if the previous character is part of an identifier or keyword
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        let word_char = c.is_alphanumeric() || c == '_' || (in_word && c == '$');
+        match c {
+            '?' if chars.peek() == Some(&'?') => {
+                chars.next();
+                out.push('?');
+            }
+            '?' => {
+                count += 1;
+                out.push('$');
+                out.push_str(&count.to_string());
+            }
+            '\'' => {
+                // an escape string, E'...', where a backslash escapes the next character
+                let escapes = in_word && out.ends_with(['e', 'E']) && {
+                    let before = out[..out.len() - 1].chars().next_back();
+                    !before.map_or(false, |c| c.is_alphanumeric() || c == '_' || c == '$')
+                };
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' if escapes => {
+                            if let Some(c) = chars.next() {
+                                out.push(c);
+                            }
+                        }
+                        // '' is a quote
+                        '\'' if chars.peek() == Some(&'\'') => {
+                            out.push('\'');
+                            chars.next();
+                        }
+                        '\'' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '"' => {
+                // a quoted identifier; "" within it is read as the end of one and the start
+                // of another
+                out.push(c);
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                out.push(c);
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                // comments nest
+                out.push(c);
+                out.push('*');
+                chars.next();
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match (c, chars.peek()) {
+                        ('/', Some('*')) => depth += 1,
+                        ('*', Some('/')) => depth -= 1,
+                        _ => continue,
+                    }
+                    out.push(chars.next().unwrap_or_default());
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            '$' if !in_word && chars.peek().map_or(false, |c| c.is_ascii_digit()) => {
+                return Err(
+                    "query has a $n placeholder, but placeholder_style is 'question'".into(),
+                );
+            }
+            '$' if !in_word => {
+                // a dollar-quoted string, $tag$...$tag$, if the tag is an identifier
+                let rest = chars.clone().collect::<String>();
+                let tag_len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .filter(|i| rest[*i..].starts_with('$'));
+                match tag_len {
+                    Some(len) => {
+                        let tag = format!("${}$", &rest[..len]);
+                        let end = rest[len + 1..]
+                            .find(&tag)
+                            .map_or(rest.len(), |i| len + 1 + i + tag.len());
+                        out.push('$');
+                        out.push_str(&rest[..end]);
+                        for _ in rest[..end].chars() {
+                            chars.next();
+                        }
+                    }
+                    None => out.push(c),
+                }
+            }
+            c => out.push(c),
+        }
+        in_word = word_char;
+    }
+    Ok((out, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(query: &str) -> (String, usize) {
+        number_placeholders(query).unwrap()
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            numbered("select * from t where a = ? and b in (?, ?)"),
+            (
+                "select * from t where a = $1 and b in ($2, $3)".to_string(),
+                3
+            )
+        );
+        // literals, identifiers, and comments
+        assert_eq!(
+            numbered(
+                "select '?', 'it''s ?', E'\\'?', \"a?\", $$?$$, $q$ $$ ? $q$, ? -- ?\n\
+                 /* ? /* ? */ ? */ + ?"
+            ),
+            (
+                "select '?', 'it''s ?', E'\\'?', \"a?\", $$?$$, $q$ $$ ? $q$, $1 -- ?\n\
+                 /* ? /* ? */ ? */ + $2"
+                    .to_string(),
+                2
+            )
+        );
+        // ?? is a ?, such as a jsonb operator
+        assert_eq!(
+            numbered("select doc ?? 'a', doc ??| ? from t where id = ?"),
+            (
+                "select doc ? 'a', doc ?| $1 from t where id = $2".to_string(),
+                2
+            )
+        );
+        // $ in identifiers
+        assert_eq!(
+            numbered("select a$b, ? from t"),
+            ("select a$b, $1 from t".to_string(), 1)
+        );
+        assert!(number_placeholders("select $1, ?").is_err());
+    }
+
+    #[test]
+    fn query() {
+        let style = PlaceholderStyle::Question;
+        assert_eq!(style.query("select ?", 1).unwrap(), "select $1");
+        assert_eq!(
+            style.query("select ?, ?", 1).unwrap_err(),
+            "query has 2 '?' placeholders, but 1 params"
+        );
+        assert_eq!(
+            PlaceholderStyle::Dollar.query("select ?", 0).unwrap(),
+            "select ?"
+        );
+    }
+}
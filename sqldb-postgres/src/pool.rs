@@ -0,0 +1,464 @@
+//! Taking connections from a link's pool.
+//!
+//! When all of the pool's connections are in use, a statement waits for one to be returned,
+//! for up to `pool.connection_timeout_secs` (`queue`, the default), or, with
+//! `pool.exhausted_behavior` `fail-fast`, fails immediately.
+//!
+//! The pool also waits while it opens a new connection, and it keeps trying to connect until
+//! the timeout, so a statement may also time out because the database is unreachable.
+//! The manager keeps the error of its last connection attempt, so that the error distinguishes
+//! the two. Each failure to connect is also recorded by the pool's circuit breaker, if the
+//! link has one; waiting for a permit of the provider's connection quota is not a failure to
+//! connect.
+//!
+use crate::{circuit::CircuitBreaker, error::DbError};
+use bb8_postgres::bb8::{ManageConnection, Pool, RunError};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// What a statement does when all of the pool's connections are in use
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ExhaustedBehavior {
+    /// wait for a connection, up to the pool's connection timeout
+    #[default]
+    Queue,
+    /// fail immediately
+    FailFast,
+}
+
+/// The error of the last attempt to open a connection for the pool, if it failed
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConnectErrors {
+    last: Arc<Mutex<Option<LastError>>>,
+}
+
+#[derive(Debug)]
+struct LastError {
+    at: Instant,
+    message: String,
+    /// true if the attempt did not get a permit of the connection quota
+    quota: bool,
+}
+
+impl ConnectErrors {
+    /// record the result of a connection attempt
+    pub(crate) fn record<T, E: fmt::Display>(&self, res: &Result<T, E>) {
+        *self.last.lock().unwrap() = res.as_ref().err().map(|e| LastError {
+            at: Instant::now(),
+            message: e.to_string(),
+            quota: false,
+        });
+    }
+
+    /// record an attempt that did not get a permit of the connection quota
+    pub(crate) fn record_quota(&self, e: &DbError) {
+        *self.last.lock().unwrap() = Some(LastError {
+            at: Instant::now(),
+            message: e.to_string(),
+            quota: true,
+        });
+    }
+
+    /// the last error, if it occurred within `within`, and whether it was the quota's
+    fn recent(&self, within: Duration) -> Option<(String, bool)> {
+        match &*self.last.lock().unwrap() {
+            Some(last) if last.at.elapsed() <= within => Some((last.message.clone(), last.quota)),
+            _ => None,
+        }
+    }
+}
+
+/// How connections are taken from a link's pool
+#[derive(Clone, Debug)]
+pub(crate) struct Checkout {
+    pub(crate) behavior: ExhaustedBehavior,
+    /// the pool's max_size, which the pool does not report
+    pub(crate) max_size: u32,
+    /// max time to wait for a connection
+    pub(crate) timeout: Duration,
+    /// errors of the pool's connection attempts
+    pub(crate) errors: ConnectErrors,
+    /// circuit breaker of the pool, if circuit_breaker_failures is set
+    pub(crate) circuit: Option<Arc<CircuitBreaker>>,
+}
+
+impl Checkout {
+    /// Returns CircuitOpen if the pool's circuit breaker is open
+    pub(crate) fn check_circuit(&self) -> Result<(), DbError> {
+        match &self.circuit {
+            Some(circuit) => circuit.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// record a connection taken from the pool, which closes the circuit breaker
+    pub(crate) fn connected(&self) {
+        if let Some(circuit) = &self.circuit {
+            circuit.success();
+        }
+    }
+
+    /// With fail-fast, returns an error if all of the pool's connections are in use
+    pub(crate) fn check<M: ManageConnection>(&self, pool: &Pool<M>) -> Result<(), DbError> {
+        let state = pool.state();
+        if self.behavior == ExhaustedBehavior::FailFast
+            && exhausted(state.connections, state.idle_connections, self.max_size)
+        {
+            return Err(DbError::Io(format!(
+                "connection pool exhausted: all {} connections are in use",
+                self.max_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// The error for a failure to get a connection from the pool. A failure to connect is
+    /// recorded by the circuit breaker, and a wait for the connection quota is not
+    pub(crate) fn error<M>(&self, pool: &Pool<M>, e: RunError<M::Error>) -> DbError
+    where
+        M: ManageConnection,
+        M::Error: fmt::Display,
+    {
+        if let Some((e, true)) = self.errors.recent(self.timeout) {
+            return DbError::Io(e);
+        }
+        let connect_error = match e {
+            RunError::TimedOut => {
+                let state = pool.state();
+                match self.errors.recent(self.timeout) {
+                    Some((e, false))
+                        if !exhausted(state.connections, state.idle_connections, self.max_size) =>
+                    {
+                        e
+                    }
+                    _ => {
+                        return DbError::Io(format!(
+                            "timed out after {}s waiting for a connection: all {} connections \
+                             are in use. Consider increasing pool.max_connections or \
+                             pool.connection_timeout_secs",
+                            self.timeout.as_secs(),
+                            self.max_size
+                        ))
+                    }
+                }
+            }
+            RunError::User(e) => e.to_string(),
+        };
+        if let Some(circuit) = &self.circuit {
+            circuit.failure();
+        }
+        DbError::Io(format!(
+            "could not connect to the database: {}",
+            connect_error
+        ))
+    }
+}
+
+/// true if all of the pool's connections are in use, and it cannot open more
+fn exhausted(connections: u32, idle: u32, max_size: u32) -> bool {
+    idle == 0 && connections >= max_size
+}
+
+/// A pool used by the links with the same settings
+struct SharedPool<P> {
+    pool: P,
+    errors: ConnectErrors,
+    /// number of leases
+    links: usize,
+}
+
+/// Pools of the links with shared_pool, by their settings. A pool is removed when the last
+/// link that uses it is deleted, and it is closed when the link's statements and
+/// transactions have returned its connections
+#[derive(Clone)]
+pub(crate) struct SharedPools<P = crate::Pool> {
+    inner: Arc<Mutex<HashMap<String, SharedPool<P>>>>,
+}
+
+impl<P> Default for SharedPools<P> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+        }
+    }
+}
+
+impl<P: Clone> SharedPools<P> {
+    /// The pool with the key, and the errors of its connection attempts, if there is one
+    pub(crate) fn get(&self, key: &str) -> Option<(P, ConnectErrors, SharedPoolLease<P>)> {
+        let mut inner = self.inner.lock().unwrap();
+        let shared = inner.get_mut(key)?;
+        shared.links += 1;
+        Some((
+            shared.pool.clone(),
+            shared.errors.clone(),
+            SharedPoolLease {
+                pools: self.clone(),
+                key: key.to_string(),
+            },
+        ))
+    }
+
+    /// Adds the pool with the key. If another link added a pool with the key in the meantime,
+    /// that pool is returned instead
+    pub(crate) fn insert(
+        &self,
+        key: String,
+        pool: P,
+        errors: ConnectErrors,
+    ) -> (P, ConnectErrors, SharedPoolLease<P>) {
+        let mut inner = self.inner.lock().unwrap();
+        let shared = inner.entry(key.clone()).or_insert(SharedPool {
+            pool,
+            errors,
+            links: 0,
+        });
+        shared.links += 1;
+        (
+            shared.pool.clone(),
+            shared.errors.clone(),
+            SharedPoolLease {
+                pools: self.clone(),
+                key,
+            },
+        )
+    }
+
+    #[cfg(test)]
+    fn links(&self, key: &str) -> Option<usize> {
+        self.inner
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|shared| shared.links)
+    }
+}
+
+/// A link's use of a shared pool. The pool is removed when the last lease is dropped
+pub(crate) struct SharedPoolLease<P = crate::Pool> {
+    pools: SharedPools<P>,
+    key: String,
+}
+
+impl<P> Drop for SharedPoolLease<P> {
+    fn drop(&mut self) {
+        let mut inner = self.pools.inner.lock().unwrap();
+        if let Some(shared) = inner.get_mut(&self.key) {
+            shared.links -= 1;
+            if shared.links == 0 {
+                inner.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bb8_postgres::bb8::PooledConnection;
+
+    /// Connections that are opened, or refused, without a database
+    struct Manager {
+        refuse: bool,
+        errors: ConnectErrors,
+    }
+
+    #[async_trait::async_trait]
+    impl ManageConnection for Manager {
+        type Connection = ();
+        type Error = std::io::Error;
+
+        async fn connect(&self) -> Result<(), Self::Error> {
+            let res = if self.refuse {
+                Err(std::io::ErrorKind::ConnectionRefused.into())
+            } else {
+                Ok(())
+            };
+            self.errors.record(&res);
+            res
+        }
+
+        async fn is_valid(&self, _: &mut PooledConnection<'_, Self>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut ()) -> bool {
+            false
+        }
+    }
+
+    /// a pool of one connection, with a 1s connection timeout
+    fn tiny_pool(behavior: ExhaustedBehavior, refuse: bool) -> (Pool<Manager>, Checkout) {
+        let checkout = Checkout {
+            behavior,
+            max_size: 1,
+            timeout: Duration::from_secs(1),
+            errors: ConnectErrors::default(),
+            circuit: None,
+        };
+        let pool = Pool::builder()
+            .max_size(checkout.max_size)
+            .connection_timeout(checkout.timeout)
+            .build_unchecked(Manager {
+                refuse,
+                errors: checkout.errors.clone(),
+            });
+        (pool, checkout)
+    }
+
+    #[test]
+    fn exhausted_pool() {
+        assert!(exhausted(1, 0, 1));
+        assert!(!exhausted(1, 1, 1));
+        // the pool can open another connection
+        assert!(!exhausted(1, 0, 2));
+        assert!(!exhausted(0, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn queue() {
+        let (pool, checkout) = tiny_pool(ExhaustedBehavior::Queue, false);
+        let conn = pool.get_owned().await.expect("connection");
+        assert!(checkout.check(&pool).is_ok());
+
+        // waits until the connection is returned
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(conn);
+        });
+        let conn = pool.get().await.expect("connection after wait");
+        release.await.unwrap();
+
+        // times out while the connection is in use
+        let Err(e) = pool.get().await else {
+            panic!("expected a timeout")
+        };
+        let e = checkout.error(&pool, e);
+        assert!(
+            e.to_string()
+                .starts_with("timed out after 1s waiting for a connection: all 1 connections"),
+            "{}",
+            e
+        );
+        drop(conn);
+    }
+
+    #[tokio::test]
+    async fn fail_fast() {
+        let (pool, checkout) = tiny_pool(ExhaustedBehavior::FailFast, false);
+        assert!(checkout.check(&pool).is_ok());
+        let conn = pool.get().await.expect("connection");
+        let e = checkout.check(&pool).expect_err("exhausted");
+        assert_eq!(
+            e.to_string(),
+            "connection pool exhausted: all 1 connections are in use"
+        );
+        drop(conn);
+        assert!(checkout.check(&pool).is_ok());
+    }
+
+    #[tokio::test]
+    async fn connect_error() {
+        for behavior in [ExhaustedBehavior::Queue, ExhaustedBehavior::FailFast] {
+            let (pool, checkout) = tiny_pool(behavior, true);
+            // the pool has no connections, so it is not exhausted
+            assert!(checkout.check(&pool).is_ok());
+            let Err(e) = pool.get().await else {
+                panic!("expected a connect error")
+            };
+            let e = checkout.error(&pool, e);
+            assert!(
+                e.to_string()
+                    .starts_with("could not connect to the database: "),
+                "{:?}: {}",
+                behavior,
+                e
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker() {
+        let circuit = || {
+            Some(Arc::new(CircuitBreaker::new(
+                2,
+                Duration::from_secs(60),
+                Duration::from_secs(60),
+            )))
+        };
+        // waits for a connection in use are not failures
+        let (pool, mut checkout) = tiny_pool(ExhaustedBehavior::Queue, false);
+        checkout.circuit = circuit();
+        let conn = pool.get().await.expect("connection");
+        for _ in 0..2 {
+            let Err(e) = pool.get().await else {
+                panic!("expected a timeout")
+            };
+            checkout.error(&pool, e);
+        }
+        assert!(checkout.check_circuit().is_ok());
+        drop(conn);
+
+        let (pool, mut checkout) = tiny_pool(ExhaustedBehavior::FailFast, true);
+        checkout.circuit = circuit();
+        for _ in 0..2 {
+            assert!(checkout.check_circuit().is_ok());
+            let Err(e) = pool.get().await else {
+                panic!("expected a connect error")
+            };
+            checkout.error(&pool, e);
+        }
+        assert!(matches!(
+            checkout.check_circuit(),
+            Err(DbError::CircuitOpen(_))
+        ));
+        checkout.connected();
+        assert!(checkout.check_circuit().is_ok());
+
+        // waits for a permit of the connection quota are not failures either
+        let (pool, mut checkout) = tiny_pool(ExhaustedBehavior::Queue, true);
+        checkout.circuit = circuit();
+        for _ in 0..2 {
+            let Err(e) = pool.get().await else {
+                panic!("expected a connect error")
+            };
+            checkout
+                .errors
+                .record_quota(&DbError::Io("connection quota exhausted".into()));
+            let e = checkout.error(&pool, e);
+            assert_eq!(e.to_string(), "connection quota exhausted");
+        }
+        assert!(checkout.check_circuit().is_ok());
+    }
+
+    #[test]
+    fn shared_pools() {
+        let pools = SharedPools::<u32>::default();
+        assert!(pools.get("a").is_none());
+        let (pool, _, first) = pools.insert("a".into(), 1, Default::default());
+        assert_eq!(pool, 1);
+        // a link that added its pool at the same time uses the first one
+        let (pool, _, second) = pools.insert("a".into(), 2, Default::default());
+        assert_eq!(pool, 1);
+        let (pool, _, third) = pools.get("a").expect("shared pool");
+        assert_eq!(pool, 1);
+        assert_eq!(pools.links("a"), Some(3));
+        let (_, _, other) = pools.insert("b".into(), 3, Default::default());
+
+        drop(first);
+        drop(second);
+        assert_eq!(pools.links("a"), Some(1));
+        drop(third);
+        assert_eq!(pools.links("a"), None);
+        assert!(pools.get("a").is_none());
+        assert_eq!(pools.links("b"), Some(1));
+        drop(other);
+        assert_eq!(pools.links("b"), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Prepared transactions, for two-phase commit.
+//!
+//! `prepare_transaction` ends a transaction opened with `begin_transaction` with
+//! `PREPARE TRANSACTION`, which stores it on the server, with its global id (gid), until it is
+//! committed with `COMMIT PREPARED` or rolled back with `ROLLBACK PREPARED`, from any
+//! session. A prepared transaction no longer belongs to the session that prepared it, so the
+//! session's connection is returned to the pool, and the transaction outlives the session,
+//! the actor's link, and the provider.
+//!
+//! The provider records the actor that prepared each gid, and rejects `commit_prepared` and
+//! `rollback_prepared` of a gid another actor prepared. A gid the provider has no record of,
+//! such as one prepared before the provider restarted, may be committed or rolled back by any
+//! actor of the link, so that a coordinator can recover its transactions.
+//!
+use crate::error::DbError;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// max length of a gid, in bytes. The server's limit is 200, with a terminating NUL
+const MAX_GID_LEN: usize = 199;
+
+/// Returns the statement that runs the command, such as `COMMIT PREPARED`, for the gid
+pub(crate) fn statement(command: &str, gid: &str) -> Result<String, String> {
+    if gid.is_empty() || gid.len() > MAX_GID_LEN {
+        return Err(format!(
+            "invalid gid '{}': expected 1 to {} bytes",
+            gid, MAX_GID_LEN
+        ));
+    }
+    // a backslash would be an escape if standard_conforming_strings is off
+    if gid.chars().any(|c| c.is_control() || c == '\\') {
+        return Err(format!(
+            "invalid gid '{}': control characters and backslashes are not allowed",
+            gid.escape_debug()
+        ));
+    }
+    Ok(format!("{} '{}'", command, gid.replace('\'', "''")))
+}
+
+/// Actors that prepared the transactions, by gid
+#[derive(Clone, Default)]
+pub(crate) struct PreparedTransactions {
+    inner: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl PreparedTransactions {
+    /// Record the transaction the actor prepared
+    pub(crate) fn insert(&self, actor_id: &str, gid: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(gid.to_string(), actor_id.to_string());
+    }
+
+    /// Returns an error if another actor prepared the transaction
+    pub(crate) fn check(&self, actor_id: &str, gid: &str) -> Result<(), DbError> {
+        match self.inner.lock().unwrap().get(gid) {
+            Some(actor) if actor != actor_id => Err(DbError::PermissionDenied(format!(
+                "prepared transaction '{}' was prepared by another actor",
+                gid
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop the record of a transaction that was committed or rolled back
+    pub(crate) fn remove(&self, gid: &str) {
+        self.inner.lock().unwrap().remove(gid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements() {
+        assert_eq!(
+            statement("PREPARE TRANSACTION", "order-42").unwrap(),
+            "PREPARE TRANSACTION 'order-42'"
+        );
+        assert_eq!(
+            statement("COMMIT PREPARED", "it's").unwrap(),
+            "COMMIT PREPARED 'it''s'"
+        );
+        assert!(statement("COMMIT PREPARED", "").is_err());
+        assert!(statement("COMMIT PREPARED", &"x".repeat(200)).is_err());
+        assert!(statement("COMMIT PREPARED", "a\\b").is_err());
+        assert!(statement("COMMIT PREPARED", "a\nb").is_err());
+    }
+
+    #[test]
+    fn owners() {
+        let prepared = PreparedTransactions::default();
+        prepared.insert("actor1", "tx1");
+        assert!(prepared.check("actor1", "tx1").is_ok());
+        assert!(matches!(
+            prepared.check("actor2", "tx1"),
+            Err(DbError::PermissionDenied(_))
+        ));
+        // unknown gids are allowed
+        assert!(prepared.check("actor2", "tx2").is_ok());
+        prepared.remove("tx1");
+        assert!(prepared.check("actor2", "tx1").is_ok());
+    }
+}
@@ -0,0 +1,120 @@
+//! Results of FetchCached, cached per actor and query.
+//!
+//! A cached result is returned until its ttl expires, without running the query. Statements
+//! that change the data do not invalidate the cached results: actors drop them with
+//! InvalidateCache. Expired results are removed when a result is added, and the results of
+//! an actor are removed when its link is updated or deleted.
+//!
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use wasmcloud_interface_sqldb::FetchResult;
+
+/// (actor_id, query)
+type CacheKey = (String, String);
+
+struct Entry {
+    result: FetchResult,
+    expires: Instant,
+}
+
+/// Cached query results of all actors
+#[derive(Clone, Default)]
+pub(crate) struct QueryCache {
+    inner: Arc<Mutex<HashMap<CacheKey, Entry>>>,
+}
+
+impl QueryCache {
+    /// Returns the cached result of the actor's query, if it has not expired
+    pub(crate) fn get(&self, actor_id: &str, query: &str) -> Option<FetchResult> {
+        self.get_at(actor_id, query, Instant::now())
+    }
+
+    fn get_at(&self, actor_id: &str, query: &str, now: Instant) -> Option<FetchResult> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .get(&(actor_id.to_string(), query.to_string()))
+            .filter(|entry| entry.expires > now)
+            .map(|entry| entry.result.clone())
+    }
+
+    /// Caches the result of the actor's query for `ttl`, and removes the expired results
+    pub(crate) fn insert(&self, actor_id: &str, query: &str, result: FetchResult, ttl: Duration) {
+        self.insert_at(actor_id, query, result, ttl, Instant::now())
+    }
+
+    fn insert_at(
+        &self,
+        actor_id: &str,
+        query: &str,
+        result: FetchResult,
+        ttl: Duration,
+        now: Instant,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.retain(|_, entry| entry.expires > now);
+        inner.insert(
+            (actor_id.to_string(), query.to_string()),
+            Entry {
+                result,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    /// Removes the cached result of the actor's query, or, without a query, all of the
+    /// actor's cached results. Returns the number of results removed
+    pub(crate) fn invalidate(&self, actor_id: &str, query: Option<&str>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.len();
+        inner.retain(|(actor, cached), _| {
+            actor != actor_id || query.map_or(false, |query| query != cached)
+        });
+        before - inner.len()
+    }
+
+    /// Removes all cached results
+    pub(crate) fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(num_rows: u64) -> FetchResult {
+        FetchResult {
+            num_rows,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ttl_and_invalidate() {
+        let cache = QueryCache::default();
+        let start = Instant::now();
+        let ttl = Duration::from_secs(1);
+        cache.insert_at("a", "select 1", result(1), ttl, start);
+        cache.insert_at("a", "select 2", result(2), ttl, start);
+        cache.insert_at("b", "select 1", result(3), ttl, start);
+        assert_eq!(cache.get_at("a", "select 1", start).unwrap().num_rows, 1);
+        assert_eq!(cache.get_at("b", "select 1", start).unwrap().num_rows, 3);
+        assert!(cache.get_at("a", "select 3", start).is_none());
+        // served until the ttl expires
+        let later = start + Duration::from_millis(1500);
+        assert!(cache.get_at("a", "select 1", later).is_none());
+
+        assert_eq!(cache.invalidate("a", Some("select 1")), 1);
+        assert_eq!(cache.invalidate("a", Some("select 1")), 0);
+        assert!(cache.get_at("a", "select 2", start).is_some());
+        assert_eq!(cache.invalidate("a", None), 1);
+        assert!(cache.get_at("b", "select 1", start).is_some());
+
+        // expired results are removed when a result is added
+        cache.insert_at("a", "select 1", result(1), ttl, later);
+        assert_eq!(cache.invalidate("b", None), 0);
+    }
+}
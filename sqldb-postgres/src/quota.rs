@@ -0,0 +1,153 @@
+//! Provider-wide limit of the open connections.
+//!
+//! Each link has its own pool, so the connections of all links together can exceed the
+//! server's `max_connections`. With the environment variable `SQLDB_TOTAL_MAX_CONNECTIONS`,
+//! each connection the provider opens, for a pool, a listener, or the audit log, takes one of
+//! that many permits, shared by all links, and holds it until the connection is closed, so
+//! idle connections hold their permits too. When all permits are taken, opening a connection
+//! waits for one, for up to the link's `pool.connection_timeout_secs`, and with
+//! `pool.exhausted_behavior` `fail-fast`, a statement that would have to open a connection
+//! fails immediately.
+//!
+use crate::{error::DbError, pool::ExhaustedBehavior};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Environment variable of the max number of connections all links may open
+pub(crate) const TOTAL_MAX_CONNECTIONS: &str = "SQLDB_TOTAL_MAX_CONNECTIONS";
+
+/// Permits of the open connections, if there is a limit, and how a link waits for them
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionQuota {
+    limit: Option<(Arc<Semaphore>, usize)>,
+    behavior: ExhaustedBehavior,
+    /// max time to wait for a permit
+    timeout: Duration,
+}
+
+impl ConnectionQuota {
+    /// A quota of `max` connections, or no limit, if it is None or 0
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        Self {
+            limit: max
+                .filter(|max| *max > 0)
+                .map(|max| (Arc::new(Semaphore::new(max)), max)),
+            ..Default::default()
+        }
+    }
+
+    /// The quota, for the connections of a link with its exhausted behavior and timeout
+    pub(crate) fn with_wait(&self, behavior: ExhaustedBehavior, timeout: Duration) -> Self {
+        Self {
+            limit: self.limit.clone(),
+            behavior,
+            timeout,
+        }
+    }
+
+    /// With fail-fast, returns an error if a pool without idle connections could not open one,
+    /// because all permits are taken
+    pub(crate) fn check(&self, idle_connections: u32) -> Result<(), DbError> {
+        match &self.limit {
+            Some((semaphore, max))
+                if self.behavior == ExhaustedBehavior::FailFast
+                    && idle_connections == 0
+                    && semaphore.available_permits() == 0 =>
+            {
+                Err(exhausted(*max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The quota set with SQLDB_TOTAL_MAX_CONNECTIONS
+    pub(crate) fn from_env() -> Result<Self, String> {
+        match std::env::var(TOTAL_MAX_CONNECTIONS) {
+            Ok(val) => val
+                .trim()
+                .parse::<usize>()
+                .map(|max| Self::new(Some(max)))
+                .map_err(|_| {
+                    format!(
+                        "invalid {}: '{}' (expected a number of connections)",
+                        TOTAL_MAX_CONNECTIONS, val
+                    )
+                }),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Take a permit for a connection that is being opened, if there is a limit. With
+    /// fail-fast, returns an error if all permits are taken, otherwise waits for one for up
+    /// to the link's connection timeout
+    pub(crate) async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, DbError> {
+        let (semaphore, max) = match &self.limit {
+            Some((semaphore, max)) => (semaphore.clone(), *max),
+            None => return Ok(None),
+        };
+        if self.behavior == ExhaustedBehavior::FailFast {
+            return match semaphore.try_acquire_owned() {
+                Ok(permit) => Ok(Some(permit)),
+                Err(_) => Err(exhausted(max)),
+            };
+        }
+        match tokio::time::timeout(self.timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            // the semaphore is never closed
+            Ok(Err(_)) | Err(_) => Err(DbError::Io(format!(
+                "timed out after {}s waiting for a connection: all {} connections of {} \
+                 are open",
+                self.timeout.as_secs(),
+                max,
+                TOTAL_MAX_CONNECTIONS
+            ))),
+        }
+    }
+}
+
+/// the error of a fail-fast link when all permits are taken
+fn exhausted(max: usize) -> DbError {
+    DbError::Io(format!(
+        "connection quota exhausted: all {} connections of {} are open",
+        max, TOTAL_MAX_CONNECTIONS
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn quota() {
+        let none = ConnectionQuota::new(Some(0));
+        assert!(matches!(none.acquire().await, Ok(None)));
+        assert!(none.check(0).is_ok());
+
+        let shared = ConnectionQuota::new(Some(1));
+        let queue = shared.with_wait(ExhaustedBehavior::Queue, Duration::from_secs(1));
+        let fail_fast = shared.with_wait(ExhaustedBehavior::FailFast, Duration::from_secs(1));
+        assert!(fail_fast.check(0).is_ok());
+        let Ok(permit) = queue.acquire().await else {
+            panic!("expected a permit")
+        };
+        let e = fail_fast.acquire().await.expect_err("exhausted");
+        assert_eq!(
+            e.to_string(),
+            "connection quota exhausted: all 1 connections of SQLDB_TOTAL_MAX_CONNECTIONS are open"
+        );
+        // a pool with an idle connection does not need a permit
+        assert!(fail_fast.check(0).is_err());
+        assert!(fail_fast.check(1).is_ok());
+        assert!(queue.check(0).is_ok());
+        let e = queue.acquire().await.expect_err("timed out");
+        assert!(e.to_string().starts_with("timed out after 1s"), "{}", e);
+
+        // waits until the permit is released
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(permit);
+        });
+        assert!(matches!(queue.acquire().await, Ok(Some(_))));
+        release.await.unwrap();
+    }
+}
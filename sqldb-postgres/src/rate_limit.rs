@@ -0,0 +1,81 @@
+//! Rate limit of the statements of a link.
+//!
+//! Each link with `max_queries_per_second` has a token bucket that holds up to that many
+//! tokens, and is refilled at that rate. A statement takes a token, or, if the bucket is
+//! empty, it is rejected without being sent to the database. An actor may therefore run
+//! a burst of up to `max_queries_per_second` statements after it has been idle.
+//!
+use std::{sync::Mutex, time::Instant};
+
+/// Token bucket of a link
+pub(crate) struct RateLimiter {
+    /// tokens added per second, and the capacity of the bucket
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    /// time the tokens were last refilled
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// The bucket starts full
+    pub(crate) fn new(max_per_second: u32) -> Self {
+        Self {
+            rate: max_per_second as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: max_per_second as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token. Returns false if there is none
+    pub(crate) fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.refilled);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// the configured max number of statements per second
+    pub(crate) fn max_per_second(&self) -> u32 {
+        self.rate as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RateLimiter::new(2);
+        let start = Instant::now();
+        assert!(limiter.try_acquire_at(start));
+        assert!(limiter.try_acquire_at(start));
+        assert!(!limiter.try_acquire_at(start));
+        // one token is added every 500ms
+        assert!(!limiter.try_acquire_at(start + Duration::from_millis(400)));
+        assert!(limiter.try_acquire_at(start + Duration::from_millis(550)));
+        assert!(!limiter.try_acquire_at(start + Duration::from_millis(600)));
+        // the bucket holds at most 2 tokens
+        let later = start + Duration::from_secs(10);
+        assert!(limiter.try_acquire_at(later));
+        assert!(limiter.try_acquire_at(later));
+        assert!(!limiter.try_acquire_at(later));
+    }
+}
@@ -0,0 +1,248 @@
+//! Statements allowed on read-only links.
+//!
+//! With `read_only` on, each connection of the link runs
+//! `SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY`, so the server rejects any statement
+//! that writes, including the statements of functions. That setting is a session variable,
+//! which a statement could change back, so the actor's statements are also checked before they
+//! are sent. The check reads the first keyword of the statement, skipping comments and
+//! parentheses, and allows queries: SELECT, WITH, VALUES, TABLE, SHOW, and EXPLAIN. It rejects
+//! a second statement after a `;`, and calls of `set_config`. It is not a parser: a query that
+//! writes, such as a WITH with a DELETE, is allowed by the check and rejected by the server.
+//!
+
+/// first keywords of the statements allowed on read-only links
+const QUERY_KEYWORDS: [&str; 6] = ["select", "with", "values", "table", "show", "explain"];
+
+/// Returns an error that says why the statement is not allowed on a read-only link
+pub(crate) fn check(statement: &str) -> Result<(), String> {
+    let tokens = tokens(statement);
+    let mut iter = tokens.iter().skip_while(|t| **t == Token::Punct('('));
+    match iter.next() {
+        Some(Token::Word(word)) if QUERY_KEYWORDS.contains(&word.as_str()) => {}
+        Some(Token::Word(word)) => {
+            return Err(format!(
+                "{} statements are not allowed",
+                word.to_ascii_uppercase()
+            ))
+        }
+        _ => return Err("only queries are allowed".into()),
+    }
+    for token in iter.by_ref() {
+        match token {
+            Token::Semicolon => break,
+            Token::Word(name) | Token::Ident(name) if name == "set_config" => {
+                return Err("set_config is not allowed".into())
+            }
+            _ => {}
+        }
+    }
+    if iter.next().is_some() {
+        return Err("multiple statements are not allowed".into());
+    }
+    Ok(())
+}
+
+/// A token of a statement. Literals and placeholders are `Quoted`, and are not read
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Token {
+    /// a keyword or unquoted identifier, in lower case
+    Word(String),
+    /// a quoted identifier, which is not a keyword
+    Ident(String),
+    Semicolon,
+    Punct(char),
+    Quoted,
+}
+
+/// The tokens of the statement, without whitespace and comments
+pub(crate) fn tokens(statement: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = statement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                // block comments nest
+                chars.next();
+                let mut depth = 1;
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    match (prev, c) {
+                        ('/', '*') => {
+                            depth += 1;
+                            prev = ' ';
+                            continue;
+                        }
+                        ('*', '/') => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                            prev = ' ';
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    prev = c;
+                }
+            }
+            '\'' => {
+                // an escape string (E'...') is read after its prefix, below
+                skip_string(&mut chars, false);
+                tokens.push(Token::Quoted);
+            }
+            '"' => {
+                // a quoted identifier, where "" is a quote
+                let mut ident = String::new();
+                while let Some(c) = chars.next() {
+                    if c == '"' {
+                        if chars.peek() == Some(&'"') {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    ident.push(c);
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            '$' if chars.peek().map_or(false, |c| c.is_ascii_digit()) => {
+                // a parameter placeholder
+                while chars.peek().map_or(false, |c| c.is_ascii_digit()) {
+                    chars.next();
+                }
+                tokens.push(Token::Quoted);
+            }
+            '$' => {
+                // a dollar-quoted string, $tag$...$tag$
+                let mut tag = String::from('$');
+                while let Some(&c) = chars.peek() {
+                    chars.next();
+                    tag.push(c);
+                    if c == '$' {
+                        break;
+                    }
+                }
+                let mut body = String::new();
+                for c in chars.by_ref() {
+                    body.push(c);
+                    if body.ends_with(&tag) {
+                        break;
+                    }
+                }
+                tokens.push(Token::Quoted);
+            }
+            ';' => tokens.push(Token::Semicolon),
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_lowercase().collect::<String>();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '$') {
+                        break;
+                    }
+                    word.extend(c.to_lowercase());
+                    chars.next();
+                }
+                if word == "e" && chars.peek() == Some(&'\'') {
+                    chars.next();
+                    skip_string(&mut chars, true);
+                    tokens.push(Token::Quoted);
+                } else {
+                    tokens.push(Token::Word(word));
+                }
+            }
+            c => tokens.push(Token::Punct(c)),
+        }
+    }
+    tokens
+}
+
+/// skip the rest of a string literal, where '' is a quote, and, in an escape string,
+/// a backslash escapes the next character
+fn skip_string(chars: &mut std::iter::Peekable<std::str::Chars<'_>>, escapes: bool) {
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if escapes => {
+                chars.next();
+            }
+            '\'' if chars.peek() == Some(&'\'') => {
+                chars.next();
+            }
+            '\'' => break,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries() {
+        for statement in [
+            "select * from t",
+            "  SELECT 1;",
+            "-- comment\nselect 1",
+            "/* a /* nested */ comment */ select 1",
+            "(select 1) union (select 2)",
+            "with t as (select 1) select * from t",
+            "values (1, 'a')",
+            "table t",
+            "show search_path",
+            "explain select * from t",
+            "select 'set_config(x)', \"Set_Config\" from t",
+            "select $1::text; -- trailing comment",
+            "select $$ ; drop table t $$",
+            "select $tag$ it's ; $tag$",
+            "select E'it\\'s ; drop table t'",
+        ] {
+            assert_eq!(check(statement), Ok(()), "{}", statement);
+        }
+    }
+
+    #[test]
+    fn writes() {
+        for (statement, error) in [
+            (
+                "insert into t values (1)",
+                "INSERT statements are not allowed",
+            ),
+            ("  Delete from t", "DELETE statements are not allowed"),
+            (
+                "/* select */ update t set a = 1",
+                "UPDATE statements are not allowed",
+            ),
+            (
+                "set default_transaction_read_only = off",
+                "SET statements are not allowed",
+            ),
+            ("", "only queries are allowed"),
+            ("'select'", "only queries are allowed"),
+            (
+                "select set_config('default_transaction_read_only', 'off', false)",
+                "set_config is not allowed",
+            ),
+            (
+                "select pg_catalog.\"set_config\"('search_path', '', false)",
+                "set_config is not allowed",
+            ),
+            (
+                "select 1; delete from t",
+                "multiple statements are not allowed",
+            ),
+            (
+                "select 'it''s'; reset all",
+                "multiple statements are not allowed",
+            ),
+        ] {
+            assert_eq!(check(statement), Err(error.to_string()), "{}", statement);
+        }
+    }
+}
@@ -0,0 +1,580 @@
+//! Query results returned to actors.
+//!
+//! Rows are CBOR-encoded as they are read from the database, so that a result
+//! larger than the link's `max_result_rows` or `max_result_bytes` is rejected
+//! when the limit is reached, without first holding the whole result in memory.
+//! With the link's `result_encoding` `json` or `msgpack`, each row is converted
+//! from CBOR as it is encoded.
+//!
+//! The rows of `fetch_stream` are returned in chunks, which are encoded as the actor
+//! asks for them, so only one chunk is held in memory. Each chunk has whole rows.
+//! In CBOR, together they are an indefinite-length array, which the actor can decode
+//! as the chunks arrive; in JSON, they are the text of an array. MessagePack does not
+//! have indefinite-length arrays, so the chunks are a sequence of rows, without a header.
+//!
+use crate::{encoding::ResultEncoding, error::DbError, ext, types};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use std::pin::Pin;
+use tokio_postgres::{Row, RowStream};
+use wasmcloud_interface_sqldb::{Column, FetchResult, SqlDbError};
+
+/// Limits on the size of query results. None is unlimited.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ResultLimits {
+    pub(crate) max_rows: Option<u64>,
+    pub(crate) max_bytes: Option<u64>,
+}
+
+/// Limits and encoding of query results
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ResultOptions {
+    pub(crate) limits: ResultLimits,
+    pub(crate) encoding: types::EncodeOptions,
+    pub(crate) result_encoding: ResultEncoding,
+    pub(crate) row_format: types::RowFormat,
+    pub(crate) column_case: ColumnCase,
+    /// with partial_on_error, a row that cannot be encoded ends the result, which has the
+    /// rows before it, and a PartialResult error
+    pub(crate) partial_on_error: bool,
+}
+
+/// Case of the column names in the `columns` of results, and of the keys of rows with
+/// row_format `map`. The names are only changed in the results, not in the query
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ColumnCase {
+    /// names as returned by the database
+    #[default]
+    Preserve,
+    /// lowercase, for example `customerid` for `"CustomerId"`
+    Lower,
+    /// uppercase, for example `CUSTOMERID`
+    Upper,
+}
+
+impl ColumnCase {
+    fn apply(self, name: &str) -> String {
+        match self {
+            ColumnCase::Preserve => name.to_string(),
+            ColumnCase::Lower => name.to_lowercase(),
+            ColumnCase::Upper => name.to_uppercase(),
+        }
+    }
+}
+
+/// Error reading the rows of a query
+pub(crate) enum ReadError {
+    /// the query failed
+    Db(tokio_postgres::Error),
+    /// the rows could not be encoded, or exceeded a limit
+    Result(DbError),
+}
+
+/// What a single-row read takes from the row of its query
+#[derive(Clone, Copy)]
+pub(crate) enum RowShape {
+    /// the row and its columns, of FetchOne, or of FetchOptional with `optional`. Without
+    /// it, no row is a NotFound error
+    Row { optional: bool },
+    /// the value of the first column, of FetchScalar. A query without columns is an error
+    Scalar,
+}
+
+impl RowShape {
+    /// true if a query that returns no rows is not an error
+    pub(crate) fn optional(self) -> bool {
+        !matches!(self, RowShape::Row { optional: false })
+    }
+
+    /// rows recorded in the audit log for a read of this shape, which has `found` a row.
+    /// Scalars do not count rows
+    pub(crate) fn audited_rows(self, found: bool) -> Option<u64> {
+        match self {
+            RowShape::Row { .. } => Some(found as u64),
+            RowShape::Scalar => None,
+        }
+    }
+}
+
+/// The single row of a query, read by FetchOne, FetchOptional, or FetchScalar
+#[derive(Default)]
+pub(crate) struct SingleRow {
+    /// the row, or the value of its first column with RowShape::Scalar, encoded alone.
+    /// None if the query returned no rows
+    pub(crate) value: Option<Vec<u8>>,
+    /// columns of the row. Empty if no row was found, and for a scalar
+    pub(crate) columns: ext::Columns,
+    /// encoding of the value
+    pub(crate) encoding: ResultEncoding,
+}
+
+/// Encodes result rows into an array of arrays, or of maps, with row_format `map`
+pub(crate) struct RowEncoder {
+    limits: ResultLimits,
+    encoding: types::EncodeOptions,
+    result_encoding: ResultEncoding,
+    row_format: types::RowFormat,
+    column_case: ColumnCase,
+    columns: ext::Columns,
+    /// map keys of the columns, with row_format `map`
+    keys: Vec<String>,
+    /// encoded rows, without the array header
+    body: Vec<u8>,
+    /// cbor of the current row, if it is converted to another encoding
+    scratch: Vec<u8>,
+    num_rows: u64,
+    partial_on_error: bool,
+    /// why the result ends early, with partial_on_error
+    partial: Option<String>,
+}
+
+impl RowEncoder {
+    pub(crate) fn new(options: ResultOptions) -> Self {
+        Self {
+            limits: options.limits,
+            encoding: options.encoding,
+            result_encoding: options.result_encoding,
+            row_format: options.row_format,
+            column_case: options.column_case,
+            columns: Vec::new(),
+            keys: Vec::new(),
+            body: Vec::new(),
+            scratch: Vec::new(),
+            num_rows: 0,
+            partial_on_error: options.partial_on_error,
+            partial: None,
+        }
+    }
+
+    /// number of rows encoded so far
+    pub(crate) fn num_rows(&self) -> u64 {
+        self.num_rows
+    }
+
+    /// true if a row could not be encoded, with partial_on_error: the rows after it are
+    /// not encoded
+    pub(crate) fn is_partial(&self) -> bool {
+        self.partial.is_some()
+    }
+
+    /// Encode a row. Returns LimitExceeded if the row is over max_rows,
+    /// or the encoded rows are larger than max_bytes.
+    pub(crate) fn push(&mut self, row: &Row) -> Result<(), DbError> {
+        if self.is_partial() {
+            return Ok(());
+        }
+        if let Some(max) = self.limits.max_rows {
+            if self.num_rows >= max {
+                return Err(DbError::LimitExceeded(format!(
+                    "query returned more than max_result_rows ({}) rows",
+                    max
+                )));
+            }
+        }
+        if self.num_rows == 0 {
+            self.columns = columns(row, self.column_case);
+            if self.row_format == types::RowFormat::Map {
+                let names = self
+                    .columns
+                    .iter()
+                    .map(|c| c.name.as_str())
+                    .collect::<Vec<_>>();
+                self.keys = types::map_keys(&names);
+            }
+        }
+        let len = self.body.len();
+        if let Err(e) = self.encode_body(row) {
+            if !self.partial_on_error {
+                return Err(e);
+            }
+            // the part of the row that was encoded is removed
+            self.body.truncate(len);
+            self.partial = Some(format!(
+                "row {} could not be encoded, and the rows from it on are not returned: {}",
+                self.num_rows + 1,
+                e
+            ));
+            return Ok(());
+        }
+        self.num_rows += 1;
+        if let Some(max) = self.limits.max_bytes {
+            if self.body.len() as u64 > max {
+                return Err(DbError::LimitExceeded(format!(
+                    "query result is larger than max_result_bytes ({} bytes)",
+                    max
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// append the encoded row to the rows
+    fn encode_body(&mut self, row: &Row) -> Result<(), DbError> {
+        match self.result_encoding {
+            ResultEncoding::Cbor => {
+                let mut enc = minicbor::Encoder::new(&mut self.body);
+                Self::encode(&mut enc, row, self.row_format, &self.keys, &self.encoding)?;
+            }
+            ResultEncoding::Json => {
+                self.encode_scratch(row)?;
+                let value =
+                    crate::encoding::cbor_to_json(&self.scratch).map_err(DbError::Encoding)?;
+                if self.num_rows > 0 {
+                    self.body.push(b',');
+                }
+                serde_json::to_writer(&mut self.body, &value)
+                    .map_err(|e| DbError::Encoding(e.to_string()))?;
+            }
+            ResultEncoding::Msgpack => {
+                self.encode_scratch(row)?;
+                crate::encoding::cbor_to_msgpack(&self.scratch, &mut self.body)
+                    .map_err(DbError::Encoding)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// start the rows of a stream with the header of an indefinite-length array
+    fn begin_stream(&mut self) {
+        match self.result_encoding {
+            // major type 4 (array), indefinite length
+            ResultEncoding::Cbor => self.body.push(0x9f),
+            ResultEncoding::Json => self.body.push(b'['),
+            ResultEncoding::Msgpack => {}
+        }
+    }
+
+    /// end the rows of a stream
+    fn end_stream(&mut self) {
+        match self.result_encoding {
+            // break
+            ResultEncoding::Cbor => self.body.push(0xff),
+            ResultEncoding::Json => self.body.push(b']'),
+            ResultEncoding::Msgpack => {}
+        }
+    }
+
+    /// remove the first `len` bytes of the encoded rows
+    fn take_body(&mut self, len: usize) -> Vec<u8> {
+        let rest = self.body.split_off(len);
+        std::mem::replace(&mut self.body, rest)
+    }
+
+    /// encode the row as cbor into the scratch buffer
+    fn encode_scratch(&mut self, row: &Row) -> Result<(), DbError> {
+        self.scratch.clear();
+        let mut enc = minicbor::Encoder::new(&mut self.scratch);
+        Self::encode(&mut enc, row, self.row_format, &self.keys, &self.encoding)
+    }
+
+    /// encode the row as a cbor array or map
+    fn encode(
+        enc: &mut minicbor::Encoder<&mut Vec<u8>>,
+        row: &Row,
+        format: types::RowFormat,
+        keys: &[String],
+        encoding: &types::EncodeOptions,
+    ) -> Result<(), DbError> {
+        match format {
+            types::RowFormat::Array => types::encode_row(enc, row, encoding),
+            types::RowFormat::Map => types::encode_row_keyed(enc, row, keys, encoding),
+        }
+        .map_err(|e| DbError::Encoding(e.to_string()))
+    }
+
+    /// build the result from the encoded rows, with a PartialResult error if a row could
+    /// not be encoded
+    pub(crate) fn finish(self) -> Rows {
+        let partial = self.partial.map(DbError::PartialResult);
+        if self.num_rows == 0 {
+            return Rows {
+                columns: if partial.is_some() {
+                    self.columns
+                } else {
+                    Vec::new()
+                },
+                error: partial,
+                encoding: self.result_encoding,
+                ..Default::default()
+            };
+        }
+        // array header: at most 9 bytes
+        let mut rows = Vec::with_capacity(self.body.len() + 9);
+        let header = match self.result_encoding {
+            ResultEncoding::Cbor => minicbor::Encoder::new(&mut rows)
+                .array(self.num_rows)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            ResultEncoding::Json => {
+                rows.push(b'[');
+                Ok(())
+            }
+            ResultEncoding::Msgpack => u32::try_from(self.num_rows)
+                .map_err(|_| "too many rows for a msgpack array".to_string())
+                .and_then(|n| {
+                    rmp::encode::write_array_len(&mut rows, n)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                }),
+        };
+        if let Err(e) = header {
+            return Rows {
+                error: Some(DbError::Encoding(e)),
+                encoding: self.result_encoding,
+                ..Default::default()
+            };
+        }
+        rows.extend_from_slice(&self.body);
+        if self.result_encoding == ResultEncoding::Json {
+            rows.push(b']');
+        }
+        Rows {
+            columns: self.columns,
+            num_rows: self.num_rows,
+            error: partial,
+            rows,
+            encoding: self.result_encoding,
+        }
+    }
+}
+
+/// Encoded rows of a result, with the type oids of the columns
+#[derive(Default)]
+pub(crate) struct Rows {
+    pub(crate) num_rows: u64,
+    pub(crate) columns: ext::Columns,
+    pub(crate) rows: Vec<u8>,
+    pub(crate) error: Option<DbError>,
+    pub(crate) encoding: ResultEncoding,
+}
+
+/// FetchResult's Column, of wasmcloud:sqldb, does not have the oid
+impl From<Rows> for FetchResult {
+    fn from(rows: Rows) -> FetchResult {
+        FetchResult {
+            num_rows: rows.num_rows,
+            columns: rows
+                .columns
+                .into_iter()
+                .map(|c| Column {
+                    ordinal: c.ordinal,
+                    name: c.name,
+                    db_type: c.db_type,
+                })
+                .collect(),
+            rows: rows.rows,
+            error: rows.error.map(SqlDbError::from),
+        }
+    }
+}
+
+/// Rows of a query, read from the server and encoded in chunks
+pub(crate) struct RowChunks {
+    stream: Pin<Box<RowStream>>,
+    enc: RowEncoder,
+    chunk_size: usize,
+    /// number of rows encoded that have not been returned
+    pending_rows: u64,
+}
+
+impl RowChunks {
+    /// The size of the whole result is not limited by max_result_rows or max_result_bytes, and
+    /// a row that cannot be encoded fails the stream, also with partial_on_error
+    pub(crate) fn new(stream: RowStream, options: ResultOptions, chunk_size: usize) -> Self {
+        let mut enc = RowEncoder::new(ResultOptions {
+            limits: ResultLimits::default(),
+            partial_on_error: false,
+            ..options
+        });
+        enc.begin_stream();
+        Self {
+            stream: Box::pin(stream),
+            enc,
+            chunk_size,
+            pending_rows: 0,
+        }
+    }
+
+    /// Returns the next chunk of whole rows, and true if it is the last chunk.
+    /// A chunk is at most chunk_size bytes, unless it has a single row that is larger.
+    pub(crate) async fn next_chunk(&mut self) -> Result<(Vec<u8>, bool), ReadError> {
+        loop {
+            let len = self.enc.body.len();
+            if len >= self.chunk_size && self.pending_rows > 0 {
+                self.pending_rows = 0;
+                return Ok((self.enc.take_body(len), false));
+            }
+            match self
+                .stream
+                .as_mut()
+                .try_next()
+                .await
+                .map_err(ReadError::Db)?
+            {
+                Some(row) => {
+                    self.enc.push(&row).map_err(ReadError::Result)?;
+                    self.pending_rows += 1;
+                    if self.enc.body.len() > self.chunk_size && self.pending_rows > 1 {
+                        // the row starts the next chunk
+                        self.pending_rows = 1;
+                        return Ok((self.enc.take_body(len), false));
+                    }
+                }
+                None => {
+                    self.enc.end_stream();
+                    self.pending_rows = 0;
+                    let len = self.enc.body.len();
+                    return Ok((self.enc.take_body(len), true));
+                }
+            }
+        }
+    }
+
+    /// encoding of the rows
+    pub(crate) fn encoding(&self) -> ResultEncoding {
+        self.enc.result_encoding
+    }
+
+    /// Returns the column descriptions, once they have been read with the first row.
+    /// They are returned only once.
+    pub(crate) fn take_columns(&mut self) -> ext::Columns {
+        std::mem::take(&mut self.enc.columns)
+    }
+}
+
+/// Read rows from the stream into the encoder, stopping after `max` rows, if set, or after
+/// a row that could not be encoded, with partial_on_error.
+/// Returns true if the stream has more rows.
+pub(crate) async fn read_rows(
+    mut stream: Pin<&mut RowStream>,
+    enc: &mut RowEncoder,
+    max: Option<u64>,
+) -> Result<bool, ReadError> {
+    while let Some(row) = stream.as_mut().try_next().await.map_err(ReadError::Db)? {
+        if Some(enc.num_rows()) == max {
+            return Ok(true);
+        }
+        enc.push(&row).map_err(ReadError::Result)?;
+        if enc.is_partial() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Read all rows from the stream, within the limits.
+/// A result that exceeds a limit is returned with its error;
+/// the database error is returned if the query failed.
+pub(crate) async fn fetch_all(
+    stream: Pin<&mut RowStream>,
+    options: ResultOptions,
+) -> Result<Rows, tokio_postgres::Error> {
+    let mut enc = RowEncoder::new(options);
+    match read_rows(stream, &mut enc, None).await {
+        Ok(_) => Ok(enc.finish()),
+        Err(ReadError::Db(e)) => Err(e),
+        Err(ReadError::Result(e)) => Ok(Rows {
+            error: Some(e),
+            ..Default::default()
+        }),
+    }
+}
+
+/// Read the single row of a query, encoded alone, not in an array, with its columns.
+/// Returns None if the query returned no rows, and a TooManyRows error if it returned more
+/// than one. The row is encoded whole, or fails, also with partial_on_error
+pub(crate) async fn fetch_single(
+    mut stream: Pin<&mut RowStream>,
+    options: ResultOptions,
+) -> Result<Option<(Vec<u8>, ext::Columns)>, ReadError> {
+    let mut enc = RowEncoder::new(ResultOptions {
+        partial_on_error: false,
+        ..options
+    });
+    match stream.as_mut().try_next().await.map_err(ReadError::Db)? {
+        Some(row) => enc.push(&row).map_err(ReadError::Result)?,
+        None => return Ok(None),
+    }
+    if stream
+        .as_mut()
+        .try_next()
+        .await
+        .map_err(ReadError::Db)?
+        .is_some()
+    {
+        return Err(ReadError::Result(DbError::TooManyRows(
+            "query returned more than one row".into(),
+        )));
+    }
+    Ok(Some((enc.body, enc.columns)))
+}
+
+/// Read the value of the first column of the first row of a query, encoded alone.
+/// Returns None if the query returned no rows. The rows after the first are not read
+pub(crate) async fn fetch_scalar(
+    mut stream: Pin<&mut RowStream>,
+    options: ResultOptions,
+) -> Result<Option<Vec<u8>>, ReadError> {
+    match stream.as_mut().try_next().await.map_err(ReadError::Db)? {
+        Some(row) => encode_scalar(&row, options)
+            .map(Some)
+            .map_err(ReadError::Result),
+        None => Ok(None),
+    }
+}
+
+/// Encode a row as a map of column name to value, in the result encoding
+pub(crate) fn encode_map(row: &Row, options: ResultOptions) -> Result<Vec<u8>, DbError> {
+    let mut cbor = Vec::new();
+    types::encode_row_map(
+        &mut minicbor::Encoder::new(&mut cbor),
+        row,
+        &options.encoding,
+    )
+    .map_err(|e| DbError::Encoding(e.to_string()))?;
+    convert(cbor, options.result_encoding)
+}
+
+/// Encode the value of the first column of a row, in the result encoding
+pub(crate) fn encode_scalar(row: &Row, options: ResultOptions) -> Result<Vec<u8>, DbError> {
+    let mut cbor = Vec::new();
+    types::encode_column(
+        &mut minicbor::Encoder::new(&mut cbor),
+        row,
+        0,
+        &options.encoding,
+    )
+    .map_err(|e| DbError::Encoding(e.to_string()))?;
+    convert(cbor, options.result_encoding)
+}
+
+/// convert a cbor data item to the result encoding
+fn convert(cbor: Vec<u8>, encoding: ResultEncoding) -> Result<Vec<u8>, DbError> {
+    match encoding {
+        ResultEncoding::Cbor => Ok(cbor),
+        ResultEncoding::Json => {
+            let value = crate::encoding::cbor_to_json(&cbor).map_err(DbError::Encoding)?;
+            serde_json::to_vec(&value).map_err(|e| DbError::Encoding(e.to_string()))
+        }
+        ResultEncoding::Msgpack => {
+            let mut out = Vec::new();
+            crate::encoding::cbor_to_msgpack(&cbor, &mut out).map_err(DbError::Encoding)?;
+            Ok(out)
+        }
+    }
+}
+
+/// column descriptions of a row
+fn columns(row: &Row, case: ColumnCase) -> ext::Columns {
+    row.columns()
+        .iter()
+        .enumerate()
+        .map(|(i, c)| ext::Column {
+            name: case.apply(c.name()),
+            ordinal: i as u32,
+            db_type: c.type_().name().to_string(),
+            oid: c.type_().oid(),
+        })
+        .collect()
+}
@@ -0,0 +1,463 @@
+//! Transactions that remain open across rpc calls.
+//!
+//! `begin_transaction` takes a connection from the link's pool and keeps it,
+//! with an open transaction, until the actor commits or rolls back.
+//! If the actor does not use the transaction for `idle_timeout`, it is rolled back
+//! and the connection is returned to the pool, so that an actor that crashes
+//! in the middle of a transaction does not hold a connection forever.
+//!
+//! A cursor opened with `fetch_cursor` is a session whose transaction
+//! contains the cursor, so it has the same idle timeout.
+//! An export opened with `copy_out` is a session whose connection
+//! is running the COPY, until its output has been read.
+//! A stream opened with `fetch_stream` is a session whose connection is running
+//! the query, until its rows have been read.
+//!
+use crate::log_level::{debug, error, warn};
+use crate::{
+    copy::CopyOut,
+    error::DbError,
+    in_flight::InFlightConnection,
+    results::{ResultOptions, RowChunks},
+};
+use bb8_postgres::tokio_postgres::{types::ToSql, Client, IsolationLevel, TransactionBuilder};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex, MutexGuard, RwLock};
+
+/// Name of the cursor in a cursor session. Each session has its own connection,
+/// so the name does not need to be unique.
+pub(crate) const CURSOR_NAME: &str = "sqldb_cursor";
+
+/// (actor_id, token)
+type SessionKey = (String, String);
+
+/// Open transactions
+#[derive(Clone, Default)]
+pub(crate) struct Sessions {
+    inner: Arc<RwLock<HashMap<SessionKey, Arc<Session>>>>,
+}
+
+/// A transaction on a connection that is held until commit or rollback
+pub(crate) struct Session {
+    state: Mutex<SessionState>,
+    /// time the session was last used, for the idle timeout
+    last_used: std::sync::Mutex<Instant>,
+    idle_timeout: Duration,
+    /// what the session was opened for
+    kind: SessionKind,
+}
+
+/// Operation that opened a session
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SessionKind {
+    /// begin_transaction
+    Transaction,
+    /// fetch_cursor: the transaction contains a cursor
+    Cursor,
+    /// copy_out: the connection is running a COPY
+    CopyOut,
+    /// fetch_stream: the connection is running a query
+    Stream,
+}
+
+/// Output of a session that is read in chunks
+pub(crate) enum Output {
+    /// output of the COPY of a copy_out session
+    Copy(CopyOut),
+    /// rows of the query of a fetch_stream session
+    Rows(RowChunks),
+}
+
+/// Connection of a session, and the status of its transaction
+pub(crate) struct SessionState {
+    pub(crate) conn: InFlightConnection<'static>,
+    /// true after a statement failed. Postgres rejects all further
+    /// statements in the transaction, and commit becomes a rollback.
+    pub(crate) aborted: bool,
+    /// true after the transaction was committed, rolled back, or expired
+    closed: bool,
+    /// output of a copy_out or fetch_stream session, until it has been read
+    pub(crate) output: Option<Output>,
+}
+
+impl Sessions {
+    /// Begin a transaction on the connection, and return its token
+    pub(crate) async fn begin(
+        &self,
+        actor_id: &str,
+        conn: InFlightConnection<'static>,
+        mode: &TransactionMode,
+        idle_timeout: Duration,
+    ) -> Result<String, DbError> {
+        conn.batch_execute(&mode.begin_statement()).await?;
+        Ok(self
+            .insert(actor_id, conn, idle_timeout, SessionKind::Transaction, None)
+            .await)
+    }
+
+    /// Begin a transaction on the connection, declare a cursor for the query in it,
+    /// and return its token
+    pub(crate) async fn declare_cursor(
+        &self,
+        actor_id: &str,
+        conn: InFlightConnection<'static>,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+        idle_timeout: Duration,
+    ) -> Result<String, DbError> {
+        conn.batch_execute("BEGIN").await?;
+        let declare = format!("DECLARE {} NO SCROLL CURSOR FOR {}", CURSOR_NAME, query);
+        if let Err(e) = conn.execute(declare.as_str(), params).await {
+            // the connection returns to the pool
+            if let Err(e) = conn.batch_execute("ROLLBACK").await {
+                debug!("rolling back cursor transaction for {}: {}", actor_id, e);
+            }
+            return Err(e.into());
+        }
+        Ok(self
+            .insert(actor_id, conn, idle_timeout, SessionKind::Cursor, None)
+            .await)
+    }
+
+    /// Begin a transaction on the connection, start a COPY ... TO STDOUT statement in it,
+    /// and return its token. The output is read in chunks of `chunk_size` bytes.
+    pub(crate) async fn copy_out(
+        &self,
+        actor_id: &str,
+        conn: InFlightConnection<'static>,
+        statement: &str,
+        chunk_size: usize,
+        idle_timeout: Duration,
+    ) -> Result<String, DbError> {
+        conn.batch_execute("BEGIN").await?;
+        let stream = match conn.copy_out(statement).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                if let Err(e) = conn.batch_execute("ROLLBACK").await {
+                    debug!("rolling back copy transaction for {}: {}", actor_id, e);
+                }
+                return Err(e.into());
+            }
+        };
+        let copy = CopyOut::new(stream, chunk_size);
+        Ok(self
+            .insert(
+                actor_id,
+                conn,
+                idle_timeout,
+                SessionKind::CopyOut,
+                Some(Output::Copy(copy)),
+            )
+            .await)
+    }
+
+    /// Begin a transaction on the connection, start the query in it, and return its token.
+    /// The rows are read in chunks of at most `chunk_size` bytes of encoded rows.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn stream_rows(
+        &self,
+        actor_id: &str,
+        conn: InFlightConnection<'static>,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+        options: ResultOptions,
+        chunk_size: usize,
+        idle_timeout: Duration,
+    ) -> Result<String, DbError> {
+        conn.batch_execute("BEGIN").await?;
+        let stream = match conn.query_raw(query, params.iter().copied()).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                if let Err(e) = conn.batch_execute("ROLLBACK").await {
+                    debug!("rolling back stream transaction for {}: {}", actor_id, e);
+                }
+                return Err(e.into());
+            }
+        };
+        let rows = RowChunks::new(stream, options, chunk_size);
+        Ok(self
+            .insert(
+                actor_id,
+                conn,
+                idle_timeout,
+                SessionKind::Stream,
+                Some(Output::Rows(rows)),
+            )
+            .await)
+    }
+
+    /// Add a connection that has an open transaction, and return its token
+    async fn insert(
+        &self,
+        actor_id: &str,
+        conn: InFlightConnection<'static>,
+        idle_timeout: Duration,
+        kind: SessionKind,
+        output: Option<Output>,
+    ) -> String {
+        let token = uuid::Uuid::new_v4().to_simple().to_string();
+        let session = Arc::new(Session {
+            state: Mutex::new(SessionState {
+                conn,
+                aborted: false,
+                closed: false,
+                output,
+            }),
+            last_used: std::sync::Mutex::new(Instant::now()),
+            idle_timeout,
+            kind,
+        });
+        let key = (actor_id.to_string(), token.clone());
+        tokio::spawn(reap_when_idle(
+            self.clone(),
+            key.clone(),
+            Arc::downgrade(&session),
+        ));
+        self.inner.write().await.insert(key, session);
+        token
+    }
+
+    /// Returns the session, if it is open
+    pub(crate) async fn get(&self, actor_id: &str, token: &str) -> Option<Arc<Session>> {
+        let key = (actor_id.to_string(), token.to_string());
+        self.inner.read().await.get(&key).cloned()
+    }
+
+    /// Removes the session. The caller must end its transaction.
+    pub(crate) async fn remove(&self, actor_id: &str, token: &str) -> Option<Arc<Session>> {
+        let key = (actor_id.to_string(), token.to_string());
+        self.inner.write().await.remove(&key)
+    }
+
+    /// Roll back all open transactions of the actor
+    pub(crate) async fn remove_actor(&self, actor_id: &str) {
+        let sessions = {
+            let mut sessions = self.inner.write().await;
+            let keys = sessions
+                .keys()
+                .filter(|(actor, _)| actor == actor_id)
+                .cloned()
+                .collect::<Vec<_>>();
+            keys.into_iter()
+                .filter_map(|key| sessions.remove(&key))
+                .collect::<Vec<_>>()
+        };
+        for session in sessions {
+            if let Err(e) = session.end("ROLLBACK").await {
+                debug!("rolling back transaction for {}: {}", actor_id, e);
+            }
+        }
+    }
+
+    /// Roll back all open transactions
+    pub(crate) async fn clear(&self) {
+        let sessions = self.inner.write().await.drain().collect::<Vec<_>>();
+        for ((actor_id, _), session) in sessions {
+            if let Err(e) = session.end("ROLLBACK").await {
+                debug!("rolling back transaction for {}: {}", actor_id, e);
+            }
+        }
+    }
+}
+
+impl Session {
+    /// Returns the operation that opened the session
+    pub(crate) fn kind(&self) -> SessionKind {
+        self.kind
+    }
+
+    /// Lock the session's connection for a statement.
+    /// Returns None if the transaction has ended.
+    pub(crate) async fn lock(&self) -> Option<MutexGuard<'_, SessionState>> {
+        let state = self.state.lock().await;
+        if state.closed {
+            return None;
+        }
+        self.touch();
+        Some(state)
+    }
+
+    /// Restart the idle timer. Called when a statement starts and when it completes,
+    /// so that a long-running statement does not count as idle time.
+    pub(crate) fn touch(&self) {
+        *self.last_used.lock().unwrap() = Instant::now();
+    }
+
+    /// End the transaction with COMMIT or ROLLBACK.
+    /// Returns NotFound if the transaction has already ended.
+    pub(crate) async fn end(&self, statement: &str) -> Result<(), DbError> {
+        let mut state = self.state.lock().await;
+        if state.closed {
+            return Err(DbError::NotFound("transaction has ended".into()));
+        }
+        state.closed = true;
+        // the rest of the COPY output or rows is discarded. It must be dropped before the
+        // statement, which the server runs after the COPY or query has finished
+        state.output = None;
+        state.conn.batch_execute(statement).await?;
+        Ok(())
+    }
+}
+
+/// Characteristics of a transaction requested by the actor
+#[derive(Debug, Default)]
+pub(crate) struct TransactionMode {
+    isolation_level: Option<IsolationLevel>,
+    read_only: bool,
+    deferrable: bool,
+}
+
+impl TransactionMode {
+    /// Validates the isolation level: "read uncommitted", "read committed",
+    /// "repeatable read", or "serializable" (case-insensitive; '_' or '-' may separate words)
+    pub(crate) fn new(
+        isolation_level: Option<&str>,
+        read_only: bool,
+        deferrable: bool,
+    ) -> Result<Self, String> {
+        let isolation_level = match isolation_level {
+            None => None,
+            Some(level) => Some(
+                match level
+                    .trim()
+                    .to_ascii_lowercase()
+                    .replace(['_', '-'], " ")
+                    .as_str()
+                {
+                    "read uncommitted" => IsolationLevel::ReadUncommitted,
+                    "read committed" => IsolationLevel::ReadCommitted,
+                    "repeatable read" => IsolationLevel::RepeatableRead,
+                    "serializable" => IsolationLevel::Serializable,
+                    _ => {
+                        return Err(format!(
+                            "invalid isolation level '{}'. Expected 'read uncommitted', \
+                             'read committed', 'repeatable read', or 'serializable'",
+                            level
+                        ))
+                    }
+                },
+            ),
+        };
+        Ok(Self {
+            isolation_level,
+            read_only,
+            deferrable,
+        })
+    }
+
+    /// Transaction builder with these characteristics
+    pub(crate) fn builder<'a>(&self, client: &'a mut Client) -> TransactionBuilder<'a> {
+        let mut builder = client.build_transaction();
+        if let Some(level) = self.isolation_level {
+            builder = builder.isolation_level(level);
+        }
+        if self.read_only {
+            builder = builder.read_only(true);
+        }
+        if self.deferrable {
+            builder = builder.deferrable(true);
+        }
+        builder
+    }
+
+    /// Statement that begins a transaction with these characteristics.
+    /// The modes are the same as for `SET TRANSACTION`.
+    pub(crate) fn begin_statement(&self) -> String {
+        let mut modes = Vec::new();
+        if let Some(level) = self.isolation_level {
+            modes.push(match level {
+                IsolationLevel::ReadUncommitted => "ISOLATION LEVEL READ UNCOMMITTED",
+                IsolationLevel::ReadCommitted => "ISOLATION LEVEL READ COMMITTED",
+                IsolationLevel::RepeatableRead => "ISOLATION LEVEL REPEATABLE READ",
+                IsolationLevel::Serializable => "ISOLATION LEVEL SERIALIZABLE",
+                // IsolationLevel is non_exhaustive; new() only returns the levels above
+                _ => unreachable!(),
+            });
+        }
+        if self.read_only {
+            modes.push("READ ONLY");
+        }
+        if self.deferrable {
+            modes.push("DEFERRABLE");
+        }
+        if modes.is_empty() {
+            "BEGIN".to_string()
+        } else {
+            format!("BEGIN {}", modes.join(", "))
+        }
+    }
+}
+
+/// Roll back the transaction if it is idle for longer than its timeout.
+/// Returns when the session ends.
+async fn reap_when_idle(sessions: Sessions, key: SessionKey, weak: Weak<Session>) {
+    loop {
+        let deadline = match weak.upgrade() {
+            Some(session) => *session.last_used.lock().unwrap() + session.idle_timeout,
+            None => return,
+        };
+        tokio::time::sleep_until(deadline.into()).await;
+        let session = match weak.upgrade() {
+            Some(session) => session,
+            None => return,
+        };
+        if session.last_used.lock().unwrap().elapsed() < session.idle_timeout {
+            continue;
+        }
+        if session.state.try_lock().is_err() {
+            // a statement is in progress. It restarts the idle timer when it completes
+            drop(session.state.lock().await);
+            continue;
+        }
+        {
+            let mut map = sessions.inner.write().await;
+            match map.get(&key) {
+                Some(current) if Arc::ptr_eq(current, &session) => {
+                    map.remove(&key);
+                }
+                // committed or rolled back
+                _ => return,
+            }
+        }
+        warn!(
+            "{}: rolling back transaction idle for more than {}s",
+            key.0,
+            session.idle_timeout.as_secs()
+        );
+        if let Err(e) = session.end("ROLLBACK").await {
+            error!("{}: rolling back idle transaction: {}", key.0, e);
+        }
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_mode() {
+        let mode = TransactionMode::new(None, false, false).unwrap();
+        assert_eq!(mode.begin_statement(), "BEGIN");
+
+        let mode = TransactionMode::new(Some("SERIALIZABLE"), true, true).unwrap();
+        assert_eq!(
+            mode.begin_statement(),
+            "BEGIN ISOLATION LEVEL SERIALIZABLE, READ ONLY, DEFERRABLE"
+        );
+        for level in ["read committed", "READ_COMMITTED", "read-committed"] {
+            let mode = TransactionMode::new(Some(level), false, false).unwrap();
+            assert_eq!(
+                mode.begin_statement(),
+                "BEGIN ISOLATION LEVEL READ COMMITTED"
+            );
+        }
+
+        assert!(TransactionMode::new(Some("snapshot"), false, false).is_err());
+        assert!(TransactionMode::new(Some("serializable; drop table t"), false, false).is_err());
+    }
+}
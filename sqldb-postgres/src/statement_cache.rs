@@ -0,0 +1,219 @@
+//! Prepared statements, cached on each connection.
+//!
+//! Prepared statements belong to the server session of the connection that prepared them,
+//! so each connection in the pool has its own cache, keyed by the sql text.
+//! When the cache is full, the least recently used statement is dropped,
+//! which closes it on the server.
+//!
+//! A statement that is dropped from the cache is closed when it is no longer used: the
+//! driver sends the Close message of the extended query protocol, which deallocates it as
+//! `DEALLOCATE` would. With statement_cache_ttl, the statements that have not been used for
+//! the ttl are dropped when the connection prepares its next statement, so that a connection
+//! that lives for days does not keep the statements of queries it no longer runs.
+//! ClearStatementCache drops all of a link's statements: the cache of each connection is
+//! cleared when the connection is next taken from the pool.
+//!
+use crate::error;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio_postgres::{types::ToSql, GenericClient, RowStream, Statement};
+
+/// Prepared statements of a connection
+pub(crate) struct StatementCache {
+    inner: Mutex<Lru<Statement>>,
+    /// statements not used for this long are dropped
+    ttl: Option<Duration>,
+    /// when the cache was created, or last cleared
+    cleared_at: Mutex<Instant>,
+}
+
+impl StatementCache {
+    /// Creates a cache that holds up to `capacity` statements.
+    /// With capacity 0, statements are prepared for each use.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Lru::new(capacity)),
+            ttl: None,
+            cleared_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Drop the statements that have not been used for the ttl
+    pub(crate) fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Drops all statements, if the cache has not been cleared since `at`
+    pub(crate) fn clear_before(&self, at: Instant) {
+        let mut cleared_at = self.cleared_at.lock().unwrap();
+        if *cleared_at < at {
+            self.inner.lock().unwrap().clear();
+            *cleared_at = Instant::now();
+        }
+    }
+
+    /// Returns the prepared statement for the sql, preparing it if it is not in the cache
+    pub(crate) async fn prepare<C: GenericClient + Sync>(
+        &self,
+        client: &C,
+        sql: &str,
+    ) -> Result<Statement, tokio_postgres::Error> {
+        if let Some(ttl) = self.ttl {
+            self.inner.lock().unwrap().expire(ttl);
+        }
+        if let Some(statement) = self.inner.lock().unwrap().get(sql) {
+            return Ok(statement);
+        }
+        let statement = client.prepare(sql).await?;
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(sql.to_string(), statement.clone());
+        Ok(statement)
+    }
+
+    /// Removes the statement, so it will be prepared again on its next use
+    pub(crate) fn remove(&self, sql: &str) {
+        self.inner.lock().unwrap().remove(sql);
+    }
+
+    /// Executes a statement, preparing it if it is not in the cache.
+    /// If the cached statement is no longer valid, it is removed from the cache.
+    pub(crate) async fn execute<C: GenericClient + Sync>(
+        &self,
+        client: &C,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, tokio_postgres::Error> {
+        let statement = self.prepare(client, sql).await?;
+        let res = client.execute(&statement, params).await;
+        if matches!(&res, Err(e) if error::is_stale_statement(e)) {
+            self.remove(sql);
+        }
+        res
+    }
+
+    /// Runs a query, preparing it if it is not in the cache, and returns the stream of its rows.
+    /// If the cached statement is no longer valid, it is removed from the cache.
+    pub(crate) async fn query_raw<C: GenericClient + Sync>(
+        &self,
+        client: &C,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<RowStream, tokio_postgres::Error> {
+        let statement = self.prepare(client, sql).await?;
+        let res = client.query_raw(&statement, params.iter().copied()).await;
+        if matches!(&res, Err(e) if error::is_stale_statement(e)) {
+            self.remove(sql);
+        }
+        res
+    }
+}
+
+/// Map with a maximum size, from which the least recently used entry is removed when it is full
+struct Lru<V> {
+    capacity: usize,
+    /// value, and the time it was last used, as the clock and as an instant
+    entries: HashMap<String, (V, u64, Instant)>,
+    clock: u64,
+}
+
+impl<V: Clone> Lru<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<V> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|(value, used, used_at)| {
+            *used = clock;
+            *used_at = Instant::now();
+            value.clone()
+        })
+    }
+
+    fn insert(&mut self, key: String, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            if let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used, _))| *used)
+                .map(|(k, _)| k.clone())
+            {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.entries
+            .insert(key, (value, self.clock, Instant::now()));
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+
+    /// remove the entries that have not been used for the ttl
+    fn expire(&mut self, ttl: Duration) {
+        self.entries
+            .retain(|_, (_, _, used_at)| used_at.elapsed() < ttl);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru() {
+        let mut lru = Lru::new(2);
+        lru.insert("a".into(), 1);
+        lru.insert("b".into(), 2);
+        assert_eq!(lru.get("a"), Some(1));
+        // b is the least recently used
+        lru.insert("c".into(), 3);
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(lru.get("a"), Some(1));
+        assert_eq!(lru.get("c"), Some(3));
+        // replacing an entry does not evict another
+        lru.insert("c".into(), 4);
+        assert_eq!(lru.get("a"), Some(1));
+        assert_eq!(lru.get("c"), Some(4));
+        lru.remove("a");
+        assert_eq!(lru.get("a"), None);
+
+        let mut lru = Lru::new(0);
+        lru.insert("a".into(), 1);
+        assert_eq!(lru.get("a"), None);
+    }
+
+    #[test]
+    fn expire() {
+        let mut lru = Lru::new(2);
+        lru.insert("a".into(), 1);
+        lru.insert("b".into(), 2);
+        std::thread::sleep(Duration::from_millis(20));
+        // a is used again, b is not
+        assert_eq!(lru.get("a"), Some(1));
+        lru.expire(Duration::from_millis(10));
+        assert_eq!(lru.get("a"), Some(1));
+        assert_eq!(lru.get("b"), None);
+        lru.clear();
+        assert_eq!(lru.get("a"), None);
+    }
+}
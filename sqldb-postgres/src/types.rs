@@ -1,92 +1,724 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+use fallible_iterator::FallibleIterator;
 use minicbor::encode::Write;
 use minicbor::Encoder;
-use tokio_postgres::types::Type;
-use tokio_postgres::Row;
+use postgres_protocol::types::{array_from_sql, ArrayValues};
+use serde::Deserialize;
+use tokio_postgres::types::{Field, FromSql, Kind, Type};
+use tokio_postgres::{Column, Row};
+
+/// Options for the encoding of column values
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct EncodeOptions {
+    pub(crate) uuid_format: UuidFormat,
+    pub(crate) numeric_format: NumericFormat,
+    pub(crate) bytea_format: ByteaFormat,
+}
+
+/// Encoding of uuid values
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum UuidFormat {
+    /// canonical hyphenated string, for example "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"
+    #[default]
+    String,
+    /// 16-byte byte string
+    Bytes,
+}
+
+/// Encoding of result rows
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RowFormat {
+    /// array of the column values, in the order of the columns
+    #[default]
+    Array,
+    /// map of column name to value
+    Map,
+}
+
+/// Encoding of numeric values
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum NumericFormat {
+    /// decimal string with all of the value's digits, for example "1234.5600",
+    /// or "NaN", "Infinity", or "-Infinity"
+    #[default]
+    String,
+    /// f64, which may lose precision
+    Float,
+}
+
+/// Encoding of bytea values
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ByteaFormat {
+    /// cbor byte string
+    #[default]
+    Bytes,
+    /// base64 (standard alphabet, with padding) string
+    Base64,
+}
 
-/// encode query result into CBOR arroy-of-arrays
-pub(crate) fn encode_rows<W>(
+/// encode a result row as a CBOR array
+pub(crate) fn encode_row<W>(
     enc: &mut Encoder<W>,
-    rows: &[Row],
-) -> Result<(), Box<dyn std::error::Error>>
+    row: &Row,
+    opts: &EncodeOptions,
+) -> Result<(), BoxError>
 where
     W: Write,
-    <W as minicbor::encode::Write>::Error: std::error::Error + 'static,
+    <W as minicbor::encode::Write>::Error: std::error::Error + Send + Sync + 'static,
 {
-    enc.array(rows.len() as u64)?;
-    for row in rows.iter() {
-        enc.array(row.len() as u64)?;
-        for (i, col) in row.columns().iter().enumerate() {
-            // TODO: check col.kind() to see if it's an array
-            //   then load with array_from_sql()
-            encode_val(enc, row, col.type_(), i)?
-        }
+    enc.array(row.len() as u64)?;
+    for (i, col) in row.columns().iter().enumerate() {
+        let value = row.try_get::<'_, usize, RawValue>(i)?;
+        encode_val(enc, col.type_(), value.0, opts).map_err(|e| column_error(col, e))?
+    }
+    Ok(())
+}
+
+/// encode a result row as a CBOR map of column name to value, for example the OUT and
+/// INOUT parameters of a procedure
+pub(crate) fn encode_row_map<W>(
+    enc: &mut Encoder<W>,
+    row: &Row,
+    opts: &EncodeOptions,
+) -> Result<(), BoxError>
+where
+    W: Write,
+    <W as minicbor::encode::Write>::Error: std::error::Error + Send + Sync + 'static,
+{
+    enc.map(row.len() as u64)?;
+    for (i, col) in row.columns().iter().enumerate() {
+        let value = row.try_get::<'_, usize, RawValue>(i)?;
+        enc.str(col.name())?;
+        encode_val(enc, col.type_(), value.0, opts).map_err(|e| column_error(col, e))?
+    }
+    Ok(())
+}
+
+/// encode a result row as a CBOR map, with the keys of `map_keys`
+pub(crate) fn encode_row_keyed<W>(
+    enc: &mut Encoder<W>,
+    row: &Row,
+    keys: &[String],
+    opts: &EncodeOptions,
+) -> Result<(), BoxError>
+where
+    W: Write,
+    <W as minicbor::encode::Write>::Error: std::error::Error + Send + Sync + 'static,
+{
+    enc.map(row.len() as u64)?;
+    for ((i, col), key) in row.columns().iter().enumerate().zip(keys) {
+        let value = row.try_get::<'_, usize, RawValue>(i)?;
+        enc.str(key)?;
+        encode_val(enc, col.type_(), value.0, opts).map_err(|e| column_error(col, e))?
     }
     Ok(())
 }
 
-/// cbor enode a single value based on the database column type
-#[inline]
-fn encode_val<'r, W>(
+/// encode the value of one column of a result row
+pub(crate) fn encode_column<W>(
+    enc: &mut Encoder<W>,
+    row: &Row,
+    idx: usize,
+    opts: &EncodeOptions,
+) -> Result<(), BoxError>
+where
+    W: Write,
+    <W as minicbor::encode::Write>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let col = &row.columns()[idx];
+    let value = row.try_get::<'_, usize, RawValue>(idx)?;
+    encode_val(enc, col.type_(), value.0, opts).map_err(|e| column_error(col, e))
+}
+
+/// an error encoding the value of a column, with the name and type of the column
+fn column_error(col: &Column, e: BoxError) -> BoxError {
+    format!("column '{}' ({}): {}", col.name(), col.type_().name(), e).into()
+}
+
+/// The map keys of columns with these names, for row_format `map`. A name that is the
+/// name of an earlier column gets the suffix `_2` for its second column, `_3` for its
+/// third, and so on, and if that is also the name of a column, the number is increased
+/// until it is not. For example, the keys of the columns `id, name, id, id_2` are
+/// `id, name, id_3, id_2`.
+pub(crate) fn map_keys(names: &[&str]) -> Vec<String> {
+    let all = names
+        .iter()
+        .copied()
+        .collect::<std::collections::HashSet<_>>();
+    let mut keys = Vec::new();
+    let mut used = std::collections::HashSet::new();
+    let mut seen = std::collections::HashMap::<&str, usize>::new();
+    for name in names.iter().copied() {
+        let count = seen.entry(name).or_insert(0);
+        *count += 1;
+        let mut key = name.to_string();
+        if *count > 1 {
+            let mut n = *count;
+            loop {
+                key = format!("{}_{}", name, n);
+                if !all.contains(key.as_str()) && !used.contains(&key) {
+                    break;
+                }
+                n += 1;
+            }
+        }
+        used.insert(key.clone());
+        keys.push(key);
+    }
+    keys
+}
+
+/// the value of a text column, such as a refcursor, which is the name of its cursor.
+/// None if it is NULL
+pub(crate) fn text_value(row: &Row, idx: usize) -> Result<Option<&str>, BoxError> {
+    match row.try_get::<'_, usize, RawValue>(idx)?.0 {
+        Some(raw) => Ok(Some(std::str::from_utf8(raw)?)),
+        None => Ok(None),
+    }
+}
+
+type BoxError = Box<dyn std::error::Error + Sync + Send>;
+
+/// A value in binary format, or None if it is NULL
+struct RawValue<'a>(Option<&'a [u8]>);
+
+impl<'a> FromSql<'a> for RawValue<'a> {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        Ok(RawValue(Some(raw)))
+    }
+
+    fn from_sql_null(_ty: &Type) -> Result<Self, BoxError> {
+        Ok(RawValue(None))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+/// cbor enode a single value, in binary format, based on the database type.
+/// NULL (`raw` is None) is a cbor null for every type.
+fn encode_val<W>(
     enc: &mut Encoder<W>,
-    row: &'r Row,
     ty: &Type,
-    i: usize,
-) -> Result<(), Box<dyn std::error::Error>>
+    raw: Option<&[u8]>,
+    opts: &EncodeOptions,
+) -> Result<(), BoxError>
 where
     W: Write,
-    <W as minicbor::encode::Write>::Error: std::error::Error + 'static,
+    <W as minicbor::encode::Write>::Error: std::error::Error + Send + Sync + 'static,
 {
+    let raw = match raw {
+        Some(raw) => raw,
+        None => {
+            enc.null()?;
+            return Ok(());
+        }
+    };
+    match ty.kind() {
+        Kind::Array(elem) => return encode_array(enc, elem, raw, opts),
+        // a domain has the binary format of its base type
+        Kind::Domain(base) => return encode_val(enc, base, Some(raw), opts),
+        // enum values as their label
+        Kind::Enum(_) => {
+            enc.str(<&str>::from_sql(ty, raw)?)?;
+            return Ok(());
+        }
+        Kind::Composite(fields) => return encode_composite(enc, Some(fields), raw, opts),
+        _ => {}
+    }
     match *ty {
         // basic types
-        Type::BOOL => enc.bool(row.get(i)),
-        Type::CHAR => enc.i16(row.get(i)),
-        Type::INT2 => enc.i16(row.get(i)),
-        Type::INT4 => enc.i32(row.get(i)),
-        Type::INT8 => enc.i64(row.get(i)),
-        Type::OID => enc.u32(row.get(i)),
-        Type::FLOAT4 => enc.f32(row.get(i)),
-        Type::FLOAT8 => enc.f64(row.get(i)),
+        Type::BOOL => enc.bool(bool::from_sql(ty, raw)?),
+        Type::CHAR => enc.i16(i8::from_sql(ty, raw)? as i16),
+        Type::INT2 => enc.i16(i16::from_sql(ty, raw)?),
+        Type::INT4 => enc.i32(i32::from_sql(ty, raw)?),
+        Type::INT8 => enc.i64(i64::from_sql(ty, raw)?),
+        Type::OID => enc.u32(u32::from_sql(ty, raw)?),
+        Type::FLOAT4 => enc.f32(f32::from_sql(ty, raw)?),
+        Type::FLOAT8 => enc.f64(f64::from_sql(ty, raw)?),
 
         // Strings
-        Type::CHAR_ARRAY
-        | Type::VARCHAR
-        | Type::TEXT
-        | Type::NAME
-        | Type::UNKNOWN
-        | Type::JSON
-        | Type::XID
-        | Type::CID
-        | Type::XML => enc.str(row.get(i)),
-
-        // byte array
-        Type::BYTEA => enc.bytes(row.get(i)),
-
-        // convert uuid to string
-        Type::UUID => enc.str(&row.get::<'r, usize, uuid::Uuid>(i).to_string()),
-
-        // timestamp as iso3339 string in UTC
-        Type::TIMESTAMP | Type::TIMESTAMPTZ => enc.str(
-            &DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(row.get(i), 0), Utc)
-                .to_rfc3339(),
+        Type::VARCHAR | Type::TEXT | Type::BPCHAR | Type::NAME | Type::UNKNOWN | Type::XML => {
+            enc.str(<&str>::from_sql(ty, raw)?)
+        }
+        // the name of a cursor, for example returned by a function or procedure
+        Type::REFCURSOR => enc.str(std::str::from_utf8(raw)?),
+        // the result of a function that returns void, such as `select pg_sleep(0)`
+        Type::VOID => enc.null(),
+        // transaction and command ids as strings
+        Type::XID | Type::CID => enc.str(&u32::from_sql(ty, raw)?.to_string()),
+
+        // json documents as structured data
+        Type::JSON | Type::JSONB => {
+            encode_json(enc, &serde_json::Value::from_sql(ty, raw)?).map(|()| enc)
+        }
+
+        // numeric as exact decimal string, or f64
+        Type::NUMERIC => {
+            let n = Numeric::from_sql(ty, raw)?;
+            match opts.numeric_format {
+                NumericFormat::String => enc.str(&n.0),
+                NumericFormat::Float => enc.f64(n.to_f64()),
+            }
+        }
+
+        // byte array, or base64 string
+        Type::BYTEA => encode_bytea(enc, raw, opts.bytea_format),
+
+        // uuid as string or bytes
+        Type::UUID => encode_uuid(enc, &uuid::Uuid::from_sql(ty, raw)?, opts.uuid_format),
+
+        // timestamp as rfc3339 string without an offset, timestamptz in UTC
+        Type::TIMESTAMP => enc.str(&Timestamp::from_sql(ty, raw)?.to_naive_string()),
+        Type::TIMESTAMPTZ => enc.str(&Timestamp::from_sql(ty, raw)?.to_utc_string()),
+        // date as "YYYY-MM-DD", time as "HH:MM:SS.ffffff"
+        Type::DATE => enc.str(&Date::from_sql(ty, raw)?.to_string()),
+        Type::TIME => enc.str(
+            &chrono::NaiveTime::from_sql(ty, raw)?
+                .format("%H:%M:%S%.6f")
+                .to_string(),
         ),
-        // date and time to string
-        Type::DATE => enc.str(&row.get::<'r, usize, chrono::NaiveDate>(i).to_string()),
 
-        Type::TIME => enc.str(&row.get::<'r, usize, chrono::NaiveTime>(i).to_string()),
+        // interval as a map of its months, days, and microseconds
+        Type::INTERVAL => {
+            let interval = Interval::from_sql(ty, raw)?;
+            enc.map(3)?
+                .str("months")?
+                .i32(interval.months)?
+                .str("days")?
+                .i32(interval.days)?
+                .str("micros")?
+                .i64(interval.micros)
+        }
 
         // ip address as string
-        Type::INET => enc.str(&row.get::<'r, usize, std::net::IpAddr>(i).to_string()),
+        Type::INET => enc.str(&std::net::IpAddr::from_sql(ty, raw)?.to_string()),
 
         // convert bit vector to bytes
-        Type::BIT | Type::VARBIT => enc.bytes(&row.get::<'r, usize, bit_vec::BitVec>(i).to_bytes()),
+        Type::BIT | Type::VARBIT => enc.bytes(&bit_vec::BitVec::from_sql(ty, raw)?.to_bytes()),
+
+        // anonymous record, for example from `select row(1, 'a')`
+        Type::RECORD => return encode_composite(enc, None, raw, opts),
 
         // anything else - encode as byte array
-        _ => enc.bytes(row.get(i)),
+        _ => enc.bytes(raw),
     }?;
     Ok(())
 }
 
+/// cbor encode an array as an array of its elements. A multi-dimensional array
+/// is encoded as nested arrays, for example `{{1,2},{3,4}}` as `[[1,2],[3,4]]`.
+/// The lower bounds of the dimensions are not included.
+fn encode_array<W>(
+    enc: &mut Encoder<W>,
+    elem: &Type,
+    raw: &[u8],
+    opts: &EncodeOptions,
+) -> Result<(), BoxError>
+where
+    W: Write,
+    <W as minicbor::encode::Write>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let array = array_from_sql(raw)?;
+    let dims = array
+        .dimensions()
+        .map(|d| Ok(d.len))
+        .collect::<Vec<i32>>()?;
+    let mut values = array.values();
+    encode_dimensions(enc, elem, &dims, &mut values, opts)?;
+    if values.next()?.is_some() {
+        return Err("invalid array: more values than its dimensions".into());
+    }
+    Ok(())
+}
+
+/// encode the values of the first dimension, each of which is a value,
+/// or for a multi-dimensional array, an array of the remaining dimensions
+fn encode_dimensions<W>(
+    enc: &mut Encoder<W>,
+    elem: &Type,
+    dims: &[i32],
+    values: &mut ArrayValues<'_>,
+    opts: &EncodeOptions,
+) -> Result<(), BoxError>
+where
+    W: Write,
+    <W as minicbor::encode::Write>::Error: std::error::Error + Send + Sync + 'static,
+{
+    // an empty array has no dimensions
+    let len = dims.first().copied().unwrap_or(0).max(0);
+    enc.array(len as u64)?;
+    for _ in 0..len {
+        if dims.len() > 1 {
+            encode_dimensions(enc, elem, &dims[1..], values, opts)?;
+        } else {
+            let value = values
+                .next()?
+                .ok_or("invalid array: fewer values than its dimensions")?;
+            encode_val(enc, elem, value, opts)?;
+        }
+    }
+    Ok(())
+}
+
+/// cbor encode a composite value as a map of field name to value.
+/// The fields of an anonymous record (`fields` is None) have no names,
+/// so they are encoded as an array, with the values of fields that are not
+/// built-in types as byte arrays.
+fn encode_composite<W>(
+    enc: &mut Encoder<W>,
+    fields: Option<&[Field]>,
+    raw: &[u8],
+    opts: &EncodeOptions,
+) -> Result<(), BoxError>
+where
+    W: Write,
+    <W as minicbor::encode::Write>::Error: std::error::Error + Send + Sync + 'static,
+{
+    // number of fields, then for each field: type oid, length (-1 for NULL), value
+    let mut buf = raw;
+    let num_fields = read_i32(&mut buf)?.max(0) as usize;
+    match fields {
+        Some(fields) if fields.len() != num_fields => {
+            return Err(format!(
+                "invalid composite value: expected {} fields, got {}",
+                fields.len(),
+                num_fields
+            )
+            .into())
+        }
+        Some(_) => enc.map(num_fields as u64)?,
+        None => enc.array(num_fields as u64)?,
+    };
+    for i in 0..num_fields {
+        let oid = read_i32(&mut buf)? as u32;
+        let len = read_i32(&mut buf)?;
+        let value = if len < 0 {
+            None
+        } else {
+            let len = len as usize;
+            if buf.len() < len {
+                return Err("invalid composite value: truncated field".into());
+            }
+            let (value, rest) = buf.split_at(len);
+            buf = rest;
+            Some(value)
+        };
+        match fields {
+            Some(fields) => {
+                enc.str(fields[i].name())?;
+                encode_val(enc, fields[i].type_(), value, opts)?;
+            }
+            None => match (Type::from_oid(oid), value) {
+                (Some(ty), value) => encode_val(enc, &ty, value, opts)?,
+                (None, Some(value)) => {
+                    enc.bytes(value)?;
+                }
+                (None, None) => {
+                    enc.null()?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// read a big-endian i32 from the front of the buffer
+fn read_i32(buf: &mut &[u8]) -> Result<i32, BoxError> {
+    if buf.len() < 4 {
+        return Err("invalid value: truncated".into());
+    }
+    let (n, rest) = buf.split_at(4);
+    *buf = rest;
+    Ok(i32::from_be_bytes(n.try_into()?))
+}
+
+/// cbor encode a bytea value in the format
+fn encode_bytea<'e, W>(
+    enc: &'e mut Encoder<W>,
+    bytes: &[u8],
+    format: ByteaFormat,
+) -> Result<&'e mut Encoder<W>, minicbor::encode::Error<W::Error>>
+where
+    W: Write,
+{
+    match format {
+        ByteaFormat::Bytes => enc.bytes(bytes),
+        ByteaFormat::Base64 => enc.str(&base64::encode(bytes)),
+    }
+}
+
+/// cbor encode a uuid in the format
+fn encode_uuid<'e, W>(
+    enc: &'e mut Encoder<W>,
+    uuid: &uuid::Uuid,
+    format: UuidFormat,
+) -> Result<&'e mut Encoder<W>, minicbor::encode::Error<W::Error>>
+where
+    W: Write,
+{
+    match format {
+        UuidFormat::String => enc.str(&uuid.to_hyphenated().to_string()),
+        UuidFormat::Bytes => enc.bytes(uuid.as_bytes()),
+    }
+}
+
+/// A numeric value, as its decimal string.
+/// Decoded from the binary format, without conversion to a float.
+struct Numeric(String);
+
+impl Numeric {
+    const POSITIVE: u16 = 0x0000;
+    const NEGATIVE: u16 = 0x4000;
+    const NAN: u16 = 0xC000;
+    const PINF: u16 = 0xD000;
+    const NINF: u16 = 0xF000;
+
+    /// Convert from binary format: the number of base-10000 digits, the weight
+    /// (exponent, in powers of 10000) of the first digit, the sign, the number of
+    /// decimal digits after the decimal point, then the digits.
+    fn parse(raw: &[u8]) -> Result<Self, BoxError> {
+        let read_u16 = |pos: usize| -> Result<u16, BoxError> {
+            raw.get(pos..pos + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .ok_or_else(|| "invalid numeric: truncated value".into())
+        };
+        let ndigits = read_u16(0)? as usize;
+        let weight = read_u16(2)? as i16 as i32;
+        let sign = read_u16(4)?;
+        let dscale = read_u16(6)? as usize;
+        let digits = (0..ndigits)
+            .map(|k| read_u16(8 + 2 * k))
+            .collect::<Result<Vec<_>, _>>()?;
+        let digit = |k: i32| -> u16 {
+            if k >= 0 && (k as usize) < digits.len() {
+                digits[k as usize]
+            } else {
+                0
+            }
+        };
+
+        let mut s = String::new();
+        match sign {
+            Self::NAN => return Ok(Self("NaN".into())),
+            Self::PINF => return Ok(Self("Infinity".into())),
+            Self::NINF => return Ok(Self("-Infinity".into())),
+            Self::NEGATIVE => s.push('-'),
+            Self::POSITIVE => {}
+            _ => return Err(format!("invalid numeric sign: {:#x}", sign).into()),
+        }
+        // integer part: digits 0..=weight
+        if weight < 0 {
+            s.push('0');
+        } else {
+            s.push_str(&digit(0).to_string());
+            for k in 1..=weight {
+                s.push_str(&format!("{:04}", digit(k)));
+            }
+        }
+        // fraction: dscale decimal digits, from the digits after weight
+        if dscale > 0 {
+            s.push('.');
+            let mut fraction = String::with_capacity(dscale + 4);
+            let mut k = weight + 1;
+            while fraction.len() < dscale {
+                fraction.push_str(&format!("{:04}", digit(k)));
+                k += 1;
+            }
+            fraction.truncate(dscale);
+            s.push_str(&fraction);
+        }
+        Ok(Self(s))
+    }
+
+    fn to_f64(&self) -> f64 {
+        match self.0.as_str() {
+            "NaN" => f64::NAN,
+            "Infinity" => f64::INFINITY,
+            "-Infinity" => f64::NEG_INFINITY,
+            s => s.parse().unwrap_or(f64::NAN),
+        }
+    }
+}
+
+impl<'a> FromSql<'a> for Numeric {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        Self::parse(raw)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+}
+
+/// A timestamp or timestamptz value. The binary format is microseconds
+/// since 2000-01-01 00:00:00 (UTC, for timestamptz), with `infinity` and
+/// `-infinity` as the largest and smallest values.
+enum Timestamp {
+    Value(NaiveDateTime),
+    Infinity,
+    NegInfinity,
+}
+
+impl Timestamp {
+    /// rfc3339 date and time without an offset, for example "1969-07-20T20:17:40.5"
+    fn to_naive_string(&self) -> String {
+        match self {
+            Timestamp::Value(t) => t.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+            Timestamp::Infinity => "infinity".into(),
+            Timestamp::NegInfinity => "-infinity".into(),
+        }
+    }
+
+    /// rfc3339 date and time in UTC, for example "1969-07-20T20:17:40.500Z"
+    fn to_utc_string(&self) -> String {
+        match self {
+            Timestamp::Value(t) => {
+                DateTime::<Utc>::from_utc(*t, Utc).to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            }
+            _ => self.to_naive_string(),
+        }
+    }
+}
+
+impl<'a> FromSql<'a> for Timestamp {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        let micros = i64::from_be_bytes(
+            raw.try_into()
+                .map_err(|_| "invalid timestamp: expected 8 bytes")?,
+        );
+        Ok(match micros {
+            i64::MAX => Timestamp::Infinity,
+            i64::MIN => Timestamp::NegInfinity,
+            _ => Timestamp::Value(
+                NaiveDate::from_ymd(2000, 1, 1)
+                    .and_hms(0, 0, 0)
+                    .checked_add_signed(chrono::Duration::microseconds(micros))
+                    .ok_or("timestamp is out of range")?,
+            ),
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::TIMESTAMP | Type::TIMESTAMPTZ)
+    }
+}
+
+/// A date value. The binary format is days since 2000-01-01,
+/// with `infinity` and `-infinity` as the largest and smallest values.
+enum Date {
+    Value(NaiveDate),
+    Infinity,
+    NegInfinity,
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Date::Value(d) => write!(f, "{}", d.format("%Y-%m-%d")),
+            Date::Infinity => f.write_str("infinity"),
+            Date::NegInfinity => f.write_str("-infinity"),
+        }
+    }
+}
+
+impl<'a> FromSql<'a> for Date {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        let days = i32::from_be_bytes(
+            raw.try_into()
+                .map_err(|_| "invalid date: expected 4 bytes")?,
+        );
+        Ok(match days {
+            i32::MAX => Date::Infinity,
+            i32::MIN => Date::NegInfinity,
+            _ => Date::Value(
+                NaiveDate::from_ymd(2000, 1, 1)
+                    .checked_add_signed(chrono::Duration::days(days as i64))
+                    .ok_or("date is out of range")?,
+            ),
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::DATE
+    }
+}
+
+/// An interval value. The fields are kept separate, as postgres does,
+/// because the length of a month or day depends on the date it is added to.
+struct Interval {
+    months: i32,
+    days: i32,
+    micros: i64,
+}
+
+impl<'a> FromSql<'a> for Interval {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        if raw.len() != 16 {
+            return Err("invalid interval: expected 16 bytes".into());
+        }
+        Ok(Interval {
+            micros: i64::from_be_bytes(raw[0..8].try_into()?),
+            days: i32::from_be_bytes(raw[8..12].try_into()?),
+            months: i32::from_be_bytes(raw[12..16].try_into()?),
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+}
+
+/// cbor encode a json value. Numbers are encoded as integers if they have no fraction.
+pub(crate) fn encode_json<W>(
+    enc: &mut Encoder<W>,
+    value: &serde_json::Value,
+) -> Result<(), minicbor::encode::Error<W::Error>>
+where
+    W: Write,
+{
+    use serde_json::Value;
+    match value {
+        Value::Null => {
+            enc.null()?;
+        }
+        Value::Bool(b) => {
+            enc.bool(*b)?;
+        }
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                enc.u64(n)?;
+            } else if let Some(n) = n.as_i64() {
+                enc.i64(n)?;
+            } else {
+                enc.f64(n.as_f64().unwrap_or(f64::NAN))?;
+            }
+        }
+        Value::String(s) => {
+            enc.str(s)?;
+        }
+        Value::Array(values) => {
+            enc.array(values.len() as u64)?;
+            for v in values {
+                encode_json(enc, v)?;
+            }
+        }
+        Value::Object(map) => {
+            enc.map(map.len() as u64)?;
+            for (k, v) in map {
+                enc.str(k)?;
+                encode_json(enc, v)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 /*
     -- these are here as a checklist of types not yet supported --
 
@@ -107,21 +739,12 @@ where
    /// PG_DDL_COMMAND - internal type for passing CollectedCommand
    pub const PG_DDL_COMMAND: Type = Type(Inner::PgDdlCommand);
 
-   /// XML&#91;&#93;
-   pub const XML_ARRAY: Type = Type(Inner::XmlArray);
-
    /// PG_NODE_TREE - string representing an internal node tree
    pub const PG_NODE_TREE: Type = Type(Inner::PgNodeTree);
 
-   /// JSON&#91;&#93;
-   pub const JSON_ARRAY: Type = Type(Inner::JsonArray);
-
    /// TABLE_AM_HANDLER
    pub const TABLE_AM_HANDLER: Type = Type(Inner::TableAmHandler);
 
-   /// XID8&#91;&#93;
-   pub const XID8_ARRAY: Type = Type(Inner::Xid8Array);
-
    /// INDEX_AM_HANDLER - pseudo-type for the result of an index AM handler function
    pub const INDEX_AM_HANDLER: Type = Type(Inner::IndexAmHandler);
 
@@ -143,15 +766,9 @@ where
    /// LINE - geometric line
    pub const LINE: Type = Type(Inner::Line);
 
-   /// LINE&#91;&#93;
-   pub const LINE_ARRAY: Type = Type(Inner::LineArray);
-
    /// CIDR - network IP address/netmask, network address
    pub const CIDR: Type = Type(Inner::Cidr);
 
-   /// CIDR&#91;&#93;
-   pub const CIDR_ARRAY: Type = Type(Inner::CidrArray);
-
 
    /// UNKNOWN - pseudo-type representing an undetermined type
    pub const UNKNOWN: Type = Type(Inner::Unknown);
@@ -159,170 +776,32 @@ where
    /// CIRCLE - geometric circle &#39;&#40;center,radius&#41;&#39;
    pub const CIRCLE: Type = Type(Inner::Circle);
 
-   /// CIRCLE&#91;&#93;
-   pub const CIRCLE_ARRAY: Type = Type(Inner::CircleArray);
-
    /// MACADDR8 - XX:XX:XX:XX:XX:XX:XX:XX, MAC address
    pub const MACADDR8: Type = Type(Inner::Macaddr8);
 
-   /// MACADDR8&#91;&#93;
-   pub const MACADDR8_ARRAY: Type = Type(Inner::Macaddr8Array);
-
    /// MONEY - monetary amounts, &#36;d,ddd.cc
    pub const MONEY: Type = Type(Inner::Money);
 
-   /// MONEY&#91;&#93;
-   pub const MONEY_ARRAY: Type = Type(Inner::MoneyArray);
-
    /// MACADDR - XX:XX:XX:XX:XX:XX, MAC address
    pub const MACADDR: Type = Type(Inner::Macaddr);
 
    /// INET - IP address/netmask, host address, netmask optional
 
-   /// BOOL&#91;&#93;
-   pub const BOOL_ARRAY: Type = Type(Inner::BoolArray);
-
-   /// NAME&#91;&#93;
-   pub const NAME_ARRAY: Type = Type(Inner::NameArray);
-
-   /// INT2&#91;&#93;
-   pub const INT2_ARRAY: Type = Type(Inner::Int2Array);
-
-   /// INT2VECTOR&#91;&#93;
-   pub const INT2_VECTOR_ARRAY: Type = Type(Inner::Int2VectorArray);
-
-   /// INT4&#91;&#93;
-   pub const INT4_ARRAY: Type = Type(Inner::Int4Array);
-
-   /// REGPROC&#91;&#93;
-   pub const REGPROC_ARRAY: Type = Type(Inner::RegprocArray);
-
-   /// TEXT&#91;&#93;
-   pub const TEXT_ARRAY: Type = Type(Inner::TextArray);
-
-   /// TID&#91;&#93;
-   pub const TID_ARRAY: Type = Type(Inner::TidArray);
-
-   /// XID&#91;&#93;
-   pub const XID_ARRAY: Type = Type(Inner::XidArray);
-
-   /// CID&#91;&#93;
-   pub const CID_ARRAY: Type = Type(Inner::CidArray);
-
-   /// OIDVECTOR&#91;&#93;
-   pub const OID_VECTOR_ARRAY: Type = Type(Inner::OidVectorArray);
-
-   /// BPCHAR&#91;&#93;
-   pub const BPCHAR_ARRAY: Type = Type(Inner::BpcharArray);
-
-   /// VARCHAR&#91;&#93;
-   pub const VARCHAR_ARRAY: Type = Type(Inner::VarcharArray);
-
-   /// INT8&#91;&#93;
-   pub const INT8_ARRAY: Type = Type(Inner::Int8Array);
-
-   /// POINT&#91;&#93;
-   pub const POINT_ARRAY: Type = Type(Inner::PointArray);
-
-   /// LSEG&#91;&#93;
-   pub const LSEG_ARRAY: Type = Type(Inner::LsegArray);
-
-   /// PATH&#91;&#93;
-   pub const PATH_ARRAY: Type = Type(Inner::PathArray);
-
-   /// BOX&#91;&#93;
-   pub const BOX_ARRAY: Type = Type(Inner::BoxArray);
-
-   /// FLOAT4&#91;&#93;
-   pub const FLOAT4_ARRAY: Type = Type(Inner::Float4Array);
-
-   /// FLOAT8&#91;&#93;
-   pub const FLOAT8_ARRAY: Type = Type(Inner::Float8Array);
-
-   /// POLYGON&#91;&#93;
-   pub const POLYGON_ARRAY: Type = Type(Inner::PolygonArray);
-
-   /// OID&#91;&#93;
-   pub const OID_ARRAY: Type = Type(Inner::OidArray);
-
    /// ACLITEM - access control list
    pub const ACLITEM: Type = Type(Inner::Aclitem);
 
-   /// ACLITEM&#91;&#93;
-   pub const ACLITEM_ARRAY: Type = Type(Inner::AclitemArray);
-
-   /// MACADDR&#91;&#93;
-   pub const MACADDR_ARRAY: Type = Type(Inner::MacaddrArray);
-
-   /// INET&#91;&#93;
-   pub const INET_ARRAY: Type = Type(Inner::InetArray);
-
-   /// BPCHAR - char&#40;length&#41;, blank-padded string, fixed storage length
-   pub const BPCHAR: Type = Type(Inner::Bpchar);
-
-   /// DATE - date
-   pub const DATE: Type = Type(Inner::Date);
-
-   /// TIME - time of day
-   pub const TIME: Type = Type(Inner::Time);
-
-   /// TIMESTAMP - date and time
-   pub const TIMESTAMP: Type = Type(Inner::Timestamp);
-
-   /// TIMESTAMP&#91;&#93;
-   pub const TIMESTAMP_ARRAY: Type = Type(Inner::TimestampArray);
-
-   /// DATE&#91;&#93;
-   pub const DATE_ARRAY: Type = Type(Inner::DateArray);
-
-   /// TIME&#91;&#93;
-   pub const TIME_ARRAY: Type = Type(Inner::TimeArray);
-
-   /// TIMESTAMPTZ - date and time with time zone
-   pub const TIMESTAMPTZ: Type = Type(Inner::Timestamptz);
-
-   /// TIMESTAMPTZ&#91;&#93;
-   pub const TIMESTAMPTZ_ARRAY: Type = Type(Inner::TimestamptzArray);
-
-   /// INTERVAL - &#64; &lt;number&gt; &lt;units&gt;, time interval
-   pub const INTERVAL: Type = Type(Inner::Interval);
-
-   /// INTERVAL&#91;&#93;
-   pub const INTERVAL_ARRAY: Type = Type(Inner::IntervalArray);
-
-   /// NUMERIC&#91;&#93;
-   pub const NUMERIC_ARRAY: Type = Type(Inner::NumericArray);
-
-   /// CSTRING&#91;&#93;
-   pub const CSTRING_ARRAY: Type = Type(Inner::CstringArray);
-
    /// TIMETZ - time of day with time zone
    pub const TIMETZ: Type = Type(Inner::Timetz);
 
-   /// TIMETZ&#91;&#93;
-   pub const TIMETZ_ARRAY: Type = Type(Inner::TimetzArray);
-
    /// BIT - fixed-length bit string
    pub const BIT: Type = Type(Inner::Bit);
 
-   /// BIT&#91;&#93;
-   pub const BIT_ARRAY: Type = Type(Inner::BitArray);
-
    /// VARBIT - variable-length bit string
    pub const VARBIT: Type = Type(Inner::Varbit);
 
-   /// VARBIT&#91;&#93;
-   pub const VARBIT_ARRAY: Type = Type(Inner::VarbitArray);
-
-   /// NUMERIC - numeric&#40;precision, decimal&#41;, arbitrary precision number
-   pub const NUMERIC: Type = Type(Inner::Numeric);
-
    /// REFCURSOR - reference to cursor &#40;portal name&#41;
    pub const REFCURSOR: Type = Type(Inner::Refcursor);
 
-   /// REFCURSOR&#91;&#93;
-   pub const REFCURSOR_ARRAY: Type = Type(Inner::RefcursorArray);
-
    /// REGPROCEDURE - registered procedure &#40;with args&#41;
    pub const REGPROCEDURE: Type = Type(Inner::Regprocedure);
 
@@ -338,24 +817,6 @@ where
    /// REGTYPE - registered type
    pub const REGTYPE: Type = Type(Inner::Regtype);
 
-   /// REGPROCEDURE&#91;&#93;
-   pub const REGPROCEDURE_ARRAY: Type = Type(Inner::RegprocedureArray);
-
-   /// REGOPER&#91;&#93;
-   pub const REGOPER_ARRAY: Type = Type(Inner::RegoperArray);
-
-   /// REGOPERATOR&#91;&#93;
-   pub const REGOPERATOR_ARRAY: Type = Type(Inner::RegoperatorArray);
-
-   /// REGCLASS&#91;&#93;
-   pub const REGCLASS_ARRAY: Type = Type(Inner::RegclassArray);
-
-   /// REGTYPE&#91;&#93;
-   pub const REGTYPE_ARRAY: Type = Type(Inner::RegtypeArray);
-
-   /// RECORD - pseudo-type representing any composite type
-   pub const RECORD: Type = Type(Inner::Record);
-
    /// CSTRING - C-style string
    pub const CSTRING: Type = Type(Inner::Cstring);
 
@@ -380,18 +841,9 @@ where
    /// ANYELEMENT - pseudo-type representing a polymorphic base type
    pub const ANYELEMENT: Type = Type(Inner::Anyelement);
 
-   /// RECORD&#91;&#93;
-   pub const RECORD_ARRAY: Type = Type(Inner::RecordArray);
-
    /// ANYNONARRAY - pseudo-type representing a polymorphic base type that is not an array
    pub const ANYNONARRAY: Type = Type(Inner::Anynonarray);
 
-   /// TXID_SNAPSHOT&#91;&#93;
-   pub const TXID_SNAPSHOT_ARRAY: Type = Type(Inner::TxidSnapshotArray);
-
-   /// UUID&#91;&#93;
-   pub const UUID_ARRAY: Type = Type(Inner::UuidArray);
-
    /// TXID_SNAPSHOT - txid snapshot
    pub const TXID_SNAPSHOT: Type = Type(Inner::TxidSnapshot);
 
@@ -401,9 +853,6 @@ where
    /// PG_LSN - PostgreSQL LSN datatype
    pub const PG_LSN: Type = Type(Inner::PgLsn);
 
-   /// PG_LSN&#91;&#93;
-   pub const PG_LSN_ARRAY: Type = Type(Inner::PgLsnArray);
-
    /// TSM_HANDLER - pseudo-type for the result of a tablesample method function
    pub const TSM_HANDLER: Type = Type(Inner::TsmHandler);
 
@@ -413,9 +862,6 @@ where
    /// PG_DEPENDENCIES - multivariate dependencies
    pub const PG_DEPENDENCIES: Type = Type(Inner::PgDependencies);
 
-   /// ANYENUM - pseudo-type representing a polymorphic base type that is an enum
-   pub const ANYENUM: Type = Type(Inner::Anyenum);
-
    /// TSVECTOR - text representation for text search
    pub const TS_VECTOR: Type = Type(Inner::TsVector);
 
@@ -425,33 +871,12 @@ where
    /// GTSVECTOR - GiST index internal text representation for text search
    pub const GTS_VECTOR: Type = Type(Inner::GtsVector);
 
-   /// TSVECTOR&#91;&#93;
-   pub const TS_VECTOR_ARRAY: Type = Type(Inner::TsVectorArray);
-
-   /// GTSVECTOR&#91;&#93;
-   pub const GTS_VECTOR_ARRAY: Type = Type(Inner::GtsVectorArray);
-
-   /// TSQUERY&#91;&#93;
-   pub const TSQUERY_ARRAY: Type = Type(Inner::TsqueryArray);
-
    /// REGCONFIG - registered text search configuration
    pub const REGCONFIG: Type = Type(Inner::Regconfig);
 
-   /// REGCONFIG&#91;&#93;
-   pub const REGCONFIG_ARRAY: Type = Type(Inner::RegconfigArray);
-
    /// REGDICTIONARY - registered text search dictionary
    pub const REGDICTIONARY: Type = Type(Inner::Regdictionary);
 
-   /// REGDICTIONARY&#91;&#93;
-   pub const REGDICTIONARY_ARRAY: Type = Type(Inner::RegdictionaryArray);
-
-   /// JSONB - Binary JSON
-   pub const JSONB: Type = Type(Inner::Jsonb);
-
-   /// JSONB&#91;&#93;
-   pub const JSONB_ARRAY: Type = Type(Inner::JsonbArray);
-
    /// ANYRANGE - pseudo-type representing a range over a polymorphic base type
    pub const ANY_RANGE: Type = Type(Inner::AnyRange);
 
@@ -461,72 +886,39 @@ where
    /// INT4RANGE - range of integers
    pub const INT4_RANGE: Type = Type(Inner::Int4Range);
 
-   /// INT4RANGE&#91;&#93;
-   pub const INT4_RANGE_ARRAY: Type = Type(Inner::Int4RangeArray);
-
    /// NUMRANGE - range of numerics
    pub const NUM_RANGE: Type = Type(Inner::NumRange);
 
-   /// NUMRANGE&#91;&#93;
-   pub const NUM_RANGE_ARRAY: Type = Type(Inner::NumRangeArray);
-
    /// TSRANGE - range of timestamps without time zone
    pub const TS_RANGE: Type = Type(Inner::TsRange);
 
-   /// TSRANGE&#91;&#93;
-   pub const TS_RANGE_ARRAY: Type = Type(Inner::TsRangeArray);
-
    /// TSTZRANGE - range of timestamps with time zone
    pub const TSTZ_RANGE: Type = Type(Inner::TstzRange);
 
-   /// TSTZRANGE&#91;&#93;
-   pub const TSTZ_RANGE_ARRAY: Type = Type(Inner::TstzRangeArray);
-
    /// DATERANGE - range of dates
    pub const DATE_RANGE: Type = Type(Inner::DateRange);
 
-   /// DATERANGE&#91;&#93;
-   pub const DATE_RANGE_ARRAY: Type = Type(Inner::DateRangeArray);
-
    /// INT8RANGE - range of bigints
    pub const INT8_RANGE: Type = Type(Inner::Int8Range);
 
-   /// INT8RANGE&#91;&#93;
-   pub const INT8_RANGE_ARRAY: Type = Type(Inner::Int8RangeArray);
-
    /// JSONPATH - JSON path
    pub const JSONPATH: Type = Type(Inner::Jsonpath);
 
-   /// JSONPATH&#91;&#93;
-   pub const JSONPATH_ARRAY: Type = Type(Inner::JsonpathArray);
-
    /// REGNAMESPACE - registered namespace
    pub const REGNAMESPACE: Type = Type(Inner::Regnamespace);
 
-   /// REGNAMESPACE&#91;&#93;
-   pub const REGNAMESPACE_ARRAY: Type = Type(Inner::RegnamespaceArray);
-
    /// REGROLE - registered role
    pub const REGROLE: Type = Type(Inner::Regrole);
 
-   /// REGROLE&#91;&#93;
-   pub const REGROLE_ARRAY: Type = Type(Inner::RegroleArray);
-
    /// REGCOLLATION - registered collation
    pub const REGCOLLATION: Type = Type(Inner::Regcollation);
 
-   /// REGCOLLATION&#91;&#93;
-   pub const REGCOLLATION_ARRAY: Type = Type(Inner::RegcollationArray);
-
    /// PG_MCV_LIST - multivariate MCV list
    pub const PG_MCV_LIST: Type = Type(Inner::PgMcvList);
 
    /// PG_SNAPSHOT - snapshot
    pub const PG_SNAPSHOT: Type = Type(Inner::PgSnapshot);
 
-   /// PG_SNAPSHOT&#91;&#93;
-   pub const PG_SNAPSHOT_ARRAY: Type = Type(Inner::PgSnapshotArray);
-
    /// XID8 - full transaction id
    pub const XID8: Type = Type(Inner::Xid8);
 
@@ -543,3 +935,246 @@ where
    pub const ANYCOMPATIBLE_RANGE: Type = Type(Inner::AnycompatibleRange);
 
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(map_keys(&["id", "name"]), vec!["id", "name"]);
+        assert_eq!(
+            map_keys(&["id", "name", "id", "id_2", "id"]),
+            vec!["id", "name", "id_3", "id_2", "id_4"]
+        );
+        assert_eq!(
+            map_keys(&["?column?", "?column?"]),
+            vec!["?column?", "?column?_2"]
+        );
+    }
+
+    #[test]
+    fn uuid() {
+        let uuid = uuid::Uuid::parse_str("A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11").unwrap();
+        let mut buf = Vec::new();
+        encode_uuid(&mut Encoder::new(&mut buf), &uuid, UuidFormat::String).unwrap();
+        assert_eq!(
+            minicbor::decode::<&str>(&buf).unwrap(),
+            "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"
+        );
+
+        let mut buf = Vec::new();
+        encode_uuid(&mut Encoder::new(&mut buf), &uuid, UuidFormat::Bytes).unwrap();
+        assert_eq!(
+            minicbor::decode::<&minicbor::bytes::ByteSlice>(&buf)
+                .unwrap()
+                .as_ref(),
+            uuid.as_bytes()
+        );
+    }
+
+    /// encode a value in binary format
+    fn encode(ty: &Type, value: &(dyn tokio_postgres::types::ToSql + Sync)) -> Vec<u8> {
+        let mut raw = bytes::BytesMut::new();
+        value.to_sql_checked(ty, &mut raw).unwrap();
+        let mut buf = Vec::new();
+        encode_val(
+            &mut Encoder::new(&mut buf),
+            ty,
+            Some(&raw),
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        buf
+    }
+
+    #[test]
+    fn array() {
+        let buf = encode(&Type::INT4_ARRAY, &vec![Some(1i32), None, Some(-3)]);
+        let mut expected = Vec::new();
+        Encoder::new(&mut expected)
+            .array(3)
+            .unwrap()
+            .i32(1)
+            .unwrap()
+            .null()
+            .unwrap()
+            .i32(-3)
+            .unwrap();
+        assert_eq!(buf, expected);
+
+        let buf = encode(&Type::TEXT_ARRAY, &vec![Some("a"), None]);
+        let mut expected = Vec::new();
+        Encoder::new(&mut expected)
+            .array(2)
+            .unwrap()
+            .str("a")
+            .unwrap()
+            .null()
+            .unwrap();
+        assert_eq!(buf, expected);
+
+        // an empty array
+        assert_eq!(encode(&Type::INT8_ARRAY, &Vec::<i64>::new()), [0x80]);
+    }
+
+    #[test]
+    fn refcursor_and_void() {
+        let mut buf = Vec::new();
+        let opts = EncodeOptions::default();
+        encode_val(
+            &mut Encoder::new(&mut buf),
+            &Type::REFCURSOR,
+            Some(b"c1"),
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(buf, [0x62, b'c', b'1']);
+        buf.clear();
+        encode_val(&mut Encoder::new(&mut buf), &Type::VOID, Some(b""), &opts).unwrap();
+        assert_eq!(buf, [0xf6]);
+    }
+
+    #[test]
+    fn null() {
+        for ty in [
+            Type::INT4,
+            Type::TEXT,
+            Type::JSONB,
+            Type::NUMERIC,
+            Type::TIMESTAMPTZ,
+            Type::INT4_ARRAY,
+            Type::RECORD,
+            // not otherwise supported
+            Type::POINT,
+        ] {
+            let mut buf = Vec::new();
+            encode_val(
+                &mut Encoder::new(&mut buf),
+                &ty,
+                None,
+                &EncodeOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(buf, [0xf6], "{}", ty);
+        }
+    }
+
+    #[test]
+    fn record() {
+        // row(1, NULL::text, 'x'::text)
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&3i32.to_be_bytes());
+        for (oid, value) in [
+            (Type::INT4.oid(), Some(&1i32.to_be_bytes()[..])),
+            (Type::TEXT.oid(), None),
+            (Type::TEXT.oid(), Some(&b"x"[..])),
+        ] {
+            raw.extend_from_slice(&oid.to_be_bytes());
+            match value {
+                Some(value) => {
+                    raw.extend_from_slice(&(value.len() as i32).to_be_bytes());
+                    raw.extend_from_slice(value);
+                }
+                None => raw.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        let mut buf = Vec::new();
+        encode_val(
+            &mut Encoder::new(&mut buf),
+            &Type::RECORD,
+            Some(&raw),
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        let mut expected = Vec::new();
+        Encoder::new(&mut expected)
+            .array(3)
+            .unwrap()
+            .i32(1)
+            .unwrap()
+            .null()
+            .unwrap()
+            .str("x")
+            .unwrap();
+        assert_eq!(buf, expected);
+
+        // truncated
+        let mut buf = Vec::new();
+        assert!(encode_val(
+            &mut Encoder::new(&mut buf),
+            &Type::RECORD,
+            Some(&raw[..raw.len() - 1]),
+            &EncodeOptions::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn bytea() {
+        let mut buf = Vec::new();
+        encode_bytea(
+            &mut Encoder::new(&mut buf),
+            &[0, 1, 255],
+            ByteaFormat::Bytes,
+        )
+        .unwrap();
+        assert_eq!(buf, [0x43, 0, 1, 255]);
+
+        let mut buf = Vec::new();
+        encode_bytea(
+            &mut Encoder::new(&mut buf),
+            &[0, 1, 255],
+            ByteaFormat::Base64,
+        )
+        .unwrap();
+        assert_eq!(minicbor::decode::<&str>(&buf).unwrap(), "AAH/");
+
+        // empty values are an empty byte string or empty string
+        let mut buf = Vec::new();
+        encode_bytea(&mut Encoder::new(&mut buf), &[], ByteaFormat::Bytes).unwrap();
+        assert_eq!(buf, [0x40]);
+        let mut buf = Vec::new();
+        encode_bytea(&mut Encoder::new(&mut buf), &[], ByteaFormat::Base64).unwrap();
+        assert_eq!(buf, [0x60]);
+    }
+
+    /// binary numeric: ndigits, weight, sign, dscale, then base-10000 digits
+    fn numeric(weight: i16, sign: u16, dscale: u16, digits: &[u16]) -> String {
+        let mut raw = Vec::new();
+        for n in [digits.len() as u16, weight as u16, sign, dscale]
+            .iter()
+            .chain(digits)
+        {
+            raw.extend_from_slice(&n.to_be_bytes());
+        }
+        Numeric::parse(&raw).unwrap().0
+    }
+
+    #[test]
+    fn timestamp() {
+        let ts =
+            |micros: i64| Timestamp::from_sql(&Type::TIMESTAMP, &micros.to_be_bytes()).unwrap();
+        assert_eq!(ts(0).to_naive_string(), "2000-01-01T00:00:00");
+        assert_eq!(ts(0).to_utc_string(), "2000-01-01T00:00:00Z");
+        assert_eq!(ts(1_500).to_naive_string(), "2000-01-01T00:00:00.001500");
+        assert_eq!(ts(-1).to_utc_string(), "1999-12-31T23:59:59.999999Z");
+        assert_eq!(ts(i64::MAX).to_utc_string(), "infinity");
+        assert_eq!(ts(i64::MIN).to_naive_string(), "-infinity");
+        assert!(Timestamp::from_sql(&Type::TIMESTAMP, &[0, 1]).is_err());
+    }
+
+    #[test]
+    fn numeric_string() {
+        assert_eq!(numeric(1, 0, 3, &[1, 2345, 6780]), "12345.678");
+        assert_eq!(numeric(0, 0x4000, 2, &[1, 5000]), "-1.50");
+        // trailing zero digits are not sent
+        assert_eq!(numeric(2, 0, 0, &[12]), "1200000000");
+        assert_eq!(numeric(-3, 0, 12, &[10]), "0.000000000010");
+        assert_eq!(numeric(0, 0, 0, &[]), "0");
+        assert_eq!(numeric(0, 0, 2, &[]), "0.00");
+        assert_eq!(numeric(0, 0xC000, 0, &[]), "NaN");
+        assert_eq!(numeric(0, 0xF000, 0, &[]), "-Infinity");
+        assert!(Numeric::parse(&[0, 1]).is_err());
+    }
+}
@@ -1,4 +1,13 @@
 use minicbor::Decode;
+#[path = "../src/ext.rs"]
+mod ext;
+use ext::{
+    AdvisoryLockRequest, BatchRequest, BeginTransactionRequest, CachedQuery, CallRequest,
+    CopyInRequest, CopyOutRequest, FetchNextRequest, FetchStreamRequest, InsertManyRequest,
+    InvalidateCacheRequest, ListTablesRequest, LoReadRequest, LoUnlinkRequest, LoWriteRequest,
+    NotifyRequest, PageRequest, ParamQuery, PrepareTransactionRequest, PreparedTransactionRequest,
+    SessionQuery, SqlDbExt, SqlDbExtSender, TableRequest, TransactionRequest, UpsertRequest,
+};
 use wasmbus_rpc::provider::prelude::*;
 use wasmcloud_interface_sqldb::*;
 use wasmcloud_test_util::provider_test::Provider;
@@ -19,7 +28,58 @@ async fn run_all() {
     tokio::time::sleep(std::time::Duration::from_secs(3)).await;
 
     let opts = TestOptions::default();
-    let res = run_selected_spawn!(&opts, health_check, query, flavor_test);
+    let res = run_selected_spawn!(
+        &opts,
+        health_check,
+        query,
+        flavor_test,
+        search_path_test,
+        session_init_test,
+        idle_in_transaction_test,
+        query_tags_test,
+        transaction_test,
+        session_test,
+        prepared_transaction_test,
+        connection_reset_test,
+        sql_error_test,
+        params_test,
+        statement_cache_test,
+        batch_test,
+        returning_test,
+        fetch_row_test,
+        cursor_test,
+        page_test,
+        limits_test,
+        timeout_test,
+        copy_in_test,
+        insert_many_test,
+        upsert_test,
+        copy_out_test,
+        large_object_test,
+        stream_test,
+        pool_stats_test,
+        server_info_test,
+        advisory_lock_test,
+        notify_test,
+        catalog_test,
+        cancel_queries_test,
+        audit_log_test,
+        rate_limit_test,
+        column_case_test,
+        concurrency_limit_test,
+        fetch_cached_test,
+        notices_test,
+        call_test,
+        json_test,
+        uuid_test,
+        numeric_test,
+        timestamp_test,
+        date_time_test,
+        bytea_test,
+        array_test,
+        custom_type_test,
+        null_test
+    );
     print_test_results(&res);
 
     let passed = res.iter().filter(|tr| tr.passed).count();
@@ -154,3 +214,3599 @@ async fn flavor_queries(ctx: &Context, client: &SqlDbSender<Provider>) -> Result
 
     Ok(())
 }
+
+#[derive(Decode)]
+struct TenantResult {
+    #[n(0)]
+    name: String,
+}
+
+/// test that unqualified table names resolve to the schema in the
+/// search_path (provider_test_config.toml sets search_path "test_tenant, public")
+async fn search_path_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    search_path_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+/// the lock_timeout and session_init statements of the test config are run on each connection
+async fn session_init_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+
+    let resp = client
+        .fetch(
+            &ctx,
+            &"select current_setting('timezone') as tz, current_setting('lock_timeout') as lt"
+                .to_string(),
+        )
+        .await?;
+    check!(resp.error.is_none())?;
+    #[derive(Decode)]
+    struct Settings {
+        #[n(0)]
+        tz: String,
+        #[n(1)]
+        lt: String,
+    }
+    let rows: Vec<Settings> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows[0].tz, "Asia/Tokyo");
+    assert_eq!(rows[0].lt, "5s");
+    Ok(())
+}
+
+/// with idle_in_transaction_session_timeout, the server ends a transaction that is idle for
+/// longer, before the provider's transaction_idle_timeout. The setting is only made for the
+/// test's transaction, so that the other tests' transactions are not ended
+async fn idle_in_transaction_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    let begin = ext_client
+        .begin_transaction(&ctx, &BeginTransactionRequest::default())
+        .await?;
+    assert!(begin.error.is_none(), "begin: {:?}", begin.error);
+    let resp = ext_client
+        .execute_in(
+            &ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "set local idle_in_transaction_session_timeout = 1000".into(),
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "execute_in: {:?}", resp.error);
+    let query = SessionQuery {
+        token: begin.token.clone(),
+        query: "select current_setting('idle_in_transaction_session_timeout')".into(),
+    };
+    let resp = ext_client.fetch_in(&ctx, &query).await?;
+    assert!(resp.error.is_none(), "fetch_in: {:?}", resp.error);
+    let rows: Vec<(String,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows[0].0, "1s");
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let resp = ext_client.fetch_in(&ctx, &query).await?;
+    assert_eq!(
+        resp.error.as_ref().map(|e| e.code.as_str()),
+        Some("connectionReset"),
+        "session terminated by the server"
+    );
+    let _ = ext_client.rollback(&ctx, &begin.token).await;
+
+    // the terminated connection is replaced
+    let resp = client.fetch(&ctx, &"select 1".to_string()).await?;
+    assert!(resp.error.is_none(), "fetch: {:?}", resp.error);
+    Ok(())
+}
+
+/// with query_tags, statements begin with a comment that names the actor
+async fn query_tags_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+
+    let resp = client
+        .fetch(
+            &ctx,
+            &"select query from pg_stat_activity where pid = pg_backend_pid()".to_string(),
+        )
+        .await?;
+    check!(resp.error.is_none())?;
+    let rows: Vec<(String,)> = minicbor::decode(&resp.rows)?;
+    assert!(rows[0].0.starts_with("/* actor="), "{}", rows[0].0);
+    assert!(
+        rows[0]
+            .0
+            .ends_with(" */ select query from pg_stat_activity where pid = pg_backend_pid()"),
+        "{}",
+        rows[0].0
+    );
+    Ok(())
+}
+
+async fn search_path_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"create schema if not exists test_tenant".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"drop table if exists test_tenant.test_tenants".to_string(),
+        )
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_tenant.test_tenants ( name VARCHAR(30) NOT NULL )".to_string(),
+        )
+        .await?;
+    let resp = client
+        .execute(
+            ctx,
+            &"insert into test_tenant.test_tenants (name) values ('Acme')".to_string(),
+        )
+        .await?;
+    assert_eq!(resp.rows_affected, 1, "1 row inserted");
+
+    // unqualified name
+    let resp = client
+        .fetch(ctx, &"select name from test_tenants".to_string())
+        .await?;
+    assert_eq!(resp.num_rows, 1, "select should have returned 1 row");
+    let rows: Vec<TenantResult> = minicbor::decode(&resp.rows)?;
+    assert_eq!(&rows.get(0).unwrap().name, "Acme");
+
+    let _resp = client
+        .execute(
+            ctx,
+            &"drop table if exists test_tenant.test_tenants".to_string(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Decode)]
+struct BalanceResult {
+    #[n(0)]
+    balance: i64,
+}
+
+/// test that statements in a transaction are committed together,
+/// and rolled back together if one fails
+async fn transaction_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    transaction_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn transaction_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_accounts".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_accounts ( id INT4 NOT NULL PRIMARY KEY, balance INT8 NOT NULL )"
+                .to_string(),
+        )
+        .await?;
+
+    let resp = ext_client
+        .transaction(
+            ctx,
+            &TransactionRequest {
+                queries: vec![
+                    "insert into test_accounts (id, balance) values (1, 100), (2, 0)".into(),
+                    "update test_accounts set balance = balance - 40 where id = 1".into(),
+                    "update test_accounts set balance = balance + 40 where id = 2".into(),
+                ],
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.committed, "transaction committed: {:?}", resp.error);
+    assert!(resp.error.is_none());
+    let rows_affected: Vec<u64> = resp.results.iter().map(|r| r.rows_affected).collect();
+    assert_eq!(rows_affected, vec![2, 1, 1]);
+
+    // the second insert violates the primary key, so the update is rolled back too
+    let resp = ext_client
+        .transaction(
+            ctx,
+            &TransactionRequest {
+                queries: vec![
+                    "update test_accounts set balance = balance - 10 where id = 1".into(),
+                    "insert into test_accounts (id, balance) values (2, 0)".into(),
+                    "update test_accounts set balance = balance + 10 where id = 2".into(),
+                ],
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(!resp.committed);
+    assert_eq!(resp.error.as_ref().map(|e| e.code.as_str()), Some("db"));
+    assert_eq!(resp.retries, 0, "not retryable");
+    assert_eq!(resp.results.len(), 2, "stops at the failed statement");
+    assert!(resp.results[1].error.is_some());
+
+    let resp = client
+        .fetch(
+            ctx,
+            &"select balance from test_accounts where id = 1".to_string(),
+        )
+        .await?;
+    let rows: Vec<BalanceResult> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows.get(0).unwrap().balance, 60, "first transaction only");
+
+    // serialization failures are retried. Sequences are not transactional,
+    // so the statement fails for the first two attempts
+    client
+        .execute(ctx, &"drop sequence if exists test_retry_seq".to_string())
+        .await?;
+    client
+        .execute(ctx, &"create sequence test_retry_seq".to_string())
+        .await?;
+    let resp = ext_client
+        .transaction(
+            ctx,
+            &TransactionRequest {
+                queries: vec![
+                    "update test_accounts set balance = balance + 1 where id = 1".into(),
+                    r#"DO $$ BEGIN
+                        IF nextval('test_retry_seq') < 3 THEN
+                            RAISE EXCEPTION 'conflict' USING ERRCODE = 'serialization_failure';
+                        END IF;
+                    END $$"#
+                        .into(),
+                ],
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.committed, "committed after retry: {:?}", resp.error);
+    assert_eq!(resp.retries, 2);
+    let resp = client
+        .fetch(
+            ctx,
+            &"select balance from test_accounts where id = 1".to_string(),
+        )
+        .await?;
+    let rows: Vec<BalanceResult> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows.get(0).unwrap().balance, 61, "updated once");
+    client
+        .execute(ctx, &"drop sequence if exists test_retry_seq".to_string())
+        .await?;
+
+    // writes are rejected in a read-only transaction
+    let resp = ext_client
+        .transaction(
+            ctx,
+            &TransactionRequest {
+                queries: vec!["update test_accounts set balance = 0".into()],
+                isolation_level: Some("serializable".into()),
+                read_only: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(!resp.committed);
+    assert!(resp.error.is_some());
+
+    // invalid isolation levels are rejected before the transaction begins
+    let resp = ext_client
+        .transaction(
+            ctx,
+            &TransactionRequest {
+                queries: vec!["update test_accounts set balance = 0".into()],
+                isolation_level: Some("snapshot".into()),
+                ..Default::default()
+            },
+        )
+        .await;
+    assert!(resp.is_err(), "invalid isolation level");
+
+    let _resp = client
+        .execute(ctx, &"drop table if exists test_accounts".to_string())
+        .await?;
+
+    Ok(())
+}
+
+/// test transactions that remain open across calls
+/// (provider_test_config.toml sets transaction_idle_timeout to 2 seconds)
+async fn session_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    session_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn session_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_sessions".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_sessions ( flavor VARCHAR(30) NOT NULL )".to_string(),
+        )
+        .await?;
+    let count = "select flavor from test_sessions".to_string();
+
+    // commit
+    let begin = ext_client
+        .begin_transaction(ctx, &BeginTransactionRequest::default())
+        .await?;
+    assert!(begin.error.is_none(), "begin: {:?}", begin.error);
+    let resp = ext_client
+        .execute_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "insert into test_sessions (flavor) values ('Vanilla')".into(),
+            },
+        )
+        .await?;
+    assert_eq!(resp.rows_affected, 1);
+    let resp = ext_client
+        .fetch_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: count.clone(),
+            },
+        )
+        .await?;
+    assert_eq!(resp.num_rows, 1, "visible in the transaction");
+    let resp = client.fetch(ctx, &count).await?;
+    assert_eq!(resp.num_rows, 0, "not visible outside the transaction");
+    let resp = ext_client.commit(ctx, &begin.token).await?;
+    assert!(resp.committed, "commit: {:?}", resp.error);
+    let resp = client.fetch(ctx, &count).await?;
+    assert_eq!(resp.num_rows, 1, "visible after commit");
+
+    // isolation level
+    let begin = ext_client
+        .begin_transaction(
+            ctx,
+            &BeginTransactionRequest {
+                isolation_level: Some("repeatable read".into()),
+                ..Default::default()
+            },
+        )
+        .await?;
+    let resp = ext_client
+        .fetch_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "select current_setting('transaction_isolation')".into(),
+            },
+        )
+        .await?;
+    let rows: Vec<(String,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows[0].0, "repeatable read");
+    ext_client.rollback(ctx, &begin.token).await?;
+
+    // rollback
+    let begin = ext_client
+        .begin_transaction(ctx, &BeginTransactionRequest::default())
+        .await?;
+    ext_client
+        .execute_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "delete from test_sessions".into(),
+            },
+        )
+        .await?;
+    let resp = ext_client.rollback(ctx, &begin.token).await?;
+    assert!(resp.error.is_none(), "rollback: {:?}", resp.error);
+    let resp = client.fetch(ctx, &count).await?;
+    assert_eq!(resp.num_rows, 1, "delete rolled back");
+    let resp = ext_client.commit(ctx, &begin.token).await?;
+    assert_eq!(
+        resp.error.as_ref().map(|e| e.code.as_str()),
+        Some("notFound"),
+        "transaction ended"
+    );
+
+    // idle timeout
+    let begin = ext_client
+        .begin_transaction(ctx, &BeginTransactionRequest::default())
+        .await?;
+    ext_client
+        .execute_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "delete from test_sessions".into(),
+            },
+        )
+        .await?;
+    tokio::time::sleep(std::time::Duration::from_secs(4)).await;
+    let resp = ext_client
+        .execute_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "delete from test_sessions".into(),
+            },
+        )
+        .await?;
+    assert_eq!(
+        resp.error.as_ref().map(|e| e.code.as_str()),
+        Some("notFound"),
+        "rolled back after idle timeout"
+    );
+    let resp = client.fetch(ctx, &count).await?;
+    assert_eq!(resp.num_rows, 1, "delete rolled back after idle timeout");
+
+    let _resp = client
+        .execute(ctx, &"drop table if exists test_sessions".to_string())
+        .await?;
+
+    Ok(())
+}
+
+/// test two-phase commit with prepare_transaction. The server must have
+/// max_prepared_transactions > 0
+async fn prepared_transaction_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    prepared_transaction_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn prepared_transaction_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    // left by an earlier run that failed
+    for gid in ["sqldb-test-commit", "sqldb-test-rollback"] {
+        let _ = ext_client
+            .rollback_prepared(ctx, &PreparedTransactionRequest { gid: gid.into() })
+            .await?;
+    }
+    client
+        .execute(ctx, &"drop table if exists test_prepared".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_prepared ( flavor VARCHAR(30) NOT NULL )".to_string(),
+        )
+        .await?;
+    let count = "select flavor from test_prepared".to_string();
+    let mut committed = 0;
+
+    for (gid, commit) in [("sqldb-test-commit", true), ("sqldb-test-rollback", false)] {
+        let begin = ext_client
+            .begin_transaction(ctx, &BeginTransactionRequest::default())
+            .await?;
+        assert!(begin.error.is_none(), "begin: {:?}", begin.error);
+        ext_client
+            .execute_in(
+                ctx,
+                &SessionQuery {
+                    token: begin.token.clone(),
+                    query: "insert into test_prepared (flavor) values ('Vanilla')".into(),
+                },
+            )
+            .await?;
+        let resp = ext_client
+            .prepare_transaction(
+                ctx,
+                &PrepareTransactionRequest {
+                    token: begin.token.clone(),
+                    gid: gid.into(),
+                },
+            )
+            .await?;
+        assert!(resp.error.is_none(), "prepare: {:?}", resp.error);
+        assert!(resp.prepared);
+        // the session has ended, and the prepared transaction is not visible yet
+        let resp = ext_client.commit(ctx, &begin.token).await?;
+        assert_eq!(resp.error.unwrap().code, "notFound");
+        let resp = client.fetch(ctx, &count).await?;
+        assert_eq!(resp.num_rows, committed);
+        let resp = client
+            .fetch(
+                ctx,
+                &format!("select gid from pg_prepared_xacts where gid = '{}'", gid),
+            )
+            .await?;
+        assert_eq!(resp.num_rows, 1);
+
+        let request = PreparedTransactionRequest { gid: gid.into() };
+        let resp = if commit {
+            ext_client.commit_prepared(ctx, &request).await?
+        } else {
+            ext_client.rollback_prepared(ctx, &request).await?
+        };
+        assert!(resp.error.is_none(), "end prepared: {:?}", resp.error);
+        assert_eq!(resp.committed, commit);
+        committed += commit as u64;
+    }
+    let resp = client.fetch(ctx, &count).await?;
+    assert_eq!(resp.num_rows, 1);
+
+    // the transaction has been committed
+    let resp = ext_client
+        .commit_prepared(
+            ctx,
+            &PreparedTransactionRequest {
+                gid: "sqldb-test-commit".into(),
+            },
+        )
+        .await?;
+    let err = resp.error.expect("not prepared");
+    assert!(err.message.contains("does not exist"), "{}", err.message);
+    Ok(())
+}
+
+/// test that a statement on a connection that was closed by the server
+/// returns a connectionReset error
+async fn connection_reset_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    connection_reset_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn connection_reset_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    let begin = ext_client
+        .begin_transaction(ctx, &BeginTransactionRequest::default())
+        .await?;
+    assert!(begin.error.is_none(), "begin: {:?}", begin.error);
+    let resp = ext_client
+        .fetch_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "select pg_backend_pid()".into(),
+            },
+        )
+        .await?;
+    let rows: Vec<(i32,)> = minicbor::decode(&resp.rows)?;
+    let pid = rows[0].0;
+    let resp = ext_client
+        .fetch_params(
+            ctx,
+            &ParamQuery {
+                query: "select pg_terminate_backend($1)".into(),
+                params: encode_params(|e| {
+                    e.array(1)?.i32(pid)?;
+                    Ok(())
+                }),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "terminate: {:?}", resp.error);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let resp = ext_client
+        .execute_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "select 1".into(),
+            },
+        )
+        .await?;
+    assert_eq!(
+        resp.error.map(|e| e.code),
+        Some("connectionReset".to_string())
+    );
+    let _ = ext_client.rollback(ctx, &begin.token).await?;
+
+    // a transaction whose connection is lost is retryable, and is retried on a new connection
+    let resp = ext_client
+        .transaction(
+            ctx,
+            &TransactionRequest {
+                queries: vec!["select pg_terminate_backend(pg_backend_pid())".into()],
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(!resp.committed);
+    assert_eq!(resp.retries, 3);
+    let err = resp.error.expect("transaction error");
+    assert_eq!(err.code, "connectionReset");
+    assert!(err.retryable);
+
+    // the pool replaces the closed connection
+    let resp = client.fetch(ctx, &"select 1".to_string()).await?;
+    assert!(resp.error.is_none(), "fetch: {:?}", resp.error);
+
+    // with retry_on_connection_error reads, a query whose connection is lost is run again,
+    // once, on a new connection; the statements of Execute are not
+    let _ = client
+        .execute(ctx, &"drop sequence if exists retry_seq".to_string())
+        .await?;
+    let resp = client
+        .execute(ctx, &"create sequence retry_seq".to_string())
+        .await?;
+    assert!(resp.error.is_none(), "create sequence: {:?}", resp.error);
+    let terminate_first = "select case when nextval('retry_seq') = 1 \
+         then pg_terminate_backend(pg_backend_pid()) else true end"
+        .to_string();
+    let resp = client.fetch(ctx, &terminate_first).await?;
+    assert!(resp.error.is_none(), "retried fetch: {:?}", resp.error);
+    let rows: Vec<(bool,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, vec![(true,)]);
+    let _ = client
+        .execute(ctx, &"alter sequence retry_seq restart".to_string())
+        .await?;
+    // ... as are the queries of FetchOne and FetchScalar
+    let resp = ext_client
+        .fetch_one(
+            ctx,
+            &ParamQuery {
+                query: terminate_first.clone(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "retried fetch_one: {:?}", resp.error);
+    let row: (bool,) = minicbor::decode(&resp.row)?;
+    assert_eq!(row, (true,));
+    let _ = client
+        .execute(ctx, &"alter sequence retry_seq restart".to_string())
+        .await?;
+    let resp = ext_client
+        .fetch_scalar(
+            ctx,
+            &ParamQuery {
+                query: terminate_first.clone(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(
+        resp.error.is_none(),
+        "retried fetch_scalar: {:?}",
+        resp.error
+    );
+    let value: bool = minicbor::decode(&resp.value)?;
+    assert!(value);
+    let _ = client
+        .execute(ctx, &"alter sequence retry_seq restart".to_string())
+        .await?;
+    let resp = client.execute(ctx, &terminate_first).await?;
+    assert_eq!(
+        resp.error.map(|e| e.code),
+        Some("connectionReset".to_string())
+    );
+    let _ = client
+        .execute(ctx, &"drop sequence retry_seq".to_string())
+        .await?;
+    Ok(())
+}
+
+/// test that database errors start with their SQLSTATE code and category
+async fn sql_error_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    sql_error_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+async fn sql_error_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_sql_errors".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_sql_errors ( id INT4 PRIMARY KEY )".to_string(),
+        )
+        .await?;
+    let insert = "insert into test_sql_errors (id) values (1)".to_string();
+    let resp = client.execute(ctx, &insert).await?;
+    assert!(resp.error.is_none(), "insert: {:?}", resp.error);
+    let err = client.execute(ctx, &insert).await?.error.expect("error");
+    assert_eq!(err.code, "db");
+    assert!(
+        err.message
+            .starts_with("SQLSTATE 23505 (integrityViolation): ERROR: duplicate key"),
+        "{}",
+        err.message
+    );
+
+    let err = client
+        .fetch(ctx, &"selec 1".to_string())
+        .await?
+        .error
+        .expect("error");
+    assert_eq!(err.code, "db");
+    assert!(
+        err.message.starts_with("SQLSTATE 42601 (syntax): "),
+        "{}",
+        err.message
+    );
+
+    let err = client
+        .fetch(ctx, &"select 1 / 0".to_string())
+        .await?
+        .error
+        .expect("error");
+    assert!(
+        err.message.starts_with("SQLSTATE 22012 (data): "),
+        "{}",
+        err.message
+    );
+    Ok(())
+}
+
+/// test statements and queries with parameters
+async fn params_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    params_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn params_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_params".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &r#"create table test_params (
+                id INT4 NOT NULL,
+                small INT2,
+                big INT8,
+                price FLOAT8,
+                name VARCHAR(40),
+                data BYTEA,
+                active BOOLEAN
+            )"#
+            .to_string(),
+        )
+        .await?;
+
+    let insert = "insert into test_params (id, small, big, price, name, data, active) \
+                  values ($1, $2, $3, $4, $5, $6, $7)";
+    let params = encode_params(|enc| {
+        enc.array(7)?
+            .u32(1)?
+            .i16(-2)?
+            .i64(i64::MAX)?
+            .f64(2.5)?
+            .str("Robert'); drop table test_params; --")?
+            .bytes(&[0, 1, 2])?
+            .bool(true)?;
+        Ok(())
+    });
+    let resp = ext_client
+        .execute_params(
+            ctx,
+            &ParamQuery {
+                query: insert.into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "insert: {:?}", resp.error);
+    assert_eq!(resp.rows_affected, 1);
+
+    // nulls, and an integer for a float column
+    let params = encode_params(|enc| {
+        enc.array(7)?
+            .u32(2)?
+            .null()?
+            .null()?
+            .u32(3)?
+            .null()?
+            .null()?
+            .null()?;
+        Ok(())
+    });
+    let resp = ext_client
+        .execute_params(
+            ctx,
+            &ParamQuery {
+                query: insert.into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "insert nulls: {:?}", resp.error);
+
+    let params = encode_params(|enc| {
+        enc.array(1)?.u32(1)?;
+        Ok(())
+    });
+    let resp = ext_client
+        .fetch_params(
+            ctx,
+            &ParamQuery {
+                query:
+                    "select small, big, price, name, data, active from test_params where id = $1"
+                        .into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "fetch: {:?}", resp.error);
+    assert_eq!(resp.num_rows, 1);
+    let rows: Vec<(i16, i64, f64, String, minicbor::bytes::ByteVec, bool)> =
+        minicbor::decode(&resp.rows)?;
+    assert_eq!(rows[0].0, -2);
+    assert_eq!(rows[0].1, i64::MAX);
+    assert_eq!(rows[0].2, 2.5);
+    assert_eq!(rows[0].3, "Robert'); drop table test_params; --");
+    assert_eq!(rows[0].4.to_vec(), vec![0u8, 1, 2]);
+    assert!(rows[0].5);
+
+    let params = encode_params(|enc| {
+        enc.array(1)?.u32(2)?;
+        Ok(())
+    });
+    let resp = ext_client
+        .fetch_params(
+            ctx,
+            &ParamQuery {
+                query: "select price from test_params where id = $1 and name is null".into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    let rows: Vec<(f64,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows[0].0, 3.0);
+
+    // value out of range for the column type
+    let params = encode_params(|enc| {
+        enc.array(2)?.u32(3)?.u32(70000)?;
+        Ok(())
+    });
+    let resp = ext_client
+        .execute_params(
+            ctx,
+            &ParamQuery {
+                query: "insert into test_params (id, small) values ($1, $2)".into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_some(), "int2 out of range");
+
+    // wrong number of parameters
+    let params = encode_params(|enc| {
+        enc.array(1)?.u32(3)?;
+        Ok(())
+    });
+    let resp = ext_client
+        .execute_params(
+            ctx,
+            &ParamQuery {
+                query: insert.into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_some(), "missing parameters");
+
+    // params that are not a cbor array
+    let resp = ext_client
+        .execute_params(
+            ctx,
+            &ParamQuery {
+                query: insert.into(),
+                params: b"not cbor".to_vec(),
+                ..Default::default()
+            },
+        )
+        .await;
+    assert!(resp.is_err(), "invalid params");
+
+    Ok(())
+}
+
+/// test that prepared statements are cached, closed with ClearStatementCache, and re-prepared
+/// after a table is altered
+async fn statement_cache_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    statement_cache_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn statement_cache_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_cache".to_string())
+        .await?;
+    client
+        .execute(ctx, &"create table test_cache ( n INT4 )".to_string())
+        .await?;
+
+    // a transaction's statements run on one connection, so they use the same cache
+    let begin = ext_client
+        .begin_transaction(ctx, &BeginTransactionRequest::default())
+        .await?;
+    let update = "insert into test_cache (n) values (1)";
+    for _ in 0..3 {
+        let resp = ext_client
+            .execute_in(
+                ctx,
+                &SessionQuery {
+                    token: begin.token.clone(),
+                    query: update.into(),
+                },
+            )
+            .await?;
+        assert!(resp.error.is_none(), "insert: {:?}", resp.error);
+    }
+    let resp = ext_client
+        .fetch_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: format!(
+                    "select count(*) from pg_prepared_statements where statement = '{}'",
+                    update
+                ),
+            },
+        )
+        .await?;
+    let rows: Vec<(i64,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows[0].0, 1, "statement is prepared once");
+    ext_client.commit(ctx, &begin.token).await?;
+
+    // after ClearStatementCache, the statements are closed on the connections that had them
+    let resp = ext_client.clear_statement_cache(ctx).await?;
+    assert!(resp.error.is_none(), "clear: {:?}", resp.error);
+    let begin = ext_client
+        .begin_transaction(ctx, &BeginTransactionRequest::default())
+        .await?;
+    let resp = ext_client
+        .fetch_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: format!(
+                    "select count(*) from pg_prepared_statements where statement = '{}'",
+                    update
+                ),
+            },
+        )
+        .await?;
+    let rows: Vec<(i64,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows[0].0, 0, "statement is closed");
+    let resp = ext_client
+        .execute_in(
+            ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: update.into(),
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "insert after clear: {:?}", resp.error);
+    ext_client.commit(ctx, &begin.token).await?;
+
+    // the result type of a cached select changes when the table is altered
+    let select = "select * from test_cache".to_string();
+    let resp = client.fetch(ctx, &select).await?;
+    assert!(resp.error.is_none(), "fetch: {:?}", resp.error);
+    assert_eq!(resp.columns.len(), 1);
+    client
+        .execute(
+            ctx,
+            &"alter table test_cache add column name VARCHAR(20) NOT NULL DEFAULT 'x'".to_string(),
+        )
+        .await?;
+    let resp = client.fetch(ctx, &select).await?;
+    assert!(resp.error.is_none(), "fetch after alter: {:?}", resp.error);
+    assert_eq!(resp.columns.len(), 2);
+    Ok(())
+}
+
+/// test batches of statements, with and without stop_on_error
+async fn batch_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    batch_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn batch_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    let queries = vec![
+        "drop table if exists test_batch".to_string(),
+        "create table test_batch ( id INT4 PRIMARY KEY )".to_string(),
+        "insert into test_batch (id) values (1), (2)".to_string(),
+        // duplicate key
+        "insert into test_batch (id) values (1)".to_string(),
+        "insert into test_batch (id) values (3)".to_string(),
+    ];
+
+    // continue after an error
+    let results = ext_client
+        .execute_batch(
+            ctx,
+            &BatchRequest {
+                queries: queries.clone(),
+                stop_on_error: false,
+                pipeline: false,
+            },
+        )
+        .await?;
+    assert_eq!(results.len(), 5);
+    assert!(results[..3].iter().all(|r| r.error.is_none()));
+    assert_eq!(results[2].rows_affected, 2);
+    assert!(results[3].error.is_some(), "duplicate key");
+    assert!(results[4].error.is_none());
+    let resp = client
+        .fetch(ctx, &"select id from test_batch".to_string())
+        .await?;
+    assert_eq!(
+        resp.num_rows, 3,
+        "statements before and after the error are committed"
+    );
+
+    // stop at the first error
+    let results = ext_client
+        .execute_batch(
+            ctx,
+            &BatchRequest {
+                queries,
+                stop_on_error: true,
+                pipeline: false,
+            },
+        )
+        .await?;
+    assert_eq!(results.len(), 4);
+    assert!(results[3].error.is_some(), "duplicate key");
+    let resp = client
+        .fetch(ctx, &"select id from test_batch".to_string())
+        .await?;
+    assert_eq!(resp.num_rows, 2, "statement after the error is not run");
+
+    // pipelined, in order, with the same results. The statements are prepared before
+    // the first one runs, so the table must already exist
+    let results = ext_client
+        .execute_batch(
+            ctx,
+            &BatchRequest {
+                queries: vec![
+                    "delete from test_batch".to_string(),
+                    "insert into test_batch (id) values (1), (2)".to_string(),
+                    "insert into test_batch (id) values (1)".to_string(),
+                    "select 1/0 from test_batch".to_string(),
+                    "insert into test_batch (id) values (3)".to_string(),
+                ],
+                pipeline: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0].rows_affected, 2);
+    assert_eq!(results[1].rows_affected, 2);
+    assert!(results[2].error.is_some(), "duplicate key");
+    assert!(results[3].error.is_some(), "division by zero");
+    assert!(results[4].error.is_none());
+    let resp = client
+        .fetch(ctx, &"select id from test_batch".to_string())
+        .await?;
+    assert_eq!(resp.num_rows, 3);
+    let resp = ext_client
+        .execute_batch(
+            ctx,
+            &BatchRequest {
+                queries: vec!["select 1".to_string()],
+                stop_on_error: true,
+                pipeline: true,
+            },
+        )
+        .await;
+    assert!(resp.is_err(), "pipeline with stop_on_error");
+
+    // empty batch
+    let resp = ext_client
+        .execute_batch(ctx, &BatchRequest::default())
+        .await;
+    assert!(resp.is_err(), "empty batch");
+    Ok(())
+}
+
+/// test that rows returned by INSERT ... RETURNING are included in the result
+async fn returning_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    returning_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn returning_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_returning".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_returning ( id SERIAL PRIMARY KEY, flavor VARCHAR(30) NOT NULL )"
+                .to_string(),
+        )
+        .await?;
+
+    let params = encode_params(|enc| {
+        enc.array(2)?.str("Vanilla")?.str("Chocolate")?;
+        Ok(())
+    });
+    let resp = ext_client
+        .execute_returning(
+            ctx,
+            &ParamQuery {
+                query: "insert into test_returning (flavor) values ($1), ($2) returning id, flavor"
+                    .into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "insert: {:?}", resp.error);
+    assert_eq!(resp.rows_affected, 2);
+    assert_eq!(resp.num_rows, 2);
+    assert_eq!(resp.columns.len(), 2);
+    assert_eq!(resp.columns[0].name, "id");
+    let rows: Vec<(i32, String)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, vec![(1, "Vanilla".into()), (2, "Chocolate".into())]);
+
+    // a statement without RETURNING returns no rows
+    let resp = ext_client
+        .execute_returning(
+            ctx,
+            &ParamQuery {
+                query: "update test_returning set flavor = 'Mint'".into(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "update: {:?}", resp.error);
+    assert_eq!(resp.rows_affected, 2);
+    assert_eq!(resp.num_rows, 0);
+    assert_eq!(resp.command_tag, "UPDATE");
+    assert_eq!(
+        ext_client
+            .execute_returning(
+                ctx,
+                &ParamQuery {
+                    query: "select 1".into(),
+                    ..Default::default()
+                },
+            )
+            .await?
+            .command_tag,
+        "SELECT"
+    );
+
+    // commands that do not affect rows: the tag says what ran
+    for (query, tag) in [
+        (
+            "create index test_returning_flavor on test_returning (flavor)",
+            "CREATE INDEX",
+        ),
+        (
+            "alter table test_returning add column size int4",
+            "ALTER TABLE",
+        ),
+        ("SET LOCAL statement_timeout = '10s'", "SET"),
+        (
+            "do $$ begin perform count(*) from test_returning; end $$",
+            "DO",
+        ),
+        ("drop index test_returning_flavor", "DROP INDEX"),
+    ] {
+        let resp = ext_client
+            .execute_returning(
+                ctx,
+                &ParamQuery {
+                    query: query.into(),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert!(resp.error.is_none(), "{}: {:?}", query, resp.error);
+        assert_eq!(resp.rows_affected, 0);
+        assert_eq!(resp.num_rows, 0);
+        assert_eq!(resp.command_tag, tag);
+    }
+
+    // the time of the statement, and of the wait for a connection, which is idle in the pool
+    let resp = ext_client
+        .execute_returning(
+            ctx,
+            &ParamQuery {
+                query: "select 1 as n from pg_sleep(0.05)".into(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "pg_sleep: {:?}", resp.error);
+    assert!(resp.duration_ms >= 50, "durationMs: {}", resp.duration_ms);
+    assert!(resp.acquire_ms < 1000, "acquireMs: {}", resp.acquire_ms);
+    Ok(())
+}
+
+/// test the single row of FetchOne and FetchOptional, and the value of FetchScalar
+async fn fetch_row_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    fetch_row_queries(&ctx, &ext_client).await?;
+    Ok(())
+}
+
+async fn fetch_row_queries(
+    ctx: &Context,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    let query = |n: i64| ParamQuery {
+        query: "select i, 'row ' || i from generate_series(1, $1::int8) as i".into(),
+        params: encode_params(|enc| {
+            enc.array(1)?.i64(n)?;
+            Ok(())
+        }),
+        ..Default::default()
+    };
+
+    // the row is encoded alone, not in an array of rows
+    let resp = ext_client.fetch_one(ctx, &query(1)).await?;
+    assert!(resp.error.is_none(), "fetch_one: {:?}", resp.error);
+    assert!(resp.found);
+    assert_eq!(resp.encoding, "cbor");
+    assert_eq!(resp.columns.len(), 2);
+    assert_eq!(resp.columns[0].name, "i");
+    let row: (i64, String) = minicbor::decode(&resp.row)?;
+    assert_eq!(row, (1, "row 1".to_string()));
+
+    let resp = ext_client.fetch_one(ctx, &query(0)).await?;
+    assert_eq!(
+        resp.error.as_ref().map(|e| e.code.as_str()),
+        Some("notFound")
+    );
+    assert!(!resp.found);
+    let resp = ext_client.fetch_one(ctx, &query(2)).await?;
+    assert_eq!(
+        resp.error.as_ref().map(|e| e.code.as_str()),
+        Some("tooManyRows")
+    );
+    assert!(resp.row.is_empty());
+
+    let resp = ext_client.fetch_optional(ctx, &query(1)).await?;
+    assert!(resp.error.is_none(), "fetch_optional: {:?}", resp.error);
+    assert!(resp.found);
+    let row: (i64, String) = minicbor::decode(&resp.row)?;
+    assert_eq!(row.0, 1);
+
+    // no row is null
+    let resp = ext_client.fetch_optional(ctx, &query(0)).await?;
+    assert!(resp.error.is_none(), "fetch_optional: {:?}", resp.error);
+    assert!(!resp.found);
+    assert!(resp.columns.is_empty());
+    assert_eq!(resp.row, vec![0xf6]);
+    let resp = ext_client.fetch_optional(ctx, &query(3)).await?;
+    assert_eq!(
+        resp.error.as_ref().map(|e| e.code.as_str()),
+        Some("tooManyRows")
+    );
+
+    // a failed query returns its error
+    let resp = ext_client
+        .fetch_optional(
+            ctx,
+            &ParamQuery {
+                query: "select * from no_such_table".into(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert_eq!(resp.error.as_ref().map(|e| e.code.as_str()), Some("db"));
+
+    // FetchScalar returns the value of the first column of the first row
+    let scalar = |query: &str| ParamQuery {
+        query: query.into(),
+        ..Default::default()
+    };
+    let resp = ext_client
+        .fetch_scalar(ctx, &scalar("select count(*) from generate_series(1, 5)"))
+        .await?;
+    assert!(resp.error.is_none(), "fetch_scalar: {:?}", resp.error);
+    assert_eq!(resp.encoding, "cbor");
+    let count: i64 = minicbor::decode(&resp.value)?;
+    assert_eq!(count, 5);
+    let resp = ext_client
+        .fetch_scalar(
+            ctx,
+            &ParamQuery {
+                query: "select 'flavor ' || $1::text, 2".into(),
+                params: encode_params(|enc| {
+                    enc.array(1)?.str("Vanilla")?;
+                    Ok(())
+                }),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "fetch_scalar: {:?}", resp.error);
+    let text: String = minicbor::decode(&resp.value)?;
+    assert_eq!(text, "flavor Vanilla");
+    // no rows is null
+    let resp = ext_client
+        .fetch_scalar(ctx, &scalar("select 1 where false"))
+        .await?;
+    assert!(resp.error.is_none(), "fetch_scalar: {:?}", resp.error);
+    assert_eq!(resp.value, vec![0xf6]);
+    // a query without columns is an error, also without rows
+    for query in ["select", "select where false"] {
+        let resp = ext_client.fetch_scalar(ctx, &scalar(query)).await?;
+        assert_eq!(
+            resp.error.as_ref().map(|e| e.code.as_str()),
+            Some("other"),
+            "{}",
+            query
+        );
+    }
+    Ok(())
+}
+
+/// test reading a query's results in batches from a cursor
+async fn cursor_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    cursor_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn cursor_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    let params = encode_params(|enc| {
+        enc.array(1)?.u32(25)?;
+        Ok(())
+    });
+    let cursor = ext_client
+        .fetch_cursor(
+            ctx,
+            &ParamQuery {
+                query: "select n from generate_series(1, $1::int4) as n".into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(cursor.error.is_none(), "fetch_cursor: {:?}", cursor.error);
+    let next = FetchNextRequest {
+        token: cursor.token.clone(),
+        batch_size: 10,
+    };
+    let mut values = Vec::new();
+    loop {
+        let resp = ext_client.fetch_next(ctx, &next).await?;
+        assert!(resp.error.is_none(), "fetch_next: {:?}", resp.error);
+        let rows: Vec<(i32,)> = minicbor::decode(&resp.rows)?;
+        assert_eq!(rows.len() as u64, resp.num_rows);
+        values.extend(rows.into_iter().map(|r| r.0));
+        if resp.done {
+            break;
+        }
+        assert_eq!(resp.num_rows, 10);
+    }
+    assert_eq!(values, (1..=25).collect::<Vec<i32>>());
+    let resp = ext_client.fetch_next(ctx, &next).await?;
+    assert_eq!(
+        resp.error.map(|e| e.code),
+        Some("notFound".to_string()),
+        "cursor is closed after the last rows"
+    );
+
+    // close a cursor before the end
+    let cursor = ext_client
+        .fetch_cursor(
+            ctx,
+            &ParamQuery {
+                query: "select n from generate_series(1, 100) as n".into(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    let next = FetchNextRequest {
+        token: cursor.token.clone(),
+        batch_size: 10,
+    };
+    let resp = ext_client.fetch_next(ctx, &next).await?;
+    assert!(!resp.done);
+    ext_client.rollback(ctx, &cursor.token).await?;
+    let resp = ext_client.fetch_next(ctx, &next).await?;
+    assert!(resp.error.is_some(), "cursor is closed by rollback");
+
+    // a transaction token is not a cursor
+    let begin = ext_client
+        .begin_transaction(ctx, &BeginTransactionRequest::default())
+        .await?;
+    let resp = ext_client
+        .fetch_next(
+            ctx,
+            &FetchNextRequest {
+                token: begin.token.clone(),
+                batch_size: 10,
+            },
+        )
+        .await?;
+    assert!(resp.error.is_some(), "not a cursor");
+    ext_client.rollback(ctx, &begin.token).await?;
+
+    // errors declaring the cursor are returned from fetch_cursor
+    let cursor = ext_client
+        .fetch_cursor(
+            ctx,
+            &ParamQuery {
+                query: "select * from no_such_table".into(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(cursor.error.is_some(), "invalid query");
+
+    // the connections are returned to the pool
+    let resp = client.fetch(ctx, &"select 1".to_string()).await?;
+    assert!(resp.error.is_none());
+    Ok(())
+}
+
+/// test reading pages of a query's results
+async fn page_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    page_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn page_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_pages".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_pages as select n::int4 from generate_series(1, 30) as n"
+                .to_string(),
+        )
+        .await?;
+    client
+        .execute(ctx, &"analyze test_pages".to_string())
+        .await?;
+    let params = encode_params(|enc| {
+        enc.array(1)?.u32(25)?;
+        Ok(())
+    });
+    let request = PageRequest {
+        query: "select n from test_pages where n <= $1 order by n;".into(),
+        params,
+        offset: 0,
+        limit: 10,
+    };
+
+    let resp = ext_client.fetch_page(ctx, &request).await?;
+    assert!(resp.error.is_none(), "fetch_page: {:?}", resp.error);
+    let rows: Vec<(i32,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, (1..=10).map(|n| (n,)).collect::<Vec<_>>());
+    assert!(resp.more);
+    assert!(resp.total_rows.is_some(), "estimated total");
+
+    let resp = ext_client
+        .fetch_page(
+            ctx,
+            &PageRequest {
+                offset: 20,
+                ..request.clone()
+            },
+        )
+        .await?;
+    let rows: Vec<(i32,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, (21..=25).map(|n| (n,)).collect::<Vec<_>>());
+    assert!(!resp.more);
+    assert_eq!(resp.total_rows, Some(25), "exact total on the last page");
+
+    // a limit in the query is applied before the page
+    let resp = ext_client
+        .fetch_page(
+            ctx,
+            &PageRequest {
+                query: "select n from test_pages order by n limit 12".into(),
+                params: Vec::new(),
+                offset: 10,
+                limit: 10,
+            },
+        )
+        .await?;
+    assert_eq!(resp.num_rows, 2);
+    assert_eq!(resp.total_rows, Some(12));
+
+    let resp = ext_client
+        .fetch_page(
+            ctx,
+            &PageRequest {
+                limit: 0,
+                ..request
+            },
+        )
+        .await;
+    assert!(resp.is_err(), "limit 0");
+
+    let resp = ext_client
+        .fetch_page(
+            ctx,
+            &PageRequest {
+                query: "select n from test_pages, pg_sleep(0.05) order by n".into(),
+                params: Vec::new(),
+                offset: 0,
+                limit: 10,
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "pg_sleep: {:?}", resp.error);
+    assert!(resp.duration_ms >= 50, "durationMs: {}", resp.duration_ms);
+    assert!(resp.acquire_ms < 1000, "acquireMs: {}", resp.acquire_ms);
+    Ok(())
+}
+
+/// test that results larger than max_result_rows or max_result_bytes are rejected
+async fn limits_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    limits_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+async fn limits_queries(ctx: &Context, client: &SqlDbSender<Provider>) -> Result<(), SqlDbError> {
+    // max_result_rows = 1000 in provider_test_config.toml
+    let resp = client
+        .fetch(
+            ctx,
+            &"select n from generate_series(1, 1000) as n".to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "at the row limit: {:?}", resp.error);
+    assert_eq!(resp.num_rows, 1000);
+
+    let resp = client
+        .fetch(
+            ctx,
+            &"select n from generate_series(1, 1001) as n".to_string(),
+        )
+        .await?;
+    let err = resp.error.expect("over the row limit");
+    assert_eq!(err.code, "limitExceeded");
+    assert_eq!(resp.num_rows, 0);
+    assert!(resp.rows.is_empty());
+
+    // max_result_bytes = 100000
+    let resp = client
+        .fetch(
+            ctx,
+            &"select repeat('x', 1000) from generate_series(1, 200)".to_string(),
+        )
+        .await?;
+    let err = resp.error.expect("over the byte limit");
+    assert_eq!(err.code, "limitExceeded");
+    assert!(err.message.contains("max_result_bytes"), "{}", err.message);
+
+    // the connection is still usable
+    let resp = client.fetch(ctx, &"select 1::int4".to_string()).await?;
+    assert!(resp.error.is_none());
+    assert_eq!(resp.num_rows, 1);
+    Ok(())
+}
+
+/// test that a statement that exceeds its timeout_ms is cancelled
+async fn timeout_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    timeout_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn timeout_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    let sleep = "select 1::int4 from pg_sleep(10)";
+    let start = std::time::Instant::now();
+    let resp = ext_client
+        .fetch_params(
+            ctx,
+            &ParamQuery {
+                query: sleep.into(),
+                timeout_ms: 200,
+                ..Default::default()
+            },
+        )
+        .await?;
+    let err = resp.error.expect("timeout");
+    assert_eq!(err.code, "timeout");
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+    // the statement was cancelled on the server
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let resp = client
+        .fetch(
+            ctx,
+            &format!(
+                "select count(*) from pg_stat_activity where state = 'active' and query = '{}'",
+                sleep
+            ),
+        )
+        .await?;
+    let rows: Vec<(i64,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, vec![(0,)]);
+
+    let resp = ext_client
+        .execute_params(
+            ctx,
+            &ParamQuery {
+                query: "select 1".into(),
+                timeout_ms: 5000,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "within the timeout: {:?}", resp.error);
+    Ok(())
+}
+
+/// test bulk loading with copy_in
+async fn copy_in_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    copy_in_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn copy_in_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(
+            ctx,
+            &"drop table if exists test_tenant.test_copy, public.test_copy".to_string(),
+        )
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table public.test_copy (id int4 primary key, flavor varchar(20) not null default 'Plain')"
+                .to_string(),
+        )
+        .await?;
+    let request = CopyInRequest {
+        table: "public.test_copy".into(),
+        columns: vec!["flavor".into(), "id".into()],
+        format: "csv".into(),
+        delimiter: Some("|".into()),
+        header: true,
+        data: b"flavor|id\nVanilla|1\n\"Rocky|Road\"|2\nMint|3\n".to_vec(),
+    };
+    let resp = ext_client.copy_in(ctx, &request).await?;
+    assert!(resp.error.is_none(), "copy_in: {:?}", resp.error);
+    assert_eq!(resp.rows_affected, 3);
+    let resp = client
+        .fetch(
+            ctx,
+            &"select id, flavor from test_copy order by id".to_string(),
+        )
+        .await?;
+    let rows: Vec<(i32, String)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(
+        rows,
+        vec![
+            (1, "Vanilla".to_string()),
+            (2, "Rocky|Road".to_string()),
+            (3, "Mint".to_string())
+        ]
+    );
+
+    // a row that fails aborts the copy: the first row is not copied either
+    let resp = ext_client
+        .copy_in(
+            ctx,
+            &CopyInRequest {
+                table: "test_copy".into(),
+                columns: vec!["id".into()],
+                format: "csv".into(),
+                data: b"4\n1\n".to_vec(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_some(), "duplicate key");
+    let resp = client
+        .fetch(ctx, &"select count(*) from test_copy".to_string())
+        .await?;
+    let rows: Vec<(i64,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, vec![(3,)]);
+
+    let resp = ext_client
+        .copy_in(
+            ctx,
+            &CopyInRequest {
+                table: "test_copy".into(),
+                format: "xml".into(),
+                ..Default::default()
+            },
+        )
+        .await;
+    assert!(resp.is_err(), "invalid format");
+    Ok(())
+}
+
+/// test bulk inserts with insert_many
+async fn insert_many_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    insert_many_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn insert_many_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_insert_many".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_insert_many (id int8 primary key, \"Name\" varchar(20), price float8)"
+                .to_string(),
+        )
+        .await?;
+    // more rows than the params of one statement allow
+    let count = 30_000u64;
+    let rows = encode_params(|enc| {
+        enc.array(count)?;
+        for id in 0..count {
+            enc.array(3)?.u64(id)?;
+            if id % 2 == 0 {
+                enc.str(&format!("item {}", id))?;
+            } else {
+                enc.null()?;
+            }
+            enc.f64(1.5)?;
+        }
+        Ok(())
+    });
+    let columns = vec!["id".to_string(), "Name".to_string(), "price".to_string()];
+    let resp = ext_client
+        .insert_many(
+            ctx,
+            &InsertManyRequest {
+                table: "test_insert_many".into(),
+                columns: columns.clone(),
+                rows,
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "insert_many: {:?}", resp.error);
+    assert_eq!(resp.rows_affected, count);
+    let resp = client
+        .fetch(
+            ctx,
+            &"select count(*), count(\"Name\"), sum(price) from test_insert_many".to_string(),
+        )
+        .await?;
+    let rows: Vec<(i64, i64, f64)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, vec![(30_000, 15_000, 45000.0)]);
+
+    // a row that is rejected aborts the insert: the first row is not inserted either
+    let rows = encode_params(|enc| {
+        enc.array(2)?;
+        enc.array(3)?.u64(count)?.str("new")?.null()?;
+        enc.array(3)?.u64(1)?.str("duplicate")?.null()?;
+        Ok(())
+    });
+    let resp = ext_client
+        .insert_many(
+            ctx,
+            &InsertManyRequest {
+                table: "test_insert_many".into(),
+                columns: columns.clone(),
+                rows,
+            },
+        )
+        .await?;
+    let error = resp.error.expect("duplicate key");
+    assert!(
+        error.message.starts_with("SQLSTATE 23505"),
+        "{}",
+        error.message
+    );
+    let resp = client
+        .fetch(
+            ctx,
+            &format!("select count(*) from test_insert_many where id = {}", count),
+        )
+        .await?;
+    let rows: Vec<(i64,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, vec![(0,)]);
+
+    // rows must have a value for each column
+    let rows = encode_params(|enc| {
+        enc.array(1)?.array(1)?.u64(1)?;
+        Ok(())
+    });
+    let resp = ext_client
+        .insert_many(
+            ctx,
+            &InsertManyRequest {
+                table: "test_insert_many".into(),
+                columns,
+                rows,
+            },
+        )
+        .await;
+    assert!(resp.is_err(), "row without a value for each column");
+    Ok(())
+}
+
+/// test inserts that update conflicting rows with upsert
+async fn upsert_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    upsert_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn upsert_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_upsert".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_upsert (id int8 primary key, flavor varchar(20), scoops int4 not null default 1)"
+                .to_string(),
+        )
+        .await?;
+    let rows = |values: &[(u64, &str)]| {
+        encode_params(|enc| {
+            enc.array(values.len() as u64)?;
+            for (id, flavor) in values {
+                enc.array(2)?.u64(*id)?.str(flavor)?;
+            }
+            Ok(())
+        })
+    };
+    let upsert = |rows: Vec<u8>, update_columns: Vec<String>| UpsertRequest {
+        table: "test_upsert".into(),
+        columns: vec!["id".into(), "flavor".into()],
+        conflict_columns: vec!["id".into()],
+        update_columns,
+        rows,
+    };
+    let resp = ext_client
+        .upsert(
+            ctx,
+            &upsert(rows(&[(1, "Vanilla"), (2, "Mint")]), vec!["flavor".into()]),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "upsert: {:?}", resp.error);
+    assert_eq!((resp.inserted, resp.updated), (2, 0));
+
+    // the row that conflicts is updated
+    let resp = ext_client
+        .upsert(
+            ctx,
+            &upsert(
+                rows(&[(2, "Rocky Road"), (3, "Lemon")]),
+                vec!["flavor".into()],
+            ),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "upsert: {:?}", resp.error);
+    assert_eq!((resp.inserted, resp.updated), (1, 1));
+    let resp = client
+        .fetch(
+            ctx,
+            &"select id, flavor from test_upsert order by id".to_string(),
+        )
+        .await?;
+    let fetched: Vec<(i64, String)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(
+        fetched,
+        vec![
+            (1, "Vanilla".to_string()),
+            (2, "Rocky Road".to_string()),
+            (3, "Lemon".to_string())
+        ]
+    );
+
+    // without update columns, the rows that conflict are skipped
+    let resp = ext_client
+        .upsert(ctx, &upsert(rows(&[(3, "Lime"), (4, "Mango")]), vec![]))
+        .await?;
+    assert!(resp.error.is_none(), "upsert: {:?}", resp.error);
+    assert_eq!((resp.inserted, resp.updated), (1, 0));
+    let resp = client
+        .fetch(
+            ctx,
+            &"select flavor from test_upsert where id = 3".to_string(),
+        )
+        .await?;
+    let fetched: Vec<(String,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(fetched, vec![("Lemon".to_string(),)]);
+
+    // the conflict columns must have a unique index
+    let resp = ext_client
+        .upsert(
+            ctx,
+            &UpsertRequest {
+                conflict_columns: vec!["flavor".into()],
+                ..upsert(rows(&[(5, "Mango")]), vec!["flavor".into()])
+            },
+        )
+        .await?;
+    let error = resp.error.expect("no unique index on flavor");
+    assert!(
+        error.message.starts_with("SQLSTATE 42P10"),
+        "{}",
+        error.message
+    );
+
+    // update columns must be columns of the rows
+    let resp = ext_client
+        .upsert(ctx, &upsert(rows(&[(5, "Mango")]), vec!["scoops".into()]))
+        .await;
+    assert!(
+        resp.is_err(),
+        "update column that is not a column of the rows"
+    );
+    Ok(())
+}
+
+/// test exporting query results with copy_out
+async fn copy_out_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    copy_out_queries(&ctx, &ext_client).await?;
+    Ok(())
+}
+
+async fn copy_out_queries(
+    ctx: &Context,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    let request = CopyOutRequest {
+        query: "select n, 'row ' || n as name from generate_series(1, 20) as n".into(),
+        format: "csv".into(),
+        header: true,
+        chunk_size: 16,
+        ..Default::default()
+    };
+    let mut resp = ext_client.copy_out(ctx, &request).await?;
+    let mut data = Vec::new();
+    let mut chunks = 0;
+    loop {
+        assert!(resp.error.is_none(), "copy_out: {:?}", resp.error);
+        assert!(resp.data.len() <= 16);
+        data.extend_from_slice(&resp.data);
+        chunks += 1;
+        if resp.done {
+            break;
+        }
+        assert_eq!(resp.data.len(), 16);
+        resp = ext_client.copy_out_next(ctx, &resp.token.clone()).await?;
+    }
+    let mut expected = "n,name\n".to_string();
+    for n in 1..=20 {
+        expected.push_str(&format!("{},row {}\n", n, n));
+    }
+    assert_eq!(String::from_utf8(data).unwrap(), expected);
+    assert_eq!(chunks, (expected.len() + 15) / 16);
+
+    // the export can be closed before the end
+    let resp = ext_client
+        .copy_out(
+            ctx,
+            &CopyOutRequest {
+                query: "select n from generate_series(1, 100000) as n".into(),
+                format: "text".into(),
+                chunk_size: 100,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(!resp.done);
+    let token = resp.token;
+    let closed = ext_client.rollback(ctx, &token).await?;
+    assert!(closed.error.is_none(), "rollback: {:?}", closed.error);
+    let resp = ext_client.copy_out_next(ctx, &token).await?;
+    assert_eq!(resp.error.map(|e| e.code), Some("notFound".to_string()));
+    Ok(())
+}
+
+/// test writing and reading a large object in parts
+async fn large_object_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    large_object_queries(&ctx, &ext_client).await?;
+    Ok(())
+}
+
+async fn large_object_queries(
+    ctx: &Context,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    let resp = ext_client.lo_create(ctx).await?;
+    assert!(resp.error.is_none(), "lo_create: {:?}", resp.error);
+    let oid = resp.oid;
+    assert_ne!(oid, 0);
+
+    // larger than a chunk, and written in two parts
+    let data = (0..600_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    for (offset, part) in [(0, &data[..400_000]), (400_000, &data[400_000..])] {
+        let resp = ext_client
+            .lo_write(
+                ctx,
+                &LoWriteRequest {
+                    oid,
+                    offset,
+                    data: part.to_vec(),
+                },
+            )
+            .await?;
+        assert!(resp.error.is_none(), "lo_write: {:?}", resp.error);
+    }
+
+    // read in parts of max_result_bytes
+    let mut read = Vec::new();
+    loop {
+        let resp = ext_client
+            .lo_read(
+                ctx,
+                &LoReadRequest {
+                    oid,
+                    offset: read.len() as u64,
+                    len: 100_000,
+                },
+            )
+            .await?;
+        assert!(resp.error.is_none(), "lo_read: {:?}", resp.error);
+        read.extend_from_slice(&resp.data);
+        if resp.eof {
+            break;
+        }
+    }
+    assert_eq!(read.len(), data.len());
+    assert!(read == data, "large object data differs");
+
+    let resp = ext_client
+        .lo_read(
+            ctx,
+            &LoReadRequest {
+                oid,
+                offset: 599_990,
+                len: 100,
+            },
+        )
+        .await?;
+    assert_eq!(resp.data, &data[599_990..]);
+    assert!(resp.eof);
+
+    // larger than max_result_bytes
+    let resp = ext_client
+        .lo_read(
+            ctx,
+            &LoReadRequest {
+                oid,
+                offset: 0,
+                len: 200_000,
+            },
+        )
+        .await?;
+    let err = resp.error.expect("limit error");
+    assert_eq!(err.code, "limitExceeded");
+
+    let resp = ext_client.lo_unlink(ctx, &LoUnlinkRequest { oid }).await?;
+    assert!(resp.error.is_none(), "lo_unlink: {:?}", resp.error);
+    let resp = ext_client
+        .lo_read(
+            ctx,
+            &LoReadRequest {
+                oid,
+                offset: 0,
+                len: 10,
+            },
+        )
+        .await?;
+    let err = resp.error.expect("unlinked");
+    assert!(err.message.contains("does not exist"), "{}", err.message);
+    Ok(())
+}
+
+/// test that the rows of fetch_stream are returned in chunks of whole rows,
+/// which together are an indefinite-length cbor array
+async fn stream_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    stream_queries(&ctx, &ext_client).await?;
+    Ok(())
+}
+
+async fn stream_queries(
+    ctx: &Context,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    let request = FetchStreamRequest {
+        query: "select n, 'row ' || n as name from generate_series(1, $1::int) as n".into(),
+        params: encode_params(|e| {
+            e.array(1)?.i32(200)?;
+            Ok(())
+        }),
+        chunk_size: 100,
+    };
+    let mut resp = ext_client.fetch_stream(ctx, &request).await?;
+    assert_eq!(resp.encoding, "cbor");
+    assert_eq!(resp.columns.len(), 2);
+    assert_eq!(resp.columns[1].name, "name");
+    let mut data = Vec::new();
+    let mut chunks = 0;
+    loop {
+        assert!(resp.error.is_none(), "fetch_stream: {:?}", resp.error);
+        assert!(resp.data.len() <= 100);
+        // each chunk has whole rows, after the array header in the first chunk
+        let mut d = minicbor::Decoder::new(&resp.data);
+        if chunks == 0 {
+            assert_eq!(d.array()?, None);
+        }
+        while d.position() < resp.data.len() && resp.data[d.position()] != 0xff {
+            let _: (i32, String) = d.decode()?;
+        }
+        data.extend_from_slice(&resp.data);
+        chunks += 1;
+        if resp.done {
+            break;
+        }
+        resp = ext_client
+            .fetch_stream_next(ctx, &resp.token.clone())
+            .await?;
+        assert!(resp.columns.is_empty());
+    }
+    assert!(chunks > 1);
+    assert_eq!(data.last(), Some(&0xff));
+    let rows: Vec<(i32, String)> = minicbor::decode(&data)?;
+    assert_eq!(rows.len(), 200);
+    assert_eq!(rows[199], (200, "row 200".to_string()));
+
+    // a row larger than the chunk size is a chunk by itself
+    let resp = ext_client
+        .fetch_stream(
+            ctx,
+            &FetchStreamRequest {
+                query: "select repeat('x', 100)".into(),
+                chunk_size: 10,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "fetch_stream: {:?}", resp.error);
+    assert!(resp.data.len() > 100);
+    let last = ext_client.fetch_stream_next(ctx, &resp.token).await?;
+    assert!(last.done);
+    assert_eq!(last.data, vec![0xff]);
+
+    // the stream can be closed before the end
+    let resp = ext_client
+        .fetch_stream(
+            ctx,
+            &FetchStreamRequest {
+                query: "select n from generate_series(1, 100000) as n".into(),
+                chunk_size: 100,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(!resp.done);
+    let token = resp.token;
+    let closed = ext_client.rollback(ctx, &token).await?;
+    assert!(closed.error.is_none(), "rollback: {:?}", closed.error);
+    let resp = ext_client.fetch_stream_next(ctx, &token).await?;
+    assert_eq!(resp.error.map(|e| e.code), Some("notFound".to_string()));
+    Ok(())
+}
+
+/// test that pool_stats counts the connections taken from the pool
+async fn pool_stats_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    let resp = client.fetch(&ctx, &"select 1".to_string()).await?;
+    assert!(resp.error.is_none(), "fetch: {:?}", resp.error);
+
+    let stats = ext_client.pool_stats(&ctx).await?;
+    assert!(stats.acquired >= 1);
+    assert!(stats.size >= 1 && stats.size <= stats.max_size);
+    assert_eq!(stats.in_use, stats.size - stats.idle);
+    assert_eq!(stats.wait_buckets.len(), 10);
+    // bucket counts are cumulative
+    assert!(stats
+        .wait_buckets
+        .windows(2)
+        .all(|w| w[0].le_ms < w[1].le_ms && w[0].count <= w[1].count));
+    Ok(())
+}
+
+/// test the version of the server, which is read once for the link
+async fn server_info_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    let info = ext_client.server_info(&ctx).await?;
+    assert!(info.error.is_none(), "server_info: {:?}", info.error);
+    assert!(info.major_version >= 10, "{:?}", info);
+    assert_eq!(info.major_version, info.version_num / 10000);
+    assert!(info.version.starts_with(&info.major_version.to_string()));
+    // installed in every database
+    assert!(info.extensions.iter().any(|e| e.name == "plpgsql"));
+    assert_eq!(ext_client.server_info(&ctx).await?, info);
+    Ok(())
+}
+
+/// test advisory locks held across calls
+async fn advisory_lock_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    let lock = AdvisoryLockRequest {
+        key: 4242,
+        timeout_ms: 200,
+    };
+
+    let resp = ext_client.try_advisory_lock(&ctx, &lock).await?;
+    assert!(resp.locked, "try_advisory_lock: {:?}", resp.error);
+    // the lock is held on another connection, so it is not available to another instance
+    let resp = ext_client.try_advisory_lock(&ctx, &lock).await?;
+    assert!(!resp.locked && resp.error.is_none());
+    let resp = ext_client.advisory_lock(&ctx, &lock).await?;
+    assert!(!resp.locked);
+    assert_eq!(resp.error.expect("timeout").code, "timeout");
+
+    let resp = ext_client.advisory_unlock(&ctx, &lock).await?;
+    assert!(!resp.locked && resp.error.is_none(), "{:?}", resp.error);
+    let resp = ext_client.advisory_unlock(&ctx, &lock).await?;
+    assert_eq!(resp.error.expect("not held").code, "notFound");
+
+    let resp = ext_client.advisory_lock(&ctx, &lock).await?;
+    assert!(resp.locked, "advisory_lock: {:?}", resp.error);
+    let resp = ext_client.advisory_unlock(&ctx, &lock).await?;
+    assert!(resp.error.is_none(), "{:?}", resp.error);
+    Ok(())
+}
+
+/// test sending notifications, on a pooled connection and in a transaction
+async fn notify_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    let notify = NotifyRequest {
+        channel: "Test \"events\"".into(),
+        payload: "'); drop table test_flavors; --".into(),
+        token: None,
+    };
+    let resp = ext_client.notify(&ctx, &notify).await?;
+    assert!(resp.queued, "notify: {:?}", resp.error);
+
+    let begin = ext_client
+        .begin_transaction(&ctx, &BeginTransactionRequest::default())
+        .await?;
+    assert!(begin.error.is_none(), "begin: {:?}", begin.error);
+    let resp = ext_client
+        .notify(
+            &ctx,
+            &NotifyRequest {
+                token: Some(begin.token.clone()),
+                ..notify.clone()
+            },
+        )
+        .await?;
+    assert!(resp.queued, "notify in transaction: {:?}", resp.error);
+    ext_client.rollback(&ctx, &begin.token).await?;
+
+    let resp = ext_client
+        .notify(
+            &ctx,
+            &NotifyRequest {
+                token: Some(begin.token),
+                ..notify.clone()
+            },
+        )
+        .await?;
+    assert_eq!(resp.error.expect("ended transaction").code, "notFound");
+
+    for channel in ["", &"c".repeat(64)] {
+        let req = NotifyRequest {
+            channel: channel.to_string(),
+            ..notify.clone()
+        };
+        assert!(ext_client.notify(&ctx, &req).await.is_err());
+    }
+    Ok(())
+}
+
+/// test listing the tables, columns, and indexes of the search path
+async fn catalog_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    for statement in [
+        "drop table if exists test_catalog",
+        r#"create table test_catalog (id serial primary key,
+            name varchar(20) not null default 'none', "Mixed Case" int)"#,
+        r#"create unique index test_catalog_name on test_catalog (name, "Mixed Case")"#,
+    ] {
+        let resp = client.execute(&ctx, &statement.to_string()).await?;
+        assert!(resp.error.is_none(), "{}: {:?}", statement, resp.error);
+    }
+
+    let resp = ext_client
+        .list_tables(&ctx, &ListTablesRequest::default())
+        .await?;
+    assert!(resp.error.is_none(), "list_tables: {:?}", resp.error);
+    // created in the first schema of the search path
+    let table = resp
+        .tables
+        .iter()
+        .find(|t| t.name == "test_catalog")
+        .expect("test_catalog");
+    assert_eq!(
+        (table.schema.as_str(), table.kind.as_str()),
+        ("test_tenant", "table")
+    );
+    let resp = ext_client
+        .list_tables(
+            &ctx,
+            &ListTablesRequest {
+                schema: Some("public".into()),
+            },
+        )
+        .await?;
+    assert!(resp.tables.iter().all(|t| t.schema == "public"));
+
+    let table = TableRequest {
+        table: "test_catalog".into(),
+        ..Default::default()
+    };
+    let resp = ext_client.list_columns(&ctx, &table).await?;
+    assert!(resp.error.is_none(), "list_columns: {:?}", resp.error);
+    let columns = resp
+        .columns
+        .iter()
+        .map(|c| (c.name.as_str(), c.data_type.as_str(), c.nullable, c.ordinal))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        columns,
+        vec![
+            ("id", "integer", false, 1),
+            ("name", "character varying(20)", false, 2),
+            ("Mixed Case", "integer", true, 3)
+        ]
+    );
+    assert_eq!(
+        resp.columns[1].default.as_deref(),
+        Some("'none'::character varying")
+    );
+
+    let resp = ext_client.list_indexes(&ctx, &table).await?;
+    assert!(resp.error.is_none(), "list_indexes: {:?}", resp.error);
+    assert_eq!(resp.indexes.len(), 2);
+    let index = &resp.indexes[1];
+    assert_eq!(index.name, "test_catalog_pkey");
+    assert!(index.primary && index.unique);
+    assert_eq!(index.columns, vec!["id"]);
+    let index = &resp.indexes[0];
+    assert_eq!(index.columns, vec!["name", "Mixed Case"]);
+    assert!(index.unique && !index.primary);
+    assert!(index
+        .definition
+        .starts_with("CREATE UNIQUE INDEX test_catalog_name"));
+
+    let resp = ext_client
+        .list_columns(
+            &ctx,
+            &TableRequest {
+                schema: Some("public".into()),
+                table: "test_catalog".into(),
+            },
+        )
+        .await?;
+    assert_eq!(resp.error.expect("not found").code, "notFound");
+    Ok(())
+}
+
+/// test cancelling the statements running for the actor. The host bridge dispatches
+/// one call at a time, so the connections in use are those of open transactions
+async fn cancel_queries_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    let resp = ext_client.cancel_queries(&ctx).await?;
+    assert!(resp.pids.is_empty(), "{:?}", resp.pids);
+
+    let begin = ext_client
+        .begin_transaction(&ctx, &BeginTransactionRequest::default())
+        .await?;
+    assert!(begin.error.is_none(), "begin: {:?}", begin.error);
+    let resp = ext_client
+        .fetch_in(
+            &ctx,
+            &SessionQuery {
+                token: begin.token.clone(),
+                query: "select pg_backend_pid()".into(),
+            },
+        )
+        .await?;
+    let rows: Vec<(i32,)> = minicbor::decode(&resp.rows)?;
+
+    // the pid of the transaction's connection, which has no running statement to cancel.
+    // The server closes each cancel request's connection, which must not drop the pid
+    for _ in 0..5 {
+        let resp = ext_client.cancel_queries(&ctx).await?;
+        assert_eq!(resp.pids, vec![rows[0].0]);
+    }
+    let resp = ext_client.rollback(&ctx, &begin.token).await?;
+    assert!(resp.error.is_none(), "rollback: {:?}", resp.error);
+    Ok(())
+}
+
+/// test that cached results are served until their ttl expires, or they are invalidated
+async fn fetch_cached_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    client
+        .execute(&ctx, &"drop table if exists test_fetch_cached".to_string())
+        .await?;
+    client
+        .execute(
+            &ctx,
+            &"create table test_fetch_cached ( n INT4 )".to_string(),
+        )
+        .await?;
+    let insert = "insert into test_fetch_cached (n) values (1)".to_string();
+    client.execute(&ctx, &insert).await?;
+
+    let query = "select count(*) from test_fetch_cached".to_string();
+    let fetch = |ttl_ms| {
+        let arg = CachedQuery {
+            query: query.clone(),
+            ttl_ms,
+        };
+        let ext_client = &ext_client;
+        let ctx = &ctx;
+        async move {
+            let resp = ext_client.fetch_cached(ctx, &arg).await?;
+            assert!(resp.error.is_none(), "fetch_cached: {:?}", resp.error);
+            let rows: Vec<(i64,)> = minicbor::decode(&resp.rows)?;
+            RpcResult::Ok(rows[0].0)
+        }
+    };
+    assert_eq!(fetch(60_000).await?, 1);
+    // Execute does not invalidate the cached result
+    client.execute(&ctx, &insert).await?;
+    assert_eq!(fetch(60_000).await?, 1);
+
+    let resp = ext_client
+        .invalidate_cache(
+            &ctx,
+            &InvalidateCacheRequest {
+                query: Some(query.clone()),
+            },
+        )
+        .await?;
+    assert_eq!(resp.invalidated, 1);
+    assert_eq!(fetch(200).await?, 2);
+
+    // the query is run again after the ttl expires
+    client.execute(&ctx, &insert).await?;
+    assert_eq!(fetch(200).await?, 2);
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert_eq!(fetch(60_000).await?, 3);
+
+    let resp = ext_client
+        .invalidate_cache(&ctx, &InvalidateCacheRequest::default())
+        .await?;
+    assert_eq!(resp.invalidated, 1);
+    Ok(())
+}
+
+/// test that the notices of a statement are returned with its result
+async fn notices_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    let resp = ext_client
+        .execute_returning(
+            &ctx,
+            &ParamQuery {
+                query: "do $$ begin raise notice 'hello %', 1; raise warning 'careful'; end $$"
+                    .into(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "execute_returning: {:?}", resp.error);
+    assert_eq!(resp.notices, vec!["NOTICE: hello 1", "WARNING: careful"]);
+
+    // notices are returned with the error, and are not returned by the next statement
+    let resp = ext_client
+        .execute_returning(
+            &ctx,
+            &ParamQuery {
+                query: "do $$ begin raise notice 'before'; raise exception 'boom'; end $$".into(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_some());
+    assert_eq!(resp.notices, vec!["NOTICE: before"]);
+    let resp = ext_client
+        .fetch_page(
+            &ctx,
+            &PageRequest {
+                query: "select 1".into(),
+                limit: 1,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "fetch_page: {:?}", resp.error);
+    assert!(resp.notices.is_empty(), "{:?}", resp.notices);
+    Ok(())
+}
+
+/// test calls of a procedure with INOUT, OUT, and refcursor parameters, and of a
+/// set-returning function
+async fn call_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    let resp = client
+        .execute(
+            &ctx,
+            &r#"create or replace procedure test_call_proc(
+                    inout total int4, step int4, out label text, inout items refcursor)
+                language plpgsql as $$
+                begin
+                    total := total + step;
+                    label := 'total ' || total;
+                    open items for select g as n from generate_series(1, total) g;
+                end $$"#
+                .to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "create procedure: {:?}", resp.error);
+
+    let resp = ext_client
+        .call(
+            &ctx,
+            &CallRequest {
+                procedure: "test_call_proc".into(),
+                params: encode_params(|e| {
+                    e.array(4)?.i32(1)?.i32(2)?.null()?.str("items_cursor")?;
+                    Ok(())
+                }),
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "call: {:?}", resp.error);
+    assert_eq!(resp.encoding, "cbor");
+    // the outputs are a map, in the order of the parameters
+    let mut dec = minicbor::Decoder::new(&resp.outputs);
+    assert_eq!(dec.map()?, Some(3));
+    assert_eq!((dec.str()?, dec.i32()?), ("total", 3));
+    assert_eq!((dec.str()?, dec.str()?), ("label", "total 3"));
+    assert_eq!((dec.str()?, dec.str()?), ("items", "items_cursor"));
+    assert_eq!(resp.result_sets.len(), 1);
+    let set = &resp.result_sets[0];
+    assert_eq!((set.name.as_str(), set.num_rows), ("items", 3));
+    let rows: Vec<(i32,)> = minicbor::decode(&set.rows)?;
+    assert_eq!(rows, vec![(1,), (2,), (3,)]);
+
+    // an invalid argument fails the call
+    let resp = ext_client
+        .call(
+            &ctx,
+            &CallRequest {
+                procedure: "test_call_proc".into(),
+                params: encode_params(|e| {
+                    e.array(1)?.i32(1)?;
+                    Ok(())
+                }),
+            },
+        )
+        .await?;
+    assert!(resp.error.is_some());
+
+    // the rows of a set-returning function are encoded as those of a table
+    let resp = client
+        .fetch(
+            &ctx,
+            &"select * from generate_series(1, 2) as g(n),               lateral (select pg_sleep(0) as slept, 'c'::refcursor as cur) x"
+                .to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "fetch: {:?}", resp.error);
+    let rows: Vec<(i32, Option<i32>, String)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, vec![(1, None, "c".into()), (2, None, "c".into())]);
+    Ok(())
+}
+
+/// test that statements are recorded in the audit table (audit_log is 'table' in the test config)
+/// the records of the audit log after `after` of the statements, or of Notify, once there
+/// are `count` of them. Records are written after the statements return, in order
+async fn audit_records(
+    ctx: &Context,
+    ext_client: &SqlDbExtSender<Provider>,
+    statements: [&str; 2],
+    after: i64,
+    count: usize,
+) -> RpcResult<Vec<(i64, String, String, String, Option<i64>)>> {
+    let mut rows = Vec::new();
+    for _ in 0..20 {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let resp = ext_client
+            .fetch_params(
+                ctx,
+                &ParamQuery {
+                    query: "select id, statement, operation, outcome, rows from sqldb_audit_log \
+                            where id > $1 and (statement = $2 or statement = $3 \
+                            or operation = 'SqlDbExt.Notify') order by id"
+                        .into(),
+                    params: encode_params(|e| {
+                        e.array(3)?
+                            .i64(after)?
+                            .str(statements[0])?
+                            .str(statements[1])?;
+                        Ok(())
+                    }),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert!(resp.error.is_none(), "audit: {:?}", resp.error);
+        rows = minicbor::decode(&resp.rows)?;
+        if rows.len() == count {
+            break;
+        }
+    }
+    Ok(rows)
+}
+
+async fn audit_log_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+
+    let statement = format!("select 'audit {}'", std::process::id());
+    let resp = client.execute(&ctx, &statement).await?;
+    assert!(resp.error.is_none(), "execute: {:?}", resp.error);
+    let rows = audit_records(&ctx, &ext_client, [&statement, &statement], 0, 1).await?;
+    let first = rows.last().map(|row| row.0).unwrap_or_default();
+
+    let resp = ext_client
+        .notify(
+            &ctx,
+            &NotifyRequest {
+                channel: "audit_events".into(),
+                payload: "audited".into(),
+                token: None,
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "notify: {:?}", resp.error);
+    let failed = format!("select * from audit_missing_{}", std::process::id());
+    let resp = client.fetch(&ctx, &failed).await?;
+    assert!(resp.error.is_some());
+
+    let rows = audit_records(&ctx, &ext_client, [&statement, &failed], first - 1, 3).await?;
+    assert_eq!(
+        rows.into_iter()
+            .map(|(_, statement, operation, outcome, rows)| (statement, operation, outcome, rows))
+            .collect::<Vec<_>>(),
+        vec![
+            (statement, "SqlDb.Execute".into(), "ok".into(), Some(1)),
+            (
+                "SELECT pg_notify($1, $2)".into(),
+                "SqlDbExt.Notify".into(),
+                "ok".into(),
+                None
+            ),
+            (failed, "SqlDb.Fetch".into(), "db".into(), None),
+        ]
+    );
+    Ok(())
+}
+
+/// test that json and jsonb documents are returned as structured cbor
+async fn json_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    json_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn json_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_json".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_json ( id SERIAL PRIMARY KEY, doc JSONB )".to_string(),
+        )
+        .await?;
+
+    let doc = r#"{"name": "Vanilla", "price": 1.5, "tags": ["cold", "sweet"], "stock": -12, "ok": true, "n": null}"#;
+    let params = encode_params(|enc| {
+        enc.array(2)?.str(doc)?.str("null")?;
+        Ok(())
+    });
+    let resp = ext_client
+        .execute_params(
+            ctx,
+            &ParamQuery {
+                query: "insert into test_json (doc) values ($1::text::jsonb), ($2::text::jsonb), (NULL)"
+                    .into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "insert: {:?}", resp.error);
+    assert_eq!(resp.rows_affected, 3);
+
+    let resp = ext_client
+        .fetch_params(
+            ctx,
+            &ParamQuery {
+                query: "select doc from test_json order by id".into(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "select: {:?}", resp.error);
+    assert_eq!(resp.num_rows, 3);
+    assert_eq!(resp.columns[0].db_type, "jsonb");
+    let expected = encode_params(|enc| {
+        enc.array(3)?;
+        // object keys are in sorted order
+        enc.array(1)?.map(6)?;
+        enc.str("n")?.null()?;
+        enc.str("name")?.str("Vanilla")?;
+        enc.str("ok")?.bool(true)?;
+        enc.str("price")?.f64(1.5)?;
+        enc.str("stock")?.i64(-12)?;
+        enc.str("tags")?.array(2)?.str("cold")?.str("sweet")?;
+        // json null, and sql NULL
+        enc.array(1)?.null()?;
+        enc.array(1)?.null()?;
+        Ok(())
+    });
+    assert_eq!(resp.rows, expected);
+
+    // json (not jsonb) is decoded the same way
+    let resp = client
+        .fetch(
+            ctx,
+            &r#"select '[1, "two", {"three": 3}]'::json"#.to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "select json: {:?}", resp.error);
+    let expected = encode_params(|enc| {
+        enc.array(1)?.array(1)?.array(3)?;
+        enc.u64(1)?.str("two")?.map(1)?.str("three")?.u64(3)?;
+        Ok(())
+    });
+    assert_eq!(resp.rows, expected);
+    Ok(())
+}
+
+/// test that uuid values are returned as hyphenated strings
+async fn uuid_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    uuid_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+async fn uuid_queries(ctx: &Context, client: &SqlDbSender<Provider>) -> Result<(), SqlDbError> {
+    let resp = client
+        .fetch(
+            ctx,
+            &"select gen_random_uuid(), gen_random_uuid()::text, 'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11'::uuid"
+                .to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "select: {:?}", resp.error);
+    assert_eq!(resp.columns[0].db_type, "uuid");
+    let rows: Vec<(String, String, String)> = minicbor::decode(&resp.rows)?;
+    let (random, text, fixed) = &rows[0];
+    // the same form as postgres's text output
+    assert_eq!(random.len(), 36);
+    assert_eq!(random.matches('-').count(), 4);
+    assert_eq!(random.to_lowercase(), *random);
+    assert_eq!(text.len(), 36);
+    assert_eq!(fixed, "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11");
+    Ok(())
+}
+
+/// test that numeric values are returned as strings without rounding
+async fn numeric_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    numeric_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+async fn numeric_queries(ctx: &Context, client: &SqlDbSender<Provider>) -> Result<(), SqlDbError> {
+    for value in [
+        "123456789012345678901234567890.123456789",
+        "-98765432109876543210",
+        "0.000000000000000000000000000001",
+        "3.14159265358979323846264338327950288419716939937510",
+        "100000000.0000",
+        "-1.50",
+        "0",
+        "NaN",
+        "Infinity",
+        "-Infinity",
+    ] {
+        let resp = client
+            .fetch(
+                ctx,
+                &format!("select '{0}'::numeric, '{0}'::numeric::text", value),
+            )
+            .await?;
+        assert!(resp.error.is_none(), "{}: {:?}", value, resp.error);
+        assert_eq!(resp.columns[0].db_type, "numeric");
+        let rows: Vec<(String, String)> = minicbor::decode(&resp.rows)?;
+        assert_eq!(rows[0].0, value);
+        // the same as postgres's text output
+        assert_eq!(rows[0].0, rows[0].1);
+    }
+
+    // scale of a column
+    let resp = client
+        .fetch(ctx, &"select 12.5::numeric(10, 4)".to_string())
+        .await?;
+    let rows: Vec<(String,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows[0].0, "12.5000");
+    Ok(())
+}
+
+/// test that timestamps are returned as rfc3339 strings, with timestamptz in UTC
+async fn timestamp_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    timestamp_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+async fn timestamp_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+) -> Result<(), SqlDbError> {
+    for (value, expected) in [
+        // timestamp is not converted
+        ("'2021-03-04 05:06:07'::timestamp", "2021-03-04T05:06:07"),
+        (
+            "'1969-07-20 20:17:40.5'::timestamp",
+            "1969-07-20T20:17:40.500",
+        ),
+        (
+            "'1850-01-01 00:00:00.000001'::timestamp",
+            "1850-01-01T00:00:00.000001",
+        ),
+        ("'infinity'::timestamp", "infinity"),
+        // timestamptz is in UTC
+        (
+            "'2021-03-04 05:06:07.25+02:00'::timestamptz",
+            "2021-03-04T03:06:07.250Z",
+        ),
+        (
+            "'1900-02-28 23:59:59.123456-05:00'::timestamptz",
+            "1900-03-01T04:59:59.123456Z",
+        ),
+        ("'-infinity'::timestamptz", "-infinity"),
+    ] {
+        let resp = client.fetch(ctx, &format!("select {}", value)).await?;
+        assert!(resp.error.is_none(), "{}: {:?}", value, resp.error);
+        let rows: Vec<(String,)> = minicbor::decode(&resp.rows)?;
+        assert_eq!(rows[0].0, expected, "{}", value);
+    }
+    Ok(())
+}
+
+/// test the encoding of date, time, and interval values
+async fn date_time_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    date_time_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+async fn date_time_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+) -> Result<(), SqlDbError> {
+    for (value, expected) in [
+        ("'2021-03-04'::date", "2021-03-04"),
+        ("'1850-12-31'::date", "1850-12-31"),
+        ("'infinity'::date", "infinity"),
+        ("'05:06:07'::time", "05:06:07.000000"),
+        ("'23:59:59.999999'::time", "23:59:59.999999"),
+        ("'00:00:00.5'::time", "00:00:00.500000"),
+    ] {
+        let resp = client.fetch(ctx, &format!("select {}", value)).await?;
+        assert!(resp.error.is_none(), "{}: {:?}", value, resp.error);
+        let rows: Vec<(String,)> = minicbor::decode(&resp.rows)?;
+        assert_eq!(rows[0].0, expected, "{}", value);
+    }
+
+    for (value, expected) in [
+        (
+            "'1 year 2 months 3 days 04:05:06.000007'::interval",
+            (14, 3, 14_706_000_007),
+        ),
+        (
+            "'-1 month -2 days -00:00:01'::interval",
+            (-1, -2, -1_000_000),
+        ),
+        ("'1 day -1 hour'::interval", (0, 1, -3_600_000_000)),
+        ("'0'::interval", (0, 0, 0)),
+    ] {
+        let resp = client.fetch(ctx, &format!("select {}", value)).await?;
+        assert!(resp.error.is_none(), "{}: {:?}", value, resp.error);
+        let mut dec = minicbor::Decoder::new(&resp.rows);
+        assert_eq!(dec.array()?, Some(1));
+        assert_eq!(dec.array()?, Some(1));
+        assert_eq!(dec.map()?, Some(3));
+        let mut fields = Vec::new();
+        for _ in 0..3 {
+            fields.push((dec.str()?.to_string(), dec.i64()?));
+        }
+        assert_eq!(
+            fields,
+            vec![
+                ("months".to_string(), expected.0 as i64),
+                ("days".to_string(), expected.1 as i64),
+                ("micros".to_string(), expected.2),
+            ],
+            "{}",
+            value
+        );
+    }
+
+    // a date after the years chrono supports cannot be encoded; with partial_on_error,
+    // the rows before it are returned, with a partialResult error
+    let resp = client
+        .fetch(
+            ctx,
+            &"select d from (values ('2021-03-04'::date), ('5874897-12-31'::date), \
+              ('2021-03-05'::date)) v(d)"
+                .to_string(),
+        )
+        .await?;
+    let err = resp.error.expect("partial result");
+    assert_eq!(err.code, "partialResult");
+    assert!(
+        err.message.starts_with("row 2 could not be encoded")
+            && err.message.contains("column 'd' (date)"),
+        "{}",
+        err.message
+    );
+    assert_eq!(resp.num_rows, 1);
+    assert_eq!(resp.columns.len(), 1);
+    let rows: Vec<(String,)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(rows, vec![("2021-03-04".to_string(),)]);
+    Ok(())
+}
+
+/// test that bytea values are returned as byte strings, with NULL distinct from empty
+async fn bytea_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    bytea_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn bytea_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_bytea".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_bytea ( id SERIAL PRIMARY KEY, data BYTEA )".to_string(),
+        )
+        .await?;
+    let blob = (0..=255u8).collect::<Vec<u8>>();
+    let params = encode_params(|enc| {
+        enc.array(2)?.bytes(&blob)?.bytes(&[])?;
+        Ok(())
+    });
+    let resp = ext_client
+        .execute_params(
+            ctx,
+            &ParamQuery {
+                query: "insert into test_bytea (data) values ($1), ($2), (NULL)".into(),
+                params,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "insert: {:?}", resp.error);
+    assert_eq!(resp.rows_affected, 3);
+
+    let resp = client
+        .fetch(ctx, &"select data from test_bytea order by id".to_string())
+        .await?;
+    assert!(resp.error.is_none(), "select: {:?}", resp.error);
+    assert_eq!(resp.num_rows, 3);
+    let expected = encode_params(|enc| {
+        enc.array(3)?;
+        enc.array(1)?.bytes(&blob)?;
+        enc.array(1)?.bytes(&[])?;
+        enc.array(1)?.null()?;
+        Ok(())
+    });
+    assert_eq!(resp.rows, expected);
+    Ok(())
+}
+
+/// test that arrays are returned as cbor arrays of their elements
+async fn array_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    array_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+async fn array_queries(ctx: &Context, client: &SqlDbSender<Provider>) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_array".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_array ( id SERIAL PRIMARY KEY, nums INT4[], tags TEXT[] )"
+                .to_string(),
+        )
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"insert into test_array (nums, tags) values \
+              ('{1, NULL, -3}', '{\"cold\", NULL, \"\"}'), ('{}', '{}'), (NULL, '{NULL}')"
+                .to_string(),
+        )
+        .await?;
+    let resp = client
+        .fetch(
+            ctx,
+            &"select nums, tags from test_array order by id".to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "select: {:?}", resp.error);
+    assert_eq!(resp.columns[0].db_type, "_int4");
+    assert_eq!(resp.columns[1].db_type, "_text");
+    type Row = (Option<Vec<Option<i32>>>, Vec<Option<String>>);
+    let rows: Vec<Row> = minicbor::decode(&resp.rows)?;
+    assert_eq!(
+        rows,
+        vec![
+            (
+                Some(vec![Some(1), None, Some(-3)]),
+                vec![Some("cold".into()), None, Some("".into())]
+            ),
+            (Some(vec![]), vec![]),
+            (None, vec![None]),
+        ]
+    );
+
+    // multi-dimensional arrays are nested arrays
+    let resp = client
+        .fetch(
+            ctx,
+            &"select '{{1,2,3},{4,5,6}}'::int8[], '[0:1]={true,false}'::bool[]".to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "select: {:?}", resp.error);
+    let rows: Vec<(Vec<Vec<i64>>, Vec<bool>)> = minicbor::decode(&resp.rows)?;
+    assert_eq!(
+        rows,
+        vec![(vec![vec![1, 2, 3], vec![4, 5, 6]], vec![true, false])]
+    );
+    Ok(())
+}
+
+/// test that enum values are returned as their labels, and composites as maps
+async fn custom_type_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov);
+    let ctx = Context::default();
+    custom_type_queries(&ctx, &client, &ext_client).await?;
+    Ok(())
+}
+
+async fn custom_type_queries(
+    ctx: &Context,
+    client: &SqlDbSender<Provider>,
+    ext_client: &SqlDbExtSender<Provider>,
+) -> Result<(), SqlDbError> {
+    for sql in [
+        "drop table if exists test_custom",
+        "drop type if exists test_item",
+        "drop type if exists test_price",
+        "drop type if exists test_mood",
+        "create type test_mood as enum ('sad', 'ok', 'happy')",
+        "create type test_price as ( amount INT4, currency TEXT )",
+        "create type test_item as ( name TEXT, mood test_mood, price test_price, tags TEXT[] )",
+        "create table test_custom ( id SERIAL PRIMARY KEY, mood test_mood, item test_item )",
+        "insert into test_custom (mood, item) values \
+         ('happy', ('cone', 'ok', (250, 'USD'), '{a,b}')), \
+         ('sad', ('cup', NULL, NULL, NULL))",
+    ] {
+        let resp = client.execute(ctx, &sql.to_string()).await?;
+        assert!(resp.error.is_none(), "{}: {:?}", sql, resp.error);
+    }
+
+    let resp = client
+        .fetch(
+            ctx,
+            &"select mood, item from test_custom order by id".to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "select: {:?}", resp.error);
+    assert_eq!(resp.columns[0].db_type, "test_mood");
+    assert_eq!(resp.columns[1].db_type, "test_item");
+    let expected = encode_params(|enc| {
+        enc.array(2)?;
+        enc.array(2)?.str("happy")?.map(4)?;
+        enc.str("name")?.str("cone")?;
+        enc.str("mood")?.str("ok")?;
+        enc.str("price")?.map(2)?;
+        enc.str("amount")?.i32(250)?;
+        enc.str("currency")?.str("USD")?;
+        enc.str("tags")?.array(2)?.str("a")?.str("b")?;
+        enc.array(2)?.str("sad")?.map(4)?;
+        enc.str("name")?.str("cup")?;
+        enc.str("mood")?.null()?;
+        enc.str("price")?.null()?;
+        enc.str("tags")?.null()?;
+        Ok(())
+    });
+    assert_eq!(resp.rows, expected);
+
+    // an anonymous record is an array of its fields
+    let resp = client
+        .fetch(ctx, &"select row(1, 'two', 'ok'::test_mood)".to_string())
+        .await?;
+    assert!(resp.error.is_none(), "select row: {:?}", resp.error);
+    let expected = encode_params(|enc| {
+        // the enum is not a built-in type, so it is its text, as bytes
+        enc.array(1)?
+            .array(1)?
+            .array(3)?
+            .i32(1)?
+            .str("two")?
+            .bytes(b"ok")?;
+        Ok(())
+    });
+    assert_eq!(resp.rows, expected);
+
+    // columns of extension results include the type oid
+    let resp = client
+        .fetch(
+            ctx,
+            &"select oid from pg_type where typname = 'test_mood'".to_string(),
+        )
+        .await?;
+    let rows: Vec<(u32,)> = minicbor::decode(&resp.rows)?;
+    let mood_oid = rows[0].0;
+    let resp = ext_client
+        .fetch_page(
+            ctx,
+            &PageRequest {
+                query: "select id, mood from test_custom order by id".into(),
+                limit: 10,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(resp.error.is_none(), "page: {:?}", resp.error);
+    assert_eq!(resp.columns[0].db_type, "int4");
+    assert_eq!(resp.columns[0].oid, 23);
+    assert_eq!(resp.columns[1].db_type, "test_mood");
+    assert_eq!(resp.columns[1].oid, mood_oid);
+    // the link's result_encoding is not set
+    assert_eq!(resp.encoding, "cbor");
+    Ok(())
+}
+
+/// test that NULL values of every type are returned as cbor null
+async fn null_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    let client = SqlDbSender::via(prov);
+    let ctx = Context::default();
+    null_queries(&ctx, &client).await?;
+    Ok(())
+}
+
+async fn null_queries(ctx: &Context, client: &SqlDbSender<Provider>) -> Result<(), SqlDbError> {
+    client
+        .execute(ctx, &"drop table if exists test_null".to_string())
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"create table test_null ( id SERIAL PRIMARY KEY, n INT8, t TEXT, b BOOL, \
+              f FLOAT8, ts TIMESTAMPTZ, p POINT )"
+                .to_string(),
+        )
+        .await?;
+    client
+        .execute(
+            ctx,
+            &"insert into test_null (n, t, b, f, ts, p) values \
+              (1, NULL, true, NULL, '2021-01-01 00:00:00+00', NULL), \
+              (NULL, 'x', NULL, 1.5, NULL, NULL), \
+              (NULL, NULL, NULL, NULL, NULL, NULL)"
+                .to_string(),
+        )
+        .await?;
+    let resp = client
+        .fetch(
+            ctx,
+            &"select n, t, b, f, ts, p from test_null order by id".to_string(),
+        )
+        .await?;
+    assert!(resp.error.is_none(), "select: {:?}", resp.error);
+    assert_eq!(resp.num_rows, 3);
+    let expected = encode_params(|enc| {
+        enc.array(3)?;
+        enc.array(6)?
+            .i64(1)?
+            .null()?
+            .bool(true)?
+            .null()?
+            .str("2021-01-01T00:00:00Z")?
+            .null()?;
+        enc.array(6)?
+            .null()?
+            .str("x")?
+            .null()?
+            .f64(1.5)?
+            .null()?
+            .null()?;
+        enc.array(6)?;
+        for _ in 0..6 {
+            enc.null()?;
+        }
+        Ok(())
+    });
+    assert_eq!(resp.rows, expected);
+    Ok(())
+}
+
+/// test that each statement of ExecuteBatch takes a token of max_queries_per_second
+async fn rate_limit_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let ext_client = SqlDbExtSender::via(prov.clone());
+    let ctx = Context::default();
+
+    relink(&prov, &[("max_queries_per_second", "2")]).await?;
+    let resp = ext_client
+        .execute_batch(
+            &ctx,
+            &BatchRequest {
+                queries: vec!["select 1".into(), "select 2".into(), "select 3".into()],
+                ..Default::default()
+            },
+        )
+        .await;
+    relink(&prov, &[]).await?;
+    let codes = resp?
+        .into_iter()
+        .map(|result| result.error.map(|e| e.code))
+        .collect::<Vec<_>>();
+    assert_eq!(codes, vec![None, None, Some("rateLimited".to_string())]);
+    Ok(())
+}
+
+/// test the column_case link value, which changes the names of the columns, and the keys
+/// of rows with row_format map
+async fn column_case_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let client = SqlDbSender::via(prov.clone());
+    let ctx = Context::default();
+    let query = r#"select 1 as "MixedCase""#.to_string();
+
+    relink(&prov, &[("column_case", "lower")]).await?;
+    let lower = client.fetch(&ctx, &query).await;
+    relink(&prov, &[("column_case", "upper"), ("row_format", "map")]).await?;
+    let upper = client.fetch(&ctx, &query).await;
+    relink(&prov, &[]).await?;
+    let preserve = client.fetch(&ctx, &query).await?;
+
+    let lower = lower?;
+    assert!(lower.error.is_none(), "lower: {:?}", lower.error);
+    assert_eq!(lower.columns[0].name, "mixedcase");
+    let upper = upper?;
+    assert!(upper.error.is_none(), "upper: {:?}", upper.error);
+    assert_eq!(upper.columns[0].name, "MIXEDCASE");
+    let mut dec = minicbor::Decoder::new(&upper.rows);
+    assert_eq!(dec.array()?, Some(1));
+    assert_eq!(dec.map()?, Some(1));
+    assert_eq!((dec.str()?, dec.i32()?), ("MIXEDCASE", 1));
+    assert!(preserve.error.is_none(), "preserve: {:?}", preserve.error);
+    assert_eq!(preserve.columns[0].name, "MixedCase");
+    Ok(())
+}
+
+/// with max_concurrent_queries 1, a statement waits for the connection held by a
+/// transaction, and fails with concurrencyLimit after concurrency_timeout_ms
+async fn concurrency_limit_test(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let client = SqlDbSender::via(prov.clone());
+    let ext_client = SqlDbExtSender::via(prov.clone());
+    let ctx = Context::default();
+
+    relink(
+        &prov,
+        &[
+            ("max_concurrent_queries", "1"),
+            ("concurrency_timeout_ms", "100"),
+        ],
+    )
+    .await?;
+    let begin = ext_client
+        .begin_transaction(&ctx, &BeginTransactionRequest::default())
+        .await;
+    let resp = client.execute(&ctx, &"select 1".to_string()).await;
+    if let Ok(begin) = &begin {
+        let _ = ext_client.rollback(&ctx, &begin.token).await;
+    }
+    // after the transaction, the connection is available again
+    let after = client.execute(&ctx, &"select 1".to_string()).await;
+    relink(&prov, &[]).await?;
+
+    let begin = begin?;
+    assert!(begin.error.is_none(), "begin: {:?}", begin.error);
+    assert_eq!(
+        resp?.error.as_ref().map(|e| e.code.as_str()),
+        Some("concurrencyLimit")
+    );
+    let after = after?;
+    assert!(after.error.is_none(), "after rollback: {:?}", after.error);
+    Ok(())
+}
+
+/// put the link of the test again, with the values of provider_test_config.toml, and
+/// `values`, which override them, and wait for the provider to update the link. The link is
+/// deleted first, since the host ignores a link put for an actor that is linked
+async fn relink(prov: &Provider, values: &[(&str, &str)]) -> RpcResult<()> {
+    let ld = wasmbus_rpc::core::LinkDefinition {
+        actor_id: prov.origin().public_key,
+        provider_id: prov.host_data.provider_key.clone(),
+        link_name: prov.host_data.link_name.clone(),
+        contract_id: "wasmcloud:sqldb".into(),
+        ..Default::default()
+    };
+    let topic = format!(
+        "wasmbus.rpc.{}.{}.{}.linkdefs.del",
+        &prov.host_data.lattice_rpc_prefix, &prov.host_data.provider_key, &prov.host_data.link_name,
+    );
+    let bytes = serde_json::to_vec(&ld).map_err(|e| RpcError::Ser(e.to_string()))?;
+    prov.rpc_client.publish(&topic, &bytes).await?;
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let config = wasmcloud_test_util::provider_test::load_config()
+        .map_err(|e| RpcError::Other(e.to_string()))?;
+    let json =
+        serde_json::to_string(&config.get("values")).map_err(|e| RpcError::Ser(e.to_string()))?;
+    let mut link_values = std::collections::HashMap::new();
+    link_values.insert(
+        "config_b64".to_string(),
+        base64::encode_config(&json, base64::STANDARD_NO_PAD),
+    );
+    link_values.extend(values.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+    prov.link_to_test(link_values)
+        .await
+        .map_err(|e| RpcError::Other(e.to_string()))?;
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    Ok(())
+}
+
+/// cbor-encode query parameters
+fn encode_params<F>(f: F) -> Vec<u8>
+where
+    F: FnOnce(
+        &mut minicbor::Encoder<&mut Vec<u8>>,
+    ) -> Result<(), minicbor::encode::Error<std::io::Error>>,
+{
+    let mut buf = Vec::new();
+    f(&mut minicbor::Encoder::new(&mut buf)).expect("encode params");
+    buf
+}